@@ -0,0 +1,297 @@
+//! Admin socket
+//!
+//! Running instance could listen on a UNIX socket, answering a request line of the client
+//! with a JSON object and closing the connection. Requests are:
+//! * `stats` - statistics of the instance, also returned to clients sending nothing;
+//! * `reload` - reload configuration, returning the validation result.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{self, Value};
+
+use crate::errors::{Error, Result};
+
+use crate::stats::SendStats;
+
+/// Time to wait for the request line, before answering with statistics
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Reload of the instance configuration, if it is supported.
+pub type Reload = Option<Box<dyn Fn() -> Result<()> + Send>>;
+
+/// Start serving requests on the socket at given path in a separate thread.
+///
+/// Socket left by a previous instance is replaced, while any other file at the path is an error.
+/// Socket is accessible by its owner only, as it allows reload.
+pub fn serve(path: &str, stats: SendStats, reload: Reload) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(Error::InternalError(format!(
+                "{} exists and is not a socket",
+                path
+            )))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = bind_private(Path::new(path))?;
+    info!("admin socket is listening on {}", path);
+
+    thread::Builder::new()
+        .name("admin".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if let Err(e) = stream.and_then(|stream| respond(stream, &stats, &reload)) {
+                    debug!("admin socket client failed: {}", e);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Bind socket accessible by its owner only, with no moment it is open to others.
+///
+/// Socket is bound in a private directory next to the path, restricted there and then moved into place.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "socket path has no file name")
+    })?;
+    let mut private = name.to_os_string();
+    private.push(format!(".{}", process::id()));
+    let dir = path.with_file_name(private);
+
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = dir.join(name);
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+
+    let _ = fs::remove_file(&bound);
+    fs::remove_dir(&dir)?;
+    listener
+}
+
+fn respond(mut stream: UnixStream, stats: &SendStats, reload: &Reload) -> io::Result<()> {
+    let response = match read_request(&stream)?.as_str() {
+        "" | "stats" => stats.to_json(),
+
+        "reload" => match reload {
+            Some(reload) => match reload() {
+                Ok(()) => json!({ "reloaded": true }),
+                Err(e) => json!({ "reloaded": false, "error": e.to_string() }),
+            },
+            None => {
+                json!({ "reloaded": false, "error": "reload is not supported by the instance" })
+            }
+        },
+
+        request => json!({ "error": format!("unknown request: {}", request) }),
+    };
+
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+    serde_json::to_writer(&mut stream, &response)?;
+    stream.write_all(b"\n")
+}
+
+/// Read request line, which is empty if client sends nothing for a while.
+fn read_request(stream: &UnixStream) -> io::Result<String> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut request = String::new();
+    match BufReader::new(stream).read_line(&mut request) {
+        Ok(_) => Ok(request.trim().to_string()),
+        Err(ref e)
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            Ok(String::new())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Send request to the instance listening on the socket at given path and return its response.
+fn request(path: &str, request: &str) -> Result<Value> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.shutdown(Shutdown::Write)?;
+
+    // reload could take a while, validating the target
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// Request statistics from the instance listening on the socket at given path.
+pub fn query(path: &str) -> Result<Value> {
+    request(path, "stats")
+}
+
+/// Make the instance listening on the socket at given path reload its configuration.
+pub fn reload(path: &str) -> Result<()> {
+    let response = request(path, "reload")?;
+    match response["error"].as_str() {
+        Some(e) => Err(Error::InternalError(e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Format statistics for humans, with throughput measured between two of them.
+pub fn format_report(before: &Value, after: &Value, interval: Duration) -> String {
+    let mut report = String::new();
+    let secs = interval.as_secs_f64();
+
+    let _ = writeln!(report, "uptime: {}s", after["uptime"]);
+
+    let records = &after["records"];
+    let _ = writeln!(
+        report,
+        "records: accepted: {}, filtered: {}, without message: {}, parse errors: {}, invalid UTF-8: {}, oversized: {}, overflowed: {}, dropped fields: {}",
+        records["accepted"],
+        records["filtered"],
+        records["no_message"],
+        records["errors"],
+        records["invalid_utf8"],
+        records["oversized"],
+        records["overflowed"],
+        records["dropped_fields"]
+    );
+
+    if let Some(queues) = after["queues"].as_object() {
+        let _ = write!(report, "queues:");
+        for (name, queue) in queues {
+            match queue["capacity"].as_u64() {
+                Some(capacity) => {
+                    let _ = write!(report, " {} {}/{}", name, queue["len"], capacity);
+                }
+                None => {
+                    let _ = write!(report, " {} {}", name, queue["len"]);
+                }
+            }
+        }
+        let _ = writeln!(report);
+    }
+
+    if let Some(targets) = after["targets"].as_object() {
+        for (name, target) in targets {
+            let previous = &before["targets"][name];
+            let rate = |counter: &str| {
+                let delta = target[counter]
+                    .as_u64()
+                    .unwrap_or(0)
+                    .saturating_sub(previous[counter].as_u64().unwrap_or(0));
+                delta as f64 / secs
+            };
+
+            let _ = writeln!(report, "target {}", name);
+            let _ = writeln!(
+                report,
+                "  throughput: {:.1} msgs/s, {:.1} KiB/s",
+                rate("messages"),
+                rate("bytes") / 1024.0
+            );
+            let _ = writeln!(
+                report,
+                "  messages: {}, bytes: {}, chunks: {}, errors: {}, dropped: {}",
+                target["messages"],
+                target["bytes"],
+                target["chunks"],
+                target["errors"],
+                target["dropped"]
+            );
+
+            match target["last_success"].as_u64() {
+                Some(ts) => {
+                    let _ = writeln!(report, "  last success: {}", ts);
+                }
+                None => {
+                    let _ = writeln!(report, "  last success: never");
+                }
+            }
+
+            let error = &target["last_error"];
+            if let Some(message) = error["error"].as_str() {
+                let _ = writeln!(
+                    report,
+                    "  last error: {} at {}",
+                    message, error["timestamp"]
+                );
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_owner_only_socket() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-admin-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("admin.sock");
+        let path = path.to_str().unwrap();
+
+        let stats = SendStats::new();
+        stats.records().record_accepted();
+        serve(path, stats.clone(), None).unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert_eq!(query(path).unwrap()["records"]["accepted"], json!(1));
+        assert!(reload(path).is_err());
+
+        // socket of a previous instance is replaced, while other files are not
+        serve(path, stats, Some(Box::new(|| Ok(())))).unwrap();
+        assert!(reload(path).is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(serve(file.to_str().unwrap(), SendStats::new(), None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_failed_reload() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-reload-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("admin.sock");
+        let path = path.to_str().unwrap();
+
+        let failing: Reload = Some(Box::new(|| {
+            Err(Error::InternalError("no targets".to_string()))
+        }));
+        serve(path, SendStats::new(), failing).unwrap();
+
+        match reload(path) {
+            Err(Error::InternalError(e)) => assert!(e.ends_with("no targets")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            request(path, "restart").unwrap()["error"],
+            json!("unknown request: restart")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
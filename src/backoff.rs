@@ -0,0 +1,114 @@
+//! Backoff after send failures
+//!
+//! While target is unreachable sending is paused for exponentially growing
+//! periods, and errors are logged once per retry instead of once per chunk.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Pause after the first failure, ms
+const INITIAL_DELAY_MS: u64 = 100;
+
+/// Maximum pause between retries, ms
+const MAX_DELAY_MS: u64 = 30_000;
+
+#[derive(Debug, Default)]
+pub struct SendBackoff {
+    failures: u32,
+    dropped: u64,
+    retry_at: Option<Instant>,
+}
+
+impl SendBackoff {
+    pub fn new() -> Self {
+        SendBackoff::default()
+    }
+
+    /// Return whether sending could be attempted now.
+    /// Messages skipped during backoff are counted as dropped.
+    pub fn ready(&mut self) -> bool {
+        match self.retry_at {
+            Some(at) if Instant::now() < at => {
+                self.dropped += 1;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Register successful send, leaving backoff if any.
+    pub fn success(&mut self) {
+        if self.failures > 0 {
+            info!(
+                "sending recovered after {} failed attempts, {} messages dropped",
+                self.failures, self.dropped
+            );
+        }
+
+        self.failures = 0;
+        self.dropped = 0;
+        self.retry_at = None;
+    }
+
+    /// Register failed send and postpone the next attempt.
+    pub fn failure<E: fmt::Display>(&mut self, err: E) {
+        self.dropped += 1;
+        self.failures = self.failures.saturating_add(1);
+
+        let delay = self.delay();
+        if self.failures == 1 {
+            error!("sender failure: {}, retry in {} ms", err, delay.as_millis());
+        } else {
+            warn!(
+                "sender still failing: {}, {} messages dropped, retry in {} ms",
+                err,
+                self.dropped,
+                delay.as_millis()
+            );
+        }
+
+        self.retry_at = Some(Instant::now() + delay);
+    }
+
+    fn delay(&self) -> Duration {
+        let shift = self.failures.saturating_sub(1).min(16);
+        Duration::from_millis((INITIAL_DELAY_MS << shift).min(MAX_DELAY_MS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_up_to_maximum() {
+        let mut backoff = SendBackoff::new();
+        assert!(backoff.ready());
+
+        backoff.failure("refused");
+        assert_eq!(backoff.delay(), Duration::from_millis(100));
+        backoff.failure("refused");
+        assert_eq!(backoff.delay(), Duration::from_millis(200));
+
+        for _ in 0..20 {
+            backoff.failure("refused");
+        }
+        assert_eq!(backoff.delay(), Duration::from_millis(MAX_DELAY_MS));
+    }
+
+    #[test]
+    fn skips_sending_until_retry() {
+        let mut backoff = SendBackoff::new();
+        backoff.failure("refused");
+        assert!(!backoff.ready());
+        assert!(!backoff.ready());
+        assert_eq!(backoff.dropped, 3);
+
+        backoff.retry_at = Some(Instant::now());
+        assert!(backoff.ready());
+
+        backoff.success();
+        assert_eq!((backoff.failures, backoff.dropped), (0, 0));
+        assert!(backoff.ready());
+    }
+}
@@ -0,0 +1,221 @@
+//! Benchmark of the processing stages
+//!
+//! Records of a corpus are decoded, transformed, compressed and chunked one
+//! by one, and resulting chunks are discarded by a null sink instead of being
+//! sent. Time spent on every stage is measured for each record.
+
+use std::fmt;
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use crate::errors::Result;
+
+use crate::config::Config;
+use crate::gelf::ChunkedMessage;
+use crate::generator::Generator;
+use crate::processing::{compression_of, decode, transform_decoded, LogRecord};
+use crate::stats::RecordStats;
+
+/// Number of distinct units in synthetic corpus
+const SYNTHETIC_UNITS: usize = 16;
+
+const STAGES: [&str; 4] = ["decode", "transform", "compress", "sink"];
+
+/// Read corpus of JSON records from a file, one per line.
+pub fn load_corpus(path: &str) -> Result<Vec<String>> {
+    let mut corpus = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            corpus.push(line);
+        }
+    }
+
+    Ok(corpus)
+}
+
+/// Generate corpus of journald-like records.
+pub fn synthetic_corpus(records: usize) -> Vec<String> {
+    let mut generator = Generator::new(SYNTHETIC_UNITS);
+    (0..records).map(|_| generator.record()).collect()
+}
+
+/// Push all records of the corpus through the processing stages.
+pub fn run(config: &Config, corpus: &[String]) -> BenchReport {
+    let mut report = BenchReport::default();
+    let mut stages: Vec<Vec<Duration>> = STAGES.iter().map(|_| Vec::new()).collect();
+    let mut decoded = LogRecord::new();
+    let stats = RecordStats::default();
+
+    let started = Instant::now();
+    for record in corpus {
+        report.records += 1;
+        report.input_bytes += record.len() as u64;
+
+        let t0 = Instant::now();
+        if decode(record, &mut decoded).is_err() {
+            continue;
+        }
+
+        let t1 = Instant::now();
+        let gelf = match transform_decoded(&mut decoded, config, &stats) {
+            Ok(gelf) => gelf,
+            Err(_) => continue,
+        };
+
+        let t2 = Instant::now();
+        let chunked = match compression_of(gelf.len(), config)
+            .compress_bytes(gelf)
+            .ok()
+            .and_then(|compressed| ChunkedMessage::new(config.chunk_size, compressed).ok())
+        {
+            Some(chunked) => chunked,
+            None => continue,
+        };
+
+        let t3 = Instant::now();
+        for chunk in chunked.chunks() {
+            report.output_bytes += black_box(chunk.len()) as u64;
+        }
+        let t4 = Instant::now();
+
+        report.messages += 1;
+        stages[0].push(t1 - t0);
+        stages[1].push(t2 - t1);
+        stages[2].push(t3 - t2);
+        stages[3].push(t4 - t3);
+    }
+    report.elapsed = started.elapsed();
+
+    report.stages = STAGES
+        .iter()
+        .zip(stages)
+        .map(|(name, samples)| StageReport::new(name, samples))
+        .collect();
+
+    report
+}
+
+#[derive(Debug, Default)]
+pub struct BenchReport {
+    pub records: u64,
+    pub messages: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub elapsed: Duration,
+    pub stages: Vec<StageReport>,
+}
+
+impl BenchReport {
+    pub fn msgs_per_sec(&self) -> f64 {
+        self.messages as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn mb_per_sec(&self) -> f64 {
+        self.input_bytes as f64 / 1_000_000_f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "records: {}, messages: {}, input: {} bytes, output: {} bytes, elapsed: {:.3} s",
+            self.records,
+            self.messages,
+            self.input_bytes,
+            self.output_bytes,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "throughput: {:.0} msgs/sec, {:.2} MB/sec",
+            self.msgs_per_sec(),
+            self.mb_per_sec()
+        )?;
+
+        for stage in &self.stages {
+            writeln!(f, "{}", stage)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Latency percentiles of a single stage.
+#[derive(Debug, Default)]
+pub struct StageReport {
+    pub name: &'static str,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl StageReport {
+    fn new(name: &'static str, mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+
+        StageReport {
+            name,
+            p50: percentile(&samples, 50),
+            p90: percentile(&samples, 90),
+            p99: percentile(&samples, 99),
+            max: samples.last().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for StageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}",
+            self.name, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+/// Return percentile of sorted samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gelf::LevelSystem;
+
+    #[test]
+    fn reports_every_stage() {
+        let mut corpus = synthetic_corpus(20);
+        corpus.push("not a record".to_string());
+
+        let mut config = Config::for_tests();
+        config.log_level_system = LevelSystem::Debug;
+        let report = run(&config, &corpus);
+
+        assert_eq!(report.records, 21);
+        assert_eq!(report.messages, 20);
+        assert!(report.output_bytes > 0);
+        let names: Vec<&str> = report.stages.iter().map(|stage| stage.name).collect();
+        assert_eq!(names, STAGES);
+    }
+
+    #[test]
+    fn percentiles_of_samples() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        let stage = StageReport::new("decode", samples);
+        assert_eq!(stage.p50, Duration::from_millis(50));
+        assert_eq!(stage.p99, Duration::from_millis(99));
+        assert_eq!(stage.max, Duration::from_millis(100));
+
+        assert_eq!(percentile(&[], 50), Duration::default());
+    }
+}
@@ -1,181 +1,2369 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
 extern crate clap;
 extern crate jctl2gray;
 extern crate loggerv;
+extern crate serde_json;
+extern crate toml;
 
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
 use std::net::ToSocketAddrs;
+use std::os::unix::fs::FileTypeExt;
 use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use clap::{App, Arg};
-use jctl2gray::config::{parse_log_source, Config, LogSource};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand, Values};
+use jctl2gray::admin;
+use jctl2gray::chaos::Chaos;
+use jctl2gray::config::{parse_log_source, Config, LogSource, Output, SharedConfig};
+use jctl2gray::decode::{self, InputFormat};
+use jctl2gray::dedup::Dedup;
+use jctl2gray::errors::Error;
+use jctl2gray::explain;
+use jctl2gray::export::JournalFormat;
+use jctl2gray::extract::Extractor;
+use jctl2gray::facility::Facility;
+use jctl2gray::fields;
+use jctl2gray::gelf::{is_valid_field_name, ChunkSize, MAX_CHUNK_SIZE, RESERVED_FIELD};
+use jctl2gray::generator;
+use jctl2gray::graylog_api;
+use jctl2gray::health;
+use jctl2gray::ignore::{FieldPattern, DEFAULT_IGNORED_FIELDS};
+use jctl2gray::multiline::Multiline;
+use jctl2gray::oneshot;
+use jctl2gray::oversize::OversizePolicy;
+use jctl2gray::pipeline::QueuePolicy;
 use jctl2gray::processing;
-use jctl2gray::{LevelMsg, LevelSystem, MessageCompression};
+#[cfg(feature = "async")]
+use jctl2gray::processing_async;
+use jctl2gray::ratelimit::RateLimiter;
+use jctl2gray::redact::Redactor;
+use jctl2gray::replay::{self, Pace};
+use jctl2gray::resolver::{ResolveStrategy, Resolver};
+use jctl2gray::sampling::Sampling;
+use jctl2gray::signal::ReloadSignal;
+use jctl2gray::stats::SendStats;
+use jctl2gray::syslog::ListenProtocol;
+use jctl2gray::tail::Pattern;
+use jctl2gray::tls::TlsOptions;
+use jctl2gray::transport::{unix_path, KafkaOptions, TransportKind};
+use jctl2gray::unit_rules::UnitRules;
+use jctl2gray::{LevelMsg, LevelSystem, MessageBuilder, MessageCompression};
+use regex::Regex;
+
+/// Action requested from the command line
+enum Command {
+    Run,
+    Multi {
+        path: String,
+    },
+    Check,
+    Ping,
+    TestFilter,
+    Send {
+        message: String,
+        level: LevelSystem,
+        host: Option<String>,
+    },
+    Replay {
+        path: String,
+        pace: Pace,
+    },
+    Bench {
+        corpus: Option<String>,
+        records: usize,
+    },
+    Generate {
+        rate: u64,
+        cardinality: usize,
+        count: Option<u64>,
+    },
+    Fields {
+        duration: Duration,
+    },
+    Decode {
+        path: Option<String>,
+        format: InputFormat,
+        port: Option<u16>,
+    },
+    Stats {
+        socket: String,
+        interval: u64,
+    },
+    Reload {
+        socket: String,
+    },
+    Completions(Shell),
+    InitConfig {
+        path: Option<String>,
+        force: bool,
+    },
+    Version,
+}
+
+/* Default values of options, shared by subcommands */
 
-fn parse_options() -> Config {
-    let args = App::new("jctl2gray")
-        .version("0.2")
+const DEFAULT_PORT: &str = "5000";
+const DEFAULT_TARGET: &str = "127.0.0.1:9000";
+const DEFAULT_TTL: &str = "60";
+const DEFAULT_FAILOVER_AFTER: &str = "3";
+const DEFAULT_TRANSPORT: &str = "udp";
+const DEFAULT_BATCH: &str = "1";
+const DEFAULT_BATCH_TIMEOUT: &str = "10";
+const DEFAULT_LISTEN_PROTOCOL: &str = "udp";
+const DEFAULT_RESOLVE: &str = "first";
+const DEFAULT_WORKERS: &str = "1";
+const DEFAULT_QUEUE: &str = "1024";
+const DEFAULT_QUEUE_POLICY: &str = "block";
+const DEFAULT_READ_BUFFER: &str = "65536";
+const DEFAULT_MULTILINE_TIMEOUT: &str = "1000";
+const DEFAULT_MAX_LINE: &str = "1048576";
+const DEFAULT_COMPRESSION: &str = "none";
+const DEFAULT_COMPRESS_MIN: &str = "0";
+#[cfg(not(feature = "zstd"))]
+const COMPRESSIONS: [&str; 3] = ["none", "gzip", "zlib"];
+#[cfg(feature = "zstd")]
+const COMPRESSIONS: [&str; 4] = ["none", "gzip", "zlib", "zstd"];
+const DEFAULT_CHUNK_SIZE: &str = "wan";
+const DEFAULT_OVERSIZE_POLICY: &str = "drop";
+const DEFAULT_STATS_INTERVAL: &str = "0";
+const DEFAULT_SYSTEM_LEVEL: &str = "info";
+const DEFAULT_CAPTURE_LIMIT: &str = "100";
+const DEFAULT_OUTPUT: &str = "graylog";
+const DEFAULT_OUTPUT_LIMIT: &str = "100";
+const DEFAULT_OUTPUT_KEEP: &str = "5";
+#[cfg(not(feature = "kafka"))]
+const OUTPUTS: [&str; 3] = ["graylog", "stdout", "file"];
+#[cfg(feature = "kafka")]
+const OUTPUTS: [&str; 4] = ["graylog", "stdout", "file", "kafka"];
+#[cfg(feature = "kafka")]
+const DEFAULT_KAFKA_TOPIC: &str = "gelf";
+#[cfg(feature = "kafka")]
+const DEFAULT_KAFKA_KEY: &str = "host";
+const DEFAULT_SPOOL_LIMIT: &str = "1024";
+const DEFAULT_ADMIN_SOCKET: &str = "/run/jctl2gray.sock";
+
+const SYSTEM_LEVELS: [&str; 8] = [
+    "emergency",
+    "alert",
+    "critical",
+    "error",
+    "warning",
+    "notice",
+    "info",
+    "debug",
+];
+
+fn app() -> App<'static, 'static> {
+    // options of `run` are accepted without subcommand as well, for backward compatibility
+    App::new("jctl2gray")
+        .version(crate_version!())
         .author("Anton Dort-Golts <dortgolts@gmail.com>")
         .about("Reading logs from stdin/journalctl and sending it to Graylog")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
-            Arg::with_name("log_source")
-                .short("s")
-                .long("source")
-                .value_name("log source")
-                .help("Log source")
-                .takes_value(true)
-                .possible_values(&["stdin", "journal"])
-                .required(true),
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Log more details, -vv for tracing")
+                .multiple(true)
+                .global(true),
         )
         .arg(
-            Arg::with_name("port")
-                .short("p")
-                .long("port")
-                .value_name("UDP-port")
-                .help("Local UDP-port to send from")
-                .takes_value(true)
-                .validator(validate_port)
-                .default_value("5000"),
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Log only warnings, -qq for errors only")
+                .multiple(true)
+                .conflicts_with("verbose")
+                .global(true),
         )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .value_name("address")
-                .help("Full address of target Graylog")
-                .takes_value(true)
-                .validator(validate_address)
-                .default_value("127.0.0.1:9000"),
+        .arg(config_arg())
+        .args(&source_args())
+        .args(&target_args())
+        .args(&message_args())
+        .args(&pipeline_args())
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Read logs and send them to Graylog (default)")
+                .arg(config_arg())
+                .args(&source_args())
+                .args(&target_args())
+                .args(&message_args())
+                .args(&pipeline_args()),
         )
-        .arg(
-            Arg::with_name("ttl")
-                .long("ttl")
-                .value_name("TTL")
-                .help("Period of resolving target's IP-address, secs")
-                .takes_value(true)
-                .validator(validate_ttl)
-                .default_value("60"),
+        .subcommand(
+            SubCommand::with_name("multi")
+                .about("Run several independent pipelines defined in the file")
+                .long_about("Run several independent pipelines defined in the file, one per line as options of the run subcommand, e.g. `-s journal -t graylog.domain.com:12201 --sys warning`. Empty lines and lines starting with # are skipped.")
+                .arg(stats_arg())
+                .arg(admin_socket_arg())
+                .arg(health_arg())
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("file")
+                        .help("File with pipelines, one per line")
+                        .required(true),
+                ),
         )
-        .arg(
-            Arg::with_name("compression")
-                .short("c")
-                .long("comp")
-                .value_name("algorithm")
-                .help("Message compression type")
-                .takes_value(true)
-                .possible_values(&["none", "gzip", "zlib"])
-                .default_value("none"),
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check that target resolves and, given --api-url, that it is a running GELF input")
+                .arg(config_arg())
+                .args(&target_args()),
         )
-        .arg(
-            Arg::with_name("opt_fields")
-                .long("opt")
-                .value_name("name=text")
-                .help("Optional fields to be attached to the message")
-                .long_help("Optional fields could be attached to every message sent to Graylog. Fields must be defined as comma delimited pairs in format <field_name=text>, e.g.: `--opt team=t1,service=backend`")
+        .subcommand(
+            SubCommand::with_name("ping")
+                .about("Send a test message to every address of the target and report the outcome")
+                .args(&target_args())
+                .args(&message_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("test-filter")
+                .about("Explain processing of records read from stdin: detected levels, fields and result")
+                .args(&message_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("send")
+                .about("Send a single message to the target, e.g. from scripts")
+                .args(&target_args())
+                .args(&message_args())
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .value_name("level")
+                        .help("Level of the message")
+                        .takes_value(true)
+                        .possible_values(&SYSTEM_LEVELS)
+                        .default_value("info"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .value_name("name")
+                        .help("Host reported by the message (default: name of the local host)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("message")
+                        .value_name("message")
+                        .help("Text of the message")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Send captured GELF messages or journald records to the target")
+                .args(&target_args())
+                .args(&message_args())
+                .args(&pipeline_args())
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("msgs/sec")
+                        .help("Rate of replay (0 - as fast as possible), instead of original pacing")
+                        .takes_value(true)
+                        .validator(validate_interval),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("file")
+                        .help("File with messages or records, one per line")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measure processing performance on a corpus of records, discarding the output")
+                .args(&message_args())
+                .arg(
+                    Arg::with_name("corpus")
+                        .long("corpus")
+                        .value_name("file")
+                        .help("File with JSON records, one per line (synthetic records by default)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("records")
+                        .short("n")
+                        .long("records")
+                        .value_name("N")
+                        .help("Number of synthetic records")
+                        .takes_value(true)
+                        .validator(validate_records)
+                        .default_value("100000"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Send synthetic journald-like records to the target, e.g. for soak testing")
+                .args(&target_args())
+                .args(&message_args())
+                .args(&pipeline_args())
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("msgs/sec")
+                        .help("Rate of generated records (0 - as fast as possible)")
+                        .takes_value(true)
+                        .validator(validate_interval)
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::with_name("cardinality")
+                        .long("cardinality")
+                        .value_name("N")
+                        .help("Number of distinct units records come from")
+                        .takes_value(true)
+                        .validator(validate_workers)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .short("n")
+                        .long("count")
+                        .value_name("N")
+                        .help("Stop after given number of records (endless by default)")
+                        .takes_value(true)
+                        .validator(validate_interval),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fields")
+                .about("Sample records of the source and report their fields with example values")
+                .args(&source_args())
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .value_name("duration")
+                        .help("Sampling window, e.g. 90, 60s or 5m")
+                        .takes_value(true)
+                        .validator(validate_duration)
+                        .default_value("60s"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about("Print GELF messages sent over the wire: reassemble chunks and decompress")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("format")
+                        .help("Format of the input: datagrams as hex lines or pcap capture")
+                        .takes_value(true)
+                        .possible_values(&["hex", "pcap"])
+                        .default_value("hex"),
+                )
+                .arg(
+                    Arg::with_name("dst_port")
+                        .long("dst-port")
+                        .value_name("port")
+                        .help("Consider only datagrams to the port in pcap capture")
+                        .takes_value(true)
+                        .validator(validate_port),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("file")
+                        .help("File with datagrams (stdin by default)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print completion script for the shell")
+                .arg(
+                    Arg::with_name("shell")
+                        .value_name("shell")
+                        .help("Shell to generate completions for")
+                        .possible_values(&Shell::variants())
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Print statistics of the running instance, requested via its admin socket")
+                .arg(
+                    Arg::with_name("admin_socket")
+                        .long("admin-socket")
+                        .value_name("path")
+                        .help("Admin socket of the instance")
+                        .takes_value(true)
+                        .default_value(DEFAULT_ADMIN_SOCKET),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("secs")
+                        .help("Period of throughput measurement")
+                        .takes_value(true)
+                        .validator(validate_ttl)
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reload")
+                .about("Make the running instance reload its configuration, via its admin socket")
+                .arg(
+                    Arg::with_name("admin_socket")
+                        .long("admin-socket")
+                        .value_name("path")
+                        .help("Admin socket of the instance")
+                        .takes_value(true)
+                        .default_value(DEFAULT_ADMIN_SOCKET),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init-config")
+                .about("Write a commented template of the config file, to stdout by default")
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite the file if it exists"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("file")
+                        .help("File to write the template to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("version").about("Print version along with enabled features"),
+        )
+}
+
+/// Where logs are read from
+fn config_arg() -> Arg<'static, 'static> {
+    Arg::with_name("config")
+        .long("config")
+        .value_name("file")
+        .help("TOML file with options, overridden by the command line ones")
+        .long_help("TOML file with options, named as long command line options, e.g. `source = \"journal\"`, `target = \"graylog.domain.com:12201\"`, `facility = [\"auth\", \"cron\"]`. Flags are set by `true`, fields of `opt` could be given as a table. Options of the command line override the file ones.")
+        .takes_value(true)
+}
+
+fn source_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("log_source")
+            .short("s")
+            .long("source")
+            .value_name("log source")
+            .help("Log source")
+            .takes_value(true)
+            .possible_values(&["stdin", "journal", "file", "syslog"])
+            .required_unless("config"),
+        Arg::with_name("path")
+            .long("path")
+            .value_name("pattern")
+            .help("Files followed by file source, e.g. /var/log/app/*.log")
+            .long_help("Files followed by file source, like `tail -F` does: rotated files are read to the end and then followed by name, truncated ones are read from the start. Wildcards `*` and `?` are allowed in the file name, files matching them are looked up while running. With --cursor-file offsets of read lines are saved, otherwise files found on start are followed from their end.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_path_pattern)
+            .required_if("log_source", "file"),
+        Arg::with_name("unit")
+            .short("u")
+            .long("unit")
+            .value_name("unit")
+            .help("Read only records of the systemd unit with journal source, could be repeated")
+            .long_help("Read only records of the systemd unit with journal source, could be repeated, e.g. `--unit nginx.service --unit myapp`. Units are passed to journalctl as -u options, or matched by _SYSTEMD_UNIT field when the journal is read natively.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("multiline_start")
+            .long("multiline-start")
+            .value_name("regex")
+            .help("Merge records of stdin source into the previous one until a message matching the regex")
+            .long_help("Merge records of stdin source into the previous one, unless their message matches the regex starting a new one, e.g. `^\\S` for stack traces indented after the first line. The first line stays the message, while all of them are sent as the full message. Only records of the same systemd unit are merged.")
+            .takes_value(true)
+            .validator(validate_regex),
+        Arg::with_name("multiline_timeout")
+            .long("multiline-timeout")
+            .value_name("ms")
+            .help("Time to wait for the next line of a multi-line message before sending it")
+            .takes_value(true)
+            .validator(validate_ttl)
+            .default_value(DEFAULT_MULTILINE_TIMEOUT),
+        Arg::with_name("listen")
+            .long("listen")
+            .value_name("address")
+            .help("Local address syslog source receives messages on, e.g. 0.0.0.0:514")
+            .takes_value(true)
+            .validator(validate_address)
+            .required_if("log_source", "syslog"),
+        Arg::with_name("listen_protocol")
+            .long("listen-protocol")
+            .value_name("protocol")
+            .help("Protocol syslog messages are received over")
+            .long_help("Protocol syslog messages are received over. Over UDP every datagram is a message, over TCP messages are framed either by octet counting or by newlines.")
+            .takes_value(true)
+            .possible_values(&["udp", "tcp", "both"])
+            .default_value(DEFAULT_LISTEN_PROTOCOL),
+    ]
+}
+
+/// Where messages are sent to
+fn target_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("port")
+            .short("p")
+            .long("port")
+            .value_name("UDP-port")
+            .help("Local UDP-port to send from")
+            .takes_value(true)
+            .validator(validate_port)
+            .default_value(DEFAULT_PORT),
+        Arg::with_name("target")
+            .short("t")
+            .long("target")
+            .value_name("address")
+            .help("Full address of target Graylog, or unix:<path> of a local socket, could be repeated to send every message to each")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_address)
+            .default_value(DEFAULT_TARGET),
+        Arg::with_name("failover")
+            .long("target-failover")
+            .value_name("address")
+            .help("Full address of backup Graylog, taking over when the first target fails")
+            .takes_value(true)
+            .validator(validate_address),
+        Arg::with_name("failover_after")
+            .long("failover-after")
+            .value_name("N")
+            .help("Number of consecutive send failures of the first target before switching to backup")
+            .long_help("Number of consecutive send failures of the first target before switching to backup. While backup is in use, the first target is tried again every 30 seconds and takes over back once it accepts a message.")
+            .takes_value(true)
+            .validator(validate_failover_after)
+            .default_value(DEFAULT_FAILOVER_AFTER),
+        Arg::with_name("transport")
+            .long("transport")
+            .value_name("transport")
+            .help("Transport of messages")
+            .long_help("Transport of messages: UDP datagrams, TCP connection, or TCP connection encrypted with TLS. Over TCP messages are sent uncompressed, as GELF requires, except for zstd compression.")
+            .takes_value(true)
+            .possible_values(&["udp", "tcp", "tls"])
+            .default_value(DEFAULT_TRANSPORT),
+        Arg::with_name("tls_ca")
+            .long("tls-ca")
+            .value_name("file")
+            .help("PEM file with certificates of trusted authorities, bundled web roots by default")
+            .long_help("PEM file with certificates of trusted authorities, bundled web roots by default. Modified file is loaded again for the next connection, while established one keeps its certificates until it fails.")
+            .takes_value(true),
+        Arg::with_name("tls_cert")
+            .long("tls-cert")
+            .value_name("file")
+            .help("PEM file with client certificate chain")
+            .long_help("PEM file with client certificate chain. Modified file is loaded again for the next connection, while established one keeps its certificates until it fails.")
+            .takes_value(true)
+            .requires("tls_key"),
+        Arg::with_name("tls_key")
+            .long("tls-key")
+            .value_name("file")
+            .help("PEM file with private key of client certificate")
+            .long_help("PEM file with private key of client certificate. Modified file is loaded again for the next connection, while established one keeps its certificates until it fails.")
+            .takes_value(true)
+            .requires("tls_cert"),
+        Arg::with_name("tls_no_verify_hostname")
+            .long("tls-no-verify-hostname")
+            .help("Accept certificate of trusted authority issued for any hostname"),
+        Arg::with_name("batch")
+            .long("batch")
+            .value_name("N")
+            .help("Write up to N messages at once over TCP")
+            .long_help("Write up to N messages at once over TCP, rather than one by one, saving syscalls at high rates. Messages are collected for at most --batch-timeout, so that batching delays them only slightly while the rate is low.")
+            .takes_value(true)
+            .validator(validate_workers)
+            .default_value(DEFAULT_BATCH),
+        Arg::with_name("batch_timeout")
+            .long("batch-timeout")
+            .value_name("ms")
+            .help("Time to collect a batch of messages for, ms")
+            .takes_value(true)
+            .validator(validate_ttl)
+            .default_value(DEFAULT_BATCH_TIMEOUT),
+        Arg::with_name("send_buffer")
+            .long("send-buffer")
+            .value_name("bytes")
+            .help("Size of the UDP socket send buffer")
+            .long_help("Size of the UDP socket send buffer, bytes (system default if not set). Bigger buffer absorbs bursts of messages, which otherwise could be dropped by the kernel. Size is capped by net.core.wmem_max.")
+            .takes_value(true)
+            .validator(validate_send_buffer),
+        Arg::with_name("ttl")
+            .long("ttl")
+            .value_name("TTL")
+            .help("Period of resolving target's IP-address, secs")
+            .takes_value(true)
+            .validator(validate_ttl)
+            .default_value(DEFAULT_TTL),
+        Arg::with_name("resolve")
+            .long("resolve")
+            .value_name("strategy")
+            .help("Choice among multiple resolved target addresses")
+            .takes_value(true)
+            .possible_values(&["first", "ipv4", "ipv6", "round-robin"])
+            .default_value(DEFAULT_RESOLVE),
+        Arg::with_name("api_url")
+            .long("api-url")
+            .value_name("URL")
+            .help("Graylog REST API URL used to validate the target GELF input on startup")
+            .takes_value(true),
+        Arg::with_name("api_token")
+            .long("api-token")
+            .value_name("token")
+            .help("Graylog REST API access token")
+            .takes_value(true)
+            .requires("api_url"),
+    ]
+}
+
+/// How records are filtered and transformed into messages
+fn message_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("compression")
+            .short("c")
+            .long("comp")
+            .value_name("algorithm")
+            .help("Message compression type")
+            .takes_value(true)
+            .possible_values(&COMPRESSIONS)
+            .default_value(DEFAULT_COMPRESSION),
+        Arg::with_name("compress_min")
+            .long("compress-min")
+            .value_name("bytes")
+            .help("Send messages smaller than the size uncompressed, e.g. 512")
+            .long_help("Send messages smaller than the size uncompressed: compressing tiny messages wastes CPU and could even make them larger. Larger messages are compressed with the algorithm of --comp.")
+            .takes_value(true)
+            .validator(validate_size)
+            .default_value(DEFAULT_COMPRESS_MIN),
+        Arg::with_name("chunk_size")
+            .long("chunk-size")
+            .value_name("size")
+            .help("Payload size of UDP chunks: wan, lan or size in bytes")
+            .long_help("Payload size of chunks messages are split into over UDP: wan (1420 bytes, fits the usual MTU), lan (8154 bytes, for networks with jumbo frames) or size in bytes, at most 8180.")
+            .takes_value(true)
+            .validator(validate_chunk_size)
+            .default_value(DEFAULT_CHUNK_SIZE),
+        Arg::with_name("oversize_policy")
+            .long("oversize-policy")
+            .value_name("policy")
+            .help("Handling of messages too large for 128 chunks of UDP")
+            .long_help("Handling of messages too large for 128 chunks of UDP: drop them, counting them as oversized in statistics, truncate the message text to fit, or keep the head of the text as the message while the text, truncated, is sent as the full message. Shrunk messages are marked with field _truncated.")
+            .takes_value(true)
+            .possible_values(&["drop", "truncate", "full-message"])
+            .default_value(DEFAULT_OVERSIZE_POLICY),
+        Arg::with_name("opt_fields")
+            .long("opt")
+            .value_name("name=text")
+            .help("Optional fields to be attached to the message")
+            .long_help("Optional fields could be attached to every message sent to Graylog. Fields must be defined as comma delimited pairs in format <field_name=text>, e.g.: `--opt team=t1,service=backend`")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true),
+        Arg::with_name("field")
+            .long("field")
+            .value_name("name=text")
+            .help("Additional field of every message, could be repeated, e.g. --field environment=prod")
+            .long_help("Additional field of every message, sent as _name, could be repeated, e.g. `--field environment=prod --field region=eu-west-1`. Text could contain commas and `=`, unlike the one of --opt. Fields override fields of the record with the same name. In the config file fields are given as a table `[field]`.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_field),
+        Arg::with_name("input_tag")
+            .long("input-tag")
+            .value_name("name")
+            .help("Attach field _input with the name of the input, e.g. to route by origin")
+            .takes_value(true),
+        Arg::with_name("rename_reserved")
+            .long("rename-reserved")
+            .help("Rename field id, reserved by GELF, to record_id instead of dropping it"),
+        Arg::with_name("rename_field")
+            .long("rename-field")
+            .value_name("from=to")
+            .help("Send field of the record under another name, could be repeated, e.g. _SYSTEMD_UNIT=unit")
+            .long_help("Send field of the record under another name, could be repeated, e.g. `--rename-field _SYSTEMD_UNIT=unit --rename-field CONTAINER_NAME=container`. Renamed field replaces the one of the record with the same name. In the config file renames are given as a table `[rename-field]`.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_rename),
+        Arg::with_name("drop_field")
+            .long("drop-field")
+            .value_name("pattern")
+            .help("Drop fields of the record matching the pattern, could be repeated, e.g. _CMDLINE or _AUDIT_*")
+            .long_help("Drop fields of the record matching the pattern, could be repeated. Pattern is either an exact name, e.g. `_CAP_EFFECTIVE`, a glob with wildcards `*` and `?`, e.g. `_AUDIT_*`, or a regex between slashes, e.g. `/^_UDEV_/`.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_field_pattern),
+        Arg::with_name("keep_field")
+            .long("keep-field")
+            .value_name("name")
+            .help("Send journald field ignored by default, could be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .possible_values(&DEFAULT_IGNORED_FIELDS),
+        Arg::with_name("redact")
+            .long("redact")
+            .value_name("preset|regex")
+            .help("Mask matches in messages and fields before sending, could be repeated, e.g. email or card")
+            .long_help("Mask matches in messages and fields before sending, could be repeated. Either a preset: api-key (known token formats and values of keys like password or token), bearer (bearer tokens), card (payment card numbers passing the Luhn check), email, or a regex. Matches are replaced with [REDACTED], when the regex has a group named `secret` only the group is, e.g. `--redact 'session=(?P<secret>\\w+)'`.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_redaction),
+        Arg::with_name("monotonic_time")
+            .long("monotonic-time")
+            .help("Take time of records of the current boot from monotonic timestamps, immune to clock steps"),
+        Arg::with_name("facility")
+            .long("facility")
+            .value_name("facility")
+            .help("Send only records of the syslog facility, could be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .possible_values(Facility::names()),
+        Arg::with_name("system_level")
+            .short("l")
+            .long("sys")
+            .value_name("level")
+            .help("System logging level threshold")
+            .takes_value(true)
+            .possible_values(&SYSTEM_LEVELS)
+            .default_value(DEFAULT_SYSTEM_LEVEL),
+        Arg::with_name("msg_level")
+            .short("m")
+            .long("msg")
+            .value_name("level")
+            .help("Message filter logging level threshold")
+            .takes_value(true)
+            .possible_values(&["fatal", "panic", "error", "warning", "info", "debug"]),
+        Arg::with_name("parse_json_message")
+            .long("parse-json-message")
+            .help("Split JSON messages into fields, sending their message and level as the ones of GELF")
+            .long_help("Split messages which are JSON objects into additional fields named by their keys, sending the `message` (or `msg`) value as the message and the `level` value as the level, which --sys threshold applies to. Nested objects are sent as JSON text, fields of the record take precedence over the ones of its message. Other messages are sent as is."),
+        Arg::with_name("parse_logfmt")
+            .long("parse-logfmt")
+            .help("Split logfmt messages into fields, sending their msg as the message")
+            .long_help("Split messages written as logfmt, e.g. `level=error msg=\"boom\" user_id=42`, into additional fields named by their keys, sending the msg value as the message. Fields of the record take precedence over the ones of its message. Messages which are not logfmt entirely are sent as is."),
+        Arg::with_name("sample_rate")
+            .long("sample-rate")
+            .value_name("rate")
+            .help("Send only a random share of records, common or of a system level, could be repeated")
+            .long_help("Send only a random share of records passing the filters, in range 0..1. Rate is either common for all levels, e.g. `0.1`, or of a system level, e.g. `debug=0.01`, could be repeated: `--sample-rate 0.5 --sample-rate debug=0.01 --sample-rate error=1`. Records of levels without own rate are sampled with the common one, all are sent by default.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_sample_rate),
+        Arg::with_name("dedup")
+            .long("dedup")
+            .value_name("window")
+            .help("Collapse repeats of the previous message within the window, e.g. 30s, into one with _repeat_count field")
+            .long_help("Collapse identical consecutive messages (same text, host and systemd unit) within the window since the first of them, e.g. 30s or 5m. The first one is sent as usual, and its repeats are sent as a single message with _repeat_count field once another message comes or the window is over.")
+            .takes_value(true)
+            .validator(validate_window),
+        Arg::with_name("rate_limit")
+            .long("rate-limit")
+            .value_name("records/s")
+            .help("Send at most given number of records per second of every systemd unit")
+            .long_help("Send at most given number of records per second of every systemd unit (_SYSTEMD_UNIT field), so that a single service could not flood Graylog. Numbers of suppressed records are logged once a minute.")
+            .takes_value(true)
+            .validator(validate_rate),
+        Arg::with_name("rate_burst")
+            .long("rate-burst")
+            .value_name("N")
+            .help("Number of records of a unit sent at once before the rate limit applies (default: one second worth)")
+            .takes_value(true)
+            .requires("rate_limit")
+            .validator(validate_workers),
+        Arg::with_name("include_regex")
+            .long("include-regex")
+            .value_name("regex")
+            .help("Send only records with message matching the regex, could be repeated to match any of them")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_regex),
+        Arg::with_name("exclude_regex")
+            .long("exclude-regex")
+            .value_name("regex")
+            .help("Drop records with message matching the regex, could be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_regex),
+        Arg::with_name("unit_rule")
+            .long("unit-rule")
+            .value_name("unit:rule")
+            .help("Level threshold, dropped messages or field of records of a systemd unit, could be repeated")
+            .long_help("Rule of records of a systemd unit, could be repeated: `sys=<level>` replaces --sys threshold, `exclude=<regex>` drops matching messages in addition to --exclude-regex, `field=<name>=<text>` attaches a field overriding --field, e.g. `--unit-rule nginx:sys=warning --unit-rule nginx:exclude='^GET /health' --unit-rule nginx:field=team=web`. Units without suffix are services. Rules are indexed by unit on start and on reload, so a record finds them by a single lookup of its _SYSTEMD_UNIT.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_unit_rule),
+        Arg::with_name("extract")
+            .long("extract")
+            .value_name("name=regex")
+            .help("Add fields captured from the message by named groups of the regex, could be repeated")
+            .long_help("Add fields captured from the message by named groups of the regex, could be repeated, e.g. `--extract 'req=(?P<method>GET|POST) (?P<path>\\S+) (?P<status>\\d{3})'` adds fields _method, _path and _status to messages of access logs. In the config file extractors are given as a table `[extract]`. Fields of the record take precedence over the extracted ones.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_extractor),
+    ]
+}
+
+/// Period of statistics logging, common for all pipelines of the process
+fn stats_arg() -> Arg<'static, 'static> {
+    Arg::with_name("stats_interval")
+        .long("stats")
+        .value_name("interval")
+        .help("Period of logging per-target send statistics, secs (0 - disabled)")
+        .takes_value(true)
+        .validator(validate_interval)
+        .default_value(DEFAULT_STATS_INTERVAL)
+}
+
+/// Admin socket, common for all pipelines of the process
+fn admin_socket_arg() -> Arg<'static, 'static> {
+    Arg::with_name("admin_socket")
+        .long("admin-socket")
+        .value_name("path")
+        .help("Serve statistics for the stats subcommand on the UNIX socket")
+        .takes_value(true)
+}
+
+/// Health endpoint, common for all pipelines of the process
+fn health_arg() -> Arg<'static, 'static> {
+    Arg::with_name("health")
+        .long("health")
+        .value_name("address")
+        .help("Serve /healthz and /status over HTTP on the address, e.g. 0.0.0.0:8080")
+        .takes_value(true)
+        .validator(validate_address)
+}
+
+/// How processing is organized
+fn pipeline_args() -> Vec<Arg<'static, 'static>> {
+    let args = vec![
+        Arg::with_name("workers")
+            .short("w")
+            .long("workers")
+            .value_name("N")
+            .help("Number of threads transforming and compressing messages")
+            .takes_value(true)
+            .validator(validate_workers)
+            .default_value(DEFAULT_WORKERS),
+        Arg::with_name("compressors")
+            .long("compressors")
+            .value_name("N")
+            .help("Number of threads compressing messages (default: number of cores minus one)")
+            .takes_value(true)
+            .validator(validate_workers),
+        Arg::with_name("queue")
+            .long("queue")
+            .value_name("capacity")
+            .help("Capacity of queues between processing stages, messages")
+            .long_help("Capacity of queues between processing stages, messages. When a queue is full, reading of the input is suspended until there is room again, so no messages are dropped, unless --queue-policy says otherwise.")
+            .takes_value(true)
+            .validator(validate_queue)
+            .default_value(DEFAULT_QUEUE),
+        Arg::with_name("queue_policy")
+            .long("queue-policy")
+            .value_name("policy")
+            .help("Handling of input records while the queue is full")
+            .long_help("Handling of input records while the queue is full: suspend reading until there is room, drop the oldest queued record, or drop the new one. Dropped records are counted as overflowed in statistics. Full queue of a target never suspends reading: its oldest message is dropped for that target, or the new one with drop-newest.")
+            .takes_value(true)
+            .possible_values(&["block", "drop-oldest", "drop-newest"])
+            .default_value(DEFAULT_QUEUE_POLICY),
+        Arg::with_name("read_buffer")
+            .long("read-buffer")
+            .value_name("bytes")
+            .help("Size of the input read buffer")
+            .long_help("Size of the input read buffer, bytes. Buffers bigger than typical records reduce number of reads from the journalctl pipe or stdin.")
+            .takes_value(true)
+            .validator(validate_read_buffer)
+            .default_value(DEFAULT_READ_BUFFER),
+        Arg::with_name("journal_format")
+            .long("journal-format")
+            .value_name("format")
+            .help("Format of input records")
+            .long_help("Format of input records: JSON objects, or journal export format. With journal source it selects output of journalctl, export one is cheaper to produce for journald and passes binary fields as is. With stdin source export format allows to forward archives exported for systemd-journal-remote, e.g. `journalctl -o export > archive.export`.")
+            .takes_value(true)
+            .possible_values(&["json", "export"])
+            .default_value("json"),
+        Arg::with_name("cursor_file")
+            .long("cursor-file")
+            .value_name("path")
+            .help("State file keeping journal cursor of the last sent record, to resume after restart")
+            .long_help("State file keeping journal cursor of the last sent record. On start journal source resumes right after the saved cursor, so that records written while the forwarder was down are not lost. The file is updated at most once per second, so after a crash a few records could be sent twice.")
+            .takes_value(true),
+        Arg::with_name("spool_dir")
+            .long("spool-dir")
+            .value_name("dir")
+            .help("Directory keeping messages which could not be sent, to send them once delivery succeeds")
+            .long_help("Directory keeping messages which could not be sent to any target. Spooled messages are sent once delivery succeeds again, including those left by the previous run. Every pipeline requires its own directory.")
+            .takes_value(true),
+        Arg::with_name("spool_limit")
+            .long("spool-limit")
+            .value_name("MB")
+            .help("Size limit of the spool, after which undeliverable messages are dropped")
+            .takes_value(true)
+            .validator(validate_interval)
+            .default_value(DEFAULT_SPOOL_LIMIT),
+        Arg::with_name("max_line")
+            .long("max-line")
+            .value_name("bytes")
+            .help("Maximal length of input line, longer ones are dropped")
+            .long_help("Maximal length of input line read from stdin, bytes. Longer lines are dropped and counted, so that a process writing an endless line could not exhaust memory of the forwarder.")
+            .takes_value(true)
+            .validator(validate_max_line)
+            .default_value(DEFAULT_MAX_LINE),
+        stats_arg(),
+        Arg::with_name("dry_run")
+            .long("dry-run")
+            .help("Process input without sending, then print summary of processed records"),
+        Arg::with_name("output")
+            .long("output")
+            .value_name("destination")
+            .help("Destination of messages: Graylog targets, stdout or file as lines of GELF JSON")
+            .long_help("Destination of messages: Graylog targets, stdout, where every message is printed as a line of GELF JSON instead of being sent, like in dry run, or file of such lines given by --output-path. Printing shows how records are mapped, filtered and redacted before pointing the forwarder to production Graylog, summary of processed records is printed to stderr then.")
+            .takes_value(true)
+            .possible_values(&OUTPUTS)
+            .default_value(DEFAULT_OUTPUT)
+            .requires_if("file", "output_path"),
+        Arg::with_name("output_path")
+            .long("output-path")
+            .value_name("file")
+            .help("File messages are appended to with --output file, e.g. /var/spool/gelf.ndjson")
+            .takes_value(true),
+        Arg::with_name("output_limit")
+            .long("output-limit")
+            .value_name("MB")
+            .help("Size of the output file, after which it is rotated")
+            .takes_value(true)
+            .validator(validate_ttl)
+            .default_value(DEFAULT_OUTPUT_LIMIT),
+        Arg::with_name("output_keep")
+            .long("output-keep")
+            .value_name("N")
+            .help("Number of rotated output files kept, as <file>.1 to <file>.N")
+            .takes_value(true)
+            .validator(validate_size)
+            .default_value(DEFAULT_OUTPUT_KEEP),
+        Arg::with_name("limit")
+            .long("limit")
+            .value_name("N")
+            .help("Exit successfully after forwarding given number of messages")
+            .takes_value(true)
+            .validator(validate_limit),
+        Arg::with_name("strict")
+            .long("strict")
+            .help("Stop on the first record failed to be parsed or transformed, e.g. to validate log format"),
+        Arg::with_name("capture")
+            .long("capture")
+            .value_name("file")
+            .help("Append every outgoing message to the file as a line of GELF JSON")
+            .takes_value(true),
+        Arg::with_name("capture_limit")
+            .long("capture-limit")
+            .value_name("MB")
+            .help("Size limit of the capture file, after which capture stops")
+            .takes_value(true)
+            .validator(validate_interval)
+            .default_value(DEFAULT_CAPTURE_LIMIT),
+        Arg::with_name("preview")
+            .long("preview")
+            .help("Print every outgoing message to stdout as a one-liner of timestamp, level, unit and message"),
+        admin_socket_arg(),
+        health_arg(),
+        Arg::with_name("chaos")
+            .long("chaos")
+            .value_name("faults")
+            .help("Inject faults at given rates, e.g. fail=0.1,slow=0.05,slow_ms=200,malformed=0.01")
+            .takes_value(true)
+            .validator(validate_chaos)
+            .hidden(true),
+    ];
+
+    #[cfg(feature = "kafka")]
+    let args = {
+        let mut args = args;
+        args.extend(vec![
+            Arg::with_name("kafka_brokers")
+                .long("kafka-brokers")
+                .value_name("host:port,...")
+                .help("Kafka brokers messages are produced to with --output kafka")
                 .takes_value(true)
                 .use_delimiter(true)
-        )
-        .arg(
-            Arg::with_name("system_level")
-                .short("l")
-                .long("sys")
-                .value_name("level")
-                .help("System logging level threshold")
+                .required_if("output", "kafka"),
+            Arg::with_name("kafka_topic")
+                .long("kafka-topic")
+                .value_name("topic")
+                .help("Kafka topic of messages")
                 .takes_value(true)
-                .possible_values(&[
-                    "emergency",
-                    "alert",
-                    "critical",
-                    "error",
-                    "warning",
-                    "notice",
-                    "info",
-                    "debug",
-                ])
-                .default_value("info"),
-        )
-        .arg(
-            Arg::with_name("msg_level")
-                .short("m")
-                .long("msg")
-                .value_name("level")
-                .help("Message filter logging level threshold")
+                .default_value(DEFAULT_KAFKA_TOPIC),
+            Arg::with_name("kafka_key")
+                .long("kafka-key")
+                .value_name("field")
+                .help("Field of GELF-message keying Kafka messages, e.g. host or _SYSTEMD_UNIT")
+                .long_help("Field of GELF-message, as sent, whose value keys Kafka messages, e.g. host or _SYSTEMD_UNIT. Messages with the same key land in the same partition, keeping their order. Empty value leaves messages unkeyed, spreading them over partitions.")
                 .takes_value(true)
-                .possible_values(&["fatal", "panic", "error", "warning", "info", "debug"]),
-        )
-        .get_matches();
+                .default_value(DEFAULT_KAFKA_KEY),
+            Arg::with_name("kafka_property")
+                .long("kafka-property")
+                .value_name("name=value")
+                .help("Property of Kafka producer, could be repeated, e.g. --kafka-property compression.type=lz4")
+                .long_help("Property of Kafka producer passed to librdkafka as is, could be repeated, e.g. `--kafka-property compression.type=lz4 --kafka-property security.protocol=ssl`.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_kafka_property),
+        ]);
+        args
+    };
+
+    #[cfg(feature = "async")]
+    let args = {
+        let mut args = args;
+        args.push(
+            Arg::with_name("async")
+                .long("async")
+                .help("Process input on asynchronous runtime"),
+        );
+        args
+    };
 
-    let log_source = parse_log_source(args.value_of("log_source").unwrap()).unwrap();
-    let sender_port: u16 = args.value_of("port").unwrap().parse().unwrap();
-    let graylog_addr = args.value_of("target").unwrap().to_string();
-    let graylog_addr_ttl: u64 = args.value_of("ttl").unwrap().parse().unwrap();
-    let compression = MessageCompression::from(args.value_of("compression").unwrap());
-    let log_level_system = LevelSystem::from(args.value_of("system_level").unwrap());
-    let log_level_message = args.value_of("msg_level").map(|l| LevelMsg::from(l));
+    args
+}
+
+fn parse_options() -> (Config, Command) {
+    let args = app().get_matches();
+
+    let (command, options) = match args.subcommand() {
+        ("run", Some(run)) => (Command::Run, run),
+        ("multi", Some(multi)) => (
+            Command::Multi {
+                path: multi.value_of("file").unwrap().to_string(),
+            },
+            multi,
+        ),
+        ("check", Some(check)) => (Command::Check, check),
+        ("test-filter", Some(test_filter)) => (Command::TestFilter, test_filter),
+        ("ping", Some(ping)) => (Command::Ping, ping),
+        ("send", Some(send)) => (
+            Command::Send {
+                message: send.value_of("message").unwrap().to_string(),
+                level: LevelSystem::from(send.value_of("level").unwrap()),
+                host: send.value_of("host").map(String::from),
+            },
+            send,
+        ),
+        ("replay", Some(replay)) => (
+            Command::Replay {
+                path: replay.value_of("file").unwrap().to_string(),
+                pace: replay
+                    .value_of("rate")
+                    .map_or(Pace::Original, |rate| Pace::Rate(rate.parse().unwrap())),
+            },
+            replay,
+        ),
+        ("bench", Some(bench)) => (
+            Command::Bench {
+                corpus: bench.value_of("corpus").map(String::from),
+                records: bench.value_of("records").unwrap().parse().unwrap(),
+            },
+            bench,
+        ),
+        ("generate", Some(generate)) => (
+            Command::Generate {
+                rate: generate.value_of("rate").unwrap().parse().unwrap(),
+                cardinality: generate.value_of("cardinality").unwrap().parse().unwrap(),
+                count: generate.value_of("count").map(|n| n.parse().unwrap()),
+            },
+            generate,
+        ),
+        ("fields", Some(fields)) => (
+            Command::Fields {
+                duration: parse_duration(fields.value_of("duration").unwrap()).unwrap(),
+            },
+            fields,
+        ),
+        ("decode", Some(decode)) => (
+            Command::Decode {
+                path: decode.value_of("file").map(String::from),
+                format: InputFormat::from(decode.value_of("format").unwrap()),
+                port: decode
+                    .value_of("dst_port")
+                    .map(|port| port.parse().unwrap()),
+            },
+            decode,
+        ),
+        ("completions", Some(completions)) => (
+            Command::Completions(completions.value_of("shell").unwrap().parse().unwrap()),
+            completions,
+        ),
+        ("stats", Some(stats)) => (
+            Command::Stats {
+                socket: stats.value_of("admin_socket").unwrap().to_string(),
+                interval: stats.value_of("interval").unwrap().parse().unwrap(),
+            },
+            stats,
+        ),
+        ("reload", Some(reload)) => (
+            Command::Reload {
+                socket: reload.value_of("admin_socket").unwrap().to_string(),
+            },
+            reload,
+        ),
+        ("init-config", Some(init_config)) => (
+            Command::InitConfig {
+                path: init_config.value_of("file").map(String::from),
+                force: init_config.is_present("force"),
+            },
+            init_config,
+        ),
+        ("version", Some(version)) => (Command::Version, version),
+        _ => (Command::Run, &args),
+    };
+
+    // logger goes first, as parsing reports some of the options
+    init_logger(log_level(options));
+
+    let file = read_config_file(options).unwrap_or_else(|e| {
+        error!("failed to read config file: {}", e);
+        process::exit(1);
+    });
+    let options = Options::new(options, file.as_ref());
+
+    if matches!(command, Command::Run) && options.value_of("log_source").is_none() {
+        error!("log source is set neither by --source nor by the config file");
+        process::exit(1);
+    }
+
+    let config = parse_config(&options);
+    if let Err(e) = validate_config(&config) {
+        error!("{}", e);
+        process::exit(1);
+    }
+
+    (config, command)
+}
+
+/// Check that options given together could be applied, e.g. on start or on reload.
+fn validate_config(config: &Config) -> jctl2gray::errors::Result<()> {
+    if config.compression.is_streamable()
+        && !config.transport.is_stream()
+        && config.output == Output::Graylog
+    {
+        return Err(Error::InternalError(format!(
+            "GELF UDP inputs do not accept {} compression, use it with tcp or tls transport, or file output",
+            config.compression
+        )));
+    }
+
+    Ok(())
+}
+
+/// Options of the command line, falling back to ones of the config file.
+struct Options<'a> {
+    cli: &'a ArgMatches<'a>,
+    file: Option<&'a ArgMatches<'a>>,
+}
+
+impl<'a> Options<'a> {
+    fn new(cli: &'a ArgMatches<'a>, file: Option<&'a ArgMatches<'a>>) -> Self {
+        Options { cli, file }
+    }
+
+    /// Return matches the option is taken from: command line, config file, or defaults otherwise.
+    fn matches(&self, name: &str) -> &'a ArgMatches<'a> {
+        match self.file {
+            Some(file) if self.cli.occurrences_of(name) == 0 && file.occurrences_of(name) > 0 => {
+                file
+            }
+            _ => self.cli,
+        }
+    }
+
+    fn value_of(&self, name: &str) -> Option<&'a str> {
+        self.matches(name).value_of(name)
+    }
+
+    fn values_of(&self, name: &str) -> Option<Values<'a>> {
+        self.matches(name).values_of(name)
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.matches(name).is_present(name)
+    }
+
+    fn occurrences_of(&self, name: &str) -> u64 {
+        self.matches(name).occurrences_of(name)
+    }
+}
+
+/// Read config file given by `--config`, parsing its options as those of the command line.
+fn read_config_file(args: &ArgMatches) -> jctl2gray::errors::Result<Option<ArgMatches<'static>>> {
+    let path = match args.value_of("config") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let bad_file = |reason: String| Error::InternalError(format!("{}: {}", path, reason));
+
+    let options: toml::value::Table = fs::read_to_string(path)
+        .map_err(|e| bad_file(e.to_string()))?
+        .parse::<toml::Value>()
+        .map_err(|e| bad_file(e.to_string()))?
+        .try_into()
+        .map_err(|e: toml::de::Error| bad_file(e.to_string()))?;
+
+    let mut words = vec![
+        "jctl2gray".to_string(),
+        "--config".to_string(),
+        path.to_string(),
+    ];
+    for (name, value) in options {
+        option_words(&name, value, &mut words).map_err(bad_file)?;
+    }
+
+    app()
+        .get_matches_from_safe(words)
+        .map(Some)
+        .map_err(|e| bad_file(e.message))
+}
+
+/// Convert option of the config file into words of the command line.
+fn option_words(
+    name: &str,
+    value: toml::Value,
+    words: &mut Vec<String>,
+) -> std::result::Result<(), String> {
+    match value {
+        toml::Value::Boolean(true) => words.push(format!("--{}", name)),
+        toml::Value::Boolean(false) => {}
+
+        // repeated options, e.g. facilities
+        toml::Value::Array(values) => {
+            for value in values {
+                option_words(name, value, words)?;
+            }
+        }
+
+        // fields given by names, e.g. `[opt]` table of additional fields
+        toml::Value::Table(fields) => {
+            for (field, value) in fields {
+                let value =
+                    option_text(value).ok_or_else(|| format!("bad value of {}.{}", name, field))?;
+                words.push(format!("--{}={}={}", name, field, value));
+            }
+        }
+
+        value => {
+            let value = option_text(value).ok_or_else(|| format!("bad value of {}", name))?;
+            words.push(format!("--{}={}", name, value));
+        }
+    }
+
+    Ok(())
+}
+
+fn option_text(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(text) => Some(text),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        toml::Value::Float(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Build config from options of the subcommand, using defaults for options it lacks.
+fn parse_config(args: &Options) -> Config {
+    let log_source = args
+        .value_of("log_source")
+        .and_then(parse_log_source)
+        .unwrap_or(LogSource::Stdin);
+    let file_paths: Vec<String> = args
+        .values_of("path")
+        .map(|paths| paths.map(String::from).collect())
+        .unwrap_or_default();
+    let units: Vec<String> = args
+        .values_of("unit")
+        .map(|units| units.map(String::from).collect())
+        .unwrap_or_default();
+    let multiline = args.value_of("multiline_start").map(|start| Multiline {
+        start: Regex::new(start).unwrap(),
+        timeout: Duration::from_millis(
+            value(args, "multiline_timeout", DEFAULT_MULTILINE_TIMEOUT)
+                .parse()
+                .unwrap(),
+        ),
+    });
+    let listen_addr = args.value_of("listen").map(String::from);
+    let listen_protocol =
+        ListenProtocol::from(value(args, "listen_protocol", DEFAULT_LISTEN_PROTOCOL));
+    let sender_port: u16 = value(args, "port", DEFAULT_PORT).parse().unwrap();
+    let send_buffer: Option<usize> = args
+        .value_of("send_buffer")
+        .map(|size| size.parse().unwrap());
+    let graylog_addrs: Vec<String> = args.values_of("target").map_or_else(
+        || vec![DEFAULT_TARGET.to_string()],
+        |targets| targets.map(String::from).collect(),
+    );
+    let failover_addr = args.value_of("failover").map(String::from);
+    let failover_after: u32 = value(args, "failover_after", DEFAULT_FAILOVER_AFTER)
+        .parse()
+        .unwrap();
+    let graylog_addr_ttl: u64 = value(args, "ttl", DEFAULT_TTL).parse().unwrap();
+    let transport = TransportKind::from(value(args, "transport", DEFAULT_TRANSPORT));
+    let output = Output::from(value(args, "output", DEFAULT_OUTPUT));
+    let tls = TlsOptions {
+        ca_cert: args.value_of("tls_ca").map(String::from),
+        client_cert: args.value_of("tls_cert").map(String::from),
+        client_key: args.value_of("tls_key").map(String::from),
+        verify_hostname: !args.is_present("tls_no_verify_hostname"),
+    };
+    let batch_size: usize = value(args, "batch", DEFAULT_BATCH).parse().unwrap();
+    if !transport.is_stream() && batch_size > 1 {
+        warn!("messages are not batched over {}", transport);
+    }
+    let batch_timeout = Duration::from_millis(
+        value(args, "batch_timeout", DEFAULT_BATCH_TIMEOUT)
+            .parse()
+            .unwrap(),
+    );
+    let resolve_strategy = ResolveStrategy::from(value(args, "resolve", DEFAULT_RESOLVE));
+    let compression = MessageCompression::from(value(args, "compression", DEFAULT_COMPRESSION));
+    let compression = if output == Output::Kafka {
+        // compressed by the producer, if configured
+        MessageCompression::None
+    } else if transport.is_stream() || output == Output::File {
+        // messages are concatenated, only zstd decompresses such stream or file as a whole
+        if compression != MessageCompression::None && !compression.is_streamable() {
+            warn!(
+                "{} compression applies to UDP only, messages are passed uncompressed",
+                compression
+            );
+            MessageCompression::None
+        } else {
+            compression
+        }
+    } else {
+        // zstd over UDP is rejected by validation
+        compression
+    };
+    let compress_min: usize = value(args, "compress_min", DEFAULT_COMPRESS_MIN)
+        .parse()
+        .unwrap();
+    if transport.is_stream() && args.occurrences_of("chunk_size") > 0 {
+        warn!("messages are not chunked over {}", transport);
+    }
+    let chunk_size = ChunkSize::from(value(args, "chunk_size", DEFAULT_CHUNK_SIZE));
+    let oversize_policy =
+        OversizePolicy::from(value(args, "oversize_policy", DEFAULT_OVERSIZE_POLICY));
+    let log_level_system = LevelSystem::from(value(args, "system_level", DEFAULT_SYSTEM_LEVEL));
+    let log_level_message = args.value_of("msg_level").map(LevelMsg::from);
+    let facilities: Vec<Facility> = args
+        .values_of("facility")
+        .map(|names| names.filter_map(Facility::from_name).collect())
+        .unwrap_or_default();
+    let sampling = args
+        .values_of("sample_rate")
+        .map(|rates| Sampling::parse(rates).unwrap());
+    let dedup = args
+        .value_of("dedup")
+        .and_then(parse_duration)
+        .map(|window| Arc::new(Dedup::new(window)));
+    let rate_limiter = args.value_of("rate_limit").map(|rate| {
+        let rate: f64 = rate.parse().unwrap();
+        let burst = args
+            .value_of("rate_burst")
+            .map_or_else(|| rate.ceil() as u32, |burst| burst.parse().unwrap());
+        Arc::new(RateLimiter::new(rate, burst))
+    });
+    let include_regex = regexes(args, "include_regex");
+    let exclude_regex = regexes(args, "exclude_regex");
+    let unit_rules = args
+        .values_of("unit_rule")
+        .map(|rules| UnitRules::parse(rules).unwrap())
+        .unwrap_or_default();
+    let extractors: Vec<Extractor> = args
+        .values_of("extract")
+        .map(|specs| specs.map(|spec| Extractor::parse(spec).unwrap()).collect())
+        .unwrap_or_default();
+    let stats_interval: u64 = value(args, "stats_interval", DEFAULT_STATS_INTERVAL)
+        .parse()
+        .unwrap();
+    let api_url = args.value_of("api_url").map(String::from);
+    let api_token = args.value_of("api_token").map(String::from);
+    let chaos = args
+        .value_of("chaos")
+        .map(|spec| Chaos::parse(spec).unwrap());
+    let async_runtime = args.is_present("async");
+    // nothing is sent while messages are printed
+    let dry_run = args.is_present("dry_run") || output == Output::Stdout;
+    let output_path = args.value_of("output_path").map(String::from);
+    let output_limit: u64 = value(args, "output_limit", DEFAULT_OUTPUT_LIMIT)
+        .parse::<u64>()
+        .unwrap()
+        * 1024
+        * 1024;
+    let output_keep: usize = value(args, "output_keep", DEFAULT_OUTPUT_KEEP)
+        .parse()
+        .unwrap();
+    let kafka = KafkaOptions {
+        brokers: args
+            .values_of("kafka_brokers")
+            .map_or_else(Vec::new, |brokers| brokers.map(String::from).collect()),
+        topic: args.value_of("kafka_topic").unwrap_or_default().to_string(),
+        key: args
+            .value_of("kafka_key")
+            .filter(|field| !field.is_empty())
+            .map(String::from),
+        properties: args
+            .values_of("kafka_property")
+            .map_or_else(Vec::new, parse_fields),
+    };
+    let strict = args.is_present("strict");
+    let limit: Option<u64> = args.value_of("limit").map(|n| n.parse().unwrap());
+    let capture = args.value_of("capture").map(String::from);
+    let preview = args.is_present("preview");
+    let admin_socket = args.value_of("admin_socket").map(String::from);
+    let health_addr = args.value_of("health").map(String::from);
+    let capture_limit: u64 = value(args, "capture_limit", DEFAULT_CAPTURE_LIMIT)
+        .parse::<u64>()
+        .unwrap()
+        * 1024
+        * 1024;
+    let workers: usize = value(args, "workers", DEFAULT_WORKERS).parse().unwrap();
+    let compressors: usize = args
+        .value_of("compressors")
+        .map_or_else(default_compressors, |n| n.parse().unwrap());
+    let queue_capacity: usize = value(args, "queue", DEFAULT_QUEUE).parse().unwrap();
+    let queue_policy = QueuePolicy::from(value(args, "queue_policy", DEFAULT_QUEUE_POLICY));
+    let read_buffer: usize = value(args, "read_buffer", DEFAULT_READ_BUFFER)
+        .parse()
+        .unwrap();
+    let max_line: usize = value(args, "max_line", DEFAULT_MAX_LINE).parse().unwrap();
+    let journal_format = JournalFormat::from(value(args, "journal_format", "json"));
+    let cursor_file = args.value_of("cursor_file").map(String::from);
+    let spool_dir = args.value_of("spool_dir").map(String::from);
+    let spool_limit: u64 = value(args, "spool_limit", DEFAULT_SPOOL_LIMIT)
+        .parse::<u64>()
+        .unwrap()
+        * 1024
+        * 1024;
+    let input_tag = args.value_of("input_tag").map(String::from);
+    let rename_reserved = args.is_present("rename_reserved");
+    let field_renames: Vec<(String, String)> = args
+        .values_of("rename_field")
+        .map_or_else(Vec::new, parse_fields);
+    let dropped_fields: Vec<FieldPattern> = args
+        .values_of("drop_field")
+        .map(|patterns| {
+            patterns
+                .map(|pattern| FieldPattern::parse(pattern).unwrap())
+                .collect()
+        })
+        .unwrap_or_default();
+    let kept_fields: Vec<String> = args
+        .values_of("keep_field")
+        .map(|names| names.map(String::from).collect())
+        .unwrap_or_default();
+    let redactor = args
+        .values_of("redact")
+        .map(|specs| Redactor::parse(specs).unwrap());
+    let parse_json_message = args.is_present("parse_json_message");
+    let parse_logfmt = args.is_present("parse_logfmt");
+    let monotonic_time = args.is_present("monotonic_time");
     let optional: Vec<(String, String)> = match args.values_of("opt_fields") {
         Some(fields) => parse_opt_fields(fields),
         None => Vec::new(),
     };
 
-    print_opt_fields(&optional);
+    let fields: Vec<(String, String)> = args.values_of("field").map_or_else(Vec::new, parse_fields);
+
+    print_opt_fields(optional.iter().chain(&fields));
+
+    if let Some(ref chaos) = chaos {
+        warn!("fault injection enabled: {:?}", chaos);
+    }
 
     Config {
         log_source,
+        file_paths,
+        units,
+        multiline,
+        listen_addr,
+        listen_protocol,
         sender_port,
-        graylog_addr,
+        send_buffer,
+        graylog_addrs,
+        failover_addr,
+        failover_after,
         graylog_addr_ttl,
+        transport,
+        tls,
+        batch_size,
+        batch_timeout,
+        resolve_strategy,
         compression,
+        compress_min,
+        chunk_size,
+        oversize_policy,
         log_level_system,
         log_level_message,
+        facilities,
+        include_regex,
+        exclude_regex,
+        unit_rules,
+        extractors,
+        sampling,
+        dedup,
+        rate_limiter,
         optional,
+        fields,
+        input_tag,
+        rename_reserved,
+        field_renames,
+        dropped_fields,
+        kept_fields,
+        redactor,
+        parse_json_message,
+        parse_logfmt,
+        monotonic_time,
+        stats_interval,
+        api_url,
+        api_token,
+        chaos,
+        async_runtime,
+        workers,
+        compressors,
+        queue_capacity,
+        queue_policy,
+        read_buffer,
+        journal_format,
+        cursor_file,
+        spool_dir,
+        spool_limit,
+        max_line,
+        dry_run,
+        output,
+        output_path,
+        output_limit,
+        output_keep,
+        kafka,
+        strict,
+        limit,
+        capture,
+        capture_limit,
+        preview,
+        admin_socket,
+        health_addr,
     }
 }
 
+/// Return value of the option, or default if subcommand has no such option.
+fn value<'a>(args: &Options<'a>, name: &str, default: &'a str) -> &'a str {
+    args.value_of(name).unwrap_or(default)
+}
+
+/// Compile values of repeated regex option, which are validated already.
+fn regexes(args: &Options, name: &str) -> Vec<Regex> {
+    args.values_of(name)
+        .map(|values| values.map(|re| Regex::new(re).unwrap()).collect())
+        .unwrap_or_default()
+}
+
 fn main() {
-    // init logger
+    // get config from CLI options
+    let (config, command) = parse_options();
+
+    match command {
+        Command::Completions(shell) => {
+            app().gen_completions_to(crate_name!(), shell, &mut io::stdout());
+            return;
+        }
+
+        Command::Version => {
+            print_version();
+            return;
+        }
+
+        Command::InitConfig { path, force } => {
+            if let Err(e) = write_config_template(path.as_deref(), force) {
+                error!("failed to write config template: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+
+        Command::Fields { duration } => {
+            match fields::sample(config.log_source, duration) {
+                Ok(report) => print!("{}", report),
+                Err(e) => {
+                    error!("failed to sample records: {}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+
+        Command::Decode { path, format, port } => {
+            if let Err(e) = decode_datagrams(path, format, port) {
+                error!("failed to decode datagrams: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+
+        Command::Stats { socket, interval } => {
+            if let Err(e) = print_stats(&socket, Duration::from_secs(interval)) {
+                error!("failed to get statistics from {}: {}", socket, e);
+                process::exit(1);
+            }
+            return;
+        }
+
+        Command::Reload { socket } => {
+            if let Err(e) = admin::reload(&socket) {
+                error!("failed to reload configuration of {}: {}", socket, e);
+                process::exit(1);
+            }
+            info!("configuration reloaded");
+            return;
+        }
+
+        Command::Bench { corpus, records } => {
+            bench(&config, corpus, records);
+            return;
+        }
+
+        Command::Check => {
+            if let Err(e) = check_target(&config) {
+                error!("target check failed: {}", e);
+                process::exit(1);
+            }
+            info!("target {} is ok", config.graylog_addrs.join(", "));
+            return;
+        }
+
+        Command::Ping => {
+            if !ping(&config) {
+                process::exit(1);
+            }
+            return;
+        }
+
+        Command::TestFilter => {
+            let stdin = io::stdin();
+            if let Err(e) = explain::run(&config, stdin.lock(), io::stdout().lock()) {
+                error!("failed to test filter: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+
+        Command::Send {
+            message,
+            level,
+            host,
+        } => {
+            let host = host.unwrap_or_else(oneshot::hostname);
+            let msg = match MessageBuilder::new(host, message).level(level).build() {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!("invalid message: {}", e);
+                    process::exit(1);
+                }
+            };
+
+            if let Err(e) = oneshot::send(&config, &SendStats::new(), msg) {
+                error!("failed to send message: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+
+        _ => {}
+    }
+
+    // make sure target is a GELF input
+    if config.api_url.is_some() && !config.dry_run {
+        if let Err(e) = check_gelf_input(&config) {
+            error!("target validation failed: {}", e);
+            process::exit(1);
+        }
+    }
+
+    // SIGHUP reloads configuration, it must be blocked before any thread is spawned
+    let reload_signal = match command {
+        Command::Run => ReloadSignal::block()
+            .map_err(|e| warn!("reload on SIGHUP is not available: {}", e))
+            .ok(),
+        _ => None,
+    };
+
+    let stats = SendStats::new();
+    if config.stats_interval > 0 {
+        spawn_stats_reporter(stats.clone(), config.stats_interval);
+    }
+
+    let config = SharedConfig::new(config);
+
+    if let Some(signal) = reload_signal {
+        let config = config.clone();
+        if let Err(e) = signal.spawn(move || reload_config(&config)) {
+            warn!("reload on SIGHUP is not available: {}", e);
+        }
+    }
+
+    if let Some(ref path) = config.load().admin_socket {
+        // pipelines are configured by their own file
+        let reload: admin::Reload = match command {
+            Command::Multi { .. } => None,
+            _ => {
+                let config = config.clone();
+                Some(Box::new(move || reload_config(&config)))
+            }
+        };
+
+        if let Err(e) = admin::serve(path, stats.clone(), reload) {
+            error!("failed to open admin socket {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(ref addr) = config.load().health_addr {
+        if let Err(e) = health::serve(addr, config.clone(), stats.clone()) {
+            error!("failed to open health endpoint {}: {}", addr, e);
+            process::exit(1);
+        }
+    }
+
+    if let Command::Multi { path } = command {
+        let result = run_pipelines(&path, &stats);
+        stats.log();
+
+        if let Err(e) = result {
+            error!("pipelines stopped: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::Replay { path, pace } = command {
+        let result = replay::run(config, stats.clone(), &path, pace);
+        stats.log();
+
+        if let Err(e) = result {
+            error!("replay stopped: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::Generate {
+        rate,
+        cardinality,
+        count,
+    } = command
+    {
+        let result = generator::run(config, stats.clone(), rate, cardinality, count);
+        stats.log();
+
+        if let Err(e) = result {
+            error!("generator stopped: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let dry_run = config.load().dry_run;
+    let output = config.load().output;
+    let log_source = config.load().log_source;
+
+    // choose source and start processing input
+    let result = match log_source {
+        LogSource::Stdin => process_stdin(config, stats.clone())
+            .map_err(|e| error!("stdin processing stopped: {}", e)),
+
+        LogSource::Journalctl => process_journalctl(config, stats.clone())
+            .map_err(|e| error!("journalctl processing stopped: {}", e)),
+
+        LogSource::File => process_files(config, stats.clone())
+            .map_err(|e| error!("file processing stopped: {}", e)),
+
+        LogSource::Syslog => process_syslog(config, stats.clone())
+            .map_err(|e| error!("syslog processing stopped: {}", e)),
+    };
+
+    if dry_run {
+        // summary is kept apart from messages printed to stdout
+        match output {
+            Output::Stdout => eprintln!("dry run | {}", stats.records().snapshot()),
+            Output::Graylog | Output::File | Output::Kafka => {
+                println!("dry run | {}", stats.records().snapshot())
+            }
+        }
+    } else {
+        stats.log();
+    }
+
+    // input ended or limit of forwarded messages reached
+    if result.is_err() {
+        process::exit(1);
+    }
+}
+
+/// Run pipelines defined in the file concurrently, until all of them stop.
+fn run_pipelines(path: &str, stats: &SendStats) -> jctl2gray::errors::Result<()> {
+    let pipelines = read_pipelines(path)?;
+    info!("starting {} pipelines", pipelines.len());
+
+    let handles = pipelines
+        .into_iter()
+        .enumerate()
+        .map(|(n, config)| {
+            let stats = stats.pipeline(&(n + 1).to_string());
+
+            thread::Builder::new()
+                .name(format!("pipeline-{}", n + 1))
+                .spawn(move || {
+                    let result = match config.log_source {
+                        LogSource::Stdin => process_stdin(SharedConfig::new(config), stats),
+                        LogSource::Journalctl => {
+                            process_journalctl(SharedConfig::new(config), stats)
+                        }
+                        LogSource::File => process_files(SharedConfig::new(config), stats),
+                        LogSource::Syslog => process_syslog(SharedConfig::new(config), stats),
+                    };
+
+                    if let Err(ref e) = result {
+                        error!("pipeline {} stopped: {}", n + 1, e);
+                    }
+                    result.is_ok()
+                })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let failed = handles
+        .into_iter()
+        .map(|handle| handle.join())
+        .filter(|result| !matches!(result, Ok(true)))
+        .count();
+
+    if failed > 0 {
+        return Err(Error::InternalError(format!("{} pipelines failed", failed)));
+    }
+    Ok(())
+}
+
+/// Read configs of pipelines, one per line as options of the run subcommand.
+fn read_pipelines(path: &str) -> jctl2gray::errors::Result<Vec<Config>> {
+    let mut pipelines = Vec::new();
+
+    for (n, line) in io::BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bad_line = |reason: String| Error::InternalError(format!("line {}: {}", n + 1, reason));
+
+        let words = split_words(line).map_err(bad_line)?;
+        let args = app()
+            .get_matches_from_safe(
+                ["jctl2gray", "run"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .chain(words),
+            )
+            .map_err(|e| bad_line(e.message))?;
+        let options = args.subcommand_matches("run").unwrap();
+        let file = read_config_file(options).map_err(|e| bad_line(e.to_string()))?;
+        let options = Options::new(options, file.as_ref());
+        let mut config = parse_config(&options);
+        validate_config(&config).map_err(|e| bad_line(e.to_string()))?;
+
+        if config.api_url.is_some() && !config.dry_run {
+            check_gelf_input(&config).map_err(|e| bad_line(e.to_string()))?;
+        }
+
+        // pipelines could not share the default port, so those not given one use an ephemeral port
+        if options.occurrences_of("port") == 0 {
+            config.sender_port = 0;
+        }
+
+        let port = config.sender_port;
+        if port != 0
+            && pipelines
+                .iter()
+                .any(|other: &Config| other.sender_port == port)
+        {
+            return Err(bad_line(format!(
+                "UDP port {} is used by another pipeline, set a distinct one with --port",
+                port
+            )));
+        }
+
+        pipelines.push(config);
+    }
+
+    let stdin_readers = pipelines
+        .iter()
+        .filter(|config| matches!(config.log_source, LogSource::Stdin))
+        .count();
+    if stdin_readers > 1 {
+        return Err(Error::InternalError(
+            "only one pipeline could read stdin".to_string(),
+        ));
+    }
+
+    if pipelines.is_empty() {
+        return Err(Error::InternalError("no pipelines defined".to_string()));
+    }
+    Ok(pipelines)
+}
+
+/// Split line into words like shell does: by whitespace, respecting quotes and backslashes.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => match chars.next() {
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => return Err(String::from("backslash at the end of line")),
+            },
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(String::from("unterminated quote"));
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parse options of the instance once again and replace its config, if they are valid.
+///
+/// Filters and fields of the new config apply to records processed afterwards,
+/// while source, target and threads are kept until restart.
+fn reload_config(config: &SharedConfig) -> jctl2gray::errors::Result<()> {
+    let args = app()
+        .get_matches_from_safe(env::args_os())
+        .map_err(|e| Error::InternalError(e.message))?;
+    let options = match args.subcommand() {
+        ("run", Some(run)) => run,
+        _ => &args,
+    };
+
+    let file = read_config_file(options)?;
+    let mut reloaded = parse_config(&Options::new(options, file.as_ref()));
+    validate_config(&reloaded)?;
+    if reloaded.api_url.is_some() && !reloaded.dry_run {
+        check_gelf_input(&reloaded)?;
+    }
+
+    let running = config.snapshot();
+    let kept = keep_targets(&running, &mut reloaded);
+    if !kept.is_empty() {
+        warn!(
+            "running targets are kept, changes of {} take effect after restart",
+            kept.join(", ")
+        );
+    }
+
+    reloaded.carry_state(&running);
+    config.store(reloaded);
+    info!("configuration reloaded");
+    Ok(())
+}
+
+/// Keep options of targets as they are running, since targets are connected on start and kept until restart,
+/// returning names of the changed ones.
+fn keep_targets(running: &Config, reloaded: &mut Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    let mut keep = |name, same: bool| {
+        if !same {
+            changed.push(name);
+        }
+    };
+
+    keep("target", running.graylog_addrs == reloaded.graylog_addrs);
+    keep(
+        "target-failover",
+        running.failover_addr == reloaded.failover_addr,
+    );
+    keep("port", running.sender_port == reloaded.sender_port);
+    keep("transport", running.transport == reloaded.transport);
+    keep("tls-*", running.tls == reloaded.tls);
+    keep("comp", running.compression == reloaded.compression);
+    keep("output", running.output == reloaded.output);
+    keep("output-path", running.output_path == reloaded.output_path);
+    keep("kafka-*", running.kafka == reloaded.kafka);
+
+    reloaded.graylog_addrs = running.graylog_addrs.clone();
+    reloaded.failover_addr = running.failover_addr.clone();
+    reloaded.sender_port = running.sender_port;
+    reloaded.transport = running.transport;
+    reloaded.tls = running.tls.clone();
+    reloaded.compression = running.compression;
+    reloaded.output = running.output;
+    reloaded.output_path = running.output_path.clone();
+    reloaded.kafka = running.kafka.clone();
+
+    changed
+}
+
+fn process_stdin(config: SharedConfig, stats: SendStats) -> jctl2gray::errors::Result<()> {
+    #[cfg(feature = "async")]
+    {
+        if config.load().async_runtime {
+            return processing_async::process_stdin(config, stats);
+        }
+    }
+
+    processing::process_stdin(config, stats)
+}
+
+fn process_journalctl(config: SharedConfig, stats: SendStats) -> jctl2gray::errors::Result<()> {
+    #[cfg(feature = "async")]
+    {
+        if config.load().async_runtime {
+            return processing_async::process_journalctl(config, stats);
+        }
+    }
+
+    processing::process_journalctl(config, stats)
+}
+
+fn process_files(config: SharedConfig, stats: SendStats) -> jctl2gray::errors::Result<()> {
+    if config.load().async_runtime {
+        return Err(Error::InternalError(
+            "file source is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    processing::process_files(config, stats)
+}
+
+fn process_syslog(config: SharedConfig, stats: SendStats) -> jctl2gray::errors::Result<()> {
+    if config.load().async_runtime {
+        return Err(Error::InternalError(
+            "syslog source is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    processing::process_syslog(config, stats)
+}
+
+fn print_version() {
+    let mut features: Vec<&str> = Vec::new();
+    if cfg!(feature = "async") {
+        features.push("async");
+    }
+    if cfg!(feature = "simd") {
+        features.push("simd");
+    }
+    if cfg!(feature = "journald-native") {
+        features.push("journald-native");
+    }
+    if cfg!(feature = "zstd") {
+        features.push("zstd");
+    }
+    if cfg!(feature = "kafka") {
+        features.push("kafka");
+    }
+
+    println!("jctl2gray {}", crate_version!());
+    println!("features: {}", features.join(", "));
+}
+
+/// Write template of the config file, refusing to replace an existing one unless forced.
+fn write_config_template(path: Option<&str>, force: bool) -> io::Result<()> {
+    let template = config_template();
+    let path = match path {
+        Some(path) => path,
+        None => return io::stdout().write_all(template.as_bytes()),
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!force)
+        .open(path)?;
+    file.write_all(template.as_bytes())?;
+    info!("config template written to {}", path);
+    Ok(())
+}
+
+/// Return config file with the commonly used options, commented out ones set to their defaults.
+fn config_template() -> String {
+    format!(
+        r#"# Config of jctl2gray, given by --config.
+# Options are named as long options of the command line, which override them.
+# Flags are set by `true`, repeated options are given as arrays.
+
+## Source
+
+# Log source: stdin, journal, file or syslog
+source = "journal"
+# Journal cursor of the last sent record, to resume after restart
+# cursor-file = "/var/lib/jctl2gray/cursor"
+# Files followed by file source
+# path = ["/var/log/nginx/access.log"]
+# Systemd units to read the journal of
+# unit = ["nginx.service"]
+
+## Target
+
+# Graylog GELF input, could be repeated to send every message to each of them
+target = "{target}"
+# Backup Graylog, taking over when the first target fails
+# target-failover = "backup.graylog.domain.com:12201"
+# Transport of messages: udp, tcp or tls
+# transport = "{transport}"
+# tls-ca = "/etc/jctl2gray/ca.pem"
+# tls-cert = "/etc/jctl2gray/client.pem"
+# tls-key = "/etc/jctl2gray/client.key"
+# Period of resolving of target hostnames, secs
+# ttl = {ttl}
+# Message compression: {compressions}
+# comp = "{compression}"
+# Output of messages: {outputs}
+# output = "{output}"
+
+## Messages
+
+# System logging level threshold
+# sys = "{system_level}"
+# Message filter logging level threshold
+# msg = "warning"
+# Send at most given number of records per second of every systemd unit
+# rate-limit = 100
+# Collapse repeats of the previous message within the window
+# dedup = "30s"
+# Send only a random share of records
+# sample-rate = ["0.5", "debug=0.01"]
+# Level threshold, dropped messages and fields of records of systemd units
+# unit-rule = ["nginx:sys=warning", "nginx:field=team=web"]
+# Fields dropped from messages
+# drop-field = ["_CMDLINE"]
+
+## Pipeline
+
+# Number of worker threads
+# workers = {workers}
+# Capacity of queues between stages of the pipeline
+# queue = {queue}
+# Handling of input records while the queue is full: block, drop-oldest or drop-newest
+# queue-policy = "{queue_policy}"
+# Directory keeping messages which could not be sent
+# spool-dir = "/var/lib/jctl2gray/spool"
+# Period of logging per-target send statistics, secs (0 - disabled)
+# stats = {stats_interval}
+# Admin socket serving statistics and reload
+# admin-socket = "{admin_socket}"
+
+# Additional fields of every message
+[opt]
+# team = "core"
+"#,
+        target = DEFAULT_TARGET,
+        transport = DEFAULT_TRANSPORT,
+        ttl = DEFAULT_TTL,
+        compressions = COMPRESSIONS.join(", "),
+        compression = DEFAULT_COMPRESSION,
+        outputs = OUTPUTS.join(", "),
+        output = DEFAULT_OUTPUT,
+        system_level = DEFAULT_SYSTEM_LEVEL,
+        workers = DEFAULT_WORKERS,
+        queue = DEFAULT_QUEUE,
+        queue_policy = DEFAULT_QUEUE_POLICY,
+        stats_interval = DEFAULT_STATS_INTERVAL,
+        admin_socket = DEFAULT_ADMIN_SOCKET,
+    )
+}
+
+/// Resolve targets and, if API URL is provided, make sure the first one is a GELF input.
+fn check_target(config: &Config) -> jctl2gray::errors::Result<()> {
+    for target in config.graylog_addrs.iter().chain(&config.failover_addr) {
+        if let Some(path) = unix_path(target) {
+            if !fs::metadata(path)?.file_type().is_socket() {
+                return Err(jctl2gray::errors::Error::InternalError(format!(
+                    "{} is not a socket",
+                    path.display()
+                )));
+            }
+            info!("target {} is a local socket", target);
+            continue;
+        }
+
+        let resolver = Resolver::new(target, config.graylog_addr_ttl, config.resolve_strategy)?;
+        info!("target {} resolved to {:?}", target, resolver.addrs());
+    }
+
+    if config.api_url.is_some() {
+        check_gelf_input(config)?;
+    } else {
+        info!("no --api-url provided, GELF input is not checked");
+    }
+
+    Ok(())
+}
+
+/// Print outcome of ping of every target address, returning whether all succeeded.
+fn ping(config: &Config) -> bool {
+    let results = match oneshot::ping(config) {
+        Ok(results) => results,
+        Err(e) => {
+            error!("ping failed: {}", e);
+            return false;
+        }
+    };
+
+    let mut succeeded = true;
+    for (target, result) in results {
+        match result {
+            Ok(elapsed) => println!("{} | sent in {:?}", target, elapsed),
+            Err(e) => {
+                println!("{} | failed: {}", target, e);
+                succeeded = false;
+            }
+        }
+    }
+
+    succeeded
+}
+
+fn check_gelf_input(config: &Config) -> jctl2gray::errors::Result<()> {
+    match config.api_url {
+        Some(ref api_url) => graylog_api::check_gelf_input(
+            api_url,
+            config.api_token.as_deref(),
+            &config.graylog_addrs[0],
+        ),
+        None => Ok(()),
+    }
+}
+
+fn bench(config: &Config, corpus: Option<String>, records: usize) {
+    let corpus = match corpus {
+        Some(path) => match jctl2gray::bench::load_corpus(&path) {
+            Ok(corpus) => corpus,
+            Err(e) => {
+                error!("failed to load corpus: {}", e);
+                process::exit(1);
+            }
+        },
+        None => jctl2gray::bench::synthetic_corpus(records),
+    };
+
+    info!("benchmarking on {} records", corpus.len());
+    print!("{}", jctl2gray::bench::run(config, &corpus));
+}
+
+fn init_logger(level: log::Level) {
     loggerv::Logger::new()
-        .max_level(log_level())
+        .max_level(level)
         .level(true)
         .separator(" | ")
         .colors(true)
         .no_module_path()
         .init()
         .unwrap();
+}
 
-    // get config from CLI options
-    let config = parse_options();
-
-    // choose source and start processing input
-    match config.log_source {
-        LogSource::Stdin => {
-            if let Err(e) = processing::process_stdin(config) {
-                error!("stdin processing stopped: {}", e);
-            }
-        }
+/// Choose logging level by number of verbose/quiet flags
+fn log_level(args: &ArgMatches) -> log::Level {
+    level_of_flags(args.occurrences_of("verbose"), args.occurrences_of("quiet"))
+}
 
-        LogSource::Journalctl => {
-            if let Err(e) = processing::process_journalctl(config) {
-                error!("journalctl processing stopped: {}", e);
-            }
-        }
+/// Choose logging level by number of verbose flags net of quiet ones.
+fn level_of_flags(verbose: u64, quiet: u64) -> log::Level {
+    match verbose as i64 - quiet as i64 {
+        0 => log::Level::Info,
+        1 => log::Level::Debug,
+        n if n > 1 => log::Level::Trace,
+        -1 => log::Level::Warn,
+        _ => log::Level::Error,
     }
+}
+
+/// Leave one core for the rest of the pipeline
+fn default_compressors() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1))
+}
+
+/// Print messages found in the file with datagrams, or in stdin
+fn decode_datagrams(
+    path: Option<String>,
+    format: InputFormat,
+    port: Option<u16>,
+) -> jctl2gray::errors::Result<()> {
+    let input: Box<dyn io::Read> = match path {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+
+    let decoded = decode::run(io::BufReader::new(input), format, port, io::stdout().lock())?;
+    debug!("{} messages decoded", decoded);
+    Ok(())
+}
+
+/// Request statistics of the running instance twice and print them along with throughput
+fn print_stats(socket: &str, interval: Duration) -> jctl2gray::errors::Result<()> {
+    let before = admin::query(socket)?;
+    thread::sleep(interval);
+    let after = admin::query(socket)?;
 
-    // normally unreachable
-    process::exit(1);
+    print!("{}", admin::format_report(&before, &after, interval));
+    Ok(())
 }
 
-/// Set different logging levels for debug/release builds
-fn log_level() -> log::Level {
-    #[cfg(debug_assertions)]
-    return log::Level::Debug;
-    #[cfg(not(debug_assertions))]
-    log::Level::Info
+/// Periodically write send statistics into the log
+fn spawn_stats_reporter(stats: SendStats, interval: u64) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval));
+        stats.log();
+    });
 }
 
 /* CLI arg validators */
 
 fn validate_address(addr: String) -> Result<(), String> {
+    if let Some(path) = unix_path(&addr) {
+        if path.as_os_str().is_empty() {
+            return Err(String::from("Empty socket path provided"));
+        }
+        return Ok(());
+    }
+
     match addr.to_socket_addrs() {
         Ok(_) => Ok(()),
         Err(_) => Err(String::from("Bad address provided")),
@@ -202,11 +2390,176 @@ fn validate_ttl(interval: String) -> Result<(), String> {
     }
 }
 
+/// Parse duration given in seconds, optionally with `s`, `m` or `h` suffix.
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let (number, unit) = match duration.char_indices().last()? {
+        (i, 's') => (&duration[..i], 1),
+        (i, 'm') => (&duration[..i], 60),
+        (i, 'h') => (&duration[..i], 3600),
+        _ => (duration, 1),
+    };
+
+    let n = number.parse::<u64>().ok()?;
+    Some(Duration::from_secs(n.checked_mul(unit)?))
+}
+
+fn validate_duration(duration: String) -> Result<(), String> {
+    match parse_duration(&duration) {
+        Some(_) => Ok(()),
+        None => Err(String::from("Bad duration provided")),
+    }
+}
+
+fn validate_window(window: String) -> Result<(), String> {
+    match parse_duration(&window) {
+        Some(window) if window > Duration::from_secs(0) => Ok(()),
+        _ => Err(String::from("Bad window provided")),
+    }
+}
+
+fn validate_interval(interval: String) -> Result<(), String> {
+    match interval.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Bad interval value provided")),
+    }
+}
+
+fn validate_workers(workers: String) -> Result<(), String> {
+    match workers.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Number of workers could not be zero")),
+        Err(_) => Err(String::from("Bad number of workers provided")),
+    }
+}
+
+fn validate_failover_after(failures: String) -> Result<(), String> {
+    match failures.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Number of failures could not be zero")),
+        Err(_) => Err(String::from("Bad number of failures provided")),
+    }
+}
+
+fn validate_limit(limit: String) -> Result<(), String> {
+    match limit.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Limit could not be zero")),
+        Err(_) => Err(String::from("Bad limit provided")),
+    }
+}
+
+fn validate_records(records: String) -> Result<(), String> {
+    match records.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Bad number of records provided")),
+    }
+}
+
+fn validate_chunk_size(size: String) -> Result<(), String> {
+    match size.as_str() {
+        "wan" | "lan" => Ok(()),
+        size => match size.parse::<u16>() {
+            Ok(n) if n > 0 && n <= MAX_CHUNK_SIZE => Ok(()),
+            _ => Err(format!(
+                "Chunk size should be wan, lan or size in bytes up to {}",
+                MAX_CHUNK_SIZE
+            )),
+        },
+    }
+}
+
+fn validate_queue(capacity: String) -> Result<(), String> {
+    match capacity.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Queue capacity could not be zero")),
+        Err(_) => Err(String::from("Bad queue capacity provided")),
+    }
+}
+
+fn validate_size(size: String) -> Result<(), String> {
+    match size.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Bad size provided")),
+    }
+}
+
+fn validate_read_buffer(size: String) -> Result<(), String> {
+    match size.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Read buffer size could not be zero")),
+        Err(_) => Err(String::from("Bad read buffer size provided")),
+    }
+}
+
+fn validate_sample_rate(rate: String) -> Result<(), String> {
+    Sampling::parse(Some(rate.as_str())).map(|_| ())
+}
+
+fn validate_unit_rule(rule: String) -> Result<(), String> {
+    UnitRules::parse(Some(rule.as_str()))?
+        .fields()
+        .try_for_each(|(name, _)| validate_field_name(name))
+}
+
+fn validate_rate(rate: String) -> Result<(), String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(()),
+        _ => Err(String::from("Bad rate provided")),
+    }
+}
+
+fn validate_extractor(extractor: String) -> Result<(), String> {
+    Extractor::parse(&extractor).map(|_| ())
+}
+
+fn validate_field_pattern(pattern: String) -> Result<(), String> {
+    FieldPattern::parse(&pattern).map(|_| ())
+}
+
+fn validate_redaction(spec: String) -> Result<(), String> {
+    Redactor::parse(vec![spec.as_str()]).map(|_| ())
+}
+
+fn validate_regex(regex: String) -> Result<(), String> {
+    match Regex::new(&regex) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn validate_path_pattern(pattern: String) -> Result<(), String> {
+    match Pattern::parse(&pattern) {
+        Ok(_) => Ok(()),
+        Err(Error::InternalError(reason)) => Err(reason),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn validate_max_line(size: String) -> Result<(), String> {
+    match size.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Maximal line length could not be zero")),
+        Err(_) => Err(String::from("Bad maximal line length provided")),
+    }
+}
+
+fn validate_send_buffer(size: String) -> Result<(), String> {
+    match size.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        Ok(_) => Err(String::from("Send buffer size could not be zero")),
+        Err(_) => Err(String::from("Bad send buffer size provided")),
+    }
+}
+
+fn validate_chaos(spec: String) -> Result<(), String> {
+    Chaos::parse(&spec).map(|_| ())
+}
+
 /* Optional fields */
 
 fn parse_opt_fields<'a, A: Iterator<Item = &'a str>>(data: A) -> Vec<(String, String)> {
     data.map(|field| field.split('='))
-        .map(|mut i| {
+        .filter_map(|mut i| {
             let name = i.next();
             let value = i.next();
             match (name, value) {
@@ -214,13 +2567,377 @@ fn parse_opt_fields<'a, A: Iterator<Item = &'a str>>(data: A) -> Vec<(String, St
                 _ => None,
             }
         })
-        .filter(|v| v.is_some())
-        .map(|v| v.unwrap())
         .map(|(name, value)| (name.to_string(), value.to_string()))
         .collect()
 }
 
-fn print_opt_fields(opt: &[(String, String)]) {
+/// Parse fields given as `name=text`, text could contain `=` itself.
+fn parse_fields<'a, A: Iterator<Item = &'a str>>(data: A) -> Vec<(String, String)> {
+    data.filter_map(|field| field.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn validate_field(field: String) -> Result<(), String> {
+    let name = match field.split_once('=') {
+        Some((name, _)) => name,
+        None => return Err(String::from("Field should be given as name=text")),
+    };
+    validate_field_name(name)
+}
+
+#[cfg(feature = "kafka")]
+fn validate_kafka_property(property: String) -> Result<(), String> {
+    match property.split_once('=') {
+        Some((name, _)) if !name.is_empty() => Ok(()),
+        _ => Err(String::from("Kafka property should be given as name=value")),
+    }
+}
+
+fn validate_rename(rename: String) -> Result<(), String> {
+    match rename.split_once('=') {
+        Some((from, to)) if !from.is_empty() => validate_field_name(to),
+        _ => Err(String::from("Rename should be given as from=to")),
+    }
+}
+
+/// Check that the name is allowed by GELF for additional fields.
+fn validate_field_name(name: &str) -> Result<(), String> {
+    if !is_valid_field_name(name) {
+        return Err(format!(
+            "Bad field name {}, only letters, digits, _, . and - are allowed",
+            name
+        ));
+    }
+    if name == RESERVED_FIELD {
+        return Err(format!("Field {} is reserved by GELF", name));
+    }
+
+    Ok(())
+}
+
+fn print_opt_fields<'a, I>(opt: I)
+where
+    I: IntoIterator<Item = &'a (String, String)>,
+{
     debug!("additional fields to be attached:");
-    opt.iter().for_each(|(n, v)| debug!("- {}: {}", n, v));
+    opt.into_iter().for_each(|(n, v)| debug!("- {}: {}", n, v));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build config of the run command from the given options.
+    fn config(options: &[&str]) -> Config {
+        let words = ["jctl2gray", "run", "-s", "stdin"].iter().chain(options);
+        let args = app().get_matches_from_safe(words).unwrap();
+        parse_config(&Options::new(args.subcommand_matches("run").unwrap(), None))
+    }
+
+    /// Return whether the run command accepts the given options.
+    fn accepts(options: &[&str]) -> bool {
+        let words = ["jctl2gray", "run", "-s", "stdin"].iter().chain(options);
+        app().get_matches_from_safe(words).is_ok()
+    }
+
+    #[test]
+    fn subcommands_take_their_options() {
+        let args = app()
+            .get_matches_from_safe(["jctl2gray", "ping", "-t", "10.0.0.1:12201"])
+            .unwrap();
+        let (name, ping) = args.subcommand();
+        assert_eq!(name, "ping");
+        assert_eq!(ping.unwrap().value_of("target"), Some("10.0.0.1:12201"));
+
+        // options of run are accepted without subcommand, but not by unrelated ones
+        let args = app()
+            .get_matches_from_safe(["jctl2gray", "-s", "stdin", "--sys", "error"])
+            .unwrap();
+        assert_eq!(args.subcommand_name(), None);
+        assert_eq!(args.value_of("system_level"), Some("error"));
+        assert!(app()
+            .get_matches_from_safe(["jctl2gray", "stats", "-t", "10.0.0.1:12201"])
+            .is_err());
+        assert!(app().get_matches_from_safe(["jctl2gray", "start"]).is_err());
+    }
+
+    #[test]
+    fn own_log_level() {
+        let level = |flags: &[&str]| {
+            let words = ["jctl2gray", "version"].iter().chain(flags);
+            let args = app().get_matches_from_safe(words).unwrap();
+            log_level(args.subcommand_matches("version").unwrap())
+        };
+
+        assert_eq!(level(&[]), log::Level::Info);
+        assert_eq!(level(&["-v"]), log::Level::Debug);
+        assert_eq!(level(&["-vv"]), log::Level::Trace);
+        assert_eq!(level(&["-q"]), log::Level::Warn);
+        assert_eq!(level(&["-qq"]), log::Level::Error);
+        assert!(app()
+            .get_matches_from_safe(["jctl2gray", "version", "-v", "-q"])
+            .is_err());
+
+        // flags cancel each other out
+        assert_eq!(level_of_flags(1, 1), log::Level::Info);
+        assert_eq!(level_of_flags(3, 1), log::Level::Trace);
+        assert_eq!(level_of_flags(1, 2), log::Level::Warn);
+        assert_eq!(level_of_flags(0, 3), log::Level::Error);
+    }
+
+    #[test]
+    fn completions_of_subcommands() {
+        let args = app()
+            .get_matches_from_safe(["jctl2gray", "completions", "bash"])
+            .unwrap();
+        let shell: Shell = args
+            .subcommand_matches("completions")
+            .unwrap()
+            .value_of("shell")
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut script = Vec::new();
+        app().gen_completions_to(crate_name!(), shell, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("init-config"));
+        assert!(script.contains("--target"));
+
+        assert!(app()
+            .get_matches_from_safe(["jctl2gray", "completions", "tcsh"])
+            .is_err());
+    }
+
+    #[test]
+    fn message_regexes() {
+        let config = config(&["--include-regex", "^GET ", "--exclude-regex", "/health"]);
+        assert_eq!(config.include_regex.len(), 1);
+        assert_eq!(config.exclude_regex[0].as_str(), "/health");
+        assert!(!accepts(&["--exclude-regex", "(unclosed"]));
+    }
+
+    #[test]
+    fn static_fields() {
+        let config = config(&["--field", "environment=prod", "--field", "url=a=b,c"]);
+        assert_eq!(
+            config.fields,
+            vec![
+                ("environment".to_string(), "prod".to_string()),
+                ("url".to_string(), "a=b,c".to_string())
+            ]
+        );
+        assert!(!accepts(&["--field", "environment"]));
+    }
+
+    #[test]
+    fn output_to_stdout_sends_nothing() {
+        let printed = config(&["--output", "stdout"]);
+        assert_eq!(printed.output, Output::Stdout);
+        assert!(printed.dry_run);
+
+        let sent = config(&[]);
+        assert_eq!(sent.output, Output::Graylog);
+        assert!(!sent.dry_run);
+        assert!(!accepts(&["--output", "console"]));
+    }
+
+    #[test]
+    fn read_buffer_size() {
+        assert_eq!(config(&[]).read_buffer, 65536);
+        assert_eq!(config(&["--read-buffer", "4096"]).read_buffer, 4096);
+        assert!(!accepts(&["--read-buffer", "0"]));
+        assert!(!accepts(&["--read-buffer", "big"]));
+    }
+
+    #[test]
+    fn durations_with_units() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("h"), None);
+        // too long to be represented
+        assert_eq!(parse_duration(&format!("{}h", u64::MAX / 60)), None);
+    }
+
+    #[test]
+    fn keeps_checked_compression_on_reload() {
+        let running = config(&["--comp", "gzip"]);
+        let mut reloaded = config(&["--comp", "zlib"]);
+        assert!(validate_config(&reloaded).is_ok());
+        assert_eq!(keep_targets(&running, &mut reloaded), vec!["comp"]);
+        assert_eq!(reloaded.compression, MessageCompression::Gzip);
+    }
+
+    #[test]
+    fn keeps_targets_on_reload() {
+        let running = config(&["--target", "10.0.0.1:12201", "--sys", "info"]);
+        let mut reloaded = config(&[
+            "--target",
+            "10.0.0.2:12201",
+            "--transport",
+            "tcp",
+            "--port",
+            "5001",
+            "--sys",
+            "warning",
+        ]);
+
+        assert_eq!(
+            keep_targets(&running, &mut reloaded),
+            vec!["target", "port", "transport"]
+        );
+        assert_eq!(reloaded.graylog_addrs, running.graylog_addrs);
+        assert_eq!(reloaded.sender_port, running.sender_port);
+        assert_eq!(reloaded.transport, running.transport);
+        // filters are applied
+        assert_eq!(reloaded.log_level_system, LevelSystem::Warning);
+
+        let mut same = config(&["--target", "10.0.0.1:12201"]);
+        assert!(keep_targets(&running, &mut same).is_empty());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn rejects_zstd_over_udp() {
+        assert!(validate_config(&config(&["--comp", "zstd"])).is_err());
+        assert!(validate_config(&config(&["--comp", "zstd", "--transport", "tcp"])).is_ok());
+        assert!(validate_config(&config(&[
+            "--comp",
+            "zstd",
+            "--output",
+            "file",
+            "--output-path",
+            "gelf.ndjson"
+        ]))
+        .is_ok());
+    }
+
+    #[test]
+    fn config_template_is_readable() {
+        let dir = env::temp_dir().join(format!("jctl2gray-template-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jctl2gray.toml");
+        let path = path.to_str().unwrap();
+
+        let read = |path: &str| {
+            let args = app()
+                .get_matches_from_safe(vec!["jctl2gray", "run", "--config", path])
+                .unwrap();
+            read_config_file(args.subcommand_matches("run").unwrap())
+                .unwrap()
+                .unwrap()
+        };
+
+        write_config_template(Some(path), false).unwrap();
+        assert!(write_config_template(Some(path), false).is_err());
+        let file = read(path);
+        assert_eq!(file.value_of("log_source"), Some("journal"));
+        assert_eq!(file.value_of("target"), Some(DEFAULT_TARGET));
+
+        // every commented out option is a valid one as well, except for the one resolving its host
+        let uncommented: String = config_template()
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(option) if option.contains(" = ") && !option.starts_with("target-") => option,
+                _ => line,
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        fs::write(path, uncommented).unwrap();
+        let file = read(path);
+        assert_eq!(file.value_of("queue_policy"), Some(DEFAULT_QUEUE_POLICY));
+        assert_eq!(file.value_of("opt_fields"), Some("team=core"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pipelines_of_file() {
+        assert_eq!(
+            split_words(r#"-s stdin --field "team=core web" --exclude 'a b'\ c"#).unwrap(),
+            vec![
+                "-s",
+                "stdin",
+                "--field",
+                "team=core web",
+                "--exclude",
+                "a b c"
+            ]
+        );
+        assert!(split_words("--exclude 'open").is_err());
+
+        let path = env::temp_dir().join(format!("jctl2gray-pipelines-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let read = |lines: &str| {
+            std::fs::write(&path, lines).unwrap();
+            read_pipelines(&path)
+        };
+
+        let pipelines =
+            read("# journal and stdin\n\n-s journal -p 5001\n-s stdin --field 'team=core web'\n")
+                .unwrap();
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[0].sender_port, 5001);
+        assert_eq!(pipelines[1].sender_port, 0);
+        assert!(matches!(pipelines[1].log_source, LogSource::Stdin));
+        assert_eq!(
+            pipelines[1].fields,
+            vec![("team".to_string(), "core web".to_string())]
+        );
+
+        let error = read("-s journal\n-s stdin --unknown\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(read("-s stdin\n-s stdin\n").is_err());
+        // only ports actually given could clash
+        let pipelines = read("-s journal -p 5000\n-s journal\n-s journal\n").unwrap();
+        let ports: Vec<u16> = pipelines.iter().map(|config| config.sender_port).collect();
+        assert_eq!(ports, vec![5000, 0, 0]);
+        let error = read("-s journal -p 5000\n-s journal --port 5000\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(read("# nothing\n").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn options_of_config_file() {
+        let path = env::temp_dir().join(format!("jctl2gray-config-{}.toml", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(
+            &path,
+            "target = \"10.0.0.1:12201\"\nsys = \"error\"\nfacility = [\"auth\", \"daemon\"]\n\
+             strict = true\n\n[field]\nteam = \"core\"\n",
+        )
+        .unwrap();
+
+        let words = [
+            "jctl2gray",
+            "run",
+            "-s",
+            "stdin",
+            "--config",
+            &path,
+            "--sys",
+            "warning",
+        ];
+        let args = app().get_matches_from_safe(words).unwrap();
+        let options = args.subcommand_matches("run").unwrap();
+        let file = read_config_file(options).unwrap();
+        let config = parse_config(&Options::new(options, file.as_ref()));
+
+        assert_eq!(config.graylog_addrs, vec!["10.0.0.1:12201"]);
+        // command line takes precedence over the file
+        assert_eq!(config.log_level_system, LevelSystem::Warning);
+        assert_eq!(config.facilities.len(), 2);
+        assert!(config.strict);
+        assert_eq!(
+            config.fields,
+            vec![("team".to_string(), "core".to_string())]
+        );
+
+        std::fs::write(&path, "unknown = 1\n").unwrap();
+        assert!(read_config_file(options).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
 }
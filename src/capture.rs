@@ -0,0 +1,105 @@
+//! Capture of outgoing messages
+//!
+//! Serialized messages are appended to a file as lines of GELF JSON,
+//! until the file reaches its size limit.
+
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::errors::Result;
+
+pub struct Capture {
+    path: String,
+    limit: u64,
+    file: Mutex<CaptureFile>,
+    exhausted: AtomicBool,
+}
+
+struct CaptureFile {
+    writer: LineWriter<File>,
+    written: u64,
+}
+
+impl Capture {
+    /// Open file for appending messages, up to `limit` bytes in total.
+    pub fn open(path: &str, limit: u64) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Capture {
+            path: path.to_string(),
+            limit,
+            file: Mutex::new(CaptureFile {
+                writer: LineWriter::new(file),
+                written,
+            }),
+            exhausted: AtomicBool::new(false),
+        })
+    }
+
+    /// Append serialized message, unless the file is full already.
+    pub fn write(&self, gelf: &[u8]) {
+        if self.exhausted.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut file = self.file.lock().expect("capture lock poisoned");
+        let size = gelf.len() as u64 + 1;
+        if file.written + size > self.limit {
+            warn!(
+                "capture file {} reached its size limit, capture stopped",
+                self.path
+            );
+            self.exhausted.store(true, Ordering::Relaxed);
+            return;
+        }
+
+        let written = file
+            .writer
+            .write_all(gelf)
+            .and_then(|_| file.writer.write_all(b"\n"));
+
+        match written {
+            Ok(_) => file.written += size,
+            Err(e) => {
+                error!(
+                    "failed to write capture file {}: {}, capture stopped",
+                    self.path, e
+                );
+                self.exhausted.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn captures_messages_up_to_limit() {
+        let path = std::env::temp_dir().join(format!("jctl2gray-capture-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+
+        let capture = Capture::open(&path, 20).unwrap();
+        capture.write(br#"{"n":1}"#);
+        capture.write(br#"{"n":2}"#);
+        // the next one would not fit, and capture stops even for smaller ones
+        capture.write(br#"{"n":3}"#);
+        capture.write(b"{}");
+        drop(capture);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+
+        // limit counts messages captured before
+        let capture = Capture::open(&path, 20).unwrap();
+        capture.write(br#"{"n":4}"#);
+        drop(capture);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Fault injection for testing purposes
+//!
+//! Randomly breaks input records and sends at configured rates,
+//! so behavior on failures could be verified without real outages.
+
+use std::borrow::Cow;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use rand;
+
+const DEFAULT_SLOW_SEND_MS: u64 = 500;
+
+/// Rates of injected faults, each in range [0, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chaos {
+    pub send_failure: f64,
+    pub slow_send: f64,
+    pub slow_send_delay: Duration,
+    pub malformed_input: f64,
+}
+
+impl Chaos {
+    /// Parse fault rates from comma delimited pairs, e.g.:
+    /// `fail=0.1,slow=0.05,slow_ms=200,malformed=0.01`.
+    pub fn parse(spec: &str) -> Result<Chaos, String> {
+        let mut chaos = Chaos {
+            send_failure: 0.0,
+            slow_send: 0.0,
+            slow_send_delay: Duration::from_millis(DEFAULT_SLOW_SEND_MS),
+            malformed_input: 0.0,
+        };
+
+        for pair in spec.split(',').filter(|p| !p.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => (k.trim(), v.trim()),
+                _ => return Err(format!("bad chaos option: {}", pair)),
+            };
+
+            match key {
+                "fail" => chaos.send_failure = parse_rate(value)?,
+                "slow" => chaos.slow_send = parse_rate(value)?,
+                "malformed" => chaos.malformed_input = parse_rate(value)?,
+                "slow_ms" => {
+                    let ms = value
+                        .parse::<u64>()
+                        .map_err(|_| format!("bad chaos delay: {}", value))?;
+                    chaos.slow_send_delay = Duration::from_millis(ms);
+                }
+                _ => return Err(format!("unknown chaos option: {}", key)),
+            }
+        }
+
+        Ok(chaos)
+    }
+
+    /// Randomly truncate input record to make it malformed.
+    pub fn corrupt_input<'a>(&self, data: &'a str) -> Cow<'a, str> {
+        if data.is_empty() || !happens(self.malformed_input) {
+            return Cow::Borrowed(data);
+        }
+
+        let cut = data
+            .char_indices()
+            .nth(rand::random::<usize>() % data.chars().count())
+            .map_or(0, |(i, _)| i);
+
+        debug!("chaos: input truncated to {} bytes", cut);
+        Cow::Owned(data[..cut].to_string())
+    }
+
+    /// Randomly slow down or fail the upcoming send.
+    pub fn before_send(&self) -> io::Result<()> {
+        if happens(self.slow_send) {
+            debug!("chaos: send delayed");
+            thread::sleep(self.slow_send_delay);
+        }
+
+        if happens(self.send_failure) {
+            return Err(io::Error::other("chaos: injected send failure"));
+        }
+
+        Ok(())
+    }
+}
+
+fn happens(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("bad chaos rate: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fault_rates() {
+        let chaos = Chaos::parse("fail=0.1, slow=0.05,slow_ms=200,malformed=1").unwrap();
+        assert_eq!(chaos.send_failure, 0.1);
+        assert_eq!(chaos.slow_send, 0.05);
+        assert_eq!(chaos.slow_send_delay, Duration::from_millis(200));
+        assert_eq!(chaos.malformed_input, 1.0);
+
+        assert!(Chaos::parse("fail=1.5").is_err());
+        assert!(Chaos::parse("fail").is_err());
+        assert!(Chaos::parse("slow_ms=soon").is_err());
+        assert!(Chaos::parse("crash=0.1").is_err());
+    }
+
+    #[test]
+    fn faults_at_edge_rates() {
+        let never = Chaos::parse("").unwrap();
+        assert_eq!(
+            never.corrupt_input("{\"MESSAGE\":\"hi\"}"),
+            "{\"MESSAGE\":\"hi\"}"
+        );
+        assert!(never.before_send().is_ok());
+
+        let always = Chaos::parse("fail=1,malformed=1").unwrap();
+        let data = "{\"MESSAGE\":\"héllo\"}";
+        for _ in 0..20 {
+            let corrupted = always.corrupt_input(data);
+            assert!(corrupted.len() < data.len());
+            assert!(data.starts_with(&*corrupted));
+        }
+        assert!(always.before_send().is_err());
+    }
+}
@@ -1,29 +1,337 @@
 /// General app config
 ///
-use gelf::{LevelMsg, LevelSystem, MessageCompression};
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::{ArcSwap, Guard};
+use regex::Regex;
+
+use crate::chaos::Chaos;
+use crate::dedup::Dedup;
+use crate::export::JournalFormat;
+use crate::extract::Extractor;
+use crate::facility::Facility;
+use crate::gelf::{ChunkSize, LevelMsg, LevelSystem, MessageCompression};
+use crate::ignore::FieldPattern;
+use crate::multiline::Multiline;
+use crate::oversize::OversizePolicy;
+use crate::pipeline::QueuePolicy;
+use crate::ratelimit::RateLimiter;
+use crate::redact::Redactor;
+use crate::resolver::ResolveStrategy;
+use crate::sampling::Sampling;
+use crate::syslog::ListenProtocol;
+use crate::tls::TlsOptions;
+use crate::transport::{KafkaOptions, TransportKind};
+use crate::unit_rules::UnitRules;
 
 #[derive(Debug, Copy, Clone)]
 pub enum LogSource {
     Stdin,
     Journalctl,
+    File,
+    Syslog,
+}
+
+/// Destination of outgoing messages
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Output {
+    Graylog,
+    Stdout,
+    File,
+    Kafka,
+}
+
+impl<'a> From<&'a str> for Output {
+    fn from(output: &'a str) -> Self {
+        match output {
+            "stdout" => Output::Stdout,
+            "file" => Output::File,
+            "kafka" => Output::Kafka,
+            _ => Output::Graylog,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Config {
     pub log_source: LogSource,
+    pub file_paths: Vec<String>,
+    pub units: Vec<String>,
+    pub multiline: Option<Multiline>,
+    pub listen_addr: Option<String>,
+    pub listen_protocol: ListenProtocol,
     pub sender_port: u16,
-    pub graylog_addr: String,
+    pub send_buffer: Option<usize>,
+    pub graylog_addrs: Vec<String>,
+    pub failover_addr: Option<String>,
+    pub failover_after: u32,
     pub graylog_addr_ttl: u64,
+    pub transport: TransportKind,
+    pub tls: TlsOptions,
+    pub batch_size: usize,
+    pub batch_timeout: Duration,
+    pub resolve_strategy: ResolveStrategy,
     pub compression: MessageCompression,
+    pub compress_min: usize,
+    pub chunk_size: ChunkSize,
+    pub oversize_policy: OversizePolicy,
     pub log_level_system: LevelSystem,
     pub log_level_message: Option<LevelMsg>,
+    pub facilities: Vec<Facility>,
+    pub include_regex: Vec<Regex>,
+    pub exclude_regex: Vec<Regex>,
+    pub unit_rules: UnitRules,
+    pub extractors: Vec<Extractor>,
+    pub sampling: Option<Sampling>,
+    pub dedup: Option<Arc<Dedup>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub parse_json_message: bool,
+    pub parse_logfmt: bool,
+    pub monotonic_time: bool,
     pub optional: Vec<(String, String)>,
+    pub fields: Vec<(String, String)>,
+    pub input_tag: Option<String>,
+    pub rename_reserved: bool,
+    pub field_renames: Vec<(String, String)>,
+    pub dropped_fields: Vec<FieldPattern>,
+    pub kept_fields: Vec<String>,
+    pub redactor: Option<Redactor>,
+    pub stats_interval: u64,
+    pub api_url: Option<String>,
+    pub api_token: Option<String>,
+    pub chaos: Option<Chaos>,
+    pub async_runtime: bool,
+    pub workers: usize,
+    pub compressors: usize,
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+    pub read_buffer: usize,
+    pub journal_format: JournalFormat,
+    pub cursor_file: Option<String>,
+    pub spool_dir: Option<String>,
+    pub spool_limit: u64,
+    pub max_line: usize,
+    pub dry_run: bool,
+    pub output: Output,
+    pub output_path: Option<String>,
+    pub output_limit: u64,
+    pub output_keep: usize,
+    pub kafka: KafkaOptions,
+    pub strict: bool,
+    pub limit: Option<u64>,
+    pub capture: Option<String>,
+    pub capture_limit: u64,
+    pub preview: bool,
+    pub admin_socket: Option<String>,
+    pub health_addr: Option<String>,
+}
+
+impl Config {
+    /// Return whether messages are split into chunks of UDP datagrams,
+    /// rather than passed whole over a stream, into a file or Kafka.
+    pub fn is_chunked(&self) -> bool {
+        !self.transport.is_stream() && !matches!(self.output, Output::File | Output::Kafka)
+    }
+
+    /// Take over dedup and rate limiting of the running config, applying own settings to them,
+    /// so that reload neither loses the collapsed repeats nor refills buckets of the units.
+    pub fn carry_state(&mut self, running: &Config) {
+        if let (Some(dedup), Some(current)) = (self.dedup.as_mut(), running.dedup.as_ref()) {
+            current.configure_like(dedup);
+            *dedup = current.clone();
+        }
+
+        if let (Some(limiter), Some(current)) =
+            (self.rate_limiter.as_mut(), running.rate_limiter.as_ref())
+        {
+            current.configure_like(limiter);
+            *limiter = current.clone();
+        }
+    }
+
+    /// Return byte terminating messages passed whole: newline in the file, null byte over streams.
+    pub fn terminator(&self) -> u8 {
+        if self.output == Output::File {
+            b'\n'
+        } else {
+            0
+        }
+    }
 }
 
 pub fn parse_log_source(level: &str) -> Option<LogSource> {
     match level {
         "stdin" => Some(LogSource::Stdin),
         "journal" => Some(LogSource::Journalctl),
+        "file" => Some(LogSource::File),
+        "syslog" => Some(LogSource::Syslog),
         _ => None,
     }
 }
+
+/// Config shared between threads, which could be replaced while running.
+///
+/// Reading never blocks on replacement, so config could be loaded for every record.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<ArcSwap<Config>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig(Arc::new(ArcSwap::from_pointee(config)))
+    }
+
+    /// Return the current config.
+    pub fn load(&self) -> Guard<Arc<Config>> {
+        self.0.load()
+    }
+
+    /// Return snapshot of the current config to be kept for a while.
+    pub fn snapshot(&self) -> Arc<Config> {
+        self.0.load_full()
+    }
+
+    /// Replace config, affecting records processed afterwards.
+    pub fn store(&self, config: Config) {
+        self.0.store(Arc::new(config));
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Config with defaults of the command line, reading stdin and sending to local Graylog.
+    pub(crate) fn for_tests() -> Config {
+        Config {
+            log_source: LogSource::Stdin,
+            file_paths: Vec::new(),
+            units: Vec::new(),
+            multiline: None,
+            listen_addr: None,
+            listen_protocol: ListenProtocol::Udp,
+            sender_port: 0,
+            send_buffer: None,
+            graylog_addrs: vec!["127.0.0.1:9000".to_string()],
+            failover_addr: None,
+            failover_after: 3,
+            graylog_addr_ttl: 60,
+            transport: TransportKind::Udp,
+            tls: TlsOptions {
+                ca_cert: None,
+                client_cert: None,
+                client_key: None,
+                verify_hostname: true,
+            },
+            batch_size: 1,
+            batch_timeout: Duration::from_millis(10),
+            resolve_strategy: ResolveStrategy::First,
+            compression: MessageCompression::None,
+            compress_min: 0,
+            chunk_size: ChunkSize::WAN,
+            oversize_policy: OversizePolicy::Drop,
+            log_level_system: LevelSystem::Informational,
+            log_level_message: None,
+            facilities: Vec::new(),
+            include_regex: Vec::new(),
+            exclude_regex: Vec::new(),
+            unit_rules: UnitRules::default(),
+            extractors: Vec::new(),
+            sampling: None,
+            dedup: None,
+            rate_limiter: None,
+            parse_json_message: false,
+            parse_logfmt: false,
+            monotonic_time: false,
+            optional: Vec::new(),
+            fields: Vec::new(),
+            input_tag: None,
+            rename_reserved: false,
+            field_renames: Vec::new(),
+            dropped_fields: Vec::new(),
+            kept_fields: Vec::new(),
+            redactor: None,
+            stats_interval: 0,
+            api_url: None,
+            api_token: None,
+            chaos: None,
+            async_runtime: false,
+            workers: 1,
+            compressors: 0,
+            queue_capacity: 1024,
+            queue_policy: QueuePolicy::Block,
+            read_buffer: 65536,
+            journal_format: JournalFormat::Json,
+            cursor_file: None,
+            spool_dir: None,
+            spool_limit: 1024 * 1024 * 1024,
+            max_line: 1024 * 1024,
+            dry_run: false,
+            output: Output::Graylog,
+            output_path: None,
+            output_limit: 100 * 1024 * 1024,
+            output_keep: 5,
+            kafka: KafkaOptions::default(),
+            strict: false,
+            limit: None,
+            capture: None,
+            capture_limit: 100 * 1024 * 1024,
+            preview: false,
+            admin_socket: None,
+            health_addr: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_carries_dedup_and_rate_limiting() {
+        let mut running = Config::for_tests();
+        running.dedup = Some(Arc::new(Dedup::new(Duration::from_secs(60))));
+        running.rate_limiter = Some(Arc::new(RateLimiter::new(0.001, 1)));
+
+        let key = Dedup::key("disk full", "host", "app.service");
+        let dedup = running.dedup.as_ref().unwrap();
+        assert_eq!(dedup.observe(key), None);
+        assert_eq!(dedup.observe(key), Some(1));
+        assert!(running.rate_limiter.as_ref().unwrap().admit("app.service"));
+
+        let mut reloaded = Config::for_tests();
+        reloaded.dedup = Some(Arc::new(Dedup::new(Duration::from_secs(120))));
+        reloaded.rate_limiter = Some(Arc::new(RateLimiter::new(0.001, 2)));
+        reloaded.carry_state(&running);
+
+        // the run of repeats goes on, and the bucket of the unit is not refilled
+        let dedup = reloaded.dedup.as_ref().unwrap();
+        assert!(Arc::ptr_eq(dedup, running.dedup.as_ref().unwrap()));
+        assert_eq!(dedup.observe(key), Some(2));
+
+        let limiter = reloaded.rate_limiter.as_ref().unwrap();
+        assert!(!limiter.admit("app.service"));
+        // while burst of the reloaded config applies to the new buckets
+        assert!(limiter.admit("db.service"));
+        assert!(limiter.admit("db.service"));
+        assert!(!limiter.admit("db.service"));
+
+        // disabled on reload, state is dropped
+        let mut disabled = Config::for_tests();
+        disabled.carry_state(&running);
+        assert!(disabled.dedup.is_none() && disabled.rate_limiter.is_none());
+    }
+
+    #[test]
+    fn stored_config_is_shared() {
+        let shared = SharedConfig::new(Config::for_tests());
+        let worker = shared.clone();
+        let snapshot = worker.snapshot();
+
+        let mut reloaded = Config::for_tests();
+        reloaded.log_level_system = LevelSystem::Error;
+        shared.store(reloaded);
+
+        // records processed afterwards see the new config, while the kept snapshot does not change
+        assert_eq!(worker.load().log_level_system, LevelSystem::Error);
+        assert_eq!(snapshot.log_level_system, LevelSystem::Informational);
+    }
+}
@@ -0,0 +1,273 @@
+//! Journal cursor persistence
+//!
+//! Cursor of the last sent record is saved into a state file, so that after restart
+//! reading resumes right after it, instead of losing records written meanwhile.
+//! File is rewritten at most once per interval: after a crash some records
+//! could be sent once again, but none are skipped.
+//!
+//! Records are processed by several workers, so messages reach the sender out of
+//! the order they were read in. Each record is given a ticket numbered in the reading
+//! order, and the cursor moves only as far as every preceding ticket is settled,
+//! whether its message was sent, or the record was filtered out or dropped.
+//! Message which failed to be sent, and was not spooled either, holds the cursor
+//! back until restart, so that reading resumes before it rather than skipping it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::errors::Result;
+
+/// Minimal period between writes of the state file
+pub(crate) const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Field of journal records holding the cursor
+pub const CURSOR_FIELD: &str = "__CURSOR";
+
+/// Read cursor saved by the previous run, if any.
+pub fn load(path: &str) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(cursor) => Ok(Some(cursor.trim().to_string()).filter(|cursor| !cursor.is_empty())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// State file updated with cursors of sent records
+pub(crate) struct CursorFile {
+    path: PathBuf,
+    cursor: Option<Arc<str>>,
+    saved_at: Option<Instant>,
+    dirty: bool,
+}
+
+impl CursorFile {
+    pub(crate) fn new(path: &str) -> Self {
+        CursorFile {
+            path: PathBuf::from(path),
+            cursor: None,
+            saved_at: None,
+            dirty: false,
+        }
+    }
+
+    /// Remember cursor of a sent record, saving it unless the file was saved recently.
+    pub(crate) fn update(&mut self, cursor: Arc<str>) {
+        self.cursor = Some(cursor);
+        self.dirty = true;
+
+        if self
+            .saved_at
+            .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL)
+        {
+            self.save();
+        }
+    }
+
+    /// Write the last cursor, replacing the file at once, so that it is never left half-written.
+    pub(crate) fn save(&mut self) {
+        let cursor = match (self.dirty, &self.cursor) {
+            (true, Some(cursor)) => cursor,
+            _ => return,
+        };
+
+        let temp = self.path.with_extension("tmp");
+        let saved =
+            fs::write(&temp, format!("{}\n", cursor)).and_then(|_| fs::rename(&temp, &self.path));
+        if let Err(e) = saved {
+            warn!("failed to save cursor to {}: {}", self.path.display(), e);
+        }
+
+        self.saved_at = Some(Instant::now());
+        self.dirty = false;
+    }
+}
+
+impl Drop for CursorFile {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+/// Reorder window, passing cursors to the state file in the order records were read
+pub(crate) struct CursorWindow {
+    state: Mutex<WindowState>,
+}
+
+struct WindowState {
+    file: CursorFile,
+    /// Number of the next ticket to be issued
+    issued: u64,
+    /// Number of the first ticket not settled yet
+    next: u64,
+    /// Tickets settled out of order
+    settled: BTreeMap<u64, Settled>,
+    /// Whether the cursor is held back by an undelivered message
+    held: bool,
+}
+
+/// Outcome of the record a ticket is issued for
+enum Settled {
+    /// Message is delivered, its cursor could be committed
+    Delivered(Arc<str>),
+    /// Record is filtered out or dropped, and does not hold back the following ones
+    Skipped,
+    /// Message failed to be delivered, cursor must not move past it
+    Failed(Arc<str>),
+}
+
+impl CursorWindow {
+    pub(crate) fn new(file: CursorFile) -> Arc<Self> {
+        Arc::new(CursorWindow {
+            state: Mutex::new(WindowState {
+                file,
+                issued: 0,
+                next: 0,
+                settled: BTreeMap::new(),
+                held: false,
+            }),
+        })
+    }
+
+    /// Issue ticket for the record just read, holding its cursor.
+    pub(crate) fn ticket(self: &Arc<Self>, cursor: Arc<str>) -> Ticket {
+        let mut state = self.state.lock().expect("cursor lock poisoned");
+        let seq = state.issued;
+        state.issued += 1;
+
+        Ticket {
+            seq,
+            cursor: Some(cursor),
+            delivered: false,
+            window: self.clone(),
+        }
+    }
+
+    /// Save the last committed cursor, if it is not saved yet.
+    pub(crate) fn save(&self) {
+        self.state.lock().expect("cursor lock poisoned").file.save();
+    }
+
+    fn settle(&self, seq: u64, settled: Settled) {
+        let mut state = self.state.lock().expect("cursor lock poisoned");
+        if state.held {
+            return;
+        }
+        state.settled.insert(seq, settled);
+
+        while let Some(settled) = {
+            let next = state.next;
+            state.settled.remove(&next)
+        } {
+            state.next += 1;
+            match settled {
+                Settled::Delivered(cursor) => state.file.update(cursor),
+                Settled::Skipped => {}
+                Settled::Failed(cursor) => {
+                    warn!(
+                        "message of record {} is not delivered, cursor is held before it until restart",
+                        cursor
+                    );
+                    state.held = true;
+                    state.settled.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Place of a record in the reading order, settled on drop.
+///
+/// Cursor is committed only if the message is delivered, otherwise the record
+/// just stops holding back cursors of the following ones, unless its message failed.
+pub(crate) struct Ticket {
+    seq: u64,
+    /// Cursor of the record, taken once the ticket is settled
+    cursor: Option<Arc<str>>,
+    delivered: bool,
+    window: Arc<CursorWindow>,
+}
+
+impl Ticket {
+    /// Settle ticket of the delivered message, committing its cursor in turn.
+    pub(crate) fn deliver(mut self) {
+        self.delivered = true;
+    }
+
+    /// Settle ticket of the message failed to be delivered, holding the cursor before it.
+    pub(crate) fn fail(mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.window.settle(self.seq, Settled::Failed(cursor));
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let settled = match self.cursor.take() {
+            Some(cursor) if self.delivered => Settled::Delivered(cursor),
+            Some(_) => Settled::Skipped,
+            None => return,
+        };
+        self.window.settle(self.seq, settled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn commits_in_reading_order() {
+        let path = std::env::temp_dir().join(format!("jctl2gray-cursor-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let window = CursorWindow::new(CursorFile::new(&path));
+
+        let first = window.ticket(Arc::from("s=1"));
+        let second = window.ticket(Arc::from("s=2"));
+        let third = window.ticket(Arc::from("s=3"));
+
+        // the second worker is done before the first one
+        thread::spawn(move || second.deliver()).join().unwrap();
+        window.save();
+        assert_eq!(load(&path).unwrap(), None);
+
+        thread::spawn(move || first.deliver()).join().unwrap();
+        window.save();
+        assert_eq!(load(&path).unwrap(), Some("s=2".to_string()));
+
+        // filtered out record does not hold back the cursor, but is not committed either
+        drop(third);
+        let fourth = window.ticket(Arc::from("s=4"));
+        fourth.deliver();
+        window.save();
+        assert_eq!(load(&path).unwrap(), Some("s=4".to_string()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn holds_cursor_before_failed_message() {
+        let path = std::env::temp_dir().join(format!("jctl2gray-held-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let window = CursorWindow::new(CursorFile::new(&path));
+
+        window.ticket(Arc::from("s=1")).deliver();
+        let failed = window.ticket(Arc::from("s=2"));
+        let third = window.ticket(Arc::from("s=3"));
+
+        // the following message is delivered, while the failed one is not spooled
+        third.deliver();
+        failed.fail();
+        window.ticket(Arc::from("s=4")).deliver();
+        window.save();
+        assert_eq!(load(&path).unwrap(), Some("s=1".to_string()));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
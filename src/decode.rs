@@ -0,0 +1,211 @@
+//! Decoding of GELF datagrams
+//!
+//! Reads datagrams as they went over the wire, either from a pcap capture
+//! or as hex lines, reassembles chunks and decompresses the messages.
+
+use std::io::{BufRead, Read, Write};
+
+use crate::errors::{Error, Result};
+
+use crate::gelf::{MessageCompression, Reassembler};
+
+/// Format of the input with datagrams
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// One datagram per line, hex-encoded
+    Hex,
+    /// Capture file in classic libpcap format
+    Pcap,
+}
+
+impl<'a> From<&'a str> for InputFormat {
+    fn from(format: &'a str) -> Self {
+        match format {
+            "pcap" => InputFormat::Pcap,
+            _ => InputFormat::Hex,
+        }
+    }
+}
+
+/// Print every message found in the input as a line of JSON, returning number of them.
+///
+/// In pcap captures only UDP datagrams to the given port are considered, if it is set.
+pub fn run<R: BufRead, W: Write>(
+    input: R,
+    format: InputFormat,
+    port: Option<u16>,
+    mut output: W,
+) -> Result<usize> {
+    let datagrams = match format {
+        InputFormat::Hex => read_hex(input)?,
+        InputFormat::Pcap => read_pcap(input, port)?,
+    };
+
+    let mut reassembler = Reassembler::new();
+    let mut decoded = 0;
+
+    for datagram in datagrams {
+        let payload = match reassembler.push(&datagram) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("skipping datagram: {}", e);
+                continue;
+            }
+        };
+
+        match MessageCompression::detect(&payload).decompress(&payload) {
+            Ok(message) => {
+                output.write_all(&message)?;
+                writeln!(output)?;
+                decoded += 1;
+            }
+            Err(e) => warn!("failed to decompress message: {}", e),
+        }
+    }
+
+    if reassembler.incomplete() > 0 {
+        warn!("{} messages lack some chunks", reassembler.incomplete());
+    }
+
+    Ok(decoded)
+}
+
+/// Read datagrams written as hex, one per line; whitespace is ignored.
+fn read_hex<R: BufRead>(input: R) -> Result<Vec<Vec<u8>>> {
+    let mut datagrams = Vec::new();
+
+    for line in input.lines() {
+        let digits: Vec<u8> = line?.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        if digits.is_empty() {
+            continue;
+        }
+
+        let datagram = digits
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .filter(|pair| pair.len() == 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| Error::InternalError("bad hex datagram".to_string()))?;
+
+        datagrams.push(datagram);
+    }
+
+    Ok(datagrams)
+}
+
+/// Link types of pcap captures
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Read payloads of UDP datagrams from a pcap capture.
+///
+/// IP fragments are not reassembled, but GELF chunks are normally small enough.
+fn read_pcap<R: Read>(mut input: R, port: Option<u16>) -> Result<Vec<Vec<u8>>> {
+    let mut header = [0; 24];
+    input.read_exact(&mut header)?;
+
+    let big_endian = match header[0..4] {
+        // microsecond and nanosecond resolution
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => true,
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => false,
+        _ => return Err(Error::InternalError("not a pcap capture".to_string())),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let link_type = read_u32(&header[20..24]) & 0x0fff_ffff;
+
+    let mut datagrams = Vec::new();
+    let mut record_header = [0; 16];
+    let mut packet = Vec::new();
+
+    loop {
+        match input.read_exact(&mut record_header) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(Error::from(e)),
+        }
+
+        let captured = read_u32(&record_header[8..12]) as usize;
+        packet.resize(captured, 0);
+        input.read_exact(&mut packet)?;
+
+        if let Some(datagram) =
+            link_payload(&packet, link_type).and_then(|ip| udp_payload(ip, port))
+        {
+            datagrams.push(datagram.to_vec());
+        }
+    }
+
+    Ok(datagrams)
+}
+
+/// Return IP packet carried by the link layer frame.
+fn link_payload(frame: &[u8], link_type: u32) -> Option<&[u8]> {
+    let (ether_type, offset) = match link_type {
+        LINKTYPE_RAW => return Some(frame),
+        // address family in host byte order, IP version is checked later anyway
+        LINKTYPE_NULL => return frame.get(4..),
+        LINKTYPE_ETHERNET => {
+            let ether_type = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            // skip VLAN tag
+            if ether_type == 0x8100 {
+                (u16::from_be_bytes([*frame.get(16)?, *frame.get(17)?]), 18)
+            } else {
+                (ether_type, 14)
+            }
+        }
+        LINKTYPE_LINUX_SLL => (u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]), 16),
+        LINKTYPE_LINUX_SLL2 => (u16::from_be_bytes([*frame.first()?, *frame.get(1)?]), 20),
+        _ => return None,
+    };
+
+    match ether_type {
+        0x0800 | 0x86dd => frame.get(offset..),
+        _ => None,
+    }
+}
+
+/// Return payload of the UDP datagram carried by the IP packet.
+fn udp_payload(packet: &[u8], port: Option<u16>) -> Option<&[u8]> {
+    let udp = match packet.first()? >> 4 {
+        4 => {
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            // UDP, not a subsequent fragment
+            let fragment_offset = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x1fff;
+            if *packet.get(9)? != 17 || fragment_offset != 0 {
+                return None;
+            }
+            packet.get(header_len..)?
+        }
+        6 => {
+            // extension headers are not supported
+            if *packet.get(6)? != 17 {
+                return None;
+            }
+            packet.get(40..)?
+        }
+        _ => return None,
+    };
+
+    let destination = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    if port.is_some_and(|port| port != destination) {
+        return None;
+    }
+
+    let len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    udp.get(8..len.max(8))
+}
@@ -0,0 +1,171 @@
+//! Suppression of repeated messages
+//!
+//! Kernel and systemd tend to flood the journal with the same line during failures.
+//! The first record of a message is sent as usual, while its identical successors
+//! within the window are collapsed into a single message with `_repeat_count` field,
+//! like syslog's "last message repeated N times". Records are identical when they
+//! have the same message, host and systemd unit.
+//!
+//! Collapsed message is the last repeat, it is sent once another message comes,
+//! once the window since the first record is over, or on exit.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Field of the collapsed message holding number of repeats
+pub const REPEAT_COUNT_FIELD: &str = "repeat_count";
+
+/// Consecutive records of the same message
+struct Run {
+    key: u64,
+    started_at: Instant,
+    repeats: u64,
+    /// Serialized last repeat along with its number
+    collapsed: Option<(u64, Vec<u8>)>,
+}
+
+struct State {
+    window: Duration,
+    run: Option<Run>,
+    /// Collapsed messages of finished runs, to be sent
+    ready: Vec<Vec<u8>>,
+}
+
+impl State {
+    fn finish_run(&mut self) {
+        if let Some((_, gelf)) = self.run.take().and_then(|run| run.collapsed) {
+            self.ready.push(gelf);
+        }
+    }
+}
+
+/// Last message seen, shared by all workers
+pub struct Dedup {
+    state: Mutex<State>,
+}
+
+impl Dedup {
+    /// Create stage collapsing repeats within `window` since the first record of a message.
+    pub fn new(window: Duration) -> Self {
+        Dedup {
+            state: Mutex::new(State {
+                window,
+                run: None,
+                ready: Vec::new(),
+            }),
+        }
+    }
+
+    /// Return key identifying the message of a record.
+    pub fn key(message: &str, host: &str, unit: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (message, host, unit).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Register record of the message, returning number of its repeats so far
+    /// if the record repeats the previous one and should be collapsed.
+    pub fn observe(&self, key: u64) -> Option<u64> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+
+        let window = state.window;
+        if let Some(ref mut run) = state.run {
+            if run.key == key && now.duration_since(run.started_at) < window {
+                run.repeats += 1;
+                return Some(run.repeats);
+            }
+        }
+
+        state.finish_run();
+        state.run = Some(Run {
+            key,
+            started_at: now,
+            repeats: 0,
+            collapsed: None,
+        });
+        None
+    }
+
+    /// Keep serialized repeat as the collapsed message of its run.
+    ///
+    /// With several workers the run could be finished by another one meanwhile,
+    /// then the repeat is dropped and the collapsed message counts one less.
+    pub fn collapse(&self, key: u64, repeats: u64, gelf: Vec<u8>) {
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+
+        if let Some(ref mut run) = state.run {
+            let newer = run.collapsed.as_ref().is_none_or(|&(n, _)| n < repeats);
+            if run.key == key && newer {
+                run.collapsed = Some((repeats, gelf));
+            }
+        }
+    }
+
+    /// Take collapsed messages ready to be sent, finishing the run once its window is over.
+    pub fn take_ready(&self) -> Vec<Vec<u8>> {
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+
+        let window = state.window;
+        let expired = state
+            .run
+            .as_ref()
+            .is_some_and(|run| run.started_at.elapsed() >= window);
+        if expired {
+            state.finish_run();
+        }
+
+        mem::take(&mut state.ready)
+    }
+
+    /// Apply window of another stage, keeping the current run, e.g. on reload.
+    pub fn configure_like(&self, other: &Dedup) {
+        let window = other.state.lock().expect("dedup lock poisoned").window;
+        self.state.lock().expect("dedup lock poisoned").window = window;
+    }
+
+    /// Take all collapsed messages, including the one of the current run.
+    pub fn flush(&self) -> Vec<Vec<u8>> {
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+        state.finish_run();
+        mem::take(&mut state.ready)
+    }
+}
+
+/// Only settings are shown, so that the dump of config does not change while running
+impl fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let window = self.state.lock().expect("dedup lock poisoned").window;
+        f.debug_struct("Dedup").field("window", &window).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_consecutive_repeats() {
+        let dedup = Dedup::new(Duration::from_secs(60));
+        let failed = Dedup::key("link is down", "box", "kernel");
+        let other = Dedup::key("link is up", "box", "kernel");
+
+        assert_eq!(dedup.observe(failed), None);
+        for n in 1..=3 {
+            assert_eq!(dedup.observe(failed), Some(n));
+            dedup.collapse(failed, n, format!("repeat {}", n).into_bytes());
+        }
+        assert!(dedup.take_ready().is_empty());
+
+        assert_eq!(dedup.observe(other), None);
+        assert_eq!(dedup.take_ready(), vec![b"repeat 3".to_vec()]);
+
+        // message without repeats is not sent again
+        assert_eq!(dedup.observe(failed), None);
+        assert!(dedup.flush().is_empty());
+    }
+}
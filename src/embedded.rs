@@ -0,0 +1,124 @@
+//! Parsing of JSON messages
+//!
+//! Applications logging structured JSON to stdout end up with the whole object
+//! in the message of the journal record. Such object is split into the message text,
+//! taken from `message` or `msg` key, its level, taken from `level` key, and other keys,
+//! which become fields. Nested objects and arrays are passed as JSON text.
+
+use std::convert::TryFrom;
+
+use serde_json::{Map, Value};
+
+use crate::gelf::LevelSystem;
+
+/// Keys holding the message text, in order of preference
+const MESSAGE_KEYS: [&str; 2] = ["message", "msg"];
+
+/// Key holding the level of the message
+const LEVEL_KEY: &str = "level";
+
+/// Parts of the JSON message
+#[derive(Debug, PartialEq)]
+pub struct Embedded {
+    pub message: Option<String>,
+    pub level: Option<LevelSystem>,
+    pub fields: Vec<(String, Value)>,
+}
+
+/// Split message into its parts, if it is a JSON object.
+pub fn parse(text: &str) -> Option<Embedded> {
+    let text = text.trim();
+    if !text.starts_with('{') {
+        return None;
+    }
+    let mut object: Map<String, Value> = serde_json::from_str(text).ok()?;
+
+    let message = MESSAGE_KEYS
+        .iter()
+        .find_map(|&key| match object.get(key) {
+            Some(Value::String(_)) => object.remove(key),
+            _ => None,
+        })
+        .and_then(|message| message.as_str().map(String::from));
+
+    // unknown levels are left as fields
+    let level = object.get(LEVEL_KEY).and_then(parse_level);
+    if level.is_some() {
+        object.remove(LEVEL_KEY);
+    }
+
+    let fields = object
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Null => None,
+            Value::Object(_) | Value::Array(_) => Some((key, Value::String(value.to_string()))),
+            scalar => Some((key, scalar)),
+        })
+        .collect();
+
+    Some(Embedded {
+        message,
+        level,
+        fields,
+    })
+}
+
+/// Parse level either named as by common loggers, or given as syslog severity.
+fn parse_level(level: &Value) -> Option<LevelSystem> {
+    if let Some(severity) = level.as_u64() {
+        return u8::try_from(severity)
+            .ok()
+            .filter(|&severity| severity <= 7)
+            .map(LevelSystem::from_num);
+    }
+
+    let level = match level.as_str()?.to_ascii_lowercase().as_str() {
+        "emerg" | "emergency" => LevelSystem::Emergency,
+        "alert" => LevelSystem::Alert,
+        "crit" | "critical" | "fatal" | "panic" => LevelSystem::Critical,
+        "err" | "error" => LevelSystem::Error,
+        "warn" | "warning" => LevelSystem::Warning,
+        "notice" => LevelSystem::Notice,
+        "info" | "information" => LevelSystem::Informational,
+        "debug" | "trace" => LevelSystem::Debug,
+        _ => return None,
+    };
+    Some(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_message_parts() {
+        let embedded = parse(
+            r#"{"level":"WARN","msg":"slow query","ms":120,"ctx":{"db":"main"},"trace":null}"#,
+        )
+        .unwrap();
+        assert_eq!(embedded.message.as_deref(), Some("slow query"));
+        assert_eq!(embedded.level, Some(LevelSystem::Warning));
+        assert_eq!(
+            embedded.fields,
+            vec![
+                ("ctx".to_string(), json!(r#"{"db":"main"}"#)),
+                ("ms".to_string(), json!(120)),
+            ]
+        );
+
+        let embedded = parse(r#"{"message":"done","msg":"other","level":"verbose"}"#).unwrap();
+        assert_eq!(embedded.message.as_deref(), Some("done"));
+        assert_eq!(embedded.level, None);
+        assert_eq!(
+            embedded.fields,
+            vec![
+                ("level".to_string(), json!("verbose")),
+                ("msg".to_string(), json!("other")),
+            ]
+        );
+
+        assert_eq!(parse("plain {text}"), None);
+        assert_eq!(parse("{broken"), None);
+        assert_eq!(parse("[1, 2]"), None);
+    }
+}
@@ -7,6 +7,8 @@ use serde_json::Error as SerdeJSONErr;
 use std::error::Error as StdErr;
 use std::io::Error as IOErr;
 
+use crate::gelf::{ValidationError, MAX_CHUNKS, MAX_CHUNK_SIZE};
+
 pub type Result<T> = StdResult<T, Error>;
 
 #[derive(Debug)]
@@ -14,8 +16,19 @@ pub enum Error {
     IOError(String),
     SerdeParsing(String),
     InsufficientLogLevel,
+    Filtered,
+    RateLimited,
+    Sampled,
+    Repeated,
     NoMessage,
     InternalError(String),
+    ApiValidation(String),
+    StrictFailure(String),
+    InvalidChunkSize(u16),
+    MessageTooLarge { size: usize, chunks: u64 },
+    ReservedField(String),
+    Validation(ValidationError),
+    Tls(String),
 }
 
 impl fmt::Display for Error {
@@ -24,8 +37,27 @@ impl fmt::Display for Error {
             Error::IOError(ref reason) => write!(f, "[IO] {}", reason),
             Error::SerdeParsing(ref reason) => write!(f, "[JSON parsing] {}", reason),
             Error::InternalError(ref reason) => write!(f, "[Internal] {}", reason),
-            ref e @ Error::InsufficientLogLevel => write!(f, "{}", e.to_string()),
-            ref e @ Error::NoMessage => write!(f, "{}", e.to_string()),
+            Error::ApiValidation(ref reason) => write!(f, "[Graylog API] {}", reason),
+            Error::StrictFailure(ref reason) => write!(f, "[Strict] {}", reason),
+            Error::InvalidChunkSize(size) => write!(
+                f,
+                "[GELF] chunk size {} is out of range 1..={}",
+                size, MAX_CHUNK_SIZE
+            ),
+            Error::MessageTooLarge { size, chunks } => write!(
+                f,
+                "[GELF] message of {} bytes needs {} chunks, while at most {} are allowed",
+                size, chunks, MAX_CHUNKS
+            ),
+            Error::ReservedField(ref field) => write!(f, "[GELF] field {} is reserved", field),
+            Error::Validation(ref e) => write!(f, "[GELF] {}", e),
+            Error::Tls(ref reason) => write!(f, "[TLS] {}", reason),
+            Error::InsufficientLogLevel => write!(f, "insufficient log level"),
+            Error::Filtered => write!(f, "filtered out"),
+            Error::RateLimited => write!(f, "rate limited"),
+            Error::Sampled => write!(f, "sampled out"),
+            Error::Repeated => write!(f, "repeated message"),
+            Error::NoMessage => write!(f, "no message found"),
         }
     }
 }
@@ -36,7 +68,18 @@ impl StdErr for Error {
             Error::IOError(ref reason) => reason.as_str(),
             Error::SerdeParsing(ref reason) => reason.as_str(),
             Error::InternalError(ref reason) => reason.as_str(),
+            Error::ApiValidation(ref reason) => reason.as_str(),
+            Error::StrictFailure(ref reason) => reason.as_str(),
+            Error::InvalidChunkSize(_) => "chunk size out of range",
+            Error::MessageTooLarge { .. } => "too many chunks in message",
+            Error::ReservedField(ref field) => field.as_str(),
+            Error::Validation(_) => "invalid message",
+            Error::Tls(ref reason) => reason.as_str(),
             Error::InsufficientLogLevel => "insufficient log level",
+            Error::Filtered => "filtered out",
+            Error::RateLimited => "rate limited",
+            Error::Sampled => "sampled out",
+            Error::Repeated => "repeated message",
             Error::NoMessage => "no message found",
         }
     }
@@ -53,3 +96,27 @@ impl From<SerdeJSONErr> for Error {
         Error::SerdeParsing(e.to_string())
     }
 }
+
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Error {
+        Error::Validation(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_of_unit_variants() {
+        assert_eq!(
+            Error::InsufficientLogLevel.to_string(),
+            "insufficient log level"
+        );
+        assert_eq!(Error::NoMessage.to_string(), "no message found");
+        assert_eq!(
+            Error::InternalError("boom".to_string()).to_string(),
+            "[Internal] boom"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Explanation of the way records are processed
+//!
+//! Shows for every record the levels and facility detected, fields passed on as metadata
+//! and either the resulting GELF message or the reason it is dropped.
+
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+
+use crate::config::Config;
+use crate::embedded;
+use crate::processing::{self, LogRecord};
+use crate::stats::RecordStats;
+
+/// Explain processing of every line of the input into the output.
+pub fn run<R: BufRead, W: Write>(config: &Config, input: R, mut output: W) -> Result<()> {
+    let mut decoded = LogRecord::new();
+
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        writeln!(output, "record {}", n + 1)?;
+        explain(config, &line, &mut decoded, &mut output)?;
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+fn explain<W: Write>(
+    config: &Config,
+    line: &str,
+    decoded: &mut LogRecord,
+    output: &mut W,
+) -> Result<()> {
+    if let Err(e) = processing::decode(line, decoded) {
+        writeln!(output, "  dropped: {}", e)?;
+        return Ok(());
+    }
+
+    // level of JSON message takes precedence
+    let embedded_level = decoded
+        .get("MESSAGE")
+        .and_then(Value::as_str)
+        .filter(|_| config.parse_json_message)
+        .and_then(embedded::parse)
+        .and_then(|embedded| embedded.level);
+
+    match embedded_level.or_else(|| processing::get_sys_log_level(decoded)) {
+        Some(level) => writeln!(
+            output,
+            "  system level: {} (threshold {})",
+            level,
+            processing::system_threshold(processing::unit_rule(decoded, config), config)
+        )?,
+        None => writeln!(output, "  system level: none")?,
+    }
+
+    let facility = processing::get_facility(decoded);
+    match facility {
+        Some(facility) => writeln!(output, "  facility: {}", facility)?,
+        None => writeln!(output, "  facility: none")?,
+    }
+
+    let message_level = decoded
+        .get("MESSAGE")
+        .and_then(Value::as_str)
+        .and_then(processing::get_msg_log_level);
+    match (message_level, config.log_level_message) {
+        (Some(level), Some(threshold)) => writeln!(
+            output,
+            "  message level: {} (threshold {})",
+            level, threshold
+        )?,
+        (Some(level), None) => writeln!(output, "  message level: {}", level)?,
+        (None, _) => writeln!(output, "  message level: none")?,
+    }
+
+    let mut fields: Vec<&str> = decoded
+        .keys()
+        .map(|field| field.as_ref())
+        .filter(|field| processing::is_metadata(field, config))
+        .collect();
+    fields.sort_unstable();
+    if fields.is_empty() {
+        writeln!(output, "  fields: none")?;
+    } else {
+        writeln!(output, "  fields: {}", fields.join(", "))?;
+    }
+
+    match processing::transform_decoded(decoded, config, &RecordStats::default()) {
+        Ok(gelf) => {
+            write!(output, "  sent: ")?;
+            output.write_all(&gelf)?;
+            writeln!(output)?;
+        }
+        Err(Error::InsufficientLogLevel) => {
+            // message level is checked first
+            let tier = match (message_level, config.log_level_message) {
+                (Some(level), Some(threshold)) if level > threshold => "message",
+                _ => "system",
+            };
+            writeln!(output, "  dropped: {} level is below the threshold", tier)?;
+        }
+        Err(Error::Filtered) => {
+            // facility is checked first
+            let facility_selected = config.facilities.is_empty()
+                || facility.is_some_and(|facility| config.facilities.contains(&facility));
+            let reason = if facility_selected {
+                "message is not selected by regexes"
+            } else {
+                "facility is not selected"
+            };
+            writeln!(output, "  dropped: {}", reason)?;
+        }
+        Err(Error::Repeated) => writeln!(output, "  dropped: repeats the previous message")?,
+        Err(Error::Sampled) => writeln!(output, "  dropped: sampled out")?,
+        Err(Error::RateLimited) => writeln!(output, "  dropped: rate limit of the unit exceeded")?,
+        Err(e) => writeln!(output, "  dropped: {}", e)?,
+    }
+
+    decoded.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gelf::LevelSystem;
+
+    #[test]
+    fn explains_sent_and_dropped_records() {
+        let mut config = Config::for_tests();
+        config.log_level_system = LevelSystem::Warning;
+        let input = [
+            json!({"MESSAGE": "disk full", "PRIORITY": "3", "SYSLOG_FACILITY": "3", "_PID": "42"}),
+            json!({"MESSAGE": "started", "PRIORITY": "6"}),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .chain(vec![String::new(), "not a record".to_string()])
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let mut output = Vec::new();
+        run(&config, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let records: Vec<&str> = output.split("\n\n").collect();
+
+        assert!(records[0].starts_with("record 1\n"));
+        assert!(records[0].contains("  system level: error (threshold warning)\n"));
+        assert!(records[0].contains("  facility: daemon\n"));
+        assert!(records[0].contains("  fields: SYSLOG_FACILITY, _PID\n"));
+        assert!(records[0].contains("  sent: {"));
+
+        assert!(records[1].contains("  dropped: system level is below the threshold"));
+
+        // numbering follows lines of the input
+        assert!(records[2].starts_with("record 4\n  dropped: "));
+    }
+}
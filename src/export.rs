@@ -0,0 +1,233 @@
+//! Journal export format
+//!
+//! Records of `journalctl -o export` (the format also accepted by `systemd-journal-remote`)
+//! are sequences of fields terminated by an empty line. A field is either `NAME=value` line,
+//! or a binary one: name line, 64-bit little-endian size, the data and a newline.
+//! Unlike JSON output, binary data is passed as is, without escaping.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, Read};
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+use crate::processing::{read_line, Line};
+use crate::record::{self, FieldName, LogRecord};
+use crate::stats::RecordStats;
+
+/// Format of journal records read from input
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum JournalFormat {
+    Json,
+    Export,
+}
+
+impl<'a> From<&'a str> for JournalFormat {
+    fn from(format: &'a str) -> Self {
+        match format {
+            "export" => JournalFormat::Export,
+            _ => JournalFormat::Json,
+        }
+    }
+}
+
+impl JournalFormat {
+    /// Return output mode of journalctl producing records of the format.
+    pub fn journalctl_output(self) -> &'static str {
+        match self {
+            JournalFormat::Json => "json",
+            JournalFormat::Export => "export",
+        }
+    }
+}
+
+/// Reader of records in the journal export format.
+///
+/// Fields longer than the limit are dropped and counted as oversized.
+pub struct ExportReader<R> {
+    input: R,
+    max_field: usize,
+    stats: Arc<RecordStats>,
+}
+
+impl<R: BufRead> ExportReader<R> {
+    pub fn new(input: R, max_field: usize, stats: Arc<RecordStats>) -> Self {
+        ExportReader {
+            input,
+            max_field,
+            stats,
+        }
+    }
+
+    /// Read the next record, returning `None` at the end of input.
+    pub fn next_record(&mut self) -> Result<Option<LogRecord>> {
+        let mut record = LogRecord::new();
+        let mut repeated = HashSet::new();
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            match read_line(&mut self.input, &mut line, self.max_field)? {
+                Line::End => return Ok(Some(record).filter(|record| !record.is_empty())),
+
+                Line::Oversized(len) => {
+                    self.oversized(len);
+                    continue;
+                }
+
+                Line::Complete => {}
+            }
+
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            // empty line ends the record, extra ones between records are skipped
+            if line.is_empty() {
+                if record.is_empty() {
+                    continue;
+                }
+                return Ok(Some(record));
+            }
+
+            let (name, value) = match line.iter().position(|&c| c == b'=') {
+                Some(eq) => (&line[..eq], line[eq + 1..].to_vec()),
+                None => match self.read_binary()? {
+                    Some(value) => (&line[..], value),
+                    None => continue,
+                },
+            };
+
+            let name = record::intern(&String::from_utf8_lossy(name));
+            insert_field(&mut record, &mut repeated, name, field_value(value));
+        }
+    }
+
+    /// Read data of binary field, skipping it if it exceeds the limit.
+    fn read_binary(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut size = [0; 8];
+        self.input.read_exact(&mut size)?;
+        let size = u64::from_le_bytes(size);
+
+        let value = if size > self.max_field as u64 {
+            io::copy(&mut (&mut self.input).take(size), &mut io::sink())?;
+            self.oversized(size);
+            None
+        } else {
+            let mut value = vec![0; size as usize];
+            self.input.read_exact(&mut value)?;
+            Some(value)
+        };
+
+        let mut newline = [0; 1];
+        self.input.read_exact(&mut newline)?;
+        if newline[0] != b'\n' {
+            return Err(Error::InternalError(
+                "malformed journal export: binary field is not terminated by newline".to_string(),
+            ));
+        }
+
+        Ok(value)
+    }
+
+    fn oversized(&self, len: u64) {
+        warn!(
+            "journal field of {} bytes exceeds limit of {} bytes, dropped",
+            len, self.max_field
+        );
+        self.stats.record_oversized();
+    }
+}
+
+impl<R: BufRead> Iterator for ExportReader<R> {
+    type Item = Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Represent field data the way JSON output of journalctl does:
+/// text as a string, anything else as an array of bytes.
+pub(crate) fn field_value(data: Vec<u8>) -> Value {
+    match String::from_utf8(data) {
+        Ok(text) => Value::String(text),
+        Err(e) => Value::Array(e.into_bytes().into_iter().map(Value::from).collect()),
+    }
+}
+
+/// Insert field into the record, collecting values of repeated fields into an array.
+pub(crate) fn insert_field(
+    record: &mut LogRecord,
+    repeated: &mut HashSet<FieldName>,
+    name: FieldName,
+    value: Value,
+) {
+    match record.get_mut(&name) {
+        None => {
+            record.insert(name, value);
+        }
+
+        Some(Value::Array(values)) if repeated.contains(&name) => values.push(value),
+
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+            repeated.insert(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &[u8], max_field: usize) -> (Vec<LogRecord>, u64) {
+        let stats = Arc::new(RecordStats::default());
+        let records = ExportReader::new(data, max_field, stats.clone())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        (records, stats.snapshot().oversized)
+    }
+
+    #[test]
+    fn text_and_binary_fields() {
+        let mut data = b"__REALTIME_TIMESTAMP=1700000000000000\nMESSAGE\n".to_vec();
+        data.extend_from_slice(&5u64.to_le_bytes());
+        data.extend_from_slice(b"a\nb\xffc\n");
+        data.extend_from_slice(b"TAG=x\nTAG=y\n\n\nMESSAGE=second\n");
+
+        let (records, oversized) = read_all(&data, 1024);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0]["__REALTIME_TIMESTAMP"],
+            json!("1700000000000000")
+        );
+        assert_eq!(records[0]["MESSAGE"], json!([97, 10, 98, 255, 99]));
+        assert_eq!(records[0]["TAG"], json!(["x", "y"]));
+        assert_eq!(records[1]["MESSAGE"], json!("second"));
+        assert_eq!(oversized, 0);
+    }
+
+    #[test]
+    fn oversized_fields() {
+        let mut data = b"MESSAGE=kept\nLONG=0123456789abcdef\nBLOB\n".to_vec();
+        data.extend_from_slice(&17u64.to_le_bytes());
+        data.extend_from_slice(b"0123456789abcdefg\n\n");
+
+        let (records, oversized) = read_all(&data, 16);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].len(), 1);
+        assert_eq!(oversized, 2);
+    }
+
+    #[test]
+    fn truncated_binary_field() {
+        let stats = Arc::new(RecordStats::default());
+        let mut reader = ExportReader::new(&b"BLOB\n\x10\0\0\0\0\0\0\0abc"[..], 64, stats);
+        assert!(reader.next_record().is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Extraction of fields from messages
+//!
+//! Extractor is a named regex with named capture groups, run against the message
+//! of every record: values of the groups that matched become fields named by the groups,
+//! e.g. `(?P<method>GET|POST) (?P<path>\S+) (?P<status>\d{3})` for access logs.
+
+use regex::Regex;
+
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    pub name: String,
+    regex: Regex,
+}
+
+impl Extractor {
+    /// Parse extractor given as `name=regex`, the regex having at least one named group.
+    pub fn parse(spec: &str) -> Result<Extractor, String> {
+        let (name, regex) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("extractor should be given as name=regex: {}", spec))?;
+        let regex = Regex::new(regex).map_err(|e| format!("bad regex of {}: {}", name, e))?;
+
+        if regex.capture_names().all(|group| group.is_none()) {
+            return Err(format!("regex of {} has no named groups", name));
+        }
+
+        Ok(Extractor {
+            name: name.to_string(),
+            regex,
+        })
+    }
+
+    /// Return values of named groups matched in the message, along with their names.
+    pub fn extract(&self, message: &str) -> Vec<(String, String)> {
+        let captures = match self.regex.captures(message) {
+            Some(captures) => captures,
+            None => return Vec::new(),
+        };
+
+        self.regex
+            .capture_names()
+            .flatten()
+            .filter_map(|group| {
+                let value = captures.name(group)?;
+                Some((group.to_string(), value.as_str().to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_named_groups() {
+        let extractor = Extractor::parse(
+            r"req=(?P<method>GET|POST) (?P<path>\S+) (?P<status>\d{3})(?: (?P<ms>\d+)ms)?",
+        )
+        .unwrap();
+        assert_eq!(extractor.name, "req");
+
+        assert_eq!(
+            extractor.extract("10.0.0.1 GET /index.html 200"),
+            vec![
+                ("method".to_string(), "GET".to_string()),
+                ("path".to_string(), "/index.html".to_string()),
+                ("status".to_string(), "200".to_string()),
+            ]
+        );
+        assert!(extractor.extract("nothing to see").is_empty());
+
+        assert!(Extractor::parse("plain=\\d+").is_err());
+        assert!(Extractor::parse("(?P<no_name>x)").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Syslog facilities
+//!
+//! Journald keeps facility of syslog-originated records in `SYSLOG_FACILITY`
+//! as a number (specified in [RFC 5424](https://tools.ietf.org/html/rfc5424)).
+
+use std::fmt;
+
+/// Names of facilities, indexed by their numbers
+const NAMES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// Syslog facility, the kind of program which produced the record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facility(u8);
+
+impl Facility {
+    /// Get facility by its number, if it is valid.
+    pub fn from_num(facility: u8) -> Option<Facility> {
+        if (facility as usize) < NAMES.len() {
+            Some(Facility(facility))
+        } else {
+            None
+        }
+    }
+
+    /// Get facility by its name, e.g. `daemon`.
+    pub fn from_name(name: &str) -> Option<Facility> {
+        NAMES
+            .iter()
+            .position(|n| *n == name)
+            .map(|n| Facility(n as u8))
+    }
+
+    /// Return names of all facilities.
+    pub fn names() -> &'static [&'static str] {
+        &NAMES
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", NAMES[self.0 as usize])
+    }
+}
@@ -0,0 +1,190 @@
+//! Discovery of record fields
+//!
+//! Samples records of the source for a while and reports which fields
+//! they have, how often and with which values, e.g. to write field lists.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader, Read};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, RecvTimeoutError};
+use serde_json::{self, Value};
+
+use crate::errors::{Error, Result};
+
+use crate::config::LogSource;
+use crate::processing::{check_journal_supported, journalctl_failed, LogRecord};
+use crate::record::Interned;
+
+/// Number of distinct example values kept per field
+const EXAMPLES: usize = 3;
+
+/// Example values longer than that are truncated, chars
+const EXAMPLE_LENGTH: usize = 40;
+
+/// Occurrences of a single field
+#[derive(Debug, Default)]
+pub struct FieldStats {
+    pub count: u64,
+    pub examples: Vec<String>,
+}
+
+/// Fields of sampled records
+#[derive(Debug, Default)]
+pub struct FieldReport {
+    pub records: u64,
+    pub fields: HashMap<String, FieldStats>,
+}
+
+impl FieldReport {
+    /// Account fields of the record.
+    pub fn add(&mut self, record: &LogRecord) {
+        self.records += 1;
+
+        for (name, value) in record {
+            let stats = self.fields.entry(name.to_string()).or_default();
+            stats.count += 1;
+
+            if stats.examples.len() < EXAMPLES {
+                let example = example(value);
+                if !stats.examples.contains(&example) {
+                    stats.examples.push(example);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for FieldReport {
+    /// Fields are listed from the most frequent one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "records sampled: {}", self.records)?;
+
+        let mut fields: Vec<_> = self.fields.iter().collect();
+        fields.sort_by(|(a_name, a), (b_name, b)| b.count.cmp(&a.count).then(a_name.cmp(b_name)));
+
+        for (name, stats) in fields {
+            writeln!(
+                f,
+                "{:<32} {:>8} {:>5.1}%  {}",
+                name,
+                stats.count,
+                stats.count as f64 * 100.0 / self.records as f64,
+                stats.examples.join(" | ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sample records of the source for given duration, or until the source ends.
+pub fn sample(source: LogSource, duration: Duration) -> Result<FieldReport> {
+    match source {
+        LogSource::Stdin => sample_input(io::stdin(), duration),
+
+        LogSource::Journalctl => {
+            check_journal_supported()?;
+
+            let mut subprocess = process::Command::new("journalctl")
+                .args(["-o", "json", "-f"])
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::null())
+                .spawn()
+                .map_err(journalctl_failed)?;
+
+            let stdout = subprocess.stdout.take().unwrap();
+            let report = sample_input(stdout, duration);
+
+            let _ = subprocess.kill();
+            let _ = subprocess.wait();
+            report
+        }
+
+        LogSource::File | LogSource::Syslog => Err(Error::InternalError(
+            "fields are sampled for stdin and journal sources only".to_string(),
+        )),
+    }
+}
+
+/// Read records in a separate thread, since reading could block past the deadline.
+fn sample_input<R: Read + Send + 'static>(input: R, duration: Duration) -> Result<FieldReport> {
+    let (tx, rx) = bounded(1024);
+
+    thread::spawn(move || {
+        let records =
+            serde_json::Deserializer::from_reader(BufReader::new(input)).into_iter::<Interned>();
+        for record in records {
+            let end = record.is_err();
+            if tx.send(record).is_err() || end {
+                return;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + duration;
+    let mut report = FieldReport::default();
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(Interned(record))) => report.add(&record),
+            Ok(Err(e)) => return Err(Error::from(e)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(report)
+}
+
+fn example(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match text.char_indices().nth(EXAMPLE_LENGTH) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_fields_of_sampled_records() {
+        let input = format!(
+            "{}\n{}\n",
+            r#"{"MESSAGE": "hello", "_PID": 1}"#,
+            json!({"MESSAGE": "x".repeat(50)}),
+        );
+
+        let report = sample_input(io::Cursor::new(input), Duration::from_secs(5)).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.fields["MESSAGE"].count, 2);
+        assert_eq!(report.fields["_PID"].examples, vec!["1"]);
+        assert_eq!(
+            report.fields["MESSAGE"].examples[1],
+            format!("{}...", "x".repeat(40))
+        );
+
+        let text = report.to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "records sampled: 2");
+        assert!(lines[1].starts_with("MESSAGE "));
+        assert!(lines[1].contains("100.0%  hello | "));
+        assert!(lines[2].starts_with("_PID "));
+        assert!(lines[2].contains("50.0%  1"));
+    }
+
+    #[test]
+    fn fails_on_malformed_input() {
+        let input = io::Cursor::new("{\"MESSAGE\": \"hello\"}\nnot json\n");
+        assert!(sample_input(input, Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Building of validated GELF-messages
+//!
+//! Setters of `Message` accept almost anything, so mistakes surface only when Graylog
+//! drops the message. `MessageBuilder` collects the message and checks constraints of GELF
+//! on build: host and message are not empty, names of additional fields consist of letters,
+//! digits, `_`, `.` and `-`, and none of them is reserved.
+
+use std::borrow::Cow;
+use std::error::Error as StdErr;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use super::{LevelSystem, Message, RESERVED_FIELD};
+
+/// Violation of GELF constraints found on build
+#[derive(Debug, PartialEq, Clone)]
+pub enum ValidationError {
+    EmptyHost,
+    EmptyShortMessage,
+    InvalidFieldName(String),
+    ReservedField(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::EmptyHost => write!(f, "host is empty"),
+            ValidationError::EmptyShortMessage => write!(f, "short message is empty"),
+            ValidationError::InvalidFieldName(ref field) => write!(
+                f,
+                "bad field name {}, only letters, digits, _, . and - are allowed",
+                field
+            ),
+            ValidationError::ReservedField(ref field) => write!(f, "field {} is reserved", field),
+        }
+    }
+}
+
+impl StdErr for ValidationError {}
+
+/// Return whether the name is allowed by GELF for additional fields, given without leading `_`.
+pub fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.' || c == b'-')
+}
+
+/// Builder of GELF-message, validating it at once on build
+pub struct MessageBuilder<'a> {
+    host: Cow<'a, str>,
+    short_message: String,
+    full_message: Option<String>,
+    timestamp: Option<f64>,
+    level: LevelSystem,
+    metadata: Vec<(Arc<str>, Value)>,
+}
+
+impl<'a> MessageBuilder<'a> {
+    /// Start building GELF-message, level defaults to Alert as with `Message::new`.
+    pub fn new<H: Into<Cow<'a, str>>>(host: H, short_message: String) -> Self {
+        MessageBuilder {
+            host: host.into(),
+            short_message,
+            full_message: None,
+            timestamp: None,
+            level: LevelSystem::Alert,
+            metadata: Vec::new(),
+        }
+    }
+
+    pub fn full_message(mut self, msg: String) -> Self {
+        self.full_message = Some(msg);
+        self
+    }
+
+    pub fn timestamp(mut self, ts: f64) -> Self {
+        self.timestamp = Some(ts);
+        self
+    }
+
+    pub fn level(mut self, level: LevelSystem) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Add metadata field, its name is checked on build.
+    pub fn metadata<K: Into<Arc<str>>>(mut self, key: K, value: Value) -> Self {
+        self.metadata.push((key.into(), value));
+        self
+    }
+
+    /// Return the message, or the first violation of GELF constraints.
+    pub fn build(self) -> Result<Message<'a>, ValidationError> {
+        if self.host.trim().is_empty() {
+            return Err(ValidationError::EmptyHost);
+        }
+        if self.short_message.trim().is_empty() {
+            return Err(ValidationError::EmptyShortMessage);
+        }
+
+        let mut msg = Message::new(self.host, self.short_message);
+        msg.full_message = self.full_message;
+        msg.timestamp = self.timestamp;
+        msg.level = self.level;
+
+        for (key, value) in self.metadata {
+            if &*key == RESERVED_FIELD {
+                return Err(ValidationError::ReservedField(key.to_string()));
+            }
+            if !is_valid_field_name(&key) {
+                return Err(ValidationError::InvalidFieldName(key.to_string()));
+            }
+            msg.metadata.insert(key, value);
+        }
+
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_on_build() {
+        let msg = MessageBuilder::new("web-1", "started".to_string())
+            .level(LevelSystem::Informational)
+            .timestamp(1.5)
+            .metadata("unit.name", json!("nginx"))
+            .build()
+            .unwrap();
+        assert_eq!(msg.short_message(), "started");
+        assert_eq!(msg.level(), LevelSystem::Informational);
+        assert_eq!(msg.metadata("unit.name"), Some(&json!("nginx")));
+
+        let build = |host, text: &str, field: &str| {
+            MessageBuilder::new(host, text.to_string())
+                .metadata(field, json!(1))
+                .build()
+                .err()
+        };
+        assert_eq!(build(" ", "text", "f"), Some(ValidationError::EmptyHost));
+        assert_eq!(
+            build("host", "\n", "f"),
+            Some(ValidationError::EmptyShortMessage)
+        );
+        assert_eq!(
+            build("host", "text", "id"),
+            Some(ValidationError::ReservedField("id".to_string()))
+        );
+        assert_eq!(
+            build("host", "text", "bad field"),
+            Some(ValidationError::InvalidFieldName("bad field".to_string()))
+        );
+    }
+}
@@ -1,8 +1,20 @@
 use rand;
 use std::cmp;
+use std::io::IoSlice;
+
+use crate::errors::{Error, Result};
 
 /// Overhead per chunk is 12 bytes: magic(2) + id(8) + pos(1) + total (1)
-const CHUNK_OVERHEAD: u8 = 12;
+pub(super) const CHUNK_OVERHEAD: u8 = 12;
+
+/// GELF allows for a maximum of 128 chunks per message
+pub const MAX_CHUNKS: u8 = 128;
+
+/// Graylog accepts datagrams of at most 8192 bytes
+const MAX_DATAGRAM_SIZE: u16 = 8192;
+
+/// Largest chunk-size fitting a datagram along with the chunk header
+pub const MAX_CHUNK_SIZE: u16 = MAX_DATAGRAM_SIZE - CHUNK_OVERHEAD as u16;
 
 /// Chunk-size for LANs
 const CHUNK_SIZE_LAN: u16 = 8154;
@@ -11,7 +23,7 @@ const CHUNK_SIZE_LAN: u16 = 8154;
 const CHUNK_SIZE_WAN: u16 = 1420;
 
 /// Magic bytes identifying a GELF message chunk
-static MAGIC_BYTES: &[u8; 2] = b"\x1e\x0f";
+pub(super) static MAGIC_BYTES: &[u8; 2] = b"\x1e\x0f";
 
 /// ChunkSize is a value type representing the size of a message-chunk
 ///
@@ -23,6 +35,16 @@ pub enum ChunkSize {
     Custom(u16),
 }
 
+impl<'a> From<&'a str> for ChunkSize {
+    /// Parse either `lan`, `wan` or the size in bytes, defaulting to WAN.
+    fn from(size: &'a str) -> Self {
+        match size {
+            "lan" => ChunkSize::LAN,
+            size => size.parse().map_or(ChunkSize::WAN, ChunkSize::Custom),
+        }
+    }
+}
+
 impl ChunkSize {
     /// Return the size associated with the chunk-size
     pub fn size(&self) -> u16 {
@@ -47,22 +69,25 @@ impl ChunkedMessage {
     ///
     /// Several sanity checks are performed on construction:
     /// - chunk_size must be greater than 0
+    /// - chunk along with its header must fit a datagram of 8192 bytes
     /// - GELF allows for a maximum of 128 chunks per message
-    pub fn new(chunk_size: ChunkSize, message: Vec<u8>) -> Option<ChunkedMessage> {
-        if chunk_size.size() < 1 {
-            return None;
+    pub fn new(chunk_size: ChunkSize, message: Vec<u8>) -> Result<ChunkedMessage> {
+        if chunk_size.size() < 1 || chunk_size.size() > MAX_CHUNK_SIZE {
+            return Err(Error::InvalidChunkSize(chunk_size.size()));
         }
 
-        // Ceiled integer division with (a + b - 1) / b
-        // Calculate with 64bit integers to avoid overflow - maybe replace with checked_*?
+        // Calculate with 64bit integers to avoid overflow
         let size = chunk_size.size() as u64;
-        let num_chunks = (message.len() as u64 + size as u64 - 1) / size;
+        let num_chunks = (message.len() as u64).div_ceil(size);
 
-        if num_chunks > 128 {
-            return None;
+        if num_chunks > MAX_CHUNKS as u64 {
+            return Err(Error::MessageTooLarge {
+                size: message.len(),
+                chunks: num_chunks,
+            });
         }
 
-        Some(ChunkedMessage {
+        Ok(ChunkedMessage {
             chunk_size,
             payload: message,
             id: ChunkedMessageId::random(),
@@ -70,6 +95,22 @@ impl ChunkedMessage {
         })
     }
 
+    /// Construct a message sent as a single chunk of any size,
+    /// e.g. over stream transports which neither need nor allow chunking
+    pub fn whole(message: Vec<u8>) -> ChunkedMessage {
+        ChunkedMessage {
+            chunk_size: ChunkSize::LAN,
+            payload: message,
+            id: ChunkedMessageId::random(),
+            num_chunks: 1,
+        }
+    }
+
+    /// Return the message payload
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
     /// Return total byte-length of the chunked message including overhead
     pub fn len(&self) -> u64 {
         if self.num_chunks > 1 {
@@ -84,68 +125,143 @@ impl ChunkedMessage {
         self.len() == 0
     }
 
-    /// Return an iterator over all chunks of the message
-    pub fn iter(&self) -> ChunkedMessageIterator {
-        ChunkedMessageIterator::new(self)
+    /// Return an iterator over all chunks of the message, each assembled into a new buffer
+    pub fn iter(&self) -> ChunkedMessageIterator<'_> {
+        ChunkedMessageIterator {
+            chunks: self.chunks(),
+        }
+    }
+
+    /// Return an iterator over all chunks of the message, borrowing their payload
+    pub fn chunks(&self) -> Chunks<'_> {
+        let mut header = [0; CHUNK_OVERHEAD as usize];
+
+        // The chunk header is only required when the message size exceeds one chunk
+        let header_len = if self.num_chunks > 1 {
+            // Chunk binary layout:
+            //  2 bytes (magic bytes)
+            //  8 bytes (message id)
+            //  1 byte  (chunk number)
+            //  1 byte  (total amount of chunks in this message)
+            //  n bytes (chunk payload)
+            header[0..2].copy_from_slice(MAGIC_BYTES);
+            header[2..10].copy_from_slice(self.id.as_bytes());
+            header[11] = self.num_chunks;
+            header.len()
+        } else {
+            0
+        };
+
+        Chunks {
+            message: self,
+            chunk_num: 0,
+            header,
+            header_len,
+        }
     }
 }
 
-/// An iterator over all a chunked message's chunks
+/// Single chunk of a message: header, if required, and a slice of the payload
+pub struct Chunk<'a> {
+    header: [u8; CHUNK_OVERHEAD as usize],
+    header_len: usize,
+    payload: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    /// Return the chunk header, empty for messages fitting into one chunk
+    pub fn header(&self) -> &[u8] {
+        &self.header[..self.header_len]
+    }
+
+    /// Return the chunk payload
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Return total byte-length of the chunk
+    pub fn len(&self) -> usize {
+        self.header_len + self.payload.len()
+    }
+
+    /// Return if chunk is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return header and payload as slices for vectored IO
+    pub fn io_slices(&self) -> [IoSlice<'_>; 2] {
+        [IoSlice::new(self.header()), IoSlice::new(self.payload)]
+    }
+
+    /// Assemble the chunk into a new buffer
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(self.len());
+        chunk.extend_from_slice(self.header());
+        chunk.extend_from_slice(self.payload);
+        chunk
+    }
+}
+
+/// An iterator over all a chunked message's chunks, borrowing their payload
 ///
 /// This always be constructed by `ChunkedMessage`
-pub struct ChunkedMessageIterator<'a> {
+pub struct Chunks<'a> {
     chunk_num: u8,
     message: &'a ChunkedMessage,
+    // header common for all chunks, except for the chunk number
+    header: [u8; CHUNK_OVERHEAD as usize],
+    header_len: usize,
 }
 
-impl<'a> ChunkedMessageIterator<'a> {
-    /// Create a new ChunkedMessageIterator
-    fn new(msg: &'a ChunkedMessage) -> ChunkedMessageIterator {
-        ChunkedMessageIterator {
-            message: msg,
-            chunk_num: 0,
-        }
-    }
-}
-
-impl<'a> Iterator for ChunkedMessageIterator<'a> {
-    type Item = Vec<u8>;
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Chunk<'a>;
 
     /// Returns the next chunk
-    fn next(&mut self) -> Option<Vec<u8>> {
+    fn next(&mut self) -> Option<Chunk<'a>> {
         if self.chunk_num >= self.message.num_chunks {
             return None;
         }
 
-        let mut chunk = Vec::new();
-
-        // Set the chunks boundaries
+        // Set the chunks boundaries, the only chunk carries the whole payload
         let chunk_size = self.message.chunk_size.size();
         let slice_start = (self.chunk_num as u32 * chunk_size as u32) as usize;
-        let slice_end = cmp::min(
-            slice_start + chunk_size as usize,
-            self.message.payload.len(),
-        );
-
-        // The chunk header is only required when the message size exceeds one chunk
-        if self.message.num_chunks > 1 {
-            // Chunk binary layout:
-            //  2 bytes (magic bytes)
-            //  8 bytes (message id)
-            //  1 byte  (chunk number)
-            //  1 byte  (total amount of chunks in this message)
-            //  n bytes (chunk payload)
-            chunk.extend(MAGIC_BYTES.iter());
-            chunk.extend(self.message.id.as_bytes());
-            chunk.push(self.chunk_num);
-            chunk.push(self.message.num_chunks);
-        }
+        let slice_end = if self.message.num_chunks == 1 {
+            self.message.payload.len()
+        } else {
+            cmp::min(
+                slice_start + chunk_size as usize,
+                self.message.payload.len(),
+            )
+        };
 
-        chunk.extend(self.message.payload[slice_start..slice_end].iter());
+        // only the chunk number differs between headers
+        let mut header = self.header;
+        header[10] = self.chunk_num;
 
         self.chunk_num += 1;
 
-        Some(chunk)
+        Some(Chunk {
+            header,
+            header_len: self.header_len,
+            payload: &self.message.payload[slice_start..slice_end],
+        })
+    }
+}
+
+/// An iterator over all a chunked message's chunks
+///
+/// This always be constructed by `ChunkedMessage`
+pub struct ChunkedMessageIterator<'a> {
+    chunks: Chunks<'a>,
+}
+
+impl<'a> Iterator for ChunkedMessageIterator<'a> {
+    type Item = Vec<u8>;
+
+    /// Returns the next chunk
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.chunks.next().map(|chunk| chunk.to_vec())
     }
 }
 
@@ -156,7 +272,7 @@ impl<'a> Iterator for ChunkedMessageIterator<'a> {
 struct ChunkedMessageId([u8; 8]);
 
 #[allow(dead_code)]
-impl<'a> ChunkedMessageId {
+impl ChunkedMessageId {
     /// Create a new, random ChunkedMessageId.
     fn random() -> ChunkedMessageId {
         let mut bytes = [0; 8];
@@ -213,7 +329,7 @@ mod tests {
         ];
 
         for raw_id in raw_ids {
-            let id = ChunkedMessageId::from_bytes(raw_id.clone());
+            let id = ChunkedMessageId::from_bytes(*raw_id);
             assert_eq!(id.as_bytes(), raw_id);
         }
     }
@@ -237,12 +353,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn chunk_size_from_str() {
+        assert_eq!(ChunkSize::from("wan").size(), CHUNK_SIZE_WAN);
+        assert_eq!(ChunkSize::from("lan").size(), CHUNK_SIZE_LAN);
+        assert_eq!(ChunkSize::from("1200").size(), 1200);
+    }
+
     #[test]
     #[should_panic]
     fn fail_too_many_chunks() {
         ChunkedMessage::new(ChunkSize::Custom(1), get_data(129)).unwrap();
     }
 
+    #[test]
+    fn chunk_limits_errors() {
+        match ChunkedMessage::new(ChunkSize::Custom(2), get_data(257)) {
+            Err(Error::MessageTooLarge { size, chunks }) => assert_eq!((size, chunks), (257, 129)),
+            _ => panic!("message of 129 chunks accepted"),
+        }
+
+        match ChunkedMessage::new(ChunkSize::Custom(8181), get_data(1)) {
+            Err(Error::InvalidChunkSize(size)) => assert_eq!(size, 8181),
+            _ => panic!("chunk exceeding datagram accepted"),
+        }
+
+        assert!(ChunkedMessage::new(ChunkSize::Custom(0), get_data(1)).is_err());
+        assert!(ChunkedMessage::new(ChunkSize::Custom(MAX_CHUNK_SIZE), get_data(1)).is_ok());
+        assert!(ChunkedMessage::new(ChunkSize::LAN, get_data(1)).is_ok());
+    }
+
     #[test]
     fn chunk_message_len() {
         let msg_1_chunk = ChunkedMessage::new(ChunkSize::Custom(1), get_data(1)).unwrap();
@@ -251,10 +391,7 @@ mod tests {
 
         assert_eq!(msg_1_chunk.len(), 1);
         assert_eq!(msg_2_chunks.len() as u32, 2 + 2 * CHUNK_OVERHEAD as u32);
-        assert_eq!(
-            msg_128_chunks.len() as u64,
-            128 + 128 * (CHUNK_OVERHEAD as u64)
-        );
+        assert_eq!(msg_128_chunks.len(), 128 + 128 * (CHUNK_OVERHEAD as u64));
     }
 
     #[test]
@@ -305,6 +442,38 @@ mod tests {
         assert_eq!(chunk[99], 99);
     }
 
+    #[test]
+    fn chunk_message_borrowed_chunks() {
+        let msg = ChunkedMessage::new(ChunkSize::Custom(33), get_data(100)).unwrap();
+        let assembled: Vec<Vec<u8>> = msg.iter().collect();
+        let borrowed: Vec<Vec<u8>> = msg.chunks().map(|chunk| chunk.to_vec()).collect();
+        assert_eq!(assembled, borrowed);
+
+        let chunk = msg.chunks().last().unwrap();
+        assert_eq!(chunk.header().len(), CHUNK_OVERHEAD as usize);
+        assert_eq!(chunk.payload(), &[99]);
+        assert_eq!(chunk.len(), CHUNK_OVERHEAD as usize + 1);
+
+        let msg = ChunkedMessage::new(ChunkSize::Custom(100), get_data(100)).unwrap();
+        let chunk = msg.chunks().next().unwrap();
+        assert!(chunk.header().is_empty());
+        assert_eq!(chunk.io_slices()[1].len(), 100);
+    }
+
+    #[test]
+    fn chunk_headers_differ_by_number() {
+        let msg = ChunkedMessage::new(ChunkSize::Custom(10), get_data(35)).unwrap();
+        let headers: Vec<Vec<u8>> = msg.chunks().map(|chunk| chunk.header().to_vec()).collect();
+        assert_eq!(headers.len(), 4);
+
+        for (n, header) in headers.iter().enumerate() {
+            assert_eq!(&header[0..2], MAGIC_BYTES);
+            assert_eq!(&header[2..10], &headers[0][2..10]);
+            assert_eq!(header[10], n as u8);
+            assert_eq!(header[11], 4);
+        }
+    }
+
     #[test]
     fn chunk_large_message_chunking() {
         // 100k of msg
@@ -313,7 +482,7 @@ mod tests {
 
     fn chunking(chunk_size: u16, msg_size: u32) {
         check_chunks(
-            chunk_size as u16,
+            chunk_size,
             msg_size,
             (msg_size / chunk_size as u32) as u8 + 1,
         );
@@ -337,7 +506,7 @@ mod tests {
     fn check_chunks(chunk_size: u16, msg_size: u32, expected_chunk_count: u8) {
         let msg_data = get_data(msg_size as usize);
         let msg_data_clone = msg_data.clone();
-        let msg = ChunkedMessage::new(ChunkSize::Custom(chunk_size as u16), msg_data).unwrap();
+        let msg = ChunkedMessage::new(ChunkSize::Custom(chunk_size), msg_data).unwrap();
         let mut counter: u8 = 0;
         for chunk in msg.iter() {
             println!("{:?}", chunk);
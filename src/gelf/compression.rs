@@ -5,7 +5,11 @@ use libflate::gzip;
 use libflate::zlib;
 
 use super::wire_message::WireMessage;
-use errors::Result;
+use crate::errors::Result;
+
+/// Level of zstd compression, the default one of the library
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
 
 /// MessageCompression represents all possible compression algorithms in GELF.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -13,6 +17,8 @@ pub enum MessageCompression {
     None,
     Gzip,
     Zlib,
+    #[cfg(feature = "zstd")]
+    Zstd,
 }
 
 impl<'a> From<&'a str> for MessageCompression {
@@ -20,23 +26,30 @@ impl<'a> From<&'a str> for MessageCompression {
         match algorithm {
             "gzip" => MessageCompression::Gzip,
             "zlib" => MessageCompression::Zlib,
+            #[cfg(feature = "zstd")]
+            "zstd" => MessageCompression::Zstd,
             _ => MessageCompression::None,
         }
     }
 }
 
-impl MessageCompression {
+impl Default for MessageCompression {
     /// Return the default compression algorithm.
-    pub fn default() -> MessageCompression {
+    fn default() -> MessageCompression {
         MessageCompression::Gzip
     }
+}
 
+impl MessageCompression {
     /// Compress a serialized message with the defined algorithm.
     pub fn compress(&self, message: &WireMessage) -> Result<Vec<u8>> {
-        let json = message.to_gelf()?;
+        self.compress_bytes(message.to_gelf()?.into_bytes())
+    }
 
+    /// Compress an already serialized message with the defined algorithm.
+    pub fn compress_bytes(&self, json: Vec<u8>) -> Result<Vec<u8>> {
         let compressed = match *self {
-            MessageCompression::None => json.into_bytes(),
+            MessageCompression::None => json,
 
             MessageCompression::Gzip => {
                 let mut cursor = io::Cursor::new(json);
@@ -51,10 +64,66 @@ impl MessageCompression {
                 io::copy(&mut cursor, &mut encoder)?;
                 encoder.finish().into_result()?
             }
+
+            #[cfg(feature = "zstd")]
+            MessageCompression::Zstd => zstd::bulk::compress(&json, ZSTD_LEVEL)?,
         };
 
         Ok(compressed)
     }
+
+    /// Return whether messages compressed with the algorithm could be concatenated into
+    /// a stream or a file, which is then decompressed as a whole, like zstd frames are.
+    pub fn is_streamable(self) -> bool {
+        match self {
+            #[cfg(feature = "zstd")]
+            MessageCompression::Zstd => true,
+            _ => false,
+        }
+    }
+
+    /// Detect compression of a message by its leading bytes.
+    pub fn detect(message: &[u8]) -> MessageCompression {
+        match message {
+            [0x1f, 0x8b, ..] => MessageCompression::Gzip,
+            #[cfg(feature = "zstd")]
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => MessageCompression::Zstd,
+            // zlib header is a multiple of 31 with deflate method
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31) =>
+            {
+                MessageCompression::Zlib
+            }
+            _ => MessageCompression::None,
+        }
+    }
+
+    /// Decompress a message compressed with the defined algorithm.
+    pub fn decompress(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+
+        match *self {
+            MessageCompression::None => decompressed.extend_from_slice(message),
+
+            MessageCompression::Gzip => {
+                let mut decoder = gzip::Decoder::new(message)?;
+                io::copy(&mut decoder, &mut decompressed)?;
+            }
+
+            MessageCompression::Zlib => {
+                let mut decoder = zlib::Decoder::new(message)?;
+                io::copy(&mut decoder, &mut decompressed)?;
+            }
+
+            #[cfg(feature = "zstd")]
+            MessageCompression::Zstd => {
+                let mut decoder = zstd::Decoder::new(message)?;
+                io::copy(&mut decoder, &mut decompressed)?;
+            }
+        }
+
+        Ok(decompressed)
+    }
 }
 
 impl fmt::Display for MessageCompression {
@@ -63,6 +132,33 @@ impl fmt::Display for MessageCompression {
             MessageCompression::None => write!(f, "none"),
             MessageCompression::Gzip => write!(f, "gzip"),
             MessageCompression::Zlib => write!(f, "zlib"),
+            #[cfg(feature = "zstd")]
+            MessageCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let json = br#"{"version":"1.1","host":"h","short_message":"hello hello hello"}"#.to_vec();
+
+        let algorithms = [
+            MessageCompression::None,
+            MessageCompression::Gzip,
+            MessageCompression::Zlib,
+            #[cfg(feature = "zstd")]
+            MessageCompression::Zstd,
+        ];
+
+        for algorithm in algorithms {
+            let compressed = algorithm.compress_bytes(json.clone()).unwrap();
+            let detected = MessageCompression::detect(&compressed);
+            assert_eq!(detected, algorithm);
+            assert_eq!(detected.decompress(&compressed).unwrap(), json);
         }
     }
 }
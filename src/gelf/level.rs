@@ -46,9 +46,9 @@ impl LevelSystem {
     }
 }
 
-impl Into<u8> for LevelSystem {
-    fn into(self) -> u8 {
-        self.to_num()
+impl From<LevelSystem> for u8 {
+    fn from(level: LevelSystem) -> u8 {
+        level.to_num()
     }
 }
 
@@ -104,7 +104,7 @@ impl<'a> From<&'a str> for LevelMsg {
             "fatal" => LevelMsg::Fatal,
             "panic" => LevelMsg::Panic,
             "error" => LevelMsg::Error,
-            "warning" => LevelMsg::Warning,
+            "warning" | "warn" => LevelMsg::Warning,
             "info" => LevelMsg::Info,
             _ => LevelMsg::Debug,
         }
@@ -1,28 +1,43 @@
+mod builder;
 mod chunked_message;
 mod compression;
 mod level;
+mod reassembler;
 mod wire_message;
 
-pub use self::chunked_message::{ChunkSize, ChunkedMessage};
+pub use self::builder::{is_valid_field_name, MessageBuilder, ValidationError};
+pub use self::chunked_message::{Chunk, ChunkSize, ChunkedMessage, MAX_CHUNKS, MAX_CHUNK_SIZE};
 pub use self::compression::MessageCompression;
 pub use self::level::{LevelMsg, LevelSystem};
+pub use self::reassembler::Reassembler;
+pub(crate) use self::wire_message::current_time_unix;
 pub use self::wire_message::{OptFieldsIterator, WireMessage};
 
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::errors::{Error, Result};
+
+/// Metadata field reserved by GELF, as `_id` would clash with the message id of Graylog
+pub const RESERVED_FIELD: &str = "id";
 
 /// Message is the representation of a GELF message.
 ///
 /// `Message` provides a fluid setter and getter interface to all of GELF's
-/// features.
+/// features. Messages validated against GELF constraints are built with `MessageBuilder`.
+///
+/// Host is either borrowed, e.g. from the decoded record, or owned: `Message<'static>`
+/// could be built in one thread and sent in another.
 pub struct Message<'a> {
-    host: &'a str,
+    host: Cow<'a, str>,
     short_message: String,
     full_message: Option<String>,
     timestamp: Option<f64>,
     level: LevelSystem,
 
-    metadata: HashMap<String, Value>,
+    metadata: HashMap<Arc<str>, Value>,
 }
 
 impl<'a> Message<'a> {
@@ -31,9 +46,9 @@ impl<'a> Message<'a> {
     /// All fields will use their defaults. This means usually Option::None.
     /// A notable exception is `level`. The GELF spec requires this field to
     /// default to Alert.
-    pub fn new(host: &'a str, short_message: String) -> Self {
+    pub fn new<H: Into<Cow<'a, str>>>(host: H, short_message: String) -> Self {
         Message {
-            host,
+            host: host.into(),
             short_message,
             full_message: None,
             timestamp: None, // if not set - will be added during serialization
@@ -42,6 +57,23 @@ impl<'a> Message<'a> {
         }
     }
 
+    /// Return the `host`
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Return the message owning its host, so that it does not borrow anything
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            host: Cow::Owned(self.host.into_owned()),
+            short_message: self.short_message,
+            full_message: self.full_message,
+            timestamp: self.timestamp,
+            level: self.level,
+            metadata: self.metadata,
+        }
+    }
+
     /// Return the `short_message`
     pub fn short_message(&self) -> &str {
         &self.short_message
@@ -104,19 +136,58 @@ impl<'a> Message<'a> {
     }
 
     /// Return all metadata
-    //pub fn all_metadata(&self) -> &HashMap<&'a str, String> {
-    pub fn all_metadata(&self) -> &HashMap<String, Value> {
+    pub fn all_metadata(&self) -> &HashMap<Arc<str>, Value> {
         &self.metadata
     }
 
+    /// Return values of all metadata for modification
+    pub fn metadata_values_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+        self.metadata.values_mut()
+    }
+
     /// Set a metadata field with given key to value
-    pub fn set_metadata(&mut self, key: String, value: Value) -> Option<&mut Self> {
-        if key == "id" {
-            // prohibited ?
-            return None;
+    ///
+    /// Reserved key `id` is rejected.
+    pub fn set_metadata<K: Into<Arc<str>>>(&mut self, key: K, value: Value) -> Result<&mut Self> {
+        let key = key.into();
+        if &*key == RESERVED_FIELD {
+            return Err(Error::ReservedField(key.to_string()));
         }
 
         self.metadata.insert(key, value);
-        Some(self)
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn owned_message_crosses_threads() {
+        let (sender, receiver) = mpsc::channel::<Message<'static>>();
+        let printer = thread::spawn(move || {
+            receiver
+                .iter()
+                .map(|msg| WireMessage::new(msg, OptFieldsIterator::new(&[])).to_gelf())
+                .collect::<Result<Vec<_>>>()
+                .unwrap()
+        });
+
+        let mut owned = Message::new(String::from("web-1"), "owned".to_string());
+        owned.set_timestamp(1.0);
+        sender.send(owned).unwrap();
+
+        let host = String::from("web-2");
+        let mut borrowed = Message::new(host.as_str(), "borrowed".to_string());
+        borrowed.set_timestamp(1.0);
+        sender.send(borrowed.into_owned()).unwrap();
+        drop(sender);
+
+        let sent = printer.join().unwrap();
+        assert!(sent[0].contains(r#""host":"web-1""#));
+        assert!(sent[1].contains(r#""host":"web-2""#));
     }
 }
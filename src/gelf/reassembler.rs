@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use super::chunked_message::{CHUNK_OVERHEAD, MAGIC_BYTES, MAX_CHUNKS};
+use crate::errors::{Error, Result};
+
+/// Reassembler collects chunks of GELF messages back into whole messages
+///
+/// Datagrams without chunk header are messages on their own, while chunks
+/// are kept by message id until all of them are received, in any order.
+#[derive(Default)]
+pub struct Reassembler {
+    partial: HashMap<[u8; 8], PartialMessage>,
+}
+
+/// Chunks of a message received so far
+struct PartialMessage {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+impl Reassembler {
+    /// Construct a new Reassembler
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    /// Take a datagram, returning payload of the message if it is complete now.
+    ///
+    /// Payload is still compressed, if compression was used.
+    pub fn push(&mut self, datagram: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !datagram.starts_with(MAGIC_BYTES) {
+            return Ok(Some(datagram.to_vec()));
+        }
+
+        if datagram.len() < CHUNK_OVERHEAD as usize {
+            return Err(Error::InternalError("truncated chunk header".to_string()));
+        }
+
+        let mut id = [0; 8];
+        id.copy_from_slice(&datagram[2..10]);
+        let number = datagram[10] as usize;
+        let total = datagram[11] as usize;
+
+        if total == 0 || total > MAX_CHUNKS as usize || number >= total {
+            return Err(Error::InternalError(format!(
+                "bad chunk number {} of {}",
+                number, total
+            )));
+        }
+
+        let partial = self.partial.entry(id).or_insert_with(|| PartialMessage {
+            chunks: vec![None; total],
+            received: 0,
+        });
+
+        if partial.chunks.len() != total {
+            return Err(Error::InternalError(
+                "total number of chunks changed within message".to_string(),
+            ));
+        }
+
+        // duplicates replace the chunk received before
+        if partial.chunks[number].is_none() {
+            partial.received += 1;
+        }
+        partial.chunks[number] = Some(datagram[CHUNK_OVERHEAD as usize..].to_vec());
+
+        if partial.received < total {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&id).unwrap();
+        Ok(Some(
+            partial.chunks.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// Return number of messages still lacking some chunks
+    pub fn incomplete(&self) -> usize {
+        self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ChunkSize, ChunkedMessage};
+    use super::*;
+
+    #[test]
+    fn reassemble_chunks_in_any_order() {
+        let payload: Vec<u8> = (0..50).collect();
+        let message = ChunkedMessage::new(ChunkSize::Custom(7), payload.clone()).unwrap();
+        let mut chunks: Vec<Vec<u8>> = message.iter().collect();
+        chunks.reverse();
+
+        let mut reassembler = Reassembler::new();
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(reassembler.push(chunk).unwrap(), None);
+        }
+        assert_eq!(reassembler.incomplete(), 1);
+
+        assert_eq!(reassembler.push(last).unwrap(), Some(payload));
+        assert_eq!(reassembler.incomplete(), 0);
+    }
+
+    #[test]
+    fn pass_unchunked_message() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(b"{}").unwrap(), Some(b"{}".to_vec()));
+    }
+}
@@ -2,10 +2,11 @@ use serde;
 use serde::ser::SerializeMap;
 use serde_json;
 
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::{ChunkSize, ChunkedMessage, Message, MessageCompression};
-use errors::{Error, Result};
+use crate::errors::Result;
 
 const GELF_VERSION: &str = "1.1";
 
@@ -30,14 +31,26 @@ impl<'a> WireMessage<'a> {
         }
     }
 
+    /// Return the message for modification, e.g. to shrink it before serializing again
+    pub fn message_mut(&mut self) -> &mut Message<'a> {
+        &mut self.message
+    }
+
     /// Return a GELF/JSON string of this message
     pub fn to_gelf(&self) -> Result<String> {
         Ok(serde_json::to_string(self)?)
     }
 
+    /// Serialize this message to GELF/JSON into a buffer of given initial capacity
+    pub fn to_gelf_bytes(&self, capacity: usize) -> Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(capacity);
+        serde_json::to_writer(&mut buffer, self)?;
+        Ok(buffer)
+    }
+
     /// Return a compressed GELF/JSON string of this message
     pub fn to_compressed_gelf(&self, compression: MessageCompression) -> Result<Vec<u8>> {
-        compression.compress(&self)
+        compression.compress(self)
     }
 
     /// Serialize the messages and prepare it for chunking
@@ -47,12 +60,7 @@ impl<'a> WireMessage<'a> {
         compression: MessageCompression,
     ) -> Result<ChunkedMessage> {
         let msg = self.to_compressed_gelf(compression)?;
-        ChunkedMessage::new(chunk_size, msg).ok_or_else(|| {
-            Error::InternalError(format!(
-                "failed to split message on {}-bytes chunks",
-                chunk_size.size()
-            ))
-        })
+        ChunkedMessage::new(chunk_size, msg)
     }
 }
 
@@ -63,16 +71,12 @@ impl<'a> serde::Serialize for WireMessage<'a> {
         S: serde::Serializer,
     {
         let mut map = serializer.serialize_map(None)?;
-        let trimmed_symbols: &[_] = &['"', ' '];
 
         map.serialize_entry("version", GELF_VERSION)?;
 
-        map.serialize_entry("host", self.message.host.trim_matches(trimmed_symbols))?;
+        map.serialize_entry("host", self.message.host.trim())?;
 
-        map.serialize_entry(
-            "short_message",
-            self.message.short_message().trim_matches(trimmed_symbols),
-        )?;
+        map.serialize_entry("short_message", self.message.short_message().trim())?;
 
         let level = self.message.level as u8;
         map.serialize_entry("level", &level)?;
@@ -88,13 +92,12 @@ impl<'a> serde::Serialize for WireMessage<'a> {
             map.serialize_value(&current_time_unix())?;
         }
 
-        for (k, v) in self.optional.clone().into_iter() {
+        for (k, v) in self.optional.clone() {
             map.serialize_entry(k, v)?;
         }
 
         for (key, value) in self.message.all_metadata().iter() {
-            let key = "_".to_string() + key;
-            map.serialize_entry(&key, value)?;
+            map.serialize_entry(&format_args!("_{}", key), value)?;
         }
 
         map.end()
@@ -127,10 +130,24 @@ impl<'a> Iterator for OptFieldsIterator<'a> {
 }
 
 /// Return current UNIX-timestamp as a seconds
-#[inline]
-fn current_time_unix() -> f64 {
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("system clock failed");
-    ts.as_secs() as f64 + ts.subsec_nanos() as f64 / 1_000_000_000_f64
+///
+/// If system clock is set before UNIX epoch, the last known good time
+/// (or 0, if there is none) is returned instead.
+pub(crate) fn current_time_unix() -> f64 {
+    static LAST_GOOD: AtomicU64 = AtomicU64::new(0);
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(ts) => {
+            let now = ts.as_secs() as f64 + ts.subsec_nanos() as f64 / 1_000_000_000_f64;
+            LAST_GOOD.store(now.to_bits(), Ordering::Relaxed);
+            now
+        }
+        Err(_) => {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                warn!("system clock is set before UNIX epoch, using the last known good time");
+            }
+            f64::from_bits(LAST_GOOD.load(Ordering::Relaxed))
+        }
+    }
 }
@@ -0,0 +1,167 @@
+//! Synthetic load generator
+//!
+//! Fabricates journald-like records with a bounded number of distinct units
+//! and feeds them into the pipeline at a given rate, so that targets and
+//! queue settings could be tried out without real logs.
+
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::{self, Rng, ThreadRng};
+
+use crate::errors::Result;
+
+use crate::config::SharedConfig;
+use crate::pipeline::Pipeline;
+use crate::stats::SendStats;
+
+/// Typical priorities of system logs, most of them informational
+static PRIORITIES: [&str; 10] = ["6", "6", "6", "6", "6", "7", "7", "5", "4", "3"];
+
+static WORDS: [&str; 12] = [
+    "request",
+    "handled",
+    "connection",
+    "closed",
+    "user",
+    "session",
+    "timeout",
+    "cache",
+    "started",
+    "stopped",
+    "retrying",
+    "failed",
+];
+
+pub struct Generator {
+    rng: ThreadRng,
+    cardinality: usize,
+    boot_id: String,
+    generated: u64,
+}
+
+impl Generator {
+    /// Create generator of records coming from `cardinality` distinct units.
+    pub fn new(cardinality: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let boot_id = format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>());
+
+        Generator {
+            rng,
+            cardinality: cardinality.max(1),
+            boot_id,
+            generated: 0,
+        }
+    }
+
+    /// Return next record serialized to JSON.
+    pub fn record(&mut self) -> String {
+        let unit = self.rng.gen_range(0, self.cardinality);
+        let words = self.rng.gen_range(3, 40);
+        let message = (0..words)
+            .map(|_| *self.rng.choose(&WORDS).unwrap())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        self.generated += 1;
+
+        json!({
+            "__CURSOR": format!("s={};i={:x}", self.boot_id, self.generated),
+            "__REALTIME_TIMESTAMP": format!("{}", now.as_micros()),
+            "_BOOT_ID": self.boot_id,
+            "_HOSTNAME": "generator",
+            "PRIORITY": *self.rng.choose(&PRIORITIES).unwrap(),
+            "_PID": format!("{}", 1000 + unit),
+            "SYSLOG_IDENTIFIER": format!("service-{}", unit),
+            "_SYSTEMD_UNIT": format!("service-{}.service", unit),
+            "MESSAGE": format!("level=info {} #{}", message, self.generated),
+        })
+        .to_string()
+    }
+}
+
+/// Feed generated records into the pipeline at given rate (0 - as fast as possible),
+/// until `count` records are generated, if it is set.
+pub fn run(
+    config: SharedConfig,
+    stats: SendStats,
+    rate: u64,
+    cardinality: usize,
+    count: Option<u64>,
+) -> Result<()> {
+    let pipeline = Pipeline::start(config, &stats)?;
+    let mut generator = Generator::new(cardinality);
+
+    info!(
+        "generating records at rate {} msgs/sec from {} units",
+        rate, cardinality
+    );
+
+    let started = Instant::now();
+    let mut generated: u64 = 0;
+    while count.is_none_or(|count| generated < count) {
+        // keep pace with the schedule rather than sleeping after every record
+        if rate > 0 {
+            let due = started + Duration::from_secs_f64(generated as f64 / rate as f64);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        pipeline.push(generator.record())?;
+        generated += 1;
+
+        if pipeline.limit_reached() {
+            break;
+        }
+    }
+
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    use serde_json::Value;
+
+    use crate::config::Config;
+    use crate::gelf::LevelSystem;
+
+    #[test]
+    fn records_of_bounded_units() {
+        let mut generator = Generator::new(3);
+        let records: Vec<Value> = (0..100)
+            .map(|_| serde_json::from_str(&generator.record()).unwrap())
+            .collect();
+
+        let units: HashSet<&str> = records
+            .iter()
+            .map(|record| record["_SYSTEMD_UNIT"].as_str().unwrap())
+            .collect();
+        assert!(units.len() <= 3);
+
+        let cursors: HashSet<&str> = records
+            .iter()
+            .map(|record| record["__CURSOR"].as_str().unwrap())
+            .collect();
+        assert_eq!(cursors.len(), 100);
+    }
+
+    #[test]
+    fn stops_after_count() {
+        let mut config = Config::for_tests();
+        config.dry_run = true;
+        config.log_level_system = LevelSystem::Debug;
+        let stats = SendStats::new();
+
+        run(SharedConfig::new(config), stats.clone(), 0, 4, Some(50)).unwrap();
+        assert_eq!(stats.records().snapshot().accepted, 50);
+    }
+}
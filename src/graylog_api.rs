@@ -0,0 +1,219 @@
+//! Startup validation of the target against Graylog REST API
+//!
+//! Verifies that the port messages are going to be sent to belongs to a running
+//! GELF UDP input, and not e.g. to the Graylog web interface.
+
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{self, Value};
+use ureq;
+
+use crate::errors::{Error, Result};
+
+const INPUT_STATES_PATH: &str = "/api/system/inputstates";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Check that Graylog, accessible with given API URL and token, has
+/// a running GELF UDP input on the port of `target` address.
+pub fn check_gelf_input(api_url: &str, token: Option<&str>, target: &str) -> Result<()> {
+    let target_port = target
+        .to_socket_addrs()?
+        .next()
+        .map(|addr| addr.port())
+        .ok_or_else(|| Error::ApiValidation(format!("cannot resolve target {}", target)))?;
+
+    let states = fetch_input_states(api_url, token)?;
+
+    let input = states.iter().find(|state| {
+        let input = &state["message_input"];
+        is_gelf_udp(input) && input["attributes"]["port"].as_u64() == Some(target_port as u64)
+    });
+
+    match input {
+        Some(state) => {
+            let title = state["message_input"]["title"]
+                .as_str()
+                .unwrap_or("untitled");
+            let status = state["state"].as_str().unwrap_or("UNKNOWN");
+
+            if status != "RUNNING" {
+                return Err(Error::ApiValidation(format!(
+                    "GELF UDP input '{}' on port {} is not running (state: {})",
+                    title, target_port, status
+                )));
+            }
+
+            debug!(
+                "Graylog GELF UDP input '{}' found on port {}",
+                title, target_port
+            );
+            Ok(())
+        }
+
+        None if api_port(api_url) == Some(target_port) => Err(Error::ApiValidation(format!(
+            "target port {} belongs to Graylog web interface/API, not to a GELF input",
+            target_port
+        ))),
+
+        None => {
+            let available: Vec<String> = states
+                .iter()
+                .map(|state| &state["message_input"])
+                .filter(|input| is_gelf_udp(input))
+                .filter_map(|input| input["attributes"]["port"].as_u64())
+                .map(|port| port.to_string())
+                .collect();
+
+            Err(Error::ApiValidation(format!(
+                "no GELF UDP input found on port {} (available GELF UDP ports: {})",
+                target_port,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )))
+        }
+    }
+}
+
+fn fetch_input_states(api_url: &str, token: Option<&str>) -> Result<Vec<Value>> {
+    let base = api_url.trim_end_matches('/').trim_end_matches("/api");
+    let url = format!("{}{}", base, INPUT_STATES_PATH);
+
+    let mut request = ureq::get(&url)
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .set("Accept", "application/json");
+
+    // Graylog access tokens are passed as a username with a fixed password
+    if let Some(token) = token {
+        let credentials = BASE64.encode(format!("{}:token", token));
+        request = request.set("Authorization", &format!("Basic {}", credentials));
+    }
+
+    let body = request
+        .call()
+        .map_err(|e| Error::ApiValidation(format!("request to {} failed: {}", url, e)))?
+        .into_string()?;
+
+    let mut decoded: Value = serde_json::from_str(&body)?;
+    match decoded["states"].take() {
+        Value::Array(states) => Ok(states),
+        _ => Err(Error::ApiValidation(
+            "unexpected response: no input states found".to_string(),
+        )),
+    }
+}
+
+fn is_gelf_udp(input: &Value) -> bool {
+    input["type"]
+        .as_str()
+        .map(|t| t.ends_with("GELFUDPInput"))
+        .unwrap_or(false)
+}
+
+/// Return port of API URL, either explicit or the scheme's default one.
+fn api_port(api_url: &str) -> Option<u16> {
+    let (scheme, rest) = api_url.split_at(api_url.find("://")?);
+    let authority = rest[3..].split('/').next()?;
+
+    match authority.rsplit(':').next().and_then(|p| p.parse().ok()) {
+        Some(port) => Some(port),
+        None if scheme == "https" => Some(443),
+        None => Some(80),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer `requests` requests with input states, returning API URL and headers of the requests.
+    fn serve(requests: usize) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/", listener.local_addr().unwrap());
+        let body = json!({"states": [
+            {"state": "RUNNING", "message_input": {
+                "title": "gelf", "type": "org.graylog2.inputs.gelf.udp.GELFUDPInput",
+                "attributes": {"port": 12201}}},
+            {"state": "FAILED", "message_input": {
+                "title": "broken", "type": "org.graylog2.inputs.gelf.udp.GELFUDPInput",
+                "attributes": {"port": 12202}}},
+            {"state": "RUNNING", "message_input": {
+                "title": "syslog", "type": "org.graylog2.inputs.syslog.udp.SyslogUDPInput",
+                "attributes": {"port": 514}}}
+        ]})
+        .to_string();
+
+        let server = thread::spawn(move || {
+            (0..requests)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut head = String::new();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        head.push_str(&line);
+                    }
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                    .unwrap();
+                    head
+                })
+                .collect()
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn checks_gelf_input_of_target_port() {
+        let (url, server) = serve(4);
+        let port = url.split(':').nth(2).unwrap().trim_end_matches("/api/");
+
+        assert!(check_gelf_input(&url, Some("secret"), "127.0.0.1:12201").is_ok());
+
+        let error = check_gelf_input(&url, None, "127.0.0.1:12202").unwrap_err();
+        assert!(error.to_string().contains("is not running (state: FAILED)"));
+
+        let error = check_gelf_input(&url, None, "127.0.0.1:514").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("available GELF UDP ports: 12201, 12202"));
+
+        let target = format!("127.0.0.1:{}", port);
+        let error = check_gelf_input(&url, None, &target).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("belongs to Graylog web interface"));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /api/system/inputstates "));
+        // token is passed as user name with `token` password
+        let credentials = BASE64.encode("secret:token");
+        assert!(requests[0].contains(&format!("Basic {}", credentials)));
+        assert!(!requests[1].contains("Basic"));
+    }
+
+    #[test]
+    fn api_port_defaults_to_scheme() {
+        assert_eq!(api_port("http://graylog:9000/api"), Some(9000));
+        assert_eq!(api_port("https://graylog/api"), Some(443));
+        assert_eq!(api_port("http://graylog"), Some(80));
+        assert_eq!(api_port("graylog:9000"), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Health endpoint
+//!
+//! Running instance could serve plain HTTP for liveness probes and operators:
+//! * `GET /healthz` - `200 ok` while the instance is forwarding, `503` with the reason otherwise;
+//! * `GET /status` - JSON object with statistics, time of the last successful send,
+//!   hash of the current config and state of the input subprocess, i.e. journalctl.
+//!
+//! Instance is considered unhealthy once journalctl has exited,
+//! or when every target failed since its last successful send;
+//! with several pipelines, once any of them is unhealthy.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::config::SharedConfig;
+use crate::errors::Result;
+use crate::stats::SendStats;
+
+/// Time to wait for the request and to write the response
+const IO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximal number of request header lines read before responding
+const MAX_HEADERS: usize = 100;
+
+/// Start serving requests on the address in a separate thread.
+pub fn serve(addr: &str, config: SharedConfig, stats: SendStats) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("health endpoint is listening on {}", addr);
+
+    thread::Builder::new()
+        .name("health".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if let Err(e) = stream.and_then(|stream| respond(stream, &config, &stats)) {
+                    debug!("health endpoint client failed: {}", e);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, config: &SharedConfig, stats: &SendStats) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // headers are of no interest, but the client expects them to be read
+    let mut header = String::new();
+    for _ in 0..MAX_HEADERS {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/healthz")) => match problem(stats) {
+            None => ("200 OK", "text/plain", "ok\n".to_string()),
+            Some(problem) => (
+                "503 Service Unavailable",
+                "text/plain",
+                format!("{}\n", problem),
+            ),
+        },
+        (Some("GET"), Some("/status")) => (
+            "200 OK",
+            "application/json",
+            format!("{}\n", status(config, stats)),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Return reason the instance is not forwarding, if any.
+fn problem(stats: &SendStats) -> Option<String> {
+    for (name, pipeline) in stats.pipelines() {
+        if let Some(problem) = problem(&pipeline) {
+            return Some(format!("pipeline {}: {}", name, problem));
+        }
+    }
+
+    if let Some(subprocess) = stats.subprocess() {
+        if let Some(exit) = subprocess.exit {
+            return Some(format!("{} exited with {}", subprocess.name, exit));
+        }
+    }
+
+    let targets = stats.to_json()["targets"].as_object().cloned()?;
+    let failing = targets.values().all(|target| {
+        let failed_at = target["last_error"]["timestamp"].as_u64();
+        let sent_at = target["last_success"].as_u64();
+        failed_at.is_some() && failed_at >= sent_at
+    });
+
+    if !targets.is_empty() && failing {
+        return Some("every target is failing".to_string());
+    }
+
+    None
+}
+
+/// Return statistics along with state of the instance.
+fn status(config: &SharedConfig, stats: &SendStats) -> Value {
+    let mut status = stats.to_json();
+
+    let last_success = status["targets"].as_object().and_then(|targets| {
+        targets
+            .values()
+            .filter_map(|target| target["last_success"].as_u64())
+            .max()
+    });
+    status["last_success"] = json!(last_success);
+
+    // config has no canonical form, but its dump is stable within the process
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", config.load()).hash(&mut hasher);
+    status["config_hash"] = json!(format!("{:016x}", hasher.finish()));
+
+    status["subprocess"] = stats
+        .subprocess()
+        .map_or(Value::Null, |subprocess| subprocess.to_json());
+    status["healthy"] = json!(problem(stats).is_none());
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use crate::config::Config;
+
+    /// Send request line to the endpoint, returning status line and body of the response.
+    fn get(addr: &str, request: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{}\r\nHost: localhost\r\n\r\n", request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn reports_failing_targets() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let stats = SendStats::new();
+        stats.target("graylog:12201");
+        serve(&addr, SharedConfig::new(Config::for_tests()), stats.clone()).unwrap();
+
+        let (status, body) = get(&addr, "GET /healthz HTTP/1.1");
+        assert_eq!(
+            (status.as_str(), body.as_str()),
+            ("HTTP/1.1 200 OK", "ok\n")
+        );
+
+        stats
+            .target("graylog:12201")
+            .chunk_failed(&"connection refused");
+        let (status, body) = get(&addr, "GET /healthz HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(body, "every target is failing\n");
+
+        let (_, body) = get(&addr, "GET /status HTTP/1.1");
+        let status: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status["healthy"], json!(false));
+        assert_eq!(status["last_success"], Value::Null);
+
+        assert_eq!(
+            get(&addr, "GET /metrics HTTP/1.1").0,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(
+            get(&addr, "POST /healthz HTTP/1.1").0,
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+    }
+
+    #[test]
+    fn reports_failing_pipeline() {
+        let stats = SendStats::new();
+        stats.pipeline("1").target("graylog:12201").message_sent();
+        let failing = stats.pipeline("2");
+        failing.subprocess_started("journalctl", 42);
+        assert_eq!(problem(&stats), None);
+
+        failing.subprocess_exited("exit status: 1");
+        assert_eq!(
+            problem(&stats).unwrap(),
+            "pipeline 2: journalctl exited with exit status: 1"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Fields ignored by name
+//!
+//! Journald attaches plenty of metadata to every record, e.g. `_CAP_EFFECTIVE` or `_AUDIT_SESSION`,
+//! which is rarely worth storing. Patterns of ignored fields are either exact names,
+//! globs with wildcards `*` and `?`, e.g. `_AUDIT_*`, or regexes between slashes, e.g. `/^_UDEV_/`.
+
+use regex::Regex;
+
+/// Fields taken into the message itself, never sent as additional ones
+pub const CONSUMED_FIELDS: [&str; 4] = ["MESSAGE", "_HOSTNAME", "__REALTIME_TIMESTAMP", "PRIORITY"];
+
+/// Fields ignored unless they are kept explicitly
+pub const DEFAULT_IGNORED_FIELDS: [&str; 5] = [
+    "__CURSOR",
+    "_BOOT_ID",
+    "_MACHINE_ID",
+    "_SYSTEMD_CGROUP",
+    "_SYSTEMD_SLICE",
+];
+
+#[derive(Debug, Clone)]
+pub enum FieldPattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl FieldPattern {
+    pub fn parse(pattern: &str) -> Result<FieldPattern, String> {
+        if pattern.is_empty() {
+            return Err(String::from("empty field pattern"));
+        }
+
+        if let Some(regex) = pattern
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            return Regex::new(regex)
+                .map(FieldPattern::Regex)
+                .map_err(|e| format!("bad regex {}: {}", pattern, e));
+        }
+
+        if !pattern.contains(['*', '?']) {
+            return Ok(FieldPattern::Exact(pattern.to_string()));
+        }
+
+        let mut regex = String::from("^");
+        for c in pattern.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Regex::new(&regex)
+            .map(FieldPattern::Regex)
+            .map_err(|e| format!("bad pattern {}: {}", pattern, e))
+    }
+
+    pub fn matches(&self, field: &str) -> bool {
+        match *self {
+            FieldPattern::Exact(ref name) => name == field,
+            FieldPattern::Regex(ref regex) => regex.is_match(field),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_kinds() {
+        let exact = FieldPattern::parse("_CAP_EFFECTIVE").unwrap();
+        assert!(exact.matches("_CAP_EFFECTIVE"));
+        assert!(!exact.matches("_CAP_EFFECTIVE_2"));
+
+        let glob = FieldPattern::parse("_AUDIT_*").unwrap();
+        assert!(glob.matches("_AUDIT_SESSION"));
+        assert!(!glob.matches("X_AUDIT_SESSION"));
+
+        let regex = FieldPattern::parse("/^_UDEV_/").unwrap();
+        assert!(regex.matches("_UDEV_SYSNAME"));
+        assert!(!regex.matches("UDEV"));
+
+        assert!(FieldPattern::parse("/(/").is_err());
+        assert!(FieldPattern::parse("").is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Native journal reader
+//!
+//! Journal is read directly through libsystemd, so that neither a journalctl binary
+//! nor parsing of its output is required. The library is loaded at runtime,
+//! so the forwarder builds and runs on hosts without it, falling back to journalctl.
+//!
+//! Like `journalctl -f`, reading starts with the last few entries and then follows new ones.
+//! Like `journalctl -u`, reading could be limited to records of given units.
+
+use std::collections::HashSet;
+use std::ffi::{c_void, CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+use crate::errors::{Error, Result};
+use crate::export::{field_value, insert_field};
+use crate::record::{self, LogRecord};
+use crate::stats::RecordStats;
+
+/// Number of past entries read on start, as journalctl does
+const PAST_ENTRIES: u64 = 10;
+
+/// Open only journal files generated on the local machine
+const SD_JOURNAL_LOCAL_ONLY: c_int = 1;
+
+/// Wait for changes of the journal without timeout
+const WAIT_FOREVER: u64 = u64::MAX;
+
+#[repr(C)]
+struct SdJournal {
+    _private: [u8; 0],
+}
+
+type Handle = *mut SdJournal;
+
+type OpenFn = unsafe extern "C" fn(*mut Handle, c_int) -> c_int;
+type CallFn = unsafe extern "C" fn(Handle) -> c_int;
+type TextFn = unsafe extern "C" fn(Handle, *const c_char) -> c_int;
+type ProcFn = unsafe extern "C" fn(Handle);
+type CountFn = unsafe extern "C" fn(Handle, u64) -> c_int;
+type ThresholdFn = unsafe extern "C" fn(Handle, usize) -> c_int;
+type DataFn = unsafe extern "C" fn(Handle, *mut *const c_void, *mut usize) -> c_int;
+type RealtimeFn = unsafe extern "C" fn(Handle, *mut u64) -> c_int;
+type MonotonicFn = unsafe extern "C" fn(Handle, *mut u64, *mut [u8; 16]) -> c_int;
+type CursorFn = unsafe extern "C" fn(Handle, *mut *mut c_char) -> c_int;
+type MatchFn = unsafe extern "C" fn(Handle, *const c_void, usize) -> c_int;
+
+/// Functions of libsystemd used to read the journal
+struct Library {
+    open: OpenFn,
+    close: ProcFn,
+    seek_tail: CallFn,
+    seek_cursor: TextFn,
+    test_cursor: TextFn,
+    previous: CallFn,
+    previous_skip: CountFn,
+    next: CallFn,
+    wait: CountFn,
+    set_data_threshold: ThresholdFn,
+    restart_data: ProcFn,
+    enumerate_data: DataFn,
+    get_realtime_usec: RealtimeFn,
+    get_monotonic_usec: MonotonicFn,
+    get_cursor: CursorFn,
+    add_match: MatchFn,
+    add_disjunction: CallFn,
+}
+
+lazy_static! {
+    static ref LIBSYSTEMD: std::result::Result<Library, String> = load_library();
+}
+
+/// Resolve symbol of the loaded library into a function pointer of the field's type.
+macro_rules! symbol {
+    ($handle:expr, $name:expr, $type:ty) => {{
+        let name = concat!($name, "\0");
+        let symbol = libc::dlsym($handle, name.as_ptr() as *const c_char);
+        if symbol.is_null() {
+            return Err(format!("symbol {} not found in libsystemd", $name));
+        }
+        std::mem::transmute::<*mut c_void, $type>(symbol)
+    }};
+}
+
+fn load_library() -> std::result::Result<Library, String> {
+    // library is never unloaded, so that resolved functions stay valid
+    unsafe {
+        let handle = libc::dlopen(
+            b"libsystemd.so.0\0".as_ptr() as *const c_char,
+            libc::RTLD_NOW,
+        );
+        if handle.is_null() {
+            let reason = libc::dlerror();
+            return Err(if reason.is_null() {
+                "failed to load libsystemd".to_string()
+            } else {
+                CStr::from_ptr(reason).to_string_lossy().into_owned()
+            });
+        }
+
+        Ok(Library {
+            open: symbol!(handle, "sd_journal_open", OpenFn),
+            close: symbol!(handle, "sd_journal_close", ProcFn),
+            seek_tail: symbol!(handle, "sd_journal_seek_tail", CallFn),
+            seek_cursor: symbol!(handle, "sd_journal_seek_cursor", TextFn),
+            test_cursor: symbol!(handle, "sd_journal_test_cursor", TextFn),
+            previous: symbol!(handle, "sd_journal_previous", CallFn),
+            previous_skip: symbol!(handle, "sd_journal_previous_skip", CountFn),
+            next: symbol!(handle, "sd_journal_next", CallFn),
+            wait: symbol!(handle, "sd_journal_wait", CountFn),
+            set_data_threshold: symbol!(handle, "sd_journal_set_data_threshold", ThresholdFn),
+            restart_data: symbol!(handle, "sd_journal_restart_data", ProcFn),
+            enumerate_data: symbol!(handle, "sd_journal_enumerate_data", DataFn),
+            get_realtime_usec: symbol!(handle, "sd_journal_get_realtime_usec", RealtimeFn),
+            get_monotonic_usec: symbol!(handle, "sd_journal_get_monotonic_usec", MonotonicFn),
+            get_cursor: symbol!(handle, "sd_journal_get_cursor", CursorFn),
+            add_match: symbol!(handle, "sd_journal_add_match", MatchFn),
+            add_disjunction: symbol!(handle, "sd_journal_add_disjunction", CallFn),
+        })
+    }
+}
+
+/// Convert return code of libsystemd, which is a negative errno on failure.
+fn check(code: c_int) -> io::Result<c_int> {
+    if code < 0 {
+        Err(io::Error::from_raw_os_error(-code))
+    } else {
+        Ok(code)
+    }
+}
+
+/// Journal opened for reading, following new entries.
+///
+/// Journal must be read from the thread which opened it.
+/// Fields longer than the limit are dropped and counted as oversized.
+pub struct Journal {
+    library: &'static Library,
+    handle: Handle,
+    max_field: usize,
+    stats: Arc<RecordStats>,
+}
+
+impl Journal {
+    /// Open journal of the local machine, positioned right after the given cursor,
+    /// or before the last few entries without it.
+    ///
+    /// Given units, only their records are read.
+    pub fn open(
+        max_field: usize,
+        stats: Arc<RecordStats>,
+        after: Option<&str>,
+        units: &[String],
+    ) -> Result<Self> {
+        let library = LIBSYSTEMD
+            .as_ref()
+            .map_err(|e| Error::InternalError(e.clone()))?;
+
+        let mut handle = ptr::null_mut();
+        unsafe { check((library.open)(&mut handle, SD_JOURNAL_LOCAL_ONLY)) }?;
+
+        let journal = Journal {
+            library,
+            handle,
+            max_field,
+            stats,
+        };
+
+        unsafe {
+            // fields are never truncated, oversized ones are dropped instead
+            check((library.set_data_threshold)(handle, 0))?;
+        }
+
+        for unit in units {
+            journal.match_unit(unit)?;
+        }
+
+        match after {
+            Some(cursor) => journal.seek_after(cursor)?,
+            None => unsafe {
+                // tail is past the last entry, reading then steps forward onto the first of them
+                check((library.seek_tail)(handle))?;
+                check((library.previous_skip)(handle, PAST_ENTRIES + 1))?;
+            },
+        }
+
+        Ok(journal)
+    }
+
+    /// Add unit to units records are read of.
+    fn match_unit(&self, unit: &str) -> Result<()> {
+        let field = unit_match(unit);
+
+        unsafe {
+            check((self.library.add_match)(
+                self.handle,
+                field.as_ptr() as *const c_void,
+                field.len(),
+            ))?;
+            check((self.library.add_disjunction)(self.handle))?;
+        }
+
+        Ok(())
+    }
+
+    /// Position journal so that the next entry is the one following the cursor.
+    fn seek_after(&self, cursor: &str) -> Result<()> {
+        let cursor = CString::new(cursor)
+            .map_err(|_| Error::InternalError(format!("invalid journal cursor {:?}", cursor)))?;
+
+        unsafe {
+            check((self.library.seek_cursor)(self.handle, cursor.as_ptr()))?;
+
+            // seeking lands at the closest entry, if the cursor's one is gone it is not skipped
+            if check((self.library.next)(self.handle))? > 0
+                && check((self.library.test_cursor)(self.handle, cursor.as_ptr()))? == 0
+            {
+                check((self.library.previous)(self.handle))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the next entry, waiting for it if required.
+    pub fn next_record(&mut self) -> Result<LogRecord> {
+        loop {
+            if unsafe { check((self.library.next)(self.handle)) }? > 0 {
+                return self.read_entry();
+            }
+
+            unsafe { check((self.library.wait)(self.handle, WAIT_FOREVER)) }?;
+        }
+    }
+
+    /// Read fields of the current entry, along with the address fields journalctl adds.
+    fn read_entry(&mut self) -> Result<LogRecord> {
+        let library = self.library;
+        let mut record = LogRecord::new();
+        let mut repeated = HashSet::new();
+
+        unsafe {
+            (library.restart_data)(self.handle);
+
+            let mut data = ptr::null();
+            let mut len = 0;
+            while check((library.enumerate_data)(self.handle, &mut data, &mut len))? > 0 {
+                let field = slice::from_raw_parts(data as *const u8, len);
+                let eq = match field.iter().position(|&c| c == b'=') {
+                    Some(eq) => eq,
+                    None => continue,
+                };
+
+                if len - eq - 1 > self.max_field {
+                    warn!(
+                        "journal field of {} bytes exceeds limit of {} bytes, dropped",
+                        len - eq - 1,
+                        self.max_field
+                    );
+                    self.stats.record_oversized();
+                    continue;
+                }
+
+                let name = record::intern(&String::from_utf8_lossy(&field[..eq]));
+                let value = field_value(field[eq + 1..].to_vec());
+                insert_field(&mut record, &mut repeated, name, value);
+            }
+
+            let mut realtime = 0;
+            check((library.get_realtime_usec)(self.handle, &mut realtime))?;
+            record.insert(
+                record::intern("__REALTIME_TIMESTAMP"),
+                json!(realtime.to_string()),
+            );
+
+            // boot id is one of the fields already
+            let mut monotonic = 0;
+            let mut boot_id = [0; 16];
+            check((library.get_monotonic_usec)(
+                self.handle,
+                &mut monotonic,
+                &mut boot_id,
+            ))?;
+            record.insert(
+                record::intern("__MONOTONIC_TIMESTAMP"),
+                json!(monotonic.to_string()),
+            );
+
+            let mut cursor = ptr::null_mut();
+            check((library.get_cursor)(self.handle, &mut cursor))?;
+            let text = CStr::from_ptr(cursor).to_string_lossy().into_owned();
+            libc::free(cursor as *mut c_void);
+            record.insert(record::intern("__CURSOR"), json!(text));
+        }
+
+        Ok(record)
+    }
+}
+
+/// Return match of records of the unit, with `.service` suffix if it has none, as journalctl does.
+fn unit_match(unit: &str) -> String {
+    if unit.contains('.') {
+        format!("_SYSTEMD_UNIT={}", unit)
+    } else {
+        format!("_SYSTEMD_UNIT={}.service", unit)
+    }
+}
+
+impl Iterator for Journal {
+    type Item = Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_record())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        unsafe { (self.library.close)(self.handle) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_matched_as_journalctl_does() {
+        assert_eq!(unit_match("nginx"), "_SYSTEMD_UNIT=nginx.service");
+        assert_eq!(
+            unit_match("logrotate.timer"),
+            "_SYSTEMD_UNIT=logrotate.timer"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_cursor() {
+        // hosts without libsystemd fall back to journalctl
+        if LIBSYSTEMD.is_err() {
+            return;
+        }
+
+        let stats = Arc::new(RecordStats::default());
+        let opened = Journal::open(1024, stats.clone(), Some("s=1\0"), &["nginx".to_string()]);
+        assert!(opened.is_err());
+        // cursor is passed to libsystemd, which rejects it
+        let opened = Journal::open(1024, stats, Some("garbage"), &["nginx".to_string()]);
+        assert!(opened.is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Output of messages into Kafka
+//!
+//! When Graylog ingests from Kafka, messages are produced to a topic as GELF JSON
+//! instead of being sent to a GELF input. Messages are keyed by a field, `host` by default,
+//! so that messages of one host land in one partition and keep their order.
+//! Delivery is acknowledged asynchronously, failed deliveries are only logged.
+
+use std::time::Duration;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use rdkafka::ClientContext;
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+use crate::transport::{KafkaOptions, Transport};
+
+/// Time to wait for the queue of the producer to free up, when it is full
+const QUEUE_FULL_WAIT: Duration = Duration::from_millis(100);
+
+/// Time to wait for queued messages to be delivered on exit
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Context of the producer, logging failed deliveries
+struct DeliveryLog;
+
+impl ClientContext for DeliveryLog {}
+
+impl ProducerContext for DeliveryLog {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult, _: Self::DeliveryOpaque) {
+        if let Err((ref e, _)) = *result {
+            warn!("message not delivered to kafka: {}", e);
+        }
+    }
+}
+
+/// Producer of GELF JSON messages to a Kafka topic
+pub struct KafkaOutput {
+    producer: BaseProducer<DeliveryLog>,
+    topic: String,
+    key: Option<String>,
+}
+
+impl KafkaOutput {
+    /// Create producer connecting to the brokers, connection itself is established in background.
+    pub fn new(options: &KafkaOptions) -> Result<Self> {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", options.brokers.join(","));
+        for (name, value) in &options.properties {
+            client.set(name, value);
+        }
+
+        let producer = client
+            .create_with_context(DeliveryLog)
+            .map_err(|e| Error::InternalError(format!("kafka producer: {}", e)))?;
+
+        Ok(KafkaOutput {
+            producer,
+            topic: options.topic.clone(),
+            key: options.key.clone(),
+        })
+    }
+}
+
+impl Transport for KafkaOutput {
+    fn send(&mut self, payload: &[u8]) -> Result<()> {
+        let key = self
+            .key
+            .as_ref()
+            .and_then(|field| message_key(payload, field));
+
+        let mut waited = false;
+        loop {
+            let mut record = BaseRecord::to(&self.topic).payload(payload);
+            if let Some(ref key) = key {
+                record = record.key(key.as_str());
+            }
+
+            match self.producer.send(record) {
+                Ok(()) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) if !waited => {
+                    // serve deliveries, giving room for the message
+                    self.producer.poll(QUEUE_FULL_WAIT);
+                    waited = true;
+                }
+                Err((e, _)) => return Err(Error::InternalError(format!("kafka: {}", e))),
+            }
+        }
+
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+}
+
+impl Drop for KafkaOutput {
+    fn drop(&mut self) {
+        if let Err(e) = self.producer.flush(FLUSH_TIMEOUT) {
+            warn!("messages queued for kafka are not delivered: {}", e);
+        }
+    }
+}
+
+/// Return value of the field of serialized GELF message, keying the message.
+fn message_key(payload: &[u8], field: &str) -> Option<String> {
+    let msg: Value = serde_json::from_slice(payload).ok()?;
+
+    match msg.get(field)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_by_field() {
+        let payload = br#"{"host":"web-1","level":6,"_unit":null}"#;
+
+        assert_eq!(message_key(payload, "host"), Some("web-1".to_string()));
+        assert_eq!(message_key(payload, "level"), Some("6".to_string()));
+        assert_eq!(message_key(payload, "_unit"), None);
+        assert_eq!(message_key(payload, "_missing"), None);
+    }
+}
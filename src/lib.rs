@@ -1,23 +1,84 @@
+extern crate arc_swap;
+extern crate base64;
 extern crate clap;
+extern crate crossbeam_channel;
+extern crate libc;
 extern crate libflate;
 extern crate loggerv;
 extern crate rand;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
 extern crate regex;
+extern crate rustls;
 extern crate serde;
-extern crate serde_json;
+#[cfg(feature = "simd")]
+extern crate simd_json;
+extern crate socket2;
+#[cfg(feature = "async")]
+extern crate tokio;
+extern crate ureq;
+extern crate webpki_roots;
 
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate serde_json;
 
+pub mod admin;
+pub mod backoff;
+pub mod bench;
+pub mod capture;
+pub mod chaos;
 pub mod config;
+pub mod cursor;
+pub mod decode;
+pub mod dedup;
+pub mod embedded;
 pub mod errors;
+pub mod explain;
+pub mod export;
+pub mod extract;
+pub mod facility;
+pub mod fields;
 pub mod gelf;
+pub mod generator;
+pub mod graylog_api;
+pub mod health;
+pub mod ignore;
+#[cfg(feature = "journald-native")]
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod logfmt;
+pub mod multiline;
+pub mod oneshot;
+pub mod output;
+pub mod oversize;
+pub mod pipeline;
+pub mod preview;
 pub mod processing;
+#[cfg(feature = "async")]
+pub mod processing_async;
+pub mod ratelimit;
+pub mod record;
+pub mod redact;
+pub mod replay;
+pub mod resolver;
+pub mod sampling;
+pub mod signal;
+pub mod spool;
+pub mod stats;
+pub mod syslog;
+pub mod tail;
+pub mod tls;
+pub mod transport;
+pub mod unit_rules;
 
-pub use gelf::ChunkedMessage;
-pub use gelf::Message;
-pub use gelf::MessageCompression;
-pub use gelf::WireMessage;
-pub use gelf::{LevelMsg, LevelSystem};
+pub use crate::gelf::ChunkedMessage;
+pub use crate::gelf::Message;
+pub use crate::gelf::MessageBuilder;
+pub use crate::gelf::MessageCompression;
+pub use crate::gelf::WireMessage;
+pub use crate::gelf::{LevelMsg, LevelSystem};
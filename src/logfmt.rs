@@ -0,0 +1,104 @@
+//! Parsing of logfmt messages
+//!
+//! Structured loggers often write messages as `key=value` pairs, e.g.
+//! `level=error msg="connection refused" user_id=42`. Values are either bare words
+//! or quoted strings with backslash escapes. Message is taken as logfmt only when
+//! it consists of such pairs entirely, so that ordinary text is never split into fields.
+
+/// Key of the pair holding the message text itself
+pub const MESSAGE_KEY: &str = "msg";
+
+/// Split message into pairs of keys and values, if it is logfmt.
+pub fn parse(text: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let (key, after_key) = rest.split_once('=')?;
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == '"') {
+            return None;
+        }
+
+        let (value, after_value) = match after_key.strip_prefix('"') {
+            Some(quoted) => parse_quoted(quoted)?,
+            None => {
+                let end = after_key
+                    .find(char::is_whitespace)
+                    .unwrap_or(after_key.len());
+                if after_key[..end].contains('"') {
+                    return None;
+                }
+                (after_key[..end].to_string(), &after_key[end..])
+            }
+        };
+
+        // pairs are separated by whitespace
+        if !after_value.is_empty() && !after_value.starts_with(char::is_whitespace) {
+            return None;
+        }
+
+        pairs.push((key.to_string(), value));
+        rest = after_value.trim_start();
+    }
+
+    if pairs.is_empty() {
+        None
+    } else {
+        Some(pairs)
+    }
+}
+
+/// Parse quoted value up to the closing quote, returning it along with the rest of the text.
+fn parse_quoted(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+
+    // closing quote is missing
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn logfmt_detection() {
+        assert_eq!(
+            parse(r#"level=error msg="boom \"here\"" user_id=42 empty="#),
+            owned(&[
+                ("level", "error"),
+                ("msg", r#"boom "here""#),
+                ("user_id", "42"),
+                ("empty", ""),
+            ])
+        );
+
+        assert_eq!(parse("Started nginx.service"), None);
+        assert_eq!(parse("retrying, attempt=3"), None);
+        assert_eq!(parse(r#"msg="unterminated"#), None);
+        assert_eq!(parse(r#"msg="glued"next=1"#), None);
+        assert_eq!(parse(""), None);
+    }
+}
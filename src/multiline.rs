@@ -0,0 +1,137 @@
+//! Merging of multi-line messages
+//!
+//! Applications writing stack traces to the journal produce a record per line.
+//! The assembler holds a record until the next one starting a message, i.e. matching
+//! the start pattern, and merges messages of the held records in between: the first line
+//! stays the message, while all lines together become the full message.
+//!
+//! Only records of the same systemd unit are merged, so that interleaved records
+//! of other services do not end up in the middle of a trace.
+
+use std::time::Duration;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::processing::LogRecord;
+use crate::ratelimit::UNIT_FIELD;
+use crate::record;
+
+/// Field of the record passed as full message of GELF-message
+pub const FULL_MESSAGE_FIELD: &str = "FULL_MESSAGE";
+
+/// Maximal number of lines merged into a message, the next one starts another message
+const MAX_LINES: usize = 1000;
+
+/// Settings of merging
+#[derive(Debug, Clone)]
+pub struct Multiline {
+    /// Pattern of the first line of a message
+    pub start: Regex,
+    /// Time to wait for the next line before the held message is passed on
+    pub timeout: Duration,
+}
+
+/// Record waiting for its continuation lines
+struct Held {
+    record: LogRecord,
+    lines: Vec<String>,
+}
+
+/// Merges continuation lines into the records they belong to
+pub struct Assembler {
+    start: Regex,
+    held: Option<Held>,
+}
+
+impl Assembler {
+    pub fn new(multiline: &Multiline) -> Self {
+        Assembler {
+            start: multiline.start.clone(),
+            held: None,
+        }
+    }
+
+    /// Take the next record, returning the previous one if it is complete.
+    pub fn push(&mut self, mut record: LogRecord) -> Option<LogRecord> {
+        if let Some(ref mut held) = self.held {
+            let continuation = match record.get("MESSAGE").and_then(Value::as_str) {
+                Some(line) => {
+                    !self.start.is_match(line)
+                        && held.lines.len() < MAX_LINES
+                        && unit(&record) == unit(&held.record)
+                }
+                None => false,
+            };
+
+            if continuation {
+                if let Some(Value::String(line)) = record.remove("MESSAGE") {
+                    held.lines.push(line);
+                }
+                return None;
+            }
+        }
+
+        let lines = match record.get("MESSAGE").and_then(Value::as_str) {
+            Some(line) => vec![line.to_string()],
+            None => Vec::new(),
+        };
+        let completed = self.flush();
+        self.held = Some(Held { record, lines });
+        completed
+    }
+
+    /// Return the held record with all its lines merged, if any.
+    pub fn flush(&mut self) -> Option<LogRecord> {
+        let Held { mut record, lines } = self.held.take()?;
+
+        if lines.len() > 1 {
+            record.insert(
+                record::intern(FULL_MESSAGE_FIELD),
+                Value::String(lines.join("\n")),
+            );
+        }
+
+        Some(record)
+    }
+}
+
+fn unit(record: &LogRecord) -> Option<&str> {
+    record.get(UNIT_FIELD).and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str, unit: &str) -> LogRecord {
+        let mut record = LogRecord::new();
+        record.insert(record::intern("MESSAGE"), json!(message));
+        record.insert(record::intern(UNIT_FIELD), json!(unit));
+        record
+    }
+
+    #[test]
+    fn merges_stack_trace() {
+        let mut assembler = Assembler::new(&Multiline {
+            start: Regex::new(r"^\S").unwrap(),
+            timeout: Duration::from_secs(1),
+        });
+
+        assert!(assembler.push(record("Exception: boom", "app")).is_none());
+        assert!(assembler.push(record("  at Main.run", "app")).is_none());
+        assert!(assembler.push(record("  at Main.main", "app")).is_none());
+
+        let trace = assembler.push(record("  unrelated", "other")).unwrap();
+        assert_eq!(trace["MESSAGE"], json!("Exception: boom"));
+        assert_eq!(
+            trace[FULL_MESSAGE_FIELD],
+            json!("Exception: boom\n  at Main.run\n  at Main.main")
+        );
+
+        let single = assembler.flush().unwrap();
+        assert_eq!(single["MESSAGE"], json!("  unrelated"));
+        assert!(!single.contains_key(FULL_MESSAGE_FIELD));
+        assert!(assembler.flush().is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! One-shot sending of a single message
+//!
+//! Used by scripts and cron jobs which emit an occasional event,
+//! rather than forward a stream of logs, and to check connectivity.
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::slice;
+use std::time::{Duration, Instant};
+
+use crate::errors::{Error, Result};
+
+use crate::config::Config;
+use crate::gelf::{
+    ChunkedMessage, LevelSystem, Message, MessageBuilder, OptFieldsIterator, WireMessage,
+};
+use crate::processing::{create_sender_udp, send_chunk, set_static_fields, whole_message};
+use crate::resolver::Resolver;
+use crate::stats::SendStats;
+use crate::transport::{configured_targets, unix_path};
+
+/// Send the message to every configured target, returning error if it was not sent to any of them.
+pub fn send(config: &Config, stats: &SendStats, mut message: Message) -> Result<()> {
+    let mut targets = configured_targets(config, stats)?;
+    set_static_fields(&mut message, config)?;
+
+    let wire = WireMessage::new(message, OptFieldsIterator::new(&config.optional));
+    let chunked = if config.transport.is_stream() {
+        whole_message(wire.to_gelf()?.into_bytes(), config)?
+    } else {
+        wire.to_chunked_message(config.chunk_size, config.compression)?
+    };
+
+    // failure itself is already reported by the sender
+    let mut unsent = Vec::new();
+    for (name, target) in config.graylog_addrs.iter().zip(targets.iter_mut()) {
+        if target.send(slice::from_ref(&chunked), config.chaos.as_ref()) == 0 {
+            unsent.push(name.as_str());
+        }
+    }
+
+    if !unsent.is_empty() {
+        return Err(Error::InternalError(format!(
+            "message was not sent to {}",
+            unsent.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Time to wait for the target to reject the test message
+const PING_REJECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Send test message to every address of all targets, returning time spent on sending.
+pub fn ping(config: &Config) -> Result<Vec<(SocketAddr, io::Result<Duration>)>> {
+    let mut addrs = Vec::new();
+    for target in &config.graylog_addrs {
+        if unix_path(target).is_some() {
+            return Err(Error::InternalError(format!(
+                "ping of local socket {} is not supported",
+                target
+            )));
+        }
+        let resolver = Resolver::new(target, config.graylog_addr_ttl, config.resolve_strategy)?;
+        addrs.extend_from_slice(resolver.addrs());
+    }
+
+    let msg = MessageBuilder::new(hostname(), "jctl2gray ping".to_string())
+        .level(LevelSystem::Informational)
+        .metadata("ping", json!(true))
+        .build()?;
+    let chunked = WireMessage::new(msg, OptFieldsIterator::new(&config.optional))
+        .to_chunked_message(config.chunk_size, config.compression)?;
+
+    let results = addrs
+        .iter()
+        .map(|target| {
+            let started = Instant::now();
+            let result = ping_target(target, &chunked).map(|_| started.elapsed());
+            (*target, result)
+        })
+        .collect();
+
+    Ok(results)
+}
+
+fn ping_target(target: &SocketAddr, chunked: &ChunkedMessage) -> io::Result<()> {
+    // any free port, so that running forwarder is not disturbed
+    let socket = create_sender_udp(target, 0, None)?;
+    socket.connect(target)?;
+
+    for chunk in chunked.chunks() {
+        send_chunk(&socket, &chunk, target)?;
+    }
+
+    // closed port is reported back by ICMP, which only connected socket receives
+    socket.set_read_timeout(Some(PING_REJECT_TIMEOUT))?;
+    match socket.recv(&mut [0; 1]) {
+        Err(ref e)
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Return name of the local host, as journald would report it.
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "undefined".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    use serde_json::Value;
+
+    use crate::transport::TransportKind;
+
+    #[test]
+    fn sends_single_message() {
+        let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+        target
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![target.local_addr().unwrap().to_string()];
+        config.optional = vec![("env".to_string(), "staging".to_string())];
+        let message = MessageBuilder::new("host".to_string(), "backup done".to_string())
+            .level(LevelSystem::Notice)
+            .build()
+            .unwrap();
+        send(&config, &SendStats::new(), message).unwrap();
+
+        let mut buf = [0; 1024];
+        let len = target.recv(&mut buf).unwrap();
+        let gelf: Value = serde_json::from_slice(&buf[..len]).unwrap();
+        assert_eq!(gelf["short_message"], json!("backup done"));
+        assert_eq!(gelf["level"], json!(5));
+        assert_eq!(gelf["env"], json!("staging"));
+    }
+
+    #[test]
+    fn reports_unsent_message() {
+        // nothing listens on the port once the socket is closed
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![closed.to_string()];
+        config.transport = TransportKind::Tcp;
+        let message = MessageBuilder::new("host".to_string(), "backup done".to_string())
+            .build()
+            .unwrap();
+
+        let error = send(&config, &SendStats::new(), message).unwrap_err();
+        assert!(error.to_string().contains("message was not sent to"));
+    }
+
+    #[test]
+    fn pings_every_address() {
+        let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+        target
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let closed = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![target.local_addr().unwrap().to_string(), closed.to_string()];
+        let results = ping(&config).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, target.local_addr().unwrap());
+        assert!(results[0].1.is_ok());
+        // closed port is reported back by ICMP
+        assert_eq!(results[1].0, closed);
+        assert!(results[1].1.is_err());
+
+        let mut buf = [0; 1024];
+        let len = target.recv(&mut buf).unwrap();
+        let gelf: Value = serde_json::from_slice(&buf[..len]).unwrap();
+        assert_eq!(gelf["_ping"], json!(true));
+
+        config.graylog_addrs = vec!["unix:/run/graylog.sock".to_string()];
+        assert!(ping(&config).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Output of messages into a file
+//!
+//! On air-gapped hosts logs are shipped by other means, so messages are appended
+//! to a file as lines of GELF JSON instead of being sent. Once the file reaches its size
+//! limit, it is rotated: renamed to `<path>.1`, while older files are shifted to `<path>.2`
+//! and so on, up to the number of kept files. Compressed messages carry their newline,
+//! so that the file of concatenated zstd frames decompresses into lines of JSON.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+
+use crate::errors::Result;
+use crate::transport::Transport;
+
+/// File of newline-delimited GELF JSON with size-based rotation
+pub struct FileOutput {
+    path: String,
+    limit: u64,
+    keep: usize,
+    /// Whether messages carry their newline, being compressed along with it
+    terminated: bool,
+    writer: LineWriter<File>,
+    written: u64,
+}
+
+impl FileOutput {
+    /// Open file for appending messages, rotating it once it exceeds `limit` bytes
+    /// and keeping `keep` rotated files.
+    pub fn open(path: &str, limit: u64, keep: usize, terminated: bool) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+
+        Ok(FileOutput {
+            path: path.to_string(),
+            limit,
+            keep,
+            terminated,
+            writer: LineWriter::new(file),
+            written,
+        })
+    }
+
+    /// Shift rotated files, dropping the oldest one, and start the file anew.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let older = format!("{}.{}", self.path, n);
+                if fs::metadata(&older).is_ok() {
+                    fs::rename(&older, format!("{}.{}", self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.writer = LineWriter::new(file);
+        self.written = 0;
+        debug!("output file {} rotated", self.path);
+
+        Ok(())
+    }
+}
+
+impl Transport for FileOutput {
+    fn send(&mut self, payload: &[u8]) -> Result<()> {
+        let terminator: &[u8] = if self.terminated { b"" } else { b"\n" };
+        let size = (payload.len() + terminator.len()) as u64;
+        if self.written > 0 && self.written + size > self.limit {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(payload)?;
+        self.writer.write_all(terminator)?;
+        self.written += size;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gelf.ndjson");
+        let path = path.to_str().unwrap();
+
+        let mut output = FileOutput::open(path, 20, 2, false).unwrap();
+        for n in 0..7 {
+            output.send(format!("{{\"n\":{}}}", n).as_bytes()).unwrap();
+        }
+        drop(output);
+
+        let read = |suffix: &str| fs::read_to_string(format!("{}{}", path, suffix)).unwrap();
+        assert_eq!(read(""), "{\"n\":6}\n");
+        assert_eq!(read(".1"), "{\"n\":4}\n{\"n\":5}\n");
+        assert_eq!(read(".2"), "{\"n\":2}\n{\"n\":3}\n");
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_lines() {
+        use crate::gelf::MessageCompression;
+
+        let path = std::env::temp_dir().join(format!("jctl2gray-zstd-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut output = FileOutput::open(path, 1 << 20, 0, true).unwrap();
+        for n in 0..3 {
+            let line = format!("{{\"n\":{}}}\n", n).into_bytes();
+            let frame = MessageCompression::Zstd.compress_bytes(line).unwrap();
+            output.send(&frame).unwrap();
+        }
+        drop(output);
+
+        let file = fs::read(path).unwrap();
+        let lines = MessageCompression::Zstd.decompress(&file).unwrap();
+        assert_eq!(lines, b"{\"n\":0}\n{\"n\":1}\n{\"n\":2}\n");
+
+        fs::remove_file(path).unwrap();
+    }
+}
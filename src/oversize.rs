@@ -0,0 +1,201 @@
+//! Handling of messages too large to be sent
+//!
+//! GELF over UDP allows at most 128 chunks per message, so larger messages could not be sent at all.
+//! Such messages are either dropped and counted as oversized, or shrunk to fit: the message text
+//! is truncated, or its head stays the message while the text, truncated, becomes the full message.
+//! Shrunk messages are marked with field `truncated`.
+
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+use crate::gelf::{ChunkSize, Message, MessageCompression, WireMessage, MAX_CHUNKS};
+use crate::record;
+
+/// Field marking messages shrunk to fit
+pub const TRUNCATED_FIELD: &str = "truncated";
+
+/// Length of the head kept as the message when the text becomes the full message, bytes
+const HEAD_LENGTH: usize = 256;
+
+/// Attempts to fit compressed message by its estimated size, before fitting it uncompressed
+const FIT_ATTEMPTS: usize = 3;
+
+/// Handling of messages exceeding the maximal number of chunks
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OversizePolicy {
+    Drop,
+    Truncate,
+    FullMessage,
+}
+
+impl<'a> From<&'a str> for OversizePolicy {
+    fn from(policy: &'a str) -> Self {
+        match policy {
+            "truncate" => OversizePolicy::Truncate,
+            "full-message" => OversizePolicy::FullMessage,
+            _ => OversizePolicy::Drop,
+        }
+    }
+}
+
+/// Return serialized message fitting the maximal number of chunks once compressed,
+/// shrinking the message according to the policy if it does not.
+pub fn fit(
+    wire: &mut WireMessage,
+    gelf: Vec<u8>,
+    chunk_size: ChunkSize,
+    compression: MessageCompression,
+    policy: OversizePolicy,
+) -> Result<Vec<u8>> {
+    let budget = MAX_CHUNKS as usize * chunk_size.size() as usize;
+    let size = payload_size(&gelf, budget, compression)?;
+    if size <= budget {
+        return Ok(gelf);
+    }
+
+    let too_large = Error::MessageTooLarge {
+        size,
+        chunks: (size as u64).div_ceil(chunk_size.size() as u64),
+    };
+    if policy == OversizePolicy::Drop {
+        return Err(too_large);
+    }
+
+    let (mut gelf, mut size) = (gelf, size);
+    for attempt in 0..=FIT_ATTEMPTS {
+        // size after compression is estimated by the ratio of the previous attempt,
+        // the last one fits the message uncompressed
+        let ratio_size = if attempt < FIT_ATTEMPTS {
+            size
+        } else {
+            gelf.len()
+        };
+        let target = (gelf.len() as u64 * budget as u64 * 9 / 10 / ratio_size as u64) as usize;
+
+        if !shrink(
+            wire.message_mut(),
+            policy,
+            gelf.len().saturating_sub(target),
+        ) {
+            break;
+        }
+
+        gelf = wire.to_gelf_bytes(target)?;
+        size = payload_size(&gelf, budget, compression)?;
+        if size <= budget {
+            return Ok(gelf);
+        }
+    }
+
+    Err(too_large)
+}
+
+/// Return size of the message once compressed, compressing it only if it may not fit uncompressed.
+fn payload_size(gelf: &[u8], budget: usize, compression: MessageCompression) -> Result<usize> {
+    if gelf.len() <= budget || compression == MessageCompression::None {
+        return Ok(gelf.len());
+    }
+    Ok(compression.compress_bytes(gelf.to_vec())?.len())
+}
+
+/// Take at least `excess` bytes out of the texts of the message, returning false if they are too short.
+fn shrink(msg: &mut Message, policy: OversizePolicy, excess: usize) -> bool {
+    let short_len = msg.short_message().len();
+    let full_len = msg.full_message().as_ref().map_or(0, String::len);
+
+    match policy {
+        OversizePolicy::Drop => return false,
+        OversizePolicy::Truncate => {
+            if excess > short_len + full_len {
+                return false;
+            }
+
+            let short_cut = excess.min(short_len);
+            let short_msg = truncated(msg.short_message(), short_len - short_cut);
+            msg.set_short_message(short_msg);
+
+            if excess > short_cut {
+                let full_msg = msg.full_message().as_deref().unwrap_or_default();
+                let full_msg = truncated(full_msg, full_len - (excess - short_cut));
+                msg.set_full_message(full_msg);
+            }
+        }
+        OversizePolicy::FullMessage => {
+            let short_msg = msg.short_message();
+            let first_line = short_msg.lines().next().unwrap_or_default();
+            let head = truncated(first_line, HEAD_LENGTH);
+
+            // full message is either already there, or the whole text is moved into it
+            let text = match msg.full_message() {
+                Some(full_msg) => full_msg.as_str(),
+                None => short_msg,
+            };
+            let kept = match (short_len + full_len).checked_sub(excess + head.len()) {
+                Some(kept) => kept,
+                None => return false,
+            };
+
+            let full_msg = truncated(text, kept);
+            msg.set_short_message(head);
+            msg.set_full_message(full_msg);
+        }
+    }
+
+    msg.set_metadata(record::intern(TRUNCATED_FIELD), Value::Bool(true))
+        .is_ok()
+}
+
+/// Return the longest prefix of the text not exceeding `max` bytes.
+fn truncated(text: &str, max: usize) -> String {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gelf::OptFieldsIterator;
+
+    fn oversized(policy: OversizePolicy) -> Result<Value> {
+        let mut msg = Message::new("host", format!("first line\n{}", "ж".repeat(2000)));
+        msg.set_timestamp(1.0);
+        let mut wire = WireMessage::new(msg, OptFieldsIterator::new(&[]));
+        let gelf = wire.to_gelf_bytes(0).unwrap();
+
+        let gelf = fit(
+            &mut wire,
+            gelf,
+            ChunkSize::Custom(16),
+            MessageCompression::None,
+            policy,
+        )?;
+        assert!(gelf.len() <= 16 * MAX_CHUNKS as usize);
+        Ok(serde_json::from_slice(&gelf).unwrap())
+    }
+
+    #[test]
+    fn oversize_policies() {
+        match oversized(OversizePolicy::Drop) {
+            Err(Error::MessageTooLarge { chunks, .. }) => assert!(chunks > MAX_CHUNKS as u64),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        let truncated = oversized(OversizePolicy::Truncate).unwrap();
+        assert!(truncated["short_message"]
+            .as_str()
+            .unwrap()
+            .starts_with("first line\nжж"));
+        assert_eq!(truncated["_truncated"], json!(true));
+
+        let moved = oversized(OversizePolicy::FullMessage).unwrap();
+        assert_eq!(moved["short_message"], json!("first line"));
+        assert!(moved["full_message"]
+            .as_str()
+            .unwrap()
+            .starts_with("first line\nжж"));
+        assert_eq!(moved["_truncated"], json!(true));
+    }
+}
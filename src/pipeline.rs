@@ -0,0 +1,1443 @@
+//! Multi-threaded processing pipeline
+//!
+//! Records pushed by the reader are transformed into GELF messages
+//! by a pool of worker threads, compressed by a pool of compressor threads,
+//! and then sent by a single sender thread to every target:
+//!
+//! ```text
+//! reader --> worker 1..N --> compressor 1..M --> sender
+//! ```
+//!
+//! With several targets the sender hands every batch to a thread of each target
+//! over its own queue, and moves on as soon as one of them sends it. Queues of the
+//! targets never block the sender: a full one drops its oldest batch, or the new one
+//! per `drop-newest` policy, so that a slow target does not hold back the others,
+//! while the fastest one paces the input.
+//!
+//! Without compression workers chunk messages themselves and pass them
+//! directly to the sender.
+//!
+//! Stages are connected by bounded queues of configured capacity. When a queue
+//! is full, the upstream stage blocks until there is room again: nothing is
+//! dropped, and a slow target eventually stalls reading of the input, leaving
+//! unread records buffered by the source (journald or the writing process).
+//! Alternatively, per queue policy the reader drops either the oldest queued
+//! record or the new one instead of blocking, so that reading never stalls.
+//!
+//! With dedup, repeats of the previous message are held back by the workers
+//! and passed downstream collapsed, once the repeating is over.
+//!
+//! Given a limit, workers stop passing messages downstream once that many are
+//! forwarded, and the reader is expected to stop on `limit_reached`.
+//!
+//! In strict mode the first failed record is kept by the worker and reported
+//! to the reader on the next push, or on finish.
+//!
+//! With a spool, messages the sender failed to deliver are stored on disk
+//! and sent again once delivery succeeds.
+//!
+//! With a cursor file, journal records are given tickets in the reading order,
+//! so that the saved cursor never runs ahead of records still being processed,
+//! nor past messages which failed to be sent or spooled.
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+
+use crate::capture::Capture;
+use crate::config::SharedConfig;
+use crate::config::{Config, Output};
+use crate::cursor::{CursorFile, CursorWindow, Ticket, CURSOR_FIELD, SAVE_INTERVAL};
+use crate::dedup::Dedup;
+use crate::gelf::ChunkedMessage;
+use crate::gelf::MessageCompression;
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaOutput;
+use crate::output::FileOutput;
+use crate::preview;
+use crate::processing::{
+    compress_message, rechunk_message, serialize_decoded, serialize_record, LogRecord,
+};
+use crate::spool::Spool;
+use crate::stats::{RecordStats, SendStats, TargetStats};
+use crate::transport::{configured_targets, CustomSender, Destination, Transport};
+
+/// Input record, either raw JSON text, already decoded one,
+/// or even already serialized GELF message
+enum Record {
+    Raw(String),
+    Decoded(LogRecord, Option<Ticket>),
+    Serialized(Vec<u8>),
+}
+
+/// Handling of a record pushed while the queue is full
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QueuePolicy {
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+impl<'a> From<&'a str> for QueuePolicy {
+    fn from(policy: &'a str) -> Self {
+        match policy {
+            "drop-oldest" => QueuePolicy::DropOldest,
+            "drop-newest" => QueuePolicy::DropNewest,
+            _ => QueuePolicy::Block,
+        }
+    }
+}
+
+impl fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueuePolicy::Block => write!(f, "block"),
+            QueuePolicy::DropOldest => write!(f, "drop-oldest"),
+            QueuePolicy::DropNewest => write!(f, "drop-newest"),
+        }
+    }
+}
+
+/// Message along with the ticket of its record in the cursor window, if any
+type WithCursor<T> = (T, Option<Ticket>);
+
+/// Messages sent at once, shared by queues of the targets
+type Batch = Arc<[ChunkedMessage]>;
+
+/// Batch queued for a target, along with the channel reporting how many of its messages were sent
+type Fanned = (Batch, Sender<usize>);
+
+/// Destination of the sender thread
+enum Target {
+    Configured(Vec<Destination>),
+    Custom(CustomSender),
+    Discarded,
+}
+
+/// Stage receiving serialized messages from the workers
+#[derive(Clone)]
+enum Downstream {
+    Compressors(Sender<WithCursor<Vec<u8>>>),
+    Sender(Sender<WithCursor<ChunkedMessage>>),
+}
+
+pub struct Pipeline {
+    records: Sender<Record>,
+    queued: Receiver<Record>,
+    stats: Arc<RecordStats>,
+    recycled: Receiver<String>,
+    saturated: Cell<bool>,
+    /// Whether records were dropped because of full queue
+    overflowed: Cell<bool>,
+    workers: Vec<JoinHandle<()>>,
+    compressors: Vec<JoinHandle<()>>,
+    sender: JoinHandle<()>,
+    config: SharedConfig,
+    failure: Arc<Mutex<Option<Error>>>,
+    forwarded: Arc<AtomicU64>,
+    cursors: Option<Arc<CursorWindow>>,
+}
+
+impl Pipeline {
+    /// Start worker and sender threads.
+    ///
+    /// Target is resolved and socket is bound before start,
+    /// so that configuration errors are reported immediately.
+    /// In dry run messages are discarded and target is not used at all,
+    /// with file or Kafka output messages are written there instead.
+    /// Config could be replaced while running, affecting filters and fields of records processed afterwards.
+    pub fn start(shared: SharedConfig, stats: &SendStats) -> Result<Self> {
+        let config = shared.snapshot();
+
+        let target = if config.dry_run {
+            Target::Discarded
+        } else if let (Output::File, Some(path)) = (config.output, &config.output_path) {
+            let terminated = config.compression != MessageCompression::None;
+            let output =
+                FileOutput::open(path, config.output_limit, config.output_keep, terminated)?;
+            Target::Custom(CustomSender::new(Box::new(output), stats.target(path)))
+        } else if config.output == Output::Kafka {
+            let name = format!("kafka/{}", config.kafka.topic);
+            Target::Custom(CustomSender::new(
+                kafka_output(&config)?,
+                stats.target(&name),
+            ))
+        } else {
+            // bind to socket, or load certificates of the connection
+            Target::Configured(configured_targets(&config, stats)?)
+        };
+
+        Pipeline::launch(shared, stats, target)
+    }
+
+    /// Start worker and sender threads, sending messages with the given transport
+    /// instead of the configured target.
+    ///
+    /// Statistics of the transport are accounted as of target named `custom`.
+    pub fn with_transport(
+        shared: SharedConfig,
+        stats: &SendStats,
+        transport: Box<dyn Transport>,
+    ) -> Result<Self> {
+        let target = Target::Custom(CustomSender::new(transport, stats.target("custom")));
+        Pipeline::launch(shared, stats, target)
+    }
+
+    fn launch(shared: SharedConfig, stats: &SendStats, target: Target) -> Result<Self> {
+        let config = shared.snapshot();
+
+        let capture = match config.capture {
+            Some(ref path) => Some(Arc::new(Capture::open(path, config.capture_limit)?)),
+            None => None,
+        };
+
+        let (records_tx, records_rx) = bounded::<Record>(config.queue_capacity);
+        let (messages_tx, messages_rx) =
+            bounded::<WithCursor<ChunkedMessage>>(config.queue_capacity);
+        let (recycled_tx, recycled_rx) = bounded::<String>(config.queue_capacity);
+
+        stats.queue("records", gauge(records_rx.clone()));
+        stats.queue("messages", gauge(messages_rx.clone()));
+
+        // there is nothing to offload without compression
+        let compressors_num = match config.compression {
+            MessageCompression::None => 0,
+            _ => config.compressors.max(1),
+        };
+
+        let (downstream, compressors) = if compressors_num > 0 {
+            let (serialized_tx, serialized_rx) =
+                bounded::<WithCursor<Vec<u8>>>(config.queue_capacity);
+            stats.queue("serialized", gauge(serialized_rx.clone()));
+
+            let compressors = (0..compressors_num)
+                .map(|n| {
+                    let config = shared.clone();
+                    let serialized = serialized_rx.clone();
+                    let messages = messages_tx.clone();
+
+                    thread::Builder::new()
+                        .name(format!("compressor-{}", n))
+                        .spawn(move || compress_messages(&config, serialized, messages))
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+
+            (Downstream::Compressors(serialized_tx), compressors)
+        } else {
+            (Downstream::Sender(messages_tx), Vec::new())
+        };
+
+        let failure = Arc::new(Mutex::new(None));
+        let forwarded = Arc::new(AtomicU64::new(0));
+        let cursors = config
+            .cursor_file
+            .as_deref()
+            .map(|path| CursorWindow::new(CursorFile::new(path)));
+
+        let workers = (0..config.workers.max(1))
+            .map(|n| {
+                let config = shared.clone();
+                let records = records_rx.clone();
+                let downstream = downstream.clone();
+                let recycled = recycled_tx.clone();
+                let stats = stats.records();
+                let capture = capture.clone();
+                let failure = failure.clone();
+                let forwarded = forwarded.clone();
+
+                thread::Builder::new()
+                    .name(format!("worker-{}", n))
+                    .spawn(move || {
+                        let worker = Worker {
+                            downstream,
+                            recycled,
+                            stats,
+                            capture,
+                            failure,
+                            forwarded,
+                        };
+                        worker.transform_records(&config, records)
+                    })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        // nothing is undeliverable in dry run
+        let spool = match (&config.spool_dir, &target) {
+            (_, Target::Discarded) | (None, _) => None,
+            (Some(dir), _) => {
+                let spool = Spool::open(dir, config.spool_limit)?;
+                let spooled = spool.counter();
+                stats.queue("spool", move || (spooled.load(Ordering::Relaxed), None));
+                Some(spool)
+            }
+        };
+
+        let sender = {
+            let config = shared.clone();
+            let delivery = Delivery {
+                messages: messages_rx,
+                cursors: cursors.clone(),
+                spool,
+                // custom transports are given messages one by one
+                batching: matches!(target, Target::Configured(_)),
+            };
+            let builder = thread::Builder::new().name("sender".to_string());
+            match target {
+                Target::Configured(mut targets) if targets.len() == 1 => {
+                    let target = targets.remove(0);
+                    builder.spawn(move || send_messages(&config, delivery, target))?
+                }
+                Target::Configured(targets) => {
+                    let queues = targets
+                        .into_iter()
+                        .enumerate()
+                        .map(|(n, mut target)| {
+                            let name = target.name().to_string();
+                            TargetQueue::start(&shared, stats, &name, n, move |batch, config| {
+                                target.send(batch, config.chaos.as_ref())
+                            })
+                        })
+                        .collect::<std::io::Result<Vec<_>>>()?;
+                    builder.spawn(move || fan_out(&config, delivery, queues))?
+                }
+                Target::Custom(sender) => {
+                    builder.spawn(move || send_custom(&config, delivery, sender))?
+                }
+                Target::Discarded => {
+                    builder.spawn(move || delivery.messages.iter().for_each(drop))?
+                }
+            }
+        };
+
+        debug!(
+            "pipeline started with {} workers, {} compressors, queue capacity {}",
+            workers.len(),
+            compressors.len(),
+            config.queue_capacity
+        );
+
+        Ok(Pipeline {
+            records: records_tx,
+            queued: records_rx,
+            stats: stats.records(),
+            recycled: recycled_rx,
+            saturated: Cell::new(false),
+            overflowed: Cell::new(false),
+            workers,
+            compressors,
+            sender,
+            config: shared,
+            failure,
+            forwarded,
+            cursors,
+        })
+    }
+
+    /// Return config used by the pipeline threads, which could be replaced while running.
+    pub fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    /// Return an empty buffer for the next record, reusing processed ones if possible.
+    pub fn buffer(&self) -> String {
+        self.recycled.try_recv().unwrap_or_default()
+    }
+
+    /// Pass raw record to the workers, handling full queue per configured policy.
+    pub fn push(&self, record: String) -> Result<()> {
+        self.enqueue(Record::Raw(record))
+    }
+
+    /// Pass decoded record to the workers, handling full queue per configured policy.
+    pub fn push_decoded(&self, record: LogRecord) -> Result<()> {
+        let ticket = self.cursors.as_ref().and_then(|cursors| {
+            let cursor = record.get(CURSOR_FIELD).and_then(Value::as_str)?;
+            Some(cursors.ticket(Arc::from(cursor)))
+        });
+        self.enqueue(Record::Decoded(record, ticket))
+    }
+
+    /// Pass serialized GELF message to be sent as is, handling full queue per configured policy.
+    pub fn push_serialized(&self, gelf: Vec<u8>) -> Result<()> {
+        self.enqueue(Record::Serialized(gelf))
+    }
+
+    fn enqueue(&self, record: Record) -> Result<()> {
+        if let Some(e) = self.failure() {
+            return Err(e);
+        }
+
+        let mut record = match self.records.try_send(record) {
+            Ok(()) => {
+                self.saturated.set(false);
+                return Ok(());
+            }
+            Err(TrySendError::Full(record)) => record,
+            Err(TrySendError::Disconnected(_)) => return Err(pipeline_stopped()),
+        };
+
+        let policy = self.config.load().queue_policy;
+        if policy != QueuePolicy::Block && !self.overflowed.replace(true) {
+            warn!(
+                "pipeline queue is full, records are dropped ({}) and counted as overflowed",
+                policy
+            );
+        }
+
+        match policy {
+            QueuePolicy::Block => {
+                if !self.saturated.replace(true) {
+                    debug!("pipeline queue is full, reading suspended");
+                }
+                self.records.send(record).map_err(|_| pipeline_stopped())
+            }
+            QueuePolicy::DropNewest => {
+                self.stats.record_overflowed();
+                Ok(())
+            }
+            QueuePolicy::DropOldest => loop {
+                // workers could take the queued records meanwhile, leaving room for the new one
+                if self.queued.try_recv().is_ok() {
+                    self.stats.record_overflowed();
+                }
+
+                record = match self.records.try_send(record) {
+                    Ok(()) => return Ok(()),
+                    Err(TrySendError::Full(record)) => record,
+                    Err(TrySendError::Disconnected(_)) => return Err(pipeline_stopped()),
+                };
+            },
+        }
+    }
+
+    /// Check whether the limit of forwarded messages is reached, so that reading could stop.
+    pub fn limit_reached(&self) -> bool {
+        let forwarded = self.forwarded.load(Ordering::Relaxed);
+        self.config
+            .load()
+            .limit
+            .is_some_and(|limit| forwarded >= limit)
+    }
+
+    /// Take failure of a record in strict mode, if any.
+    fn failure(&self) -> Option<Error> {
+        self.failure.lock().expect("failure lock poisoned").take()
+    }
+
+    /// Wait until all pushed records are processed and stop threads.
+    ///
+    /// Failure of a record in strict mode is returned, unless it was already.
+    pub fn finish(self) -> Result<()> {
+        drop(self.records);
+
+        for worker in self.workers {
+            if worker.join().is_err() {
+                error!("worker thread panicked");
+            }
+        }
+
+        for compressor in self.compressors {
+            if compressor.join().is_err() {
+                error!("compressor thread panicked");
+            }
+        }
+
+        if self.sender.join().is_err() {
+            error!("sender thread panicked");
+        }
+
+        match self.failure.lock().expect("failure lock poisoned").take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+fn pipeline_stopped() -> Error {
+    Error::InternalError("pipeline stopped".to_string())
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_output(config: &Config) -> Result<Box<dyn Transport>> {
+    Ok(Box::new(KafkaOutput::new(&config.kafka)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_output(_config: &Config) -> Result<Box<dyn Transport>> {
+    Err(Error::InternalError(
+        "kafka output requires the kafka feature".to_string(),
+    ))
+}
+
+/// Stage transforming records into serialized messages
+struct Worker {
+    downstream: Downstream,
+    recycled: Sender<String>,
+    stats: Arc<RecordStats>,
+    capture: Option<Arc<Capture>>,
+    failure: Arc<Mutex<Option<Error>>>,
+    forwarded: Arc<AtomicU64>,
+}
+
+impl Worker {
+    fn transform_records(&self, shared: &SharedConfig, records: Receiver<Record>) {
+        let mut scratch = LogRecord::new();
+
+        loop {
+            // collapsed repeats are sent even while there are no records
+            let received = if shared.load().dedup.is_some() {
+                records.recv_timeout(SAVE_INTERVAL)
+            } else {
+                records.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+
+            let config = &*shared.load();
+            let record = match received {
+                Ok(record) => record,
+                Err(RecvTimeoutError::Timeout) => {
+                    if !self.forward_collapsed(config, Dedup::take_ready) {
+                        return;
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.forward_collapsed(config, Dedup::flush);
+                    return;
+                }
+            };
+
+            // records left after the limit are drained without processing
+            if config
+                .limit
+                .is_some_and(|limit| self.forwarded.load(Ordering::Relaxed) >= limit)
+            {
+                continue;
+            }
+
+            let mut ticket = None;
+            let serialized = match record {
+                Record::Raw(mut text) => {
+                    let serialized =
+                        serialize_record(text.trim(), config, &mut scratch, &self.stats);
+
+                    // hand buffer back to the reader, unless it has enough already
+                    text.clear();
+                    let _ = self.recycled.try_send(text);
+                    serialized
+                }
+
+                Record::Decoded(mut decoded, issued) => {
+                    ticket = issued;
+                    serialize_decoded(&mut decoded, config, &self.stats)
+                }
+
+                Record::Serialized(gelf) => {
+                    self.stats.record_accepted();
+                    Ok(Some(gelf))
+                }
+            };
+
+            // records are still drained after failure, so that reader is never blocked
+            let serialized = match serialized {
+                Ok(serialized) => serialized,
+                Err(e) => {
+                    let mut failure = self.failure.lock().expect("failure lock poisoned");
+                    if failure.is_none() {
+                        *failure = Some(e);
+                    }
+                    None
+                }
+            };
+
+            // repeats collapsed by this record go first, to keep the order of messages
+            if !self.forward_collapsed(config, Dedup::take_ready) {
+                return;
+            }
+
+            if let Some(gelf) = serialized {
+                if !self.forward(config, gelf, ticket) {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Pass collapsed repeats taken from the dedup stage downstream, if it is configured.
+    fn forward_collapsed<F>(&self, config: &Config, take: F) -> bool
+    where
+        F: FnOnce(&Dedup) -> Vec<Vec<u8>>,
+    {
+        let collapsed = config.dedup.as_deref().map(take).unwrap_or_default();
+        collapsed
+            .into_iter()
+            .all(|gelf| self.forward(config, gelf, None))
+    }
+
+    /// Pass message downstream, returning false once the downstream is gone.
+    fn forward(&self, config: &Config, gelf: Vec<u8>, ticket: Option<Ticket>) -> bool {
+        // messages past the limit are dropped, while reader is about to stop
+        if let Some(limit) = config.limit {
+            if self.forwarded.fetch_add(1, Ordering::Relaxed) >= limit {
+                return true;
+            }
+        }
+
+        if let Some(ref capture) = self.capture {
+            capture.write(&gelf);
+        }
+
+        if config.preview {
+            preview::print(&gelf);
+        }
+
+        if config.output == Output::Stdout {
+            preview::print_json(&gelf);
+        }
+
+        match self.downstream {
+            Downstream::Compressors(ref compressors) => compressors.send((gelf, ticket)).is_ok(),
+            Downstream::Sender(ref sender) => match compress_message(gelf, config) {
+                Some(chunked) => sender.send((chunked, ticket)).is_ok(),
+                None => true,
+            },
+        }
+    }
+}
+
+fn compress_messages(
+    shared: &SharedConfig,
+    serialized: Receiver<WithCursor<Vec<u8>>>,
+    messages: Sender<WithCursor<ChunkedMessage>>,
+) {
+    for (gelf, ticket) in serialized {
+        if let Some(chunked) = compress_message(gelf, &shared.load()) {
+            if messages.send((chunked, ticket)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn send_messages(shared: &SharedConfig, delivery: Delivery, mut target: Destination) {
+    delivery.run(shared, |batch, config| {
+        target.send(batch, config.chaos.as_ref())
+    })
+}
+
+/// Hand every batch to queues of the targets, waiting until one of them sends it.
+///
+/// Batch dropped by the full queue of a target counts as not sent by that target.
+/// Otherwise the batch counts as sent as far as any of the targets got with it.
+fn fan_out(shared: &SharedConfig, delivery: Delivery, mut queues: Vec<TargetQueue>) {
+    delivery.run(shared, |batch, config| {
+        let (sent_tx, sent_rx) = bounded(queues.len());
+        for queue in queues.iter_mut() {
+            queue.push((batch.clone(), sent_tx.clone()), config.queue_policy);
+        }
+        drop(sent_tx);
+
+        // cursor moves on once the message is sent to any target,
+        // so that an unavailable one does not hold it back
+        let mut delivered = 0;
+        for sent in sent_rx.iter() {
+            delivered = delivered.max(sent);
+            if delivered == batch.len() {
+                break;
+            }
+        }
+        delivered
+    });
+
+    for queue in queues {
+        queue.finish();
+    }
+}
+
+/// Queue of a target served by its own thread
+struct TargetQueue {
+    name: String,
+    batches: Sender<Fanned>,
+    /// Receiving end, taking the oldest batch out of the full queue
+    queued: Receiver<Fanned>,
+    stats: Arc<TargetStats>,
+    /// Whether batches were dropped because of full queue
+    overflowed: bool,
+    thread: JoinHandle<()>,
+}
+
+impl TargetQueue {
+    fn start<F>(
+        shared: &SharedConfig,
+        stats: &SendStats,
+        name: &str,
+        n: usize,
+        mut send: F,
+    ) -> std::io::Result<Self>
+    where
+        F: FnMut(&[ChunkedMessage], &Config) -> usize + Send + 'static,
+    {
+        let capacity = shared.load().queue_capacity;
+        let (batches_tx, batches_rx) = bounded::<Fanned>(capacity);
+        stats.queue(&format!("target {}", name), gauge(batches_rx.clone()));
+
+        let thread = {
+            let config = shared.clone();
+            let batches = batches_rx.clone();
+            thread::Builder::new()
+                .name(format!("sender-{}", n))
+                .spawn(move || {
+                    for (batch, sent) in batches {
+                        let _ = sent.send(send(&batch, &config.load()));
+                    }
+                })?
+        };
+
+        Ok(TargetQueue {
+            stats: stats.target(name),
+            name: name.to_string(),
+            batches: batches_tx,
+            queued: batches_rx,
+            overflowed: false,
+            thread,
+        })
+    }
+
+    /// Queue the batch, dropping the oldest queued one when the queue is full,
+    /// or this one per `drop-newest` policy.
+    ///
+    /// The sender is never blocked by a slow target, whatever the policy is.
+    fn push(&mut self, fanned: Fanned, policy: QueuePolicy) {
+        let mut fanned = match self.batches.try_send(fanned) {
+            Ok(()) => return,
+            Err(TrySendError::Full(fanned)) => fanned,
+            Err(TrySendError::Disconnected(_)) => return,
+        };
+
+        if !self.overflowed {
+            self.overflowed = true;
+            warn!(
+                "queue of target {} is full, messages are dropped ({}) and counted as dropped",
+                self.name,
+                if policy == QueuePolicy::DropNewest {
+                    QueuePolicy::DropNewest
+                } else {
+                    QueuePolicy::DropOldest
+                }
+            );
+        }
+
+        if policy == QueuePolicy::DropNewest {
+            self.dropped(&fanned.0);
+            return;
+        }
+
+        loop {
+            // the thread could take the queued batches meanwhile, leaving room for the new one
+            if let Ok((oldest, _)) = self.queued.try_recv() {
+                self.dropped(&oldest);
+            }
+
+            fanned = match self.batches.try_send(fanned) {
+                Ok(()) => return,
+                Err(TrySendError::Full(fanned)) => fanned,
+                Err(TrySendError::Disconnected(_)) => return,
+            };
+        }
+    }
+
+    fn dropped(&self, batch: &Batch) {
+        batch.iter().for_each(|_| self.stats.message_dropped());
+    }
+
+    /// Wait until queued batches are sent and stop the thread.
+    fn finish(self) {
+        drop(self.batches);
+        drop(self.queued);
+        if self.thread.join().is_err() {
+            error!("sender thread of target {} panicked", self.name);
+        }
+    }
+}
+
+fn send_custom(shared: &SharedConfig, delivery: Delivery, mut sender: CustomSender) {
+    delivery.run(shared, |batch, _| {
+        batch
+            .iter()
+            .take_while(|chunked| sender.send(chunked))
+            .count()
+    })
+}
+
+/// Messages to be sent, along with bookkeeping of the sent ones
+struct Delivery {
+    messages: Receiver<WithCursor<ChunkedMessage>>,
+    cursors: Option<Arc<CursorWindow>>,
+    spool: Option<Spool>,
+    /// Whether messages could be sent in batches over streams, if configured
+    batching: bool,
+}
+
+impl Delivery {
+    /// Send every message, spooling undelivered ones if possible.
+    ///
+    /// Messages are sent in batches of up to `batch_size`, collected for at most `batch_timeout`.
+    /// `send` returns how many messages of the batch were sent from its start, the rest are spooled.
+    /// Tickets of the batch are delivered once it is either sent or spooled, moving the cursor
+    /// on as soon as preceding records are settled. While there are no messages,
+    /// the last cursor is saved and spooled messages are sent.
+    fn run<F>(mut self, shared: &SharedConfig, mut send: F)
+    where
+        F: FnMut(&Batch, &Config) -> usize,
+    {
+        let periodic = self.cursors.is_some() || self.spool.is_some();
+        let mut tickets = Vec::new();
+
+        loop {
+            let received = if periodic {
+                self.messages.recv_timeout(SAVE_INTERVAL)
+            } else {
+                self.messages
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            };
+
+            let config = shared.load();
+            let (chunked, ticket) = match received {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(cursors) = self.cursors.as_ref() {
+                        cursors.save();
+                    }
+                    self.drain_spool(&config, &mut send);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            let mut batch = vec![chunked];
+            tickets.extend(ticket);
+
+            if self.batching && config.transport.is_stream() && config.batch_size > 1 {
+                let deadline = Instant::now() + config.batch_timeout;
+                while batch.len() < config.batch_size {
+                    match self.messages.recv_deadline(deadline) {
+                        Ok((chunked, ticket)) => {
+                            batch.push(chunked);
+                            tickets.extend(ticket);
+                        }
+                        // disconnection is noticed once the batch is sent
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            let batch = Batch::from(batch);
+            let delivered = send(&batch, &config);
+            let sent = delivered == batch.len();
+            let spooled = match self.spool.as_mut() {
+                Some(spool) if !sent => {
+                    // only undelivered messages are spooled, so that they are not sent twice;
+                    // each of them is pushed even if a preceding one does not fit
+                    let pushed = batch
+                        .iter()
+                        .skip(delivered)
+                        .filter(|chunked| spool.push(chunked.payload()))
+                        .count();
+                    pushed == batch.len() - delivered
+                }
+                _ => false,
+            };
+
+            // undelivered messages hold the cursor back, so that they are read again after restart
+            if sent || spooled {
+                tickets.drain(..).for_each(Ticket::deliver);
+            } else {
+                tickets.drain(..).for_each(Ticket::fail);
+            }
+
+            if sent {
+                self.drain_spool(&config, &mut send);
+            }
+        }
+    }
+
+    /// Send a batch of spooled messages, if any.
+    fn drain_spool<F>(&mut self, config: &Config, send: &mut F)
+    where
+        F: FnMut(&Batch, &Config) -> usize,
+    {
+        if let Some(spool) = self.spool.as_mut().filter(|spool| !spool.is_empty()) {
+            spool.drain(|payload| match rechunk_message(payload, config) {
+                Some(chunked) => send(&Batch::from(vec![chunked]), config) == 1,
+                None => true,
+            });
+        }
+    }
+}
+
+/// Return function reporting depth of the queue.
+fn gauge<T>(queue: Receiver<T>) -> impl Fn() -> (usize, Option<usize>) + Send + 'static
+where
+    T: Send + 'static,
+{
+    move || (queue.len(), queue.capacity())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crossbeam_channel::unbounded;
+
+    fn shared(capacity: usize) -> SharedConfig {
+        let mut config = Config::for_tests();
+        config.queue_capacity = capacity;
+        SharedConfig::new(config)
+    }
+
+    fn message(n: usize) -> ChunkedMessage {
+        ChunkedMessage::whole(format!("{{\"n\":{}}}", n).into_bytes())
+    }
+
+    fn delivery(messages: Receiver<WithCursor<ChunkedMessage>>) -> Delivery {
+        Delivery {
+            messages,
+            cursors: None,
+            spool: None,
+            batching: false,
+        }
+    }
+
+    /// Transport passing every sent payload into the channel, along with the name of sending thread
+    struct Threaded(Sender<(Option<String>, Vec<u8>)>);
+
+    impl Transport for Threaded {
+        fn send(&mut self, payload: &[u8]) -> Result<()> {
+            let name = thread::current().name().map(str::to_string);
+            let _ = self.0.send((name, payload.to_vec()));
+            Ok(())
+        }
+    }
+
+    /// Transport passing every sent payload into the channel
+    struct Recorded(Sender<Vec<u8>>);
+
+    impl Transport for Recorded {
+        fn send(&mut self, payload: &[u8]) -> Result<()> {
+            let _ = self.0.send(payload.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_pass_workers_and_sender_threads() {
+        let mut config = Config::for_tests();
+        config.workers = 4;
+        let stats = SendStats::new();
+        let (sent_tx, sent_rx) = unbounded();
+        let pipeline = Pipeline::with_transport(
+            SharedConfig::new(config),
+            &stats,
+            Box::new(Threaded(sent_tx)),
+        )
+        .unwrap();
+        assert_eq!(pipeline.workers.len(), 4);
+
+        for n in 0..100 {
+            let record = format!("{{\"MESSAGE\":\"message {}\",\"PRIORITY\":\"6\"}}", n);
+            pipeline.push(record).unwrap();
+        }
+        pipeline.finish().unwrap();
+
+        // workers transform records concurrently, so they could be sent in any order
+        let mut messages: Vec<String> = sent_rx
+            .try_iter()
+            .map(|(thread, payload)| {
+                assert_eq!(thread.as_deref(), Some("sender"));
+                let gelf: Value = serde_json::from_slice(&payload).unwrap();
+                gelf["short_message"].as_str().unwrap().to_string()
+            })
+            .collect();
+        messages.sort_by_key(|message| message[8..].parse::<u32>().unwrap());
+        let expected: Vec<String> = (0..100).map(|n| format!("message {}", n)).collect();
+        assert_eq!(messages, expected);
+        assert_eq!(stats.target("custom").snapshot().messages, 100);
+    }
+
+    /// Transport stuck on every message until the sender of the gate is dropped
+    struct Gated(Receiver<()>, Sender<Vec<u8>>);
+
+    impl Transport for Gated {
+        fn send(&mut self, payload: &[u8]) -> Result<()> {
+            let _ = self.0.recv();
+            let _ = self.1.send(payload.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Start queue of a target passing payloads of the sent batches into the returned channel.
+    fn recording(
+        shared: &SharedConfig,
+        stats: &SendStats,
+        name: &str,
+    ) -> (TargetQueue, Receiver<Vec<u8>>) {
+        let (sent_tx, sent_rx) = unbounded();
+        let queue = TargetQueue::start(shared, stats, name, 0, move |batch, _| {
+            for chunked in batch {
+                let _ = sent_tx.send(chunked.payload().to_vec());
+            }
+            batch.len()
+        })
+        .unwrap();
+        (queue, sent_rx)
+    }
+
+    /// Start queue of a target stuck on its first batch until the returned sender is dropped.
+    fn stuck(shared: &SharedConfig, stats: &SendStats, name: &str) -> (TargetQueue, Sender<()>) {
+        let (release_tx, release_rx) = bounded::<()>(0);
+        let queue = TargetQueue::start(shared, stats, name, 0, move |batch, _| {
+            let _ = release_rx.recv();
+            batch.len()
+        })
+        .unwrap();
+        (queue, release_tx)
+    }
+
+    /// Start queue of a target recording payloads of the sent batches, which is stuck
+    /// after taking the first one until the returned sender is dropped.
+    fn gated(
+        shared: &SharedConfig,
+        stats: &SendStats,
+        name: &str,
+    ) -> (TargetQueue, Receiver<Vec<u8>>, Sender<()>) {
+        let (sent_tx, sent_rx) = unbounded();
+        let (release_tx, release_rx) = bounded::<()>(0);
+        let queue = TargetQueue::start(shared, stats, name, 0, move |batch, _| {
+            for chunked in batch {
+                let _ = sent_tx.send(chunked.payload().to_vec());
+            }
+            let _ = release_rx.recv();
+            batch.len()
+        })
+        .unwrap();
+        (queue, sent_rx, release_tx)
+    }
+
+    /// Push batches of single messages numbered from 0 into the queue of capacity 2,
+    /// the first of which is taken by the gated target, returning payloads it sent after that.
+    fn overflow(policy: QueuePolicy, stats: &SendStats) -> Vec<Vec<u8>> {
+        let shared = shared(2);
+        let (mut queue, sent, release) = gated(&shared, stats, "gated");
+        let fanned = |n| {
+            let (sent_tx, _) = bounded(1);
+            (Batch::from(vec![message(n)]), sent_tx)
+        };
+
+        queue.push(fanned(0), policy);
+        assert_eq!(
+            sent.recv_timeout(Duration::from_secs(5)).unwrap(),
+            message(0).payload()
+        );
+        for n in 1..5 {
+            queue.push(fanned(n), policy);
+        }
+
+        drop(release);
+        queue.finish();
+        sent.try_iter().collect()
+    }
+
+    #[test]
+    fn full_queue_drops_oldest() {
+        let stats = SendStats::new();
+        let sent = overflow(QueuePolicy::DropOldest, &stats);
+
+        assert_eq!(
+            sent,
+            [3, 4]
+                .iter()
+                .map(|&n| message(n).payload().to_vec())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(stats.target("gated").snapshot().dropped, 2);
+    }
+
+    #[test]
+    fn full_queue_drops_newest() {
+        let stats = SendStats::new();
+        let sent = overflow(QueuePolicy::DropNewest, &stats);
+
+        assert_eq!(
+            sent,
+            [1, 2]
+                .iter()
+                .map(|&n| message(n).payload().to_vec())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(stats.target("gated").snapshot().dropped, 2);
+    }
+
+    /// Start queue of a target failing to send any batch.
+    fn failing(shared: &SharedConfig, stats: &SendStats, name: &str) -> TargetQueue {
+        TargetQueue::start(shared, stats, name, 0, |_, _| 0).unwrap()
+    }
+
+    /// Fan messages numbered from 0 with cursors `s=<n>` out to the queues,
+    /// returning the saved cursor once all of them are settled.
+    fn fan_out_with_cursors(
+        shared: &SharedConfig,
+        queues: Vec<TargetQueue>,
+        n: usize,
+    ) -> Option<String> {
+        let path = std::env::temp_dir().join(format!(
+            "jctl2gray-fan-out-{}-{}",
+            queues.len(),
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap().to_string();
+        let window = CursorWindow::new(CursorFile::new(&path));
+
+        let (messages_tx, messages_rx) = bounded(n);
+        for n in 0..n {
+            let ticket = window.ticket(Arc::from(format!("s={}", n)));
+            messages_tx.send((message(n), Some(ticket))).unwrap();
+        }
+        drop(messages_tx);
+
+        let mut delivery = delivery(messages_rx);
+        delivery.cursors = Some(window.clone());
+        fan_out(shared, delivery, queues);
+
+        window.save();
+        let saved = crate::cursor::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        saved
+    }
+
+    #[test]
+    fn failing_target_does_not_hold_cursor() {
+        let shared = shared(16);
+        let stats = SendStats::new();
+        let (healthy, sent) = recording(&shared, &stats, "healthy");
+        let queues = vec![failing(&shared, &stats, "failing"), healthy];
+
+        let saved = fan_out_with_cursors(&shared, queues, 3);
+        assert_eq!(sent.try_iter().count(), 3);
+        assert_eq!(saved, Some("s=2".to_string()));
+
+        // cursor stays before messages sent by none of the targets
+        let queues = vec![
+            failing(&shared, &stats, "failing"),
+            failing(&shared, &stats, "unavailable"),
+        ];
+        assert_eq!(fan_out_with_cursors(&shared, queues, 3), None);
+    }
+
+    #[test]
+    fn slow_target_does_not_stall_others() {
+        let shared = shared(2);
+        let stats = SendStats::new();
+        let (stuck, release) = stuck(&shared, &stats, "stuck");
+        let (healthy, sent) = recording(&shared, &stats, "healthy");
+
+        let (messages_tx, messages_rx) = bounded(16);
+        let sender = {
+            let shared = shared.clone();
+            thread::spawn(move || fan_out(&shared, delivery(messages_rx), vec![stuck, healthy]))
+        };
+
+        for n in 0..10 {
+            messages_tx.send((message(n), None)).unwrap();
+        }
+        for n in 0..10 {
+            let payload = sent
+                .recv_timeout(Duration::from_secs(5))
+                .expect("healthy target stalled");
+            assert_eq!(payload, message(n).payload());
+        }
+
+        drop(messages_tx);
+        drop(release);
+        sender.join().unwrap();
+
+        // one batch is held by the stuck thread, and two more are queued
+        assert!(stats.target("stuck").snapshot().dropped >= 7);
+        assert_eq!(stats.target("healthy").snapshot().dropped, 0);
+    }
+
+    #[test]
+    fn workers_and_compressors_forward_every_record() {
+        let mut config = Config::for_tests();
+        config.workers = 4;
+        config.compressors = 2;
+        config.compression = MessageCompression::Gzip;
+        let shared = SharedConfig::new(config);
+
+        let stats = SendStats::new();
+        let (sent_tx, sent_rx) = unbounded();
+        let pipeline =
+            Pipeline::with_transport(shared, &stats, Box::new(Recorded(sent_tx))).unwrap();
+
+        for n in 0..100 {
+            let record =
+                json!({"MESSAGE": format!("record {}", n), "PRIORITY": "3", "_HOSTNAME": "host"});
+            pipeline.push(record.to_string()).unwrap();
+        }
+        pipeline.finish().unwrap();
+
+        let mut received: Vec<String> = sent_rx
+            .try_iter()
+            .map(|payload| {
+                let gelf = MessageCompression::Gzip.decompress(&payload).unwrap();
+                let gelf: Value = serde_json::from_slice(&gelf).unwrap();
+                gelf["short_message"].as_str().unwrap().to_string()
+            })
+            .collect();
+        received.sort();
+
+        let mut expected: Vec<String> = (0..100).map(|n| format!("record {}", n)).collect();
+        expected.sort();
+        assert_eq!(received, expected);
+        assert_eq!(stats.records().snapshot().accepted, 100);
+    }
+
+    #[test]
+    fn flushes_partial_batch_on_timeout() {
+        let mut config = Config::for_tests();
+        config.transport = crate::transport::TransportKind::Tcp;
+        config.batch_size = 10;
+        config.batch_timeout = Duration::from_millis(50);
+        let shared = SharedConfig::new(config);
+
+        let (messages_tx, messages_rx) = bounded(16);
+        let (batches_tx, batches_rx) = unbounded();
+        let sender = {
+            let shared = shared.clone();
+            let mut delivery = delivery(messages_rx);
+            delivery.batching = true;
+            thread::spawn(move || {
+                delivery.run(&shared, |batch, _| {
+                    batches_tx.send(batch.len()).unwrap();
+                    batch.len()
+                })
+            })
+        };
+
+        for n in 0..3 {
+            messages_tx.send((message(n), None)).unwrap();
+        }
+        // the input is still open, but the batch is sent once the timeout passes
+        assert_eq!(batches_rx.recv_timeout(Duration::from_secs(5)), Ok(3));
+
+        messages_tx.send((message(3), None)).unwrap();
+        drop(messages_tx);
+        sender.join().unwrap();
+        assert_eq!(batches_rx.try_iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    /// Transport passing the given number of payloads into the channel, failing afterwards
+    struct FailingAfter(usize, Sender<Vec<u8>>);
+
+    impl Transport for FailingAfter {
+        fn send(&mut self, payload: &[u8]) -> Result<()> {
+            if self.0 == 0 {
+                return Err(Error::InternalError("unavailable".to_string()));
+            }
+            self.0 -= 1;
+            let _ = self.1.send(payload.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spools_only_undelivered_part_of_batch() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-partial-{}", std::process::id()));
+        let dir = dir.to_str().unwrap().to_string();
+        let mut config = Config::for_tests();
+        config.transport = crate::transport::TransportKind::Tcp;
+        config.batch_size = 3;
+        let shared = SharedConfig::new(config);
+
+        let (messages_tx, messages_rx) = bounded(16);
+        for n in 0..3 {
+            messages_tx.send((message(n), None)).unwrap();
+        }
+        drop(messages_tx);
+
+        let stats = SendStats::new();
+        let (sent_tx, sent_rx) = unbounded();
+        let sender = CustomSender::new(Box::new(FailingAfter(1, sent_tx)), stats.target("custom"));
+        let mut delivery = delivery(messages_rx);
+        delivery.batching = true;
+        delivery.spool = Some(Spool::open(&dir, 1 << 20).unwrap());
+        send_custom(&shared, delivery, sender);
+
+        // the first message is sent, the rest of the batch is spooled
+        assert_eq!(
+            sent_rx.try_iter().collect::<Vec<_>>(),
+            [message(0).payload()]
+        );
+        let mut spooled = Vec::new();
+        Spool::open(&dir, 1 << 20).unwrap().drain(|payload| {
+            spooled.push(payload.to_vec());
+            true
+        });
+        assert_eq!(spooled, [message(1).payload(), message(2).payload()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_queues_suspend_reading() {
+        let mut config = Config::for_tests();
+        config.queue_capacity = 1;
+        let shared = SharedConfig::new(config);
+
+        let stats = SendStats::new();
+        let (release_tx, release_rx) = bounded::<()>(0);
+        let (sent_tx, sent_rx) = unbounded();
+        let transport = Box::new(Gated(release_rx, sent_tx));
+        let pipeline = Pipeline::with_transport(shared, &stats, transport).unwrap();
+
+        let (pushed_tx, pushed_rx) = unbounded();
+        let reader = thread::spawn(move || {
+            for n in 0..10 {
+                let record = json!({"MESSAGE": format!("record {}", n), "PRIORITY": "3"});
+                pipeline.push(record.to_string()).unwrap();
+                pushed_tx.send(n).unwrap();
+            }
+            pipeline.finish().unwrap();
+        });
+
+        // the sender, a worker and the queues between them hold a few records,
+        // while the reader waits for room
+        thread::sleep(Duration::from_millis(200));
+        assert!(pushed_rx.try_iter().count() < 10);
+
+        drop(release_tx);
+        reader.join().unwrap();
+        assert_eq!(sent_rx.try_iter().count(), 10);
+        assert_eq!(stats.records().snapshot().overflowed, 0);
+    }
+
+    #[test]
+    fn recycles_record_buffers() {
+        let stats = SendStats::new();
+        let (sent_tx, sent_rx) = unbounded();
+        let pipeline =
+            Pipeline::with_transport(shared(16), &stats, Box::new(Recorded(sent_tx))).unwrap();
+
+        let mut record = pipeline.buffer();
+        assert_eq!(record.capacity(), 0);
+        record.push_str(&json!({"MESSAGE": "hello", "PRIORITY": "3"}).to_string());
+        let capacity = record.capacity();
+        pipeline.push(record).unwrap();
+        sent_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // buffer is handed back by the worker once the record is transformed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let recycled = loop {
+            let buffer = pipeline.buffer();
+            if buffer.capacity() > 0 || Instant::now() > deadline {
+                break buffer;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(recycled.is_empty());
+        assert_eq!(recycled.capacity(), capacity);
+        pipeline.finish().unwrap();
+    }
+
+    #[test]
+    fn dry_run_sends_nothing() {
+        let target = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        target.set_nonblocking(true).unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![target.local_addr().unwrap().to_string()];
+        config.dry_run = true;
+        let stats = SendStats::new();
+        let pipeline = Pipeline::start(SharedConfig::new(config), &stats).unwrap();
+
+        pipeline
+            .push(json!({"MESSAGE": "sent", "PRIORITY": "3"}).to_string())
+            .unwrap();
+        pipeline
+            .push(json!({"MESSAGE": "filtered", "PRIORITY": "7"}).to_string())
+            .unwrap();
+        pipeline.push("not a record".to_string()).unwrap();
+        pipeline.finish().unwrap();
+
+        // records are summarized as usual
+        let records = stats.records().snapshot();
+        assert_eq!(
+            (records.accepted, records.filtered, records.errors),
+            (1, 1, 1)
+        );
+        assert!(target.recv(&mut [0; 1024]).is_err());
+    }
+
+    #[test]
+    fn strict_mode_reports_failed_record() {
+        let mut config = Config::for_tests();
+        config.strict = true;
+        let stats = SendStats::new();
+        let (sent_tx, _sent_rx) = unbounded();
+        let pipeline = Pipeline::with_transport(
+            SharedConfig::new(config),
+            &stats,
+            Box::new(Recorded(sent_tx)),
+        )
+        .unwrap();
+
+        let pushed = pipeline
+            .push(json!({"MESSAGE": "sent", "PRIORITY": "3"}).to_string())
+            .and_then(|_| pipeline.push("not a record".to_string()));
+        // failure is reported on the next push or on finish, whichever comes first
+        let failure = match pushed {
+            Err(e) => e,
+            Ok(()) => pipeline.finish().unwrap_err(),
+        };
+
+        match failure {
+            Error::StrictFailure(reason) => assert!(reason.ends_with("record: not a record")),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(stats.records().snapshot().errors, 1);
+    }
+
+    #[test]
+    fn stops_forwarding_at_limit() {
+        let mut config = Config::for_tests();
+        config.limit = Some(3);
+        let stats = SendStats::new();
+        let (sent_tx, sent_rx) = unbounded();
+        let pipeline = Pipeline::with_transport(
+            SharedConfig::new(config),
+            &stats,
+            Box::new(Recorded(sent_tx)),
+        )
+        .unwrap();
+
+        let mut pushed = 0;
+        while !pipeline.limit_reached() && pushed < 100 {
+            let record = json!({"MESSAGE": format!("record {}", pushed), "PRIORITY": "3"});
+            pipeline.push(record.to_string()).unwrap();
+            pushed += 1;
+            thread::sleep(Duration::from_millis(10));
+        }
+        pipeline.finish().unwrap();
+
+        assert!(pushed < 100, "limit was never reached");
+        assert_eq!(sent_rx.try_iter().count(), 3);
+    }
+}
@@ -0,0 +1,152 @@
+//! Preview of outgoing messages
+//!
+//! Serialized messages are printed to stdout either as one-liners of timestamp, level, unit
+//! and message, colorized by level when stdout is a terminal, or as lines of GELF JSON.
+
+use std::io::{self, IsTerminal, Write};
+
+use serde_json::{self, Value};
+
+use crate::gelf::{current_time_unix, LevelSystem};
+
+lazy_static! {
+    static ref COLORED: bool = io::stdout().is_terminal();
+}
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+
+/// Print serialized GELF message as a line to stdout.
+pub fn print(gelf: &[u8]) {
+    let message: Value = match serde_json::from_slice(gelf) {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("failed to preview message: {}", e);
+            return;
+        }
+    };
+
+    let line = format_line(&message, *COLORED);
+    let _ = io::stdout().lock().write_all(line.as_bytes());
+}
+
+/// Print serialized GELF message to stdout as a line of JSON.
+pub fn print_json(gelf: &[u8]) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(gelf).and_then(|_| stdout.write_all(b"\n"));
+}
+
+/// Format GELF message as a line, optionally with color escapes.
+fn format_line(message: &Value, colored: bool) -> String {
+    let timestamp = message["timestamp"]
+        .as_f64()
+        .unwrap_or_else(current_time_unix);
+    let level = LevelSystem::from(message["level"].as_u64().unwrap_or(6) as u8);
+    let unit = message["__SYSTEMD_UNIT"]
+        .as_str()
+        .or_else(|| message["_SYSLOG_IDENTIFIER"].as_str())
+        .unwrap_or("-");
+    let text = message["short_message"].as_str().unwrap_or_default();
+
+    if colored {
+        format!(
+            "{}{}{} {}{:<9}{} {}: {}\n",
+            DIM,
+            format_timestamp(timestamp),
+            RESET,
+            level_color(level),
+            level.to_string(),
+            RESET,
+            unit,
+            text
+        )
+    } else {
+        format!(
+            "{} {:<9} {}: {}\n",
+            format_timestamp(timestamp),
+            level.to_string(),
+            unit,
+            text
+        )
+    }
+}
+
+fn level_color(level: LevelSystem) -> &'static str {
+    match level {
+        LevelSystem::Emergency | LevelSystem::Alert | LevelSystem::Critical => "\x1b[1;31m",
+        LevelSystem::Error => "\x1b[31m",
+        LevelSystem::Warning => "\x1b[33m",
+        LevelSystem::Notice => "\x1b[36m",
+        LevelSystem::Informational => "\x1b[32m",
+        LevelSystem::Debug => DIM,
+    }
+}
+
+/// Format UNIX timestamp as UTC date and time with milliseconds.
+fn format_timestamp(timestamp: f64) -> String {
+    let millis = (timestamp * 1000.0) as i64;
+    let secs = millis.div_euclid(1000);
+    let (year, month, day) = civil_date(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis.rem_euclid(1000)
+    )
+}
+
+/// Convert days since UNIX epoch into a date of the proleptic Gregorian calendar.
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_formatting() {
+        assert_eq!(format_timestamp(0.0), "1970-01-01 00:00:00.000");
+        assert_eq!(
+            format_timestamp(1_700_000_000.123_456),
+            "2023-11-14 22:13:20.123"
+        );
+        assert_eq!(format_timestamp(951_825_600.5), "2000-02-29 12:00:00.500");
+    }
+
+    #[test]
+    fn line_formatting() {
+        let message = json!({
+            "short_message": "started",
+            "level": 4,
+            "timestamp": 1_700_000_000.0,
+            "__SYSTEMD_UNIT": "app.service"
+        });
+
+        assert_eq!(
+            format_line(&message, false),
+            "2023-11-14 22:13:20.000 warning   app.service: started\n"
+        );
+    }
+}
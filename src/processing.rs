@@ -1,260 +1,1999 @@
-use std::collections::HashMap;
-use std::io::{self, BufRead, BufReader};
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::borrow::Cow;
+use std::cell::Cell;
+#[cfg(feature = "simd")]
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, IoSlice, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::process;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
+use crossbeam_channel::{bounded, RecvTimeoutError};
 use regex::Regex;
-use serde_json;
-
-use errors::{Error, Result};
-
-use config::Config;
-use gelf::{ChunkSize, ChunkedMessage, Message, OptFieldsIterator, WireMessage};
-use gelf::{LevelMsg, LevelSystem};
-
-const IGNORED_FIELDS: [&str; 9] = [
-    "MESSAGE",
-    "_HOSTNAME",
-    "__REALTIME_TIMESTAMP",
-    "PRIORITY",
-    "__CURSOR",
-    "_BOOT_ID",
-    "_MACHINE_ID",
-    "_SYSTEMD_CGROUP",
-    "_SYSTEMD_SLICE",
-];
-
-type LogRecord = HashMap<String, serde_json::Value>;
-
-pub fn process_journalctl(config: Config) -> Result<()> {
-    // check OS
-    if !is_platform_supported() {
-        return Err(Error::InternalError(
-            "operating system currently unsupported".to_string(),
-        ));
+use serde_json::{self, Value};
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, Type};
+
+use crate::errors::{Error, Result};
+
+use crate::backoff::SendBackoff;
+use crate::chaos::Chaos;
+use crate::config::{Config, SharedConfig};
+use crate::cursor;
+use crate::dedup::{Dedup, REPEAT_COUNT_FIELD};
+use crate::embedded;
+use crate::export::{ExportReader, JournalFormat};
+use crate::facility::Facility;
+use crate::gelf::{current_time_unix, is_valid_field_name, LevelMsg, LevelSystem, RESERVED_FIELD};
+use crate::gelf::{
+    Chunk, ChunkedMessage, Message, MessageCompression, OptFieldsIterator, WireMessage,
+};
+use crate::ignore::{CONSUMED_FIELDS, DEFAULT_IGNORED_FIELDS};
+#[cfg(feature = "journald-native")]
+use crate::journal::Journal;
+use crate::logfmt;
+use crate::multiline::{Assembler, Multiline, FULL_MESSAGE_FIELD};
+use crate::oversize;
+use crate::pipeline::Pipeline;
+use crate::ratelimit::UNIT_FIELD;
+use crate::record::{self, Interned};
+use crate::stats::{RecordStats, SendStats, TargetStats};
+use crate::syslog;
+use crate::tail::{Pattern, Tail, POLL_INTERVAL};
+use crate::unit_rules::UnitRule;
+
+/// Initial guess of serialized message size, bytes
+const INITIAL_SERIALIZED_SIZE: usize = 512;
+
+pub use crate::record::LogRecord;
+
+thread_local! {
+    // moving average of serialized message sizes, used to pre-size buffers
+    static SERIALIZED_SIZE: Cell<usize> = const { Cell::new(INITIAL_SERIALIZED_SIZE) };
+}
+
+pub fn process_journalctl(config: SharedConfig, stats: SendStats) -> Result<()> {
+    check_journal_supported()?;
+
+    let after_cursor = match config.load().cursor_file {
+        Some(ref path) => cursor::load(path)?,
+        None => None,
+    };
+    if let Some(ref cursor) = after_cursor {
+        info!("resuming journal after cursor {}", cursor);
     }
 
-    let mut subprocess = process::Command::new("journalctl")
-        .args(&["-o", "json", "-f"])
+    #[cfg(feature = "journald-native")]
+    match Journal::open(
+        config.load().max_line,
+        stats.records(),
+        after_cursor.as_deref(),
+        &config.load().units,
+    ) {
+        Ok(journal) => {
+            debug!("start reading journal natively");
+            let pipeline = Pipeline::start(config, &stats)?;
+            return forward_records(journal, pipeline);
+        }
+        Err(e) => warn!("failed to open journal natively, using journalctl: {}", e),
+    }
+
+    let journal_format = config.load().journal_format;
+    let max_line = config.load().max_line;
+    let mut subprocess = journalctl_command(&config.load(), after_cursor.as_deref())
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::piped())
-        .spawn()?;
+        .spawn()
+        .map_err(journalctl_failed)?;
+    stats.subprocess_started("journalctl", subprocess.id());
 
     // Dirty trick. In theory it doesn't have to work, because an operating system
     // is allowed to make the BufReader wait for more data in read, but in practice
     // the operating systems prefer the early "short reads" to waiting.
-    let mut subprocess_stdout = BufReader::new(subprocess.stdout.as_mut().unwrap());
-    let mut subprocess_stderr = BufReader::new(subprocess.stderr.as_mut().unwrap());
+    let subprocess_stdout =
+        BufReader::with_capacity(config.load().read_buffer, subprocess.stdout.take().unwrap());
 
-    // bind to socket
-    let sender = create_sender_udp(config.sender_port)?;
+    let complaints = drain_complaints(subprocess.stderr.take().unwrap())?;
 
-    // obtain target address (first resolve may fail)
-    let (mut target_addr, mut target_addr_updated_at) = get_target_addr(&config.graylog_addr)?;
+    let pipeline = Pipeline::start(config, &stats)?;
 
     debug!("start reading from journalctl");
 
-    let mut buff = String::new();
+    let records: Box<dyn Iterator<Item = Result<LogRecord>>> = match journal_format {
+        JournalFormat::Json => Box::new(json_records(subprocess_stdout, max_line, stats.records())),
+        JournalFormat::Export => Box::new(ExportReader::new(
+            subprocess_stdout,
+            max_line,
+            stats.records(),
+        )),
+    };
+    for record in records {
+        match record {
+            Ok(record) => pipeline.push_decoded(record)?,
+
+            Err(e) => {
+                pipeline.finish()?;
+                subprocess.kill()?;
+                subprocess.wait()?;
+                return Err(e);
+            }
+        }
+
+        if pipeline.limit_reached() {
+            pipeline.finish()?;
+            info!("limit of forwarded messages reached");
+            subprocess.kill()?;
+            subprocess.wait()?;
+            return Ok(());
+        }
+    }
+
+    // stdout was closed
+    pipeline.finish()?;
+
+    let status = subprocess.wait()?;
+    stats.subprocess_exited(status);
+    let reason = complaints.join().unwrap_or_default();
+
+    Err(Error::InternalError(format!(
+        "journalctl exited with {}: {}",
+        status, reason
+    )))
+}
+
+/// Build journalctl command following records of the configured units, after the cursor if any.
+fn journalctl_command(config: &Config, after_cursor: Option<&str>) -> process::Command {
+    let mut journalctl = process::Command::new("journalctl");
+    journalctl.args(["-o", config.journal_format.journalctl_output(), "-f"]);
+    if let Some(cursor) = after_cursor {
+        journalctl.arg(format!("--after-cursor={}", cursor));
+    }
+    for unit in &config.units {
+        journalctl.args(["-u", unit]);
+    }
+    journalctl
+}
+
+pub fn process_stdin(config: SharedConfig, stats: SendStats) -> Result<()> {
+    if let Some(multiline) = config.load().multiline.clone() {
+        if config.load().journal_format == JournalFormat::Json {
+            let pipeline = Pipeline::start(config, &stats)?;
+            debug!("start reading from stdin, merging multi-line messages");
+            return forward_multiline(&multiline, pipeline, stats.records());
+        }
+    }
+
+    let mut stdin = BufReader::with_capacity(config.load().read_buffer, io::stdin().lock());
+    let journal_format = config.load().journal_format;
+    let max_line = config.load().max_line;
+    let pipeline = Pipeline::start(config, &stats)?;
+    let records = stats.records();
+
+    debug!("start reading from stdin");
+
+    if journal_format == JournalFormat::Export {
+        return forward_records(ExportReader::new(stdin, max_line, records), pipeline);
+    }
+
     loop {
-        subprocess_stdout.read_line(&mut buff)?;
+        // lines are read as bytes, so that invalid UTF-8 never stops reading
+        let mut log_line = pipeline.buffer().into_bytes();
 
-        {
-            let msg = buff.trim();
+        match read_line(&mut stdin, &mut log_line, max_line) {
+            Ok(Line::End) => break,
+
+            Ok(Line::Complete) => pipeline.push(line_text(log_line, &records))?,
+
+            Ok(Line::Oversized(len)) => line_oversized(len, max_line, &records),
+
+            Err(err) => {
+                pipeline.finish()?;
+                return Err(Error::from(err));
+            }
+        }
+
+        if pipeline.limit_reached() {
+            info!("limit of forwarded messages reached");
+            break;
+        }
+    }
+
+    pipeline.finish()
+}
+
+pub fn process_files(config: SharedConfig, stats: SendStats) -> Result<()> {
+    let patterns = config
+        .load()
+        .file_paths
+        .iter()
+        .map(|path| Pattern::parse(path))
+        .collect::<Result<Vec<_>>>()?;
+    let records = stats.records();
+    let mut tail = Tail::open(
+        patterns,
+        config.load().cursor_file.as_deref(),
+        config.load().max_line,
+        records.clone(),
+    )?;
+    let pipeline = Pipeline::start(config, &stats)?;
+
+    debug!("start tailing files");
+
+    loop {
+        let read = tail.poll(|path, line| {
+            let mut record = LogRecord::new();
+            record.insert(record::intern("MESSAGE"), json!(line_text(line, &records)));
+            record.insert(record::intern("FILE"), json!(path.to_string_lossy()));
+            pipeline.push_decoded(record)
+        });
+
+        match read {
+            Ok(0) => {
+                tail.save();
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            Ok(_) => {}
+
+            Err(e) => {
+                pipeline.finish()?;
+                return Err(e);
+            }
+        }
+
+        if pipeline.limit_reached() {
+            info!("limit of forwarded messages reached");
+            break;
+        }
+    }
+
+    pipeline.finish()
+}
+
+pub fn process_syslog(config: SharedConfig, stats: SendStats) -> Result<()> {
+    let (addr, protocol) = {
+        let config = config.load();
+        (
+            config.listen_addr.clone().unwrap_or_default(),
+            config.listen_protocol,
+        )
+    };
+    let records = syslog::listen(
+        &addr,
+        protocol,
+        config.load().max_line,
+        config.load().queue_capacity,
+        stats.records(),
+    )?;
+    let pipeline = Pipeline::start(config, &stats)?;
 
-            // verify if stdout was closed
-            if msg.is_empty() {
-                let mut err_buff = String::new();
-                subprocess_stderr.read_line(&mut err_buff)?;
-                return Err(Error::InternalError(err_buff));
+    info!(
+        "listening for syslog messages on {} over {}",
+        addr, protocol
+    );
+
+    forward_records(records.into_iter().map(Ok), pipeline)
+}
+
+/// Forward records read from stdin, merging continuation lines into the records they belong to.
+///
+/// Lines are read by a separate thread, so that the held record is passed on
+/// once no lines come within the timeout.
+fn forward_multiline(
+    multiline: &Multiline,
+    pipeline: Pipeline,
+    records: Arc<RecordStats>,
+) -> Result<()> {
+    let (read_buffer, max_line, capacity) = {
+        let config = pipeline.config().load();
+        (config.read_buffer, config.max_line, config.queue_capacity)
+    };
+    let (lines_tx, lines_rx) = bounded(capacity);
+
+    let stats = records;
+    thread::Builder::new()
+        .name("stdin".to_string())
+        .spawn(move || {
+            let mut input = BufReader::with_capacity(read_buffer, io::stdin());
+            loop {
+                let mut line = Vec::new();
+                let read = match read_line(&mut input, &mut line, max_line) {
+                    Ok(Line::End) => return,
+                    Ok(Line::Complete) => Ok(line_text(line, &stats)),
+                    Ok(Line::Oversized(len)) => {
+                        line_oversized(len, max_line, &stats);
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                };
+
+                let failed = read.is_err();
+                if lines_tx.send(read).is_err() || failed {
+                    return;
+                }
             }
+        })?;
 
-            // renew outdated target address
-            if target_addr_updated_at
-                .elapsed()
-                .unwrap_or_default()
-                .as_secs()
-                > config.graylog_addr_ttl
-            {
-                match get_target_addr(&config.graylog_addr) {
-                    Ok((addr, updated_at)) => {
-                        target_addr = addr;
-                        target_addr_updated_at = updated_at;
-                        debug!("target address updated");
+    let mut assembler = Assembler::new(multiline);
+
+    loop {
+        let completed = match lines_rx.recv_timeout(multiline.timeout) {
+            Ok(Ok(text)) => {
+                let mut record = LogRecord::new();
+                match decode(text.trim(), &mut record) {
+                    Ok(()) => assembler.push(record),
+
+                    // line is passed as is, so that workers account the failure
+                    Err(_) => {
+                        if let Some(held) = assembler.flush() {
+                            pipeline.push_decoded(held)?;
+                        }
+                        pipeline.push(text)?;
+                        None
                     }
+                }
+            }
 
-                    // use outdated address
-                    Err(e) => warn!("cannot resolve graylog address: {}", e),
+            Ok(Err(e)) => {
+                if let Some(held) = assembler.flush() {
+                    pipeline.push_decoded(held)?;
                 }
+                pipeline.finish()?;
+                return Err(Error::from(e));
             }
 
-            process_log_record(msg, &config, &sender, &target_addr);
+            Err(RecvTimeoutError::Timeout) => assembler.flush(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if let Some(record) = completed {
+            pipeline.push_decoded(record)?;
         }
 
-        buff.clear();
+        if pipeline.limit_reached() {
+            info!("limit of forwarded messages reached");
+            return pipeline.finish();
+        }
     }
-}
 
-pub fn process_stdin(config: Config) -> Result<()> {
-    // bind to socket
-    let sender = create_sender_udp(config.sender_port)?;
+    if let Some(held) = assembler.flush() {
+        pipeline.push_decoded(held)?;
+    }
 
-    // obtain target address (first resolve may fail)
-    let (mut target_addr, mut target_addr_updated_at) = get_target_addr(&config.graylog_addr)?;
+    pipeline.finish()
+}
 
-    debug!("start reading from stdin");
+/// Decode JSON records right from the reader, whatever way they are split into lines.
+///
+/// Malformed record is reported and counted as failed, and decoding goes on from the next line.
+/// Records longer than `max_line` bytes are skipped the same way and counted as oversized.
+fn json_records<R: BufRead>(
+    mut input: R,
+    max_line: usize,
+    stats: Arc<RecordStats>,
+) -> impl Iterator<Item = Result<LogRecord>> {
+    std::iter::from_fn(move || loop {
+        match skip_whitespace(&mut input) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(Error::from(e))),
+        }
 
-    let stdin_stream = io::stdin();
-    for raw in stdin_stream.lock().lines() {
-        // renew outdated target address
-        if target_addr_updated_at
-            .elapsed()
-            .unwrap_or_default()
-            .as_secs()
-            > config.graylog_addr_ttl
+        let mut limited = (&mut input).take(max_line as u64);
+        let failure = match serde_json::Deserializer::from_reader(&mut limited)
+            .into_iter::<Interned>()
+            .next()
         {
-            match get_target_addr(&config.graylog_addr) {
-                Ok((addr, updated_at)) => {
-                    target_addr = addr;
-                    target_addr_updated_at = updated_at;
-                    debug!("target address updated");
-                }
+            Some(Ok(Interned(record))) => return Some(Ok(record)),
+            Some(Err(e)) if e.is_io() => return Some(Err(Error::from(e))),
+            Some(Err(e)) => Some(e),
+            None => None,
+        };
+        let exhausted = limited.limit() == 0;
+
+        // the rest of the record is skipped up to the next line
+        let skipped = match read_line(&mut input, &mut Vec::new(), 0) {
+            Ok(Line::Oversized(len)) => len,
+            Ok(_) => 0,
+            Err(e) => return Some(Err(Error::from(e))),
+        };
 
-                // use outdated address
-                Err(e) => warn!("cannot resolve graylog address: {}", e),
+        match failure {
+            Some(e) if !exhausted => {
+                warn!("malformed journal record skipped: {}", e);
+                stats.record_failed();
             }
+            _ => line_oversized(max_line as u64 + skipped, max_line, &stats),
         }
+    })
+}
+
+/// Consume whitespace between records, returning whether there is anything left to read.
+fn skip_whitespace<R: BufRead>(input: &mut R) -> io::Result<bool> {
+    loop {
+        let available = match input.fill_buf() {
+            Ok(available) => available,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(false);
+        }
+
+        let spaces = available
+            .iter()
+            .take_while(|c| c.is_ascii_whitespace())
+            .count();
+        let rest = spaces < available.len();
+        input.consume(spaces);
+        if rest {
+            return Ok(true);
+        }
+    }
+}
+
+/// Forward decoded records until input ends.
+fn forward_records<I>(records: I, pipeline: Pipeline) -> Result<()>
+where
+    I: Iterator<Item = Result<LogRecord>>,
+{
+    for record in records {
+        match record {
+            Ok(record) => pipeline.push_decoded(record)?,
 
-        match raw {
-            Ok(log_line) => {
-                process_log_record(&log_line.trim(), &config, &sender, &target_addr);
+            Err(e) => {
+                pipeline.finish()?;
+                return Err(e);
             }
+        }
 
-            Err(err) => return Err(Error::from(err)),
+        if pipeline.limit_reached() {
+            info!("limit of forwarded messages reached");
+            break;
         }
     }
 
-    Ok(())
+    pipeline.finish()
 }
 
-fn process_log_record(data: &str, config: &Config, sender: &UdpSocket, target: &SocketAddr) {
-    match transform_record(data, config) {
-        Ok(compressed_gelf) => {
-            if let Some(chunked) = ChunkedMessage::new(ChunkSize::WAN, compressed_gelf) {
-                for chunk in chunked.iter() {
-                    if let Err(e) = sender.send_to(&chunk, &target) {
-                        error!("sender failure: {}", e);
-                    }
+/// UDP sockets along with bookkeeping of their target
+pub(crate) struct UdpSender {
+    port: u16,
+    send_buffer: Option<usize>,
+    socket_v4: Option<UdpSocket>,
+    socket_v6: Option<UdpSocket>,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl UdpSender {
+    /// Bind sockets for address families of all given targets.
+    pub(crate) fn new(
+        port: u16,
+        send_buffer: Option<usize>,
+        targets: &[SocketAddr],
+        stats: Arc<TargetStats>,
+    ) -> Result<Self> {
+        let mut sender = UdpSender {
+            port,
+            send_buffer,
+            socket_v4: None,
+            socket_v6: None,
+            stats,
+            backoff: SendBackoff::new(),
+        };
+
+        for target in targets {
+            sender.socket(target)?;
+        }
+
+        Ok(sender)
+    }
+
+    /// Return socket suitable for the target, binding it on first use.
+    fn socket(&mut self, target: &SocketAddr) -> io::Result<&UdpSocket> {
+        let socket = match *target {
+            SocketAddr::V4(_) => &mut self.socket_v4,
+            SocketAddr::V6(_) => &mut self.socket_v6,
+        };
+
+        if socket.is_none() {
+            *socket = Some(create_sender_udp(target, self.port, self.send_buffer)?);
+        }
+
+        Ok(socket.as_ref().unwrap())
+    }
+
+    /// Send all chunks of the message, unless target is in backoff, returning whether it was sent.
+    pub(crate) fn send(
+        &mut self,
+        chunked: &ChunkedMessage,
+        target: &SocketAddr,
+        chaos: Option<&Chaos>,
+    ) -> bool {
+        if !self.backoff.ready() {
+            self.stats.message_dropped();
+            return false;
+        }
+
+        for chunk in chunked.chunks() {
+            let sent = self.socket(target).and_then(|socket| match chaos {
+                Some(chaos) => chaos
+                    .before_send()
+                    .and_then(|_| send_chunk(socket, &chunk, target)),
+                None => send_chunk(socket, &chunk, target),
+            });
+
+            match sent {
+                Ok(sent) => self.stats.chunk_sent(sent as u64),
+                Err(e) => {
+                    // rest of the chunks are useless without this one
+                    self.stats.chunk_failed(&e);
+                    self.stats.message_dropped();
+                    self.backoff.failure(e);
+                    return false;
                 }
             }
         }
 
-        // ignore
-        Err(Error::InsufficientLogLevel) => {}
+        self.stats.message_sent();
+        self.backoff.success();
+        true
+    }
+}
+
+/// Send header and payload of the chunk in a single datagram, without copying them together.
+pub(crate) fn send_chunk(
+    socket: &UdpSocket,
+    chunk: &Chunk,
+    target: &SocketAddr,
+) -> io::Result<usize> {
+    SockRef::from(socket).send_to_vectored(&chunk.io_slices(), &SockAddr::from(*target))
+}
+
+/// Write all the slices with vectored writes, without copying them together.
+///
+/// Short write may end in the middle of a slice, the rest of it is written next.
+pub fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut slices: &mut [IoSlice],
+) -> io::Result<()> {
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Transform record into a serialized, but not yet compressed GELF message.
+/// Records which should not be sent are reported into the log,
+/// or in strict mode failed ones are returned as an error.
+pub(crate) fn serialize_record(
+    data: &str,
+    config: &Config,
+    scratch: &mut LogRecord,
+    stats: &RecordStats,
+) -> Result<Option<Vec<u8>>> {
+    let data = match config.chaos {
+        Some(ref chaos) => chaos.corrupt_input(data),
+        None => Cow::Borrowed(data),
+    };
+
+    let result = transform_record(&data, config, scratch, stats);
+    account_record(result, || data.to_string(), config, stats)
+}
+
+/// Transform already decoded record into a serialized, but not yet compressed GELF message.
+/// Records which should not be sent are reported into the log,
+/// or in strict mode failed ones are returned as an error.
+pub(crate) fn serialize_decoded(
+    record: &mut LogRecord,
+    config: &Config,
+    stats: &RecordStats,
+) -> Result<Option<Vec<u8>>> {
+    let result = transform_decoded(record, config, stats);
+    // fields left in the record after failure are good enough to recognize it
+    let data = || {
+        let fields = record.iter().map(|(k, v)| (k.to_string(), v.clone()));
+        Value::Object(fields.collect()).to_string()
+    };
+    account_record(result, data, config, stats)
+}
+
+/// Outcome of reading a line of limited length
+#[derive(Debug, PartialEq)]
+pub(crate) enum Line {
+    End,
+    Complete,
+    /// Line is skipped, given its total length
+    Oversized(u64),
+}
+
+/// Read line up to the newline or the end of input, skipping lines longer than `max` bytes.
+pub(crate) fn read_line<R: BufRead>(
+    input: &mut R,
+    line: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<Line> {
+    let mut read: u64 = 0;
+
+    loop {
+        let available = match input.fill_buf() {
+            Ok(available) => available,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+
+        let (used, complete) = match available.iter().position(|&c| c == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (available.len(), false),
+        };
+
+        // beyond the limit the rest of the line is only consumed
+        read += used as u64;
+        if read <= max as u64 {
+            line.extend_from_slice(&available[..used]);
+        } else {
+            line.clear();
+        }
+
+        input.consume(used);
+        if complete {
+            break;
+        }
+    }
+
+    Ok(line_outcome(read, max))
+}
+
+/// Return outcome of reading line of given total length.
+pub(crate) fn line_outcome(read: u64, max: usize) -> Line {
+    match read {
+        0 => Line::End,
+        read if read > max as u64 => Line::Oversized(read),
+        _ => Line::Complete,
+    }
+}
+
+/// Account a line dropped for exceeding the length limit.
+pub(crate) fn line_oversized(len: u64, max: usize, stats: &RecordStats) {
+    warn!(
+        "input line of {} bytes exceeds limit of {} bytes, dropped",
+        len, max
+    );
+    stats.record_oversized();
+}
+
+/// Convert line read as bytes into text, replacing invalid UTF-8 sequences.
+pub(crate) fn line_text(line: Vec<u8>, stats: &RecordStats) -> String {
+    String::from_utf8(line).unwrap_or_else(|e| {
+        stats.record_invalid_utf8();
+        String::from_utf8_lossy(e.as_bytes()).into_owned()
+    })
+}
+
+/// Compress serialized GELF message and split it into chunks.
+pub(crate) fn compress_message(gelf: Vec<u8>, config: &Config) -> Option<ChunkedMessage> {
+    if !config.is_chunked() {
+        return match whole_message(gelf, config) {
+            Ok(chunked) => Some(chunked),
+            Err(e) => {
+                warn!("compression error: {}", e);
+                None
+            }
+        };
+    }
+
+    let compressed_gelf = match compression_of(gelf.len(), config).compress_bytes(gelf) {
+        Ok(compressed_gelf) => compressed_gelf,
+        Err(e) => {
+            warn!("compression error: {}", e);
+            return None;
+        }
+    };
+
+    match ChunkedMessage::new(config.chunk_size, compressed_gelf) {
+        Ok(chunked) => Some(chunked),
+        Err(e) => {
+            warn!("message dropped: {}", e);
+            None
+        }
+    }
+}
+
+/// Return message passed whole over a stream or into a file.
+///
+/// Compressed message carries its terminator, so that concatenated messages decompress
+/// into the plain stream or file. All of them are compressed then, regardless of the size.
+pub(crate) fn whole_message(mut gelf: Vec<u8>, config: &Config) -> Result<ChunkedMessage> {
+    if config.compression == MessageCompression::None {
+        return Ok(ChunkedMessage::whole(gelf));
+    }
+
+    gelf.push(config.terminator());
+    Ok(ChunkedMessage::whole(
+        config.compression.compress_bytes(gelf)?,
+    ))
+}
+
+/// Return the algorithm to compress message of given size with, small ones are not worth compressing.
+pub(crate) fn compression_of(len: usize, config: &Config) -> MessageCompression {
+    if len < config.compress_min {
+        MessageCompression::None
+    } else {
+        config.compression
+    }
+}
+
+/// Restore message to be sent from its payload, e.g. the one spooled before.
+pub(crate) fn rechunk_message(payload: &[u8], config: &Config) -> Option<ChunkedMessage> {
+    if !config.is_chunked() {
+        return Some(ChunkedMessage::whole(payload.to_vec()));
+    }
 
-        Err(Error::NoMessage) => debug!("no message field found"),
+    match ChunkedMessage::new(config.chunk_size, payload.to_vec()) {
+        Ok(chunked) => Some(chunked),
+        Err(e) => {
+            warn!("message dropped: {}", e);
+            None
+        }
+    }
+}
+
+fn account_record<F>(
+    result: Result<Vec<u8>>,
+    data: F,
+    config: &Config,
+    stats: &RecordStats,
+) -> Result<Option<Vec<u8>>>
+where
+    F: FnOnce() -> String,
+{
+    match result {
+        Ok(gelf) => {
+            stats.record_accepted();
+            return Ok(Some(gelf));
+        }
+
+        Err(Error::InsufficientLogLevel)
+        | Err(Error::Filtered)
+        | Err(Error::RateLimited)
+        | Err(Error::Sampled)
+        | Err(Error::Repeated) => stats.record_filtered(),
+
+        Err(e @ Error::MessageTooLarge { .. }) => {
+            stats.record_oversized();
+            warn!("message dropped: {}", e);
+        }
+
+        Err(Error::NoMessage) => {
+            stats.record_without_message();
+            if config.strict {
+                return Err(Error::StrictFailure(format!(
+                    "no message, record: {}",
+                    data()
+                )));
+            }
+            debug!("no message field found");
+        }
 
-        Err(e) => warn!("parsing error: {}, message: {}", e, data),
+        Err(e) => {
+            stats.record_failed();
+            if config.strict {
+                return Err(Error::StrictFailure(format!("{}, record: {}", e, data())));
+            }
+            warn!("parsing error: {}, message: {}", e, data());
+        }
     }
+
+    Ok(None)
 }
 
-/// Try to decode original JSON, transform fields to GELF format, serialize and compress it.
-fn transform_record(data: &str, config: &Config) -> Result<Vec<u8>> {
-    // decode
-    let decoded: LogRecord = serde_json::from_str(data)?;
+/// Try to decode original JSON, transform fields to GELF format and serialize it.
+///
+/// Record is decoded into `decoded` map, which is left empty
+/// but keeps its allocated capacity for the next record.
+fn transform_record(
+    data: &str,
+    config: &Config,
+    decoded: &mut LogRecord,
+    stats: &RecordStats,
+) -> Result<Vec<u8>> {
+    decode(data, decoded)?;
+    transform_decoded(decoded, config, stats)
+}
+
+/// Decode JSON record into the map with the parser chosen at build time.
+pub(crate) fn decode(data: &str, decoded: &mut LogRecord) -> Result<()> {
+    #[cfg(feature = "simd")]
+    return decode_record_simd(data, decoded);
+    #[cfg(not(feature = "simd"))]
+    decode_record(data, decoded)
+}
+
+/// Transform fields of decoded record to GELF format and serialize it.
+///
+/// Fields dropped on the way, e.g. the reserved one, are accounted in `stats`.
+pub(crate) fn transform_decoded(
+    decoded: &mut LogRecord,
+    config: &Config,
+    stats: &RecordStats,
+) -> Result<Vec<u8>> {
+    // filter by facility, records without one are never selected
+    if !config.facilities.is_empty() {
+        match get_facility(decoded) {
+            Some(facility) if config.facilities.contains(&facility) => {}
+            _ => return Err(Error::Filtered),
+        }
+    }
+
+    // absolutely mandatory field, taken out of the record to avoid copying
+    let short_msg = decoded.remove("MESSAGE").ok_or(Error::NoMessage)?;
+    let binary = short_msg.is_array();
+    let short_msg = field_text(short_msg);
 
-    // absolutely mandatory field
-    let short_msg = decoded
-        .get("MESSAGE")
-        .ok_or(Error::NoMessage)?
-        .to_owned()
-        .to_string();
+    // rules of the unit are looked up once per record
+    let rule = unit_rule(decoded, config);
 
-    let host = decoded
-        .get("_HOSTNAME")
-        .map_or("undefined".to_string(), |h| h.to_string());
+    if !message_selected(&short_msg, config) || rule.is_some_and(|rule| rule.excludes(&short_msg)) {
+        return Err(Error::Filtered);
+    }
+
+    let hostname = decoded.remove("_HOSTNAME").map(field_text);
+    let host = hostname.as_deref().unwrap_or("undefined");
 
     // filter by message level
-    if config.log_level_message.is_some() {
+    if let Some(threshold) = config.log_level_message {
         if let Some(msg_level) = get_msg_log_level(&short_msg) {
-            if msg_level > config.log_level_message.unwrap() {
+            if msg_level > threshold {
                 return Err(Error::InsufficientLogLevel);
             }
         }
     }
 
+    // identity of the message, taken before its text is moved
+    let dedup_key = config.dedup.as_ref().map(|_| {
+        let unit = decoded
+            .get(UNIT_FIELD)
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        Dedup::key(&short_msg, host, unit)
+    });
+
+    // structured message is split into fields, its text becomes the message
+    let (short_msg, message_level, mut message_fields) = split_message(short_msg, config);
+
+    for extractor in &config.extractors {
+        let extracted = extractor.extract(&short_msg);
+        message_fields.extend(extracted.into_iter().map(|(k, v)| (k, Value::String(v))));
+    }
+
     // create GELF-message
-    let mut msg = Message::new(&host, short_msg);
+    let mut msg = Message::new(host, short_msg);
 
-    // filter by system log-level
-    if let Some(log_level) = decoded
-        .get("PRIORITY")
-        .and_then(|raw_level| raw_level.as_str())
-        .and_then(|value| value.parse::<u8>().ok())
-        .map(LevelSystem::from)
-    {
-        if log_level > config.log_level_system {
+    // lines merged into the record, or its own full message
+    if let Some(full_msg) = decoded.remove(FULL_MESSAGE_FIELD) {
+        msg.set_full_message(field_text(full_msg));
+    }
+
+    // filter by system log-level, the one of the message takes precedence
+    let level = message_level.or_else(|| get_sys_log_level(decoded));
+    if let Some(log_level) = level {
+        if log_level > system_threshold(rule, config) {
             return Err(Error::InsufficientLogLevel);
         }
 
         msg.set_level(log_level);
     }
 
+    if let Some(ref sampling) = config.sampling {
+        if !sampling.sample(level) {
+            return Err(Error::Sampled);
+        }
+    }
+
+    // repeats of the previous message are collapsed, without taking tokens
+    let repeat = match (&config.dedup, dedup_key) {
+        (Some(dedup), Some(key)) => dedup.observe(key).map(|repeats| (dedup, key, repeats)),
+        _ => None,
+    };
+
+    // only records passing filters take tokens of their unit
+    if let (Some(limiter), None) = (&config.rate_limiter, repeat) {
+        let unit = decoded
+            .get(UNIT_FIELD)
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        if !limiter.admit(unit) {
+            return Err(Error::RateLimited);
+        }
+    }
+
     // timestamp
-    if let Some(ts) = decoded.get("__REALTIME_TIMESTAMP") {
-        // convert from systemd's format of microseconds expressed as
-        // an integer to graylog's float format, eg: "seconds.microseconds"
-        ts.as_str()
-            .and_then(|s| s.parse::<f64>().ok())
-            .map(|t| msg.set_timestamp(t / 1_000_000_f64));
+    let timestamp = if config.monotonic_time {
+        get_monotonic_timestamp(decoded).or_else(|| get_timestamp(decoded))
+    } else {
+        get_timestamp(decoded)
+    };
+    if let Some(ts) = timestamp {
+        msg.set_timestamp(ts);
     }
 
-    // additional fields
-    for (k, v) in decoded.into_iter() {
-        if is_metadata(&k) {
-            msg.set_metadata(k, v);
+    // additional fields, names outside of GELF's allowed set are renamed afterwards
+    let mut invalid = Vec::new();
+    let mut mapped = Vec::new();
+    for (k, v) in decoded.drain() {
+        if !is_metadata(&k, config) {
+            continue;
+        }
+
+        if let Some((_, to)) = config.field_renames.iter().find(|(from, _)| **from == *k) {
+            mapped.push((to, v));
+            continue;
+        }
+
+        if &*k == RESERVED_FIELD {
+            if config.rename_reserved {
+                invalid.push((k, v));
+            } else {
+                warn!(
+                    "field dropped: {}, use --rename-reserved to keep it",
+                    Error::ReservedField(k.to_string())
+                );
+                stats.field_dropped();
+            }
+        } else if is_valid_field_name(&k) {
+            msg.set_metadata(k, v)?;
+        } else {
+            invalid.push((k, v));
         }
     }
 
-    config.compression.compress(&WireMessage::new(
-        msg,
-        OptFieldsIterator::new(&config.optional),
-    ))
+    // renamed fields replace the ones of the same name
+    for (name, v) in mapped {
+        msg.set_metadata(record::intern(name), v)?;
+    }
+
+    if !invalid.is_empty() {
+        // sorted for the same record to be renamed the same way every time
+        invalid.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut renamed = Vec::with_capacity(invalid.len());
+        for (k, v) in invalid {
+            let normalized = if &*k == RESERVED_FIELD {
+                String::from("record_id")
+            } else {
+                normalize_field_name(&k)
+            };
+            let mut name = normalized.clone();
+            let mut n = 1;
+            while msg.metadata(&name).is_some() {
+                n += 1;
+                name = format!("{}_{}", normalized, n);
+            }
+
+            renamed.push(format!("{} -> {}", k, name));
+            msg.set_metadata(name, v)?;
+        }
+
+        msg.set_metadata(
+            record::intern("field_renamed"),
+            Value::String(renamed.join(", ")),
+        )?;
+    }
+
+    // fields of the record take precedence over the ones of its message
+    for (k, v) in message_fields {
+        let name = match normalize_field_name(&k) {
+            name if name != RESERVED_FIELD => name,
+            _ if config.rename_reserved => String::from("record_id"),
+            _ => continue,
+        };
+        if msg.metadata(&name).is_none() {
+            msg.set_metadata(name, v)?;
+        }
+    }
+
+    // message was not valid UTF-8, so its text is lossy
+    if binary {
+        msg.set_metadata(record::intern("binary_message"), Value::Bool(true))?;
+    }
+
+    // origin of the record, overriding field of the same name
+    if let Some(ref tag) = config.input_tag {
+        msg.set_metadata(record::intern("input"), Value::String(tag.clone()))?;
+    }
+
+    set_static_fields(&mut msg, config)?;
+
+    // fields of the unit override the common ones
+    for (name, value) in rule.iter().flat_map(|rule| &rule.fields) {
+        msg.set_metadata(record::intern(name), Value::String(value.clone()))?;
+    }
+
+    if let Some((_, _, repeats)) = repeat {
+        msg.set_metadata(record::intern(REPEAT_COUNT_FIELD), Value::from(repeats))?;
+    }
+
+    // secrets are masked in everything sent
+    if let Some(ref redactor) = config.redactor {
+        redactor.redact_message(&mut msg);
+    }
+
+    let mut wire = WireMessage::new(msg, OptFieldsIterator::new(&config.optional));
+
+    let gelf = SERIALIZED_SIZE.with(|average| {
+        // leave some room for messages slightly bigger than usual
+        let gelf = wire.to_gelf_bytes(average.get() + average.get() / 4)?;
+        average.set((average.get() * 7 + gelf.len()) / 8);
+        Ok::<_, Error>(gelf)
+    })?;
+
+    // GELF over streams is not chunked, so messages of any size are sent
+    let gelf = if !config.is_chunked() {
+        gelf
+    } else {
+        let compression = compression_of(gelf.len(), config);
+        oversize::fit(
+            &mut wire,
+            gelf,
+            config.chunk_size,
+            compression,
+            config.oversize_policy,
+        )?
+    };
+
+    // repeat is sent later as the collapsed message of its run
+    if let Some((dedup, key, repeats)) = repeat {
+        dedup.collapse(key, repeats, gelf);
+        return Err(Error::Repeated);
+    }
+
+    Ok(gelf)
+}
+
+/// Attach additional fields given by the config, overriding fields of the same name.
+pub(crate) fn set_static_fields(msg: &mut Message, config: &Config) -> Result<()> {
+    for (name, value) in &config.fields {
+        msg.set_metadata(record::intern(name), Value::String(value.clone()))?;
+    }
+    Ok(())
+}
+
+/// Split structured message, either JSON or logfmt one if enabled,
+/// into its text, level and fields. Other messages are left as is.
+fn split_message(
+    text: String,
+    config: &Config,
+) -> (String, Option<LevelSystem>, Vec<(String, Value)>) {
+    if config.parse_json_message {
+        if let Some(embedded) = embedded::parse(&text) {
+            let message = embedded.message.unwrap_or(text);
+            return (message, embedded.level, embedded.fields);
+        }
+    }
+
+    if config.parse_logfmt {
+        if let Some(mut pairs) = logfmt::parse(&text) {
+            let message = match pairs.iter().position(|(k, _)| k == logfmt::MESSAGE_KEY) {
+                Some(i) => pairs.remove(i).1,
+                None => text,
+            };
+            let fields = pairs
+                .into_iter()
+                .map(|(k, v)| (k, Value::String(v)))
+                .collect();
+            return (message, None, fields);
+        }
+    }
+
+    (text, None, Vec::new())
+}
+
+/// Return text of the field value as is, without JSON quotes and escapes.
+///
+/// Journald encodes values which are not valid UTF-8 as arrays of bytes,
+/// those are decoded replacing invalid sequences.
+fn field_text(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        Value::Array(items) => {
+            let bytes: Option<Vec<u8>> = items
+                .iter()
+                .map(|item| item.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect();
+            match bytes {
+                Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                None => Value::Array(items).to_string(),
+            }
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Decode JSON record into the map, reusing its allocation.
+fn decode_record(data: &str, decoded: &mut LogRecord) -> Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_str(data);
+    record::deserialize_into(&mut deserializer, decoded)?;
+    deserializer.end()?;
+    Ok(())
+}
+
+/// Decode JSON record into the map with SIMD-accelerated parser.
+///
+/// Records rejected by simd-json are decoded once again by serde_json,
+/// which also provides consistent error messages.
+#[cfg(feature = "simd")]
+fn decode_record_simd(data: &str, decoded: &mut LogRecord) -> Result<()> {
+    thread_local! {
+        // simd-json parses in place, so it needs a mutable copy of the input
+        static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    let parsed = SCRATCH.with(|scratch| {
+        let mut bytes = scratch.borrow_mut();
+        bytes.clear();
+        bytes.extend_from_slice(data.as_bytes());
+        simd_json::serde::from_slice::<Interned>(&mut bytes)
+    });
+
+    match parsed {
+        Ok(Interned(record)) => {
+            *decoded = record;
+            Ok(())
+        }
+        Err(_) => decode_record(data, decoded),
+    }
+}
+
+/// Return syslog facility of the record, if any.
+pub(crate) fn get_facility(decoded: &LogRecord) -> Option<Facility> {
+    get_number(decoded, "SYSLOG_FACILITY")
+        .or_else(|| get_syslog_pri(decoded).map(|pri| pri >> 3))
+        .and_then(|value| u8::try_from(value).ok())
+        .and_then(Facility::from_num)
+}
+
+/// Return system log-level of the record from its priority, if any.
+pub(crate) fn get_sys_log_level(decoded: &LogRecord) -> Option<LevelSystem> {
+    get_number(decoded, "PRIORITY")
+        .or_else(|| get_syslog_pri(decoded).map(|pri| pri & 0x07))
+        .and_then(|value| u8::try_from(value).ok())
+        .map(LevelSystem::from)
+}
+
+/// Return journal time of the record, secs.
+///
+/// Systemd's format of microseconds expressed as an integer (either as a string or as a number)
+/// is converted to graylog's float format, eg: "seconds.microseconds".
+pub(crate) fn get_timestamp(decoded: &LogRecord) -> Option<f64> {
+    get_number(decoded, "__REALTIME_TIMESTAMP")
+        .map(|us| (us / 1_000_000) as f64 + (us % 1_000_000) as f64 / 1_000_000_f64)
+}
+
+/// Return time of the record by its monotonic timestamp since boot, secs.
+///
+/// Unlike journal time, it is not affected by steps of the system clock after start,
+/// but only records of the current boot could be placed this way.
+fn get_monotonic_timestamp(decoded: &LogRecord) -> Option<f64> {
+    lazy_static! {
+        static ref BOOT: Option<(String, f64)> = current_boot();
+    }
+
+    let (ref boot_id, boot_time) = *BOOT.as_ref()?;
+    if decoded.get("_BOOT_ID").and_then(Value::as_str) != Some(boot_id.as_str()) {
+        return None;
+    }
+
+    get_number(decoded, "__MONOTONIC_TIMESTAMP").map(|us| boot_time + us as f64 / 1_000_000_f64)
+}
+
+/// Return ID of the current boot, formatted as journald does, along with its time.
+fn current_boot() -> Option<(String, f64)> {
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map_err(|e| warn!("failed to read boot ID: {}", e))
+        .ok()?
+        .trim()
+        .replace('-', "");
+
+    // journald uses monotonic clock, which does not include time of suspend
+    let mut monotonic = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut monotonic) } != 0 {
+        warn!("failed to read monotonic clock");
+        return None;
+    }
+    let uptime = monotonic.tv_sec as f64 + monotonic.tv_nsec as f64 / 1_000_000_000_f64;
+
+    Some((boot_id, current_time_unix() - uptime))
+}
+
+/// Return PRI part of the original syslog message, e.g. `13` or `<13>`.
+fn get_syslog_pri(decoded: &LogRecord) -> Option<u64> {
+    match decoded.get("SYSLOG_PRI")? {
+        Value::String(pri) => pri.trim_matches(|c| c == '<' || c == '>').parse().ok(),
+        other => other.as_u64(),
+    }
+}
+
+/// Return numeric field, which journald formats either as a string or as a number.
+fn get_number(decoded: &LogRecord, field: &str) -> Option<u64> {
+    match decoded.get(field)? {
+        Value::String(value) => value.trim().parse().ok(),
+        other => other.as_u64(),
+    }
+}
+
+/// Check whether field is sent as an additional one, rather than ignored.
+pub(crate) fn is_metadata(field: &str, config: &Config) -> bool {
+    if CONSUMED_FIELDS.contains(&field) {
+        return false;
+    }
+
+    if DEFAULT_IGNORED_FIELDS.contains(&field) && !config.kept_fields.iter().any(|k| k == field) {
+        return false;
+    }
+
+    !config
+        .dropped_fields
+        .iter()
+        .any(|pattern| pattern.matches(field))
+}
+
+/// Replace characters of the field name not allowed by GELF with `_`.
+fn normalize_field_name(field: &str) -> String {
+    if field.is_empty() {
+        return String::from("_");
+    }
+
+    field
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
 }
 
-fn is_metadata(field: &str) -> bool {
-    !IGNORED_FIELDS.contains(&field)
+/// Keep the last complaint of journalctl to report it on exit,
+/// draining stderr in a separate thread so that journalctl never blocks on it.
+fn drain_complaints<R: Read + Send + 'static>(stderr: R) -> io::Result<JoinHandle<String>> {
+    thread::Builder::new()
+        .name("journalctl-stderr".to_string())
+        .spawn(move || {
+            let mut last = String::new();
+            for line in BufReader::new(stderr).lines().map_while(io::Result::ok) {
+                warn!("journalctl: {}", line);
+                last = line;
+            }
+            last
+        })
 }
 
-fn is_platform_supported() -> bool {
-    cfg!(target_os = "linux")
+/// Explain failure to start journalctl, suggesting what could be done about it.
+pub(crate) fn journalctl_failed(e: io::Error) -> Error {
+    let advice = "make sure systemd is installed and journalctl is in PATH, \
+                  or read the journal through stdin, e.g. `journalctl -o json -f | jctl2gray -s stdin`";
+
+    match e.kind() {
+        io::ErrorKind::NotFound => {
+            Error::InternalError(format!("journalctl not found: {}", advice))
+        }
+        io::ErrorKind::PermissionDenied => Error::InternalError(format!(
+            "journalctl could not be executed, permission denied: {}",
+            advice
+        )),
+        _ => Error::InternalError(format!("failed to start journalctl: {}", e)),
+    }
 }
 
-fn get_msg_log_level(msg: &str) -> Option<LevelMsg> {
+/// Make sure journal could be read on this platform.
+///
+/// Only the journal source is tied to systemd, other sources work on any UNIX.
+pub(crate) fn check_journal_supported() -> Result<()> {
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        Err(Error::InternalError(
+            "journal source is supported on Linux only, use stdin source instead".to_string(),
+        ))
+    }
+}
+
+/// Return rules of the unit the record comes from, if any.
+pub(crate) fn unit_rule<'a>(decoded: &LogRecord, config: &'a Config) -> Option<&'a UnitRule> {
+    if config.unit_rules.is_empty() {
+        return None;
+    }
+
+    let unit = decoded.get(UNIT_FIELD).and_then(Value::as_str)?;
+    config.unit_rules.get(unit)
+}
+
+/// Return threshold of system level, either the one of the unit or the common one.
+pub(crate) fn system_threshold(rule: Option<&UnitRule>, config: &Config) -> LevelSystem {
+    rule.and_then(|rule| rule.level)
+        .unwrap_or(config.log_level_system)
+}
+
+/// Check message against include and exclude regexes: it must match any of the former and none of the latter.
+pub(crate) fn message_selected(msg: &str, config: &Config) -> bool {
+    (config.include_regex.is_empty() || config.include_regex.iter().any(|re| re.is_match(msg)))
+        && !config.exclude_regex.iter().any(|re| re.is_match(msg))
+}
+
+pub(crate) fn get_msg_log_level(msg: &str) -> Option<LevelMsg> {
     lazy_static! {
-        // try to find pattern in message: 'level=some_log_level'
-        static ref RE: Regex = Regex::new(r#"level=([a-zA-Z]+ )"#).unwrap();
+        // try to find pattern in message: 'level=some_log_level', also 'lvl=', 'level:',
+        // quoted as in JSON and in any case
+        static ref RE: Regex =
+            Regex::new(r#"(?i)\b(?:level|lvl)["']?\s*[=:]\s*["']?([a-z]+)\b"#).unwrap();
     }
 
     // first group match
-    let level = RE.captures(msg)?.get(1)?.as_str().trim();
-    Some(LevelMsg::from(level))
+    let level = RE.captures(msg)?.get(1)?.as_str().to_ascii_lowercase();
+    Some(LevelMsg::from(level.as_str()))
 }
 
-/// Just bind a socket to any interface.
-fn create_sender_udp(port: u16) -> Result<UdpSocket> {
-    Ok(UdpSocket::bind(format!("0.0.0.0:{}", port))?)
+/// Just bind a socket suitable for the target to any interface.
+///
+/// IPv6 sockets are restricted to IPv6 only, so that
+/// IPv4 socket could be bound to the same port.
+/// Send buffer is enlarged, if its size is given.
+pub(crate) fn create_sender_udp(
+    target: &SocketAddr,
+    port: u16,
+    send_buffer: Option<usize>,
+) -> io::Result<UdpSocket> {
+    let addr = match *target {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port),
+    };
+
+    let domain = Domain::for_address(addr);
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    if let Some(size) = send_buffer {
+        socket.set_send_buffer_size(size)?;
+
+        // kernel caps the size by net.core.wmem_max (and doubles it for bookkeeping)
+        let actual = socket.send_buffer_size()?;
+        if actual < size {
+            warn!(
+                "send buffer is {} bytes instead of {}, consider raising net.core.wmem_max",
+                actual, size
+            );
+        } else {
+            debug!("send buffer is {} bytes", actual);
+        }
+    }
+
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
 }
 
-/// Try to resolve and return first IP-address for given host.
-fn get_target_addr(host: &str) -> io::Result<(SocketAddr, SystemTime)> {
-    let mut addrs = host.to_socket_addrs()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore::FieldPattern;
+    use crate::unit_rules::UnitRules;
+
+    /// Turn JSON object into the record, as decoded from the journal.
+    fn decoded(json: Value) -> LogRecord {
+        json.as_object()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (crate::record::intern(k), v.clone()))
+            .collect()
+    }
+
+    /// Writer accepting a few bytes per write, taking them across slices of vectored writes,
+    /// and interrupted before every other write
+    struct ShortWrites(Vec<u8>, bool);
+
+    impl Write for ShortWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+
+            let taken: Vec<u8> = bufs
+                .iter()
+                .flat_map(|buf| buf.iter())
+                .take(3)
+                .copied()
+                .collect();
+            self.0.extend_from_slice(&taken);
+            Ok(taken.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_all_slices() {
+        let mut writer = ShortWrites(Vec::new(), false);
+        let mut slices = [
+            IoSlice::new(b"{\"n\":1}"),
+            IoSlice::new(&[0]),
+            IoSlice::new(b"{\"n\":22}"),
+            IoSlice::new(&[0]),
+        ];
+        write_all_vectored(&mut writer, &mut slices).unwrap();
+        assert_eq!(writer.0, b"{\"n\":1}\0{\"n\":22}\0");
+
+        let mut full = &mut [0u8; 4][..];
+        let mut slices = [IoSlice::new(b"{\"n\":1}")];
+        let written = write_all_vectored(&mut full, &mut slices);
+        assert_eq!(written.unwrap_err().kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn field_text_extraction() {
+        let escaped: Value = serde_json::from_str(r#""say \"hi\" \u00e9""#).unwrap();
+        assert_eq!(field_text(escaped), "say \"hi\" \u{e9}");
+        assert_eq!(field_text(json!([104, 105, 0xff])), "hi\u{fffd}");
+        assert_eq!(field_text(json!(42)), "42");
+    }
+
+    #[test]
+    fn priority_representations() {
+        let level = |record: Value| get_sys_log_level(&decoded(record));
+
+        assert_eq!(level(json!({"PRIORITY": "3"})), Some(LevelSystem::Error));
+        assert_eq!(level(json!({"PRIORITY": 4})), Some(LevelSystem::Warning));
+        assert_eq!(
+            level(json!({"SYSLOG_PRI": "<13>"})),
+            Some(LevelSystem::Notice)
+        );
+        assert_eq!(level(json!({"SYSLOG_PRI": 11})), Some(LevelSystem::Error));
+        assert_eq!(level(json!({"PRIORITY": "high"})), None);
+    }
+
+    #[test]
+    fn message_level_detection() {
+        assert_eq!(
+            get_msg_log_level("level=error failed to connect"),
+            Some(LevelMsg::Error)
+        );
+        assert_eq!(
+            get_msg_log_level("connected level=info"),
+            Some(LevelMsg::Info)
+        );
+        assert_eq!(
+            get_msg_log_level("LEVEL=Warning disk is full"),
+            Some(LevelMsg::Warning)
+        );
+        assert_eq!(
+            get_msg_log_level("lvl=fatal shutting down"),
+            Some(LevelMsg::Fatal)
+        );
+        assert_eq!(get_msg_log_level("level: panic"), Some(LevelMsg::Panic));
+        assert_eq!(
+            get_msg_log_level(r#"{"level":"error","msg":"failed"}"#),
+            Some(LevelMsg::Error)
+        );
+        assert_eq!(
+            get_msg_log_level("time=now level='info' msg=ok"),
+            Some(LevelMsg::Info)
+        );
+        assert_eq!(get_msg_log_level("sealevel=high"), None);
+        assert_eq!(get_msg_log_level("no level here"), None);
+    }
+
+    #[test]
+    fn field_name_normalization() {
+        assert!(is_valid_field_name("_SYSTEMD_UNIT"));
+        assert!(is_valid_field_name("http.status-code"));
+        assert!(!is_valid_field_name("@timestamp"));
+        assert!(!is_valid_field_name(""));
+
+        assert_eq!(normalize_field_name("@timestamp"), "_timestamp");
+        assert_eq!(normalize_field_name("user name"), "user_name");
+        assert_eq!(normalize_field_name("время"), "_____");
+        assert_eq!(normalize_field_name(""), "_");
+    }
+
+    #[test]
+    fn line_length_limit() {
+        let mut input = io::BufReader::with_capacity(4, &b"short\nvery long line\nend"[..]);
+        let mut line = Vec::new();
+
+        assert_eq!(read_line(&mut input, &mut line, 8).unwrap(), Line::Complete);
+        assert_eq!(line, b"short\n");
+
+        line.clear();
+        assert_eq!(
+            read_line(&mut input, &mut line, 8).unwrap(),
+            Line::Oversized(15)
+        );
+        assert!(line.is_empty());
+
+        assert_eq!(read_line(&mut input, &mut line, 8).unwrap(), Line::Complete);
+        assert_eq!(line, b"end");
+
+        line.clear();
+        assert_eq!(read_line(&mut input, &mut line, 8).unwrap(), Line::End);
+    }
+
+    #[test]
+    fn realtime_timestamp_representations() {
+        let timestamp = |value: Value| {
+            let mut decoded = LogRecord::new();
+            decoded.insert(crate::record::intern("__REALTIME_TIMESTAMP"), value);
+            get_timestamp(&decoded)
+        };
+
+        assert_eq!(
+            timestamp(json!("1700000000123456")),
+            Some(1_700_000_000.123_456)
+        );
+        assert_eq!(
+            timestamp(json!(1_700_000_000_500_000_u64)),
+            Some(1_700_000_000.5)
+        );
+        assert_eq!(timestamp(json!("yesterday")), None);
+    }
+
+    #[test]
+    fn unit_rules_apply_to_their_unit() {
+        let mut config = Config::for_tests();
+        config.fields = vec![("team".to_string(), "core".to_string())];
+        config.unit_rules = UnitRules::parse(vec![
+            "nginx:sys=warning",
+            "nginx:exclude=^GET /health",
+            "nginx:field=team=web",
+        ])
+        .unwrap();
+
+        let transform = |unit: &str, priority: &str, message: &str| {
+            let mut decoded =
+                decoded(json!({"MESSAGE": message, "PRIORITY": priority, "_SYSTEMD_UNIT": unit}));
+            transform_decoded(&mut decoded, &config, &RecordStats::default())
+                .map(|gelf| serde_json::from_slice::<Value>(&gelf).unwrap())
+        };
+
+        let gelf = transform("nginx.service", "4", "upstream timed out").unwrap();
+        assert_eq!(gelf["_team"], json!("web"));
+        assert!(matches!(
+            transform("nginx.service", "6", "GET /index 200"),
+            Err(Error::InsufficientLogLevel)
+        ));
+        assert!(matches!(
+            transform("nginx.service", "3", "GET /health 500"),
+            Err(Error::Filtered)
+        ));
+
+        // other units keep the common threshold and fields
+        let gelf = transform("sshd.service", "6", "GET /health 200").unwrap();
+        assert_eq!(gelf["_team"], json!("core"));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_decoding_matches_serde() {
+        let data =
+            r#"{"MESSAGE":"caf\u00e9 \"ok\"","PRIORITY":"6","MESSAGE_ID":[1,2,3],"_PID":42}"#;
+        let (mut simd, mut serde) = (LogRecord::new(), LogRecord::new());
+        decode_record_simd(data, &mut simd).unwrap();
+        decode_record(data, &mut serde).unwrap();
+        assert_eq!(simd, serde);
+        assert_eq!(simd.get("MESSAGE"), Some(&json!("caf\u{e9} \"ok\"")));
+
+        // rejected records fail as they do with serde_json
+        let malformed = r#"{"MESSAGE":"cut"#;
+        let simd_error = decode_record_simd(malformed, &mut simd).unwrap_err();
+        let serde_error = decode_record(malformed, &mut serde).unwrap_err();
+        assert_eq!(simd_error.to_string(), serde_error.to_string());
+    }
+
+    #[test]
+    fn json_records_across_lines() {
+        let input = "{\"MESSAGE\":\"one\"}{\"MESSAGE\":\n\"two\"}\n\n  {\"MESSAGE\":\"three\"}\n{\"MESSAGE\"";
+        let stats = Arc::new(RecordStats::default());
+        let mut records = json_records(
+            BufReader::with_capacity(4, input.as_bytes()),
+            1024,
+            stats.clone(),
+        );
+
+        for expected in ["one", "two", "three"] {
+            let record = records.next().unwrap().unwrap();
+            assert_eq!(record.get("MESSAGE"), Some(&json!(expected)));
+        }
+        // truncated record is counted as failed at the end of input
+        assert!(records.next().is_none());
+        assert_eq!(stats.snapshot().errors, 1);
+    }
+
+    #[test]
+    fn json_records_skip_malformed_and_oversized() {
+        let long = "x".repeat(100);
+        let input = format!(
+            "{{\"MESSAGE\":\"one\"}}\n{{\"MESSAGE\":one}} trailing\n{{\"MESSAGE\":\"{}\"}}\n{{\"MESSAGE\":\"two\"}}\n",
+            long
+        );
+        let stats = Arc::new(RecordStats::default());
+        let records: Vec<LogRecord> = json_records(
+            BufReader::with_capacity(4, input.as_bytes()),
+            64,
+            stats.clone(),
+        )
+        .collect::<Result<_>>()
+        .unwrap();
+
+        let messages: Vec<&Value> = records.iter().map(|r| r.get("MESSAGE").unwrap()).collect();
+        assert_eq!(messages, [&json!("one"), &json!("two")]);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.errors, 1);
+        assert_eq!(snapshot.oversized, 1);
+    }
+
+    #[test]
+    fn host_and_message_taken_as_trimmed_text() {
+        let transform = |record: Value| {
+            let mut decoded = decoded(record);
+            let gelf =
+                transform_decoded(&mut decoded, &Config::for_tests(), &RecordStats::default());
+            serde_json::from_slice::<Value>(&gelf.unwrap()).unwrap()
+        };
+
+        let gelf = transform(
+            json!({"MESSAGE": "\"quoted\" reply ", "_HOSTNAME": "web-1", "PRIORITY": "3"}),
+        );
+        // quotes of the text are kept, while trailing whitespace is trimmed
+        assert_eq!(gelf["short_message"], json!("\"quoted\" reply"));
+        assert_eq!(gelf["host"], json!("web-1"));
+
+        let gelf = transform(json!({"MESSAGE": "no host", "PRIORITY": "3"}));
+        assert_eq!(gelf["host"], json!("undefined"));
+    }
+
+    #[test]
+    fn compresses_serialized_message() {
+        let mut config = Config::for_tests();
+        config.compression = MessageCompression::Gzip;
+        let gelf = json!({"short_message": "x".repeat(10_000)})
+            .to_string()
+            .into_bytes();
+
+        let chunked = compress_message(gelf.clone(), &config).unwrap();
+        assert!(chunked.payload().len() < gelf.len());
+        assert_eq!(
+            MessageCompression::Gzip
+                .decompress(chunked.payload())
+                .unwrap(),
+            gelf
+        );
+    }
+
+    #[test]
+    fn buffers_sized_by_average_message() {
+        // average is kept per thread, so a fresh one starts from the initial guess
+        std::thread::spawn(|| {
+            let config = Config::for_tests();
+            let message = "x".repeat(4000);
+            let mut gelf = Vec::new();
+            for _ in 0..40 {
+                let mut decoded = LogRecord::new();
+                decoded.insert(crate::record::intern("MESSAGE"), json!(message));
+                decoded.insert(crate::record::intern("PRIORITY"), json!("3"));
+                gelf = transform_decoded(&mut decoded, &config, &RecordStats::default()).unwrap();
+            }
+
+            let average = SERIALIZED_SIZE.with(Cell::get);
+            assert!(average > INITIAL_SERIALIZED_SIZE && average <= gelf.len());
+            // the buffer is big enough for the usual message from the start
+            assert!(gelf.capacity() >= gelf.len() && gelf.capacity() < 2 * gelf.len());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn facility_filtering() {
+        let mut config = Config::for_tests();
+        config.facilities = vec![
+            Facility::from_name("auth").unwrap(),
+            Facility::from_name("daemon").unwrap(),
+        ];
 
-    // UDP sendto always takes first resolved address
-    let target_addr = addrs
-        .next()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "empty address list"))?;
-    let target_addr_updated_at = SystemTime::now();
+        let transform = |record: Value| {
+            let mut decoded = decoded(record);
+            transform_decoded(&mut decoded, &config, &RecordStats::default())
+        };
 
-    Ok((target_addr, target_addr_updated_at))
+        assert!(
+            transform(json!({"MESSAGE": "login", "PRIORITY": "3", "SYSLOG_FACILITY": "4"})).is_ok()
+        );
+        // facility is also taken from the syslog priority, daemon.err here
+        assert!(transform(json!({"MESSAGE": "started", "SYSLOG_PRI": "<27>"})).is_ok());
+        assert!(matches!(
+            transform(json!({"MESSAGE": "oops", "PRIORITY": "3", "SYSLOG_FACILITY": "0"})),
+            Err(Error::Filtered)
+        ));
+        assert!(matches!(
+            transform(json!({"MESSAGE": "no facility", "PRIORITY": "3"})),
+            Err(Error::Filtered)
+        ));
+    }
+
+    #[test]
+    fn records_tagged_with_input() {
+        let transform = |config: &Config| {
+            let mut decoded =
+                decoded(json!({"MESSAGE": "hello", "PRIORITY": "3", "input": "record"}));
+            let gelf = transform_decoded(&mut decoded, config, &RecordStats::default());
+            serde_json::from_slice::<Value>(&gelf.unwrap()).unwrap()
+        };
+
+        let mut config = Config::for_tests();
+        assert_eq!(transform(&config)["_input"], json!("record"));
+
+        // tag overrides field of the same name
+        config.input_tag = Some("journal".to_string());
+        assert_eq!(transform(&config)["_input"], json!("journal"));
+    }
+
+    #[test]
+    fn sets_send_buffer_of_udp_socket() {
+        let target: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let send_buffer = |socket: &UdpSocket| SockRef::from(socket).send_buffer_size().unwrap();
+
+        let default = create_sender_udp(&target, 0, None).unwrap();
+        let sized = create_sender_udp(&target, 0, Some(8192)).unwrap();
+        assert!(send_buffer(&sized) >= 8192);
+        assert_ne!(send_buffer(&sized), send_buffer(&default));
+    }
+
+    #[test]
+    fn binary_message_is_tagged() {
+        let transform = |message: Value| {
+            let mut decoded = LogRecord::new();
+            decoded.insert(crate::record::intern("MESSAGE"), message);
+            decoded.insert(crate::record::intern("PRIORITY"), json!("3"));
+            let gelf =
+                transform_decoded(&mut decoded, &Config::for_tests(), &RecordStats::default());
+            serde_json::from_slice::<Value>(&gelf.unwrap()).unwrap()
+        };
+
+        let gelf = transform(json!([77, 101, 0xff, 33]));
+        assert_eq!(gelf["short_message"], json!("Me\u{fffd}!"));
+        assert_eq!(gelf["_binary_message"], json!(true));
+
+        let gelf = transform(json!("Me!"));
+        assert!(gelf.get("_binary_message").is_none());
+    }
+
+    #[test]
+    fn keeps_last_complaint_of_subprocess() {
+        let mut subprocess = process::Command::new("sh")
+            .args([
+                "-c",
+                "echo starting >&2; echo 'no journal files' >&2; exit 3",
+            ])
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let complaints = drain_complaints(subprocess.stderr.take().unwrap()).unwrap();
+        let status = subprocess.wait().unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(complaints.join().unwrap(), "no journal files");
+    }
+
+    #[test]
+    fn journal_supported_on_linux_only() {
+        let supported = check_journal_supported();
+        assert_eq!(supported.is_ok(), cfg!(target_os = "linux"));
+        if let Err(e) = supported {
+            assert!(e.to_string().contains("use stdin source"));
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_lines_are_kept() {
+        let stats = RecordStats::default();
+        let mut input = io::BufReader::new(&b"caf\xe9\nok\n"[..]);
+        let mut lines = Vec::new();
+
+        loop {
+            let mut line = Vec::new();
+            match read_line(&mut input, &mut line, 1024).unwrap() {
+                Line::Complete => lines.push(line_text(line, &stats)),
+                _ => break,
+            }
+        }
+
+        assert_eq!(lines, vec!["caf\u{fffd}\n", "ok\n"]);
+        assert_eq!(stats.snapshot().invalid_utf8, 1);
+    }
+
+    #[test]
+    fn reserved_field_dropped_or_renamed() {
+        let transform = |config: &Config, stats: &RecordStats| {
+            let mut decoded = decoded(json!({"MESSAGE": "hello", "PRIORITY": "3", "id": "42"}));
+            let gelf = transform_decoded(&mut decoded, config, stats);
+            serde_json::from_slice::<Value>(&gelf.unwrap()).unwrap()
+        };
+
+        let mut config = Config::for_tests();
+        let stats = RecordStats::default();
+        let gelf = transform(&config, &stats);
+        assert!(gelf.get("_id").is_none() && gelf.get("_record_id").is_none());
+        assert_eq!(stats.snapshot().dropped_fields, 1);
+
+        config.rename_reserved = true;
+        let gelf = transform(&config, &stats);
+        assert_eq!(gelf["_record_id"], json!("42"));
+        assert_eq!(stats.snapshot().dropped_fields, 1);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn monotonic_timestamp_of_current_boot() {
+        // sandboxes without procfs have no boot ID
+        let (boot_id, boot_time) = match current_boot() {
+            Some(boot) => boot,
+            None => return,
+        };
+        let record = |boot_id: &str| {
+            let mut decoded = LogRecord::new();
+            decoded.insert(crate::record::intern("_BOOT_ID"), json!(boot_id));
+            decoded.insert(
+                crate::record::intern("__MONOTONIC_TIMESTAMP"),
+                json!("5000000"),
+            );
+            decoded
+        };
+
+        let ts = get_monotonic_timestamp(&record(&boot_id)).unwrap();
+        assert!((ts - (boot_time + 5.0)).abs() < 1.0);
+        assert!(ts < current_time_unix());
+
+        // records of other boots have no monotonic time
+        assert_eq!(get_monotonic_timestamp(&record("0000")), None);
+    }
+
+    #[test]
+    fn explains_journalctl_failure() {
+        let missing = process::Command::new("jctl2gray-missing-journalctl")
+            .spawn()
+            .map_err(journalctl_failed)
+            .unwrap_err();
+        assert!(missing.to_string().contains("journalctl not found"));
+        assert!(missing.to_string().contains("-s stdin"));
+
+        let path = std::env::temp_dir().join(format!("jctl2gray-journalctl-{}", process::id()));
+        std::fs::write(&path, "").unwrap();
+        let denied = process::Command::new(&path)
+            .spawn()
+            .map_err(journalctl_failed)
+            .unwrap_err();
+        assert!(denied.to_string().contains("permission denied"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn journalctl_reads_configured_units() {
+        let mut config = Config::for_tests();
+        config.units = vec!["nginx.service".to_string(), "myapp".to_string()];
+        let command = journalctl_command(&config, Some("s=1"));
+
+        assert_eq!(command.get_program(), "journalctl");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                "-o",
+                "json",
+                "-f",
+                "--after-cursor=s=1",
+                "-u",
+                "nginx.service",
+                "-u",
+                "myapp"
+            ]
+        );
+    }
+
+    #[test]
+    fn messages_selected_by_regexes() {
+        let mut config = Config::for_tests();
+        assert!(message_selected("anything", &config));
+
+        config.include_regex = vec![Regex::new("^GET ").unwrap(), Regex::new("^POST ").unwrap()];
+        config.exclude_regex = vec![Regex::new("/health").unwrap()];
+        assert!(message_selected("GET /index", &config));
+        assert!(message_selected("POST /login", &config));
+        assert!(!message_selected("PUT /index", &config));
+        // exclusion wins over inclusion
+        assert!(!message_selected("GET /health", &config));
+    }
+
+    #[test]
+    fn static_fields_on_every_message() {
+        let mut config = Config::for_tests();
+        config.fields = vec![
+            ("environment".to_string(), "prod".to_string()),
+            ("region".to_string(), "eu-west-1".to_string()),
+        ];
+
+        let mut decoded =
+            decoded(json!({"MESSAGE": "hello", "PRIORITY": "3", "environment": "dev"}));
+        let gelf = transform_decoded(&mut decoded, &config, &RecordStats::default()).unwrap();
+        let gelf: Value = serde_json::from_slice(&gelf).unwrap();
+
+        // fields override the ones of the record
+        assert_eq!(gelf["_environment"], json!("prod"));
+        assert_eq!(gelf["_region"], json!("eu-west-1"));
+    }
+
+    #[test]
+    fn fields_renamed_and_dropped() {
+        let mut config = Config::for_tests();
+        config.field_renames = vec![
+            ("_SYSTEMD_UNIT".to_string(), "unit".to_string()),
+            ("CONTAINER_NAME".to_string(), "container".to_string()),
+        ];
+        config.dropped_fields = vec![FieldPattern::parse("_AUDIT_*").unwrap()];
+
+        let mut decoded = decoded(json!({
+            "MESSAGE": "hello",
+            "PRIORITY": "3",
+            "_SYSTEMD_UNIT": "nginx.service",
+            "CONTAINER_NAME": "web",
+            "unit": "replaced",
+            "_AUDIT_SESSION": "4",
+        }));
+        let gelf = transform_decoded(&mut decoded, &config, &RecordStats::default()).unwrap();
+        let gelf: Value = serde_json::from_slice(&gelf).unwrap();
+
+        assert_eq!(gelf["_unit"], json!("nginx.service"));
+        assert_eq!(gelf["_container"], json!("web"));
+        assert!(gelf.get("__SYSTEMD_UNIT").is_none());
+        assert!(gelf.get("_CONTAINER_NAME").is_none());
+        assert!(gelf.get("__AUDIT_SESSION").is_none());
+    }
+
+    #[test]
+    fn small_messages_sent_uncompressed() {
+        let mut config = Config::for_tests();
+        config.compression = MessageCompression::Gzip;
+        config.compress_min = 512;
+
+        let small = json!({"short_message": "hi"}).to_string().into_bytes();
+        let chunked = compress_message(small.clone(), &config).unwrap();
+        assert_eq!(chunked.payload(), &small[..]);
+
+        let large = json!({"short_message": "x".repeat(1000)})
+            .to_string()
+            .into_bytes();
+        let chunked = compress_message(large.clone(), &config).unwrap();
+        assert!(chunked.payload().len() < large.len());
+    }
 }
@@ -0,0 +1,657 @@
+//! Asynchronous processing on tokio runtime
+//!
+//! Input is read by one task and handed over through a channel to another
+//! task, which transforms records and sends them. On interrupt reading stops,
+//! while records already read are still sent before returning.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use socket2::{SockAddr, SockRef};
+use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, Interest};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::process::Command;
+use tokio::runtime::Builder;
+use tokio::signal;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::errors::{Error, Result};
+
+use crate::backoff::SendBackoff;
+use crate::capture::Capture;
+use crate::chaos::Chaos;
+use crate::config::{Output, SharedConfig};
+use crate::export::JournalFormat;
+use crate::gelf::{Chunk, ChunkedMessage, MessageCompression};
+use crate::pipeline::QueuePolicy;
+use crate::preview;
+use crate::processing::{
+    check_journal_supported, compress_message, create_sender_udp, journalctl_failed, line_outcome,
+    line_oversized, line_text, serialize_record, Line, LogRecord,
+};
+use crate::resolver::Resolver;
+use crate::stats::{RecordStats, SendStats, TargetStats};
+use crate::transport::{unix_path, TransportKind, CONNECT_TIMEOUT, WRITE_TIMEOUT};
+
+pub fn process_journalctl(config: SharedConfig, stats: SendStats) -> Result<()> {
+    check_journal_supported()?;
+    check_supported(&config)?;
+
+    run(async move {
+        let mut subprocess = Command::new("journalctl")
+            .args(["-o", "json", "-f"])
+            .args(config.load().units.iter().flat_map(|unit| ["-u", unit]))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(journalctl_failed)?;
+        if let Some(pid) = subprocess.id() {
+            stats.subprocess_started("journalctl", pid);
+        }
+
+        let stdout = subprocess.stdout.take().unwrap();
+        let stderr = subprocess.stderr.take().unwrap();
+
+        // keep the last complaint of journalctl to report it on exit
+        let complaints = tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut last = String::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("journalctl: {}", line);
+                last = line;
+            }
+            last
+        });
+
+        debug!("start reading from journalctl");
+
+        if process_input(stdout, config, stats.clone()).await? != Stop::EndOfInput {
+            return Ok(());
+        }
+
+        let status = subprocess.wait().await?;
+        stats.subprocess_exited(status);
+        let reason = complaints.await.unwrap_or_default();
+
+        Err(Error::InternalError(format!(
+            "journalctl exited with {}: {}",
+            status, reason
+        )))
+    })
+}
+
+pub fn process_stdin(config: SharedConfig, stats: SendStats) -> Result<()> {
+    check_supported(&config)?;
+
+    run(async move {
+        debug!("start reading from stdin");
+
+        process_input(io::stdin(), config, stats).await?;
+        Ok(())
+    })
+}
+
+/// Only JSON records are read, only UDP or TCP to a single target is used
+/// and cursor is not saved on asynchronous runtime.
+fn check_supported(config: &SharedConfig) -> Result<()> {
+    let config = config.load();
+
+    if config.journal_format != JournalFormat::Json {
+        return Err(Error::InternalError(
+            "journal export format is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.cursor_file.is_some() {
+        return Err(Error::InternalError(
+            "cursor file is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.queue_policy != QueuePolicy::Block {
+        return Err(Error::InternalError(format!(
+            "{} queue policy is not supported on asynchronous runtime",
+            config.queue_policy
+        )));
+    }
+
+    if config.multiline.is_some() {
+        return Err(Error::InternalError(
+            "multi-line merging is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.dedup.is_some() {
+        return Err(Error::InternalError(
+            "dedup is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.spool_dir.is_some() {
+        return Err(Error::InternalError(
+            "spool is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.graylog_addrs.len() > 1 {
+        return Err(Error::InternalError(
+            "multiple targets are not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if unix_path(&config.graylog_addrs[0]).is_some() {
+        return Err(Error::InternalError(
+            "unix socket target is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.failover_addr.is_some() {
+        return Err(Error::InternalError(
+            "target failover is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.output == Output::File {
+        return Err(Error::InternalError(
+            "file output is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.output == Output::Kafka {
+        return Err(Error::InternalError(
+            "kafka output is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.transport.is_stream() && config.compression != MessageCompression::None {
+        return Err(Error::InternalError(
+            "compression over streams is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.batch_size > 1 {
+        return Err(Error::InternalError(
+            "batching is not supported on asynchronous runtime".to_string(),
+        ));
+    }
+
+    if config.transport == TransportKind::Tls {
+        return Err(Error::InternalError(format!(
+            "{} transport is not supported on asynchronous runtime",
+            config.transport
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    EndOfInput,
+    Interrupted,
+    LimitReached,
+}
+
+/// Run future to completion on a new runtime.
+fn run<F: Future<Output = Result<()>>>(future: F) -> Result<()> {
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    let result = runtime.block_on(future);
+
+    // do not wait for blocking reads of stdin
+    runtime.shutdown_background();
+    result
+}
+
+/// Read lines from the input and send them until input ends or process is interrupted.
+async fn process_input<R>(input: R, shared: SharedConfig, stats: SendStats) -> Result<Stop>
+where
+    R: AsyncRead + Unpin,
+{
+    let config = shared.snapshot();
+
+    // in dry run messages are discarded and target is not used at all
+    let target = if config.dry_run {
+        None
+    } else {
+        // obtain target address (first resolve may fail)
+        let target = &config.graylog_addrs[0];
+        let resolver = Resolver::new(target, config.graylog_addr_ttl, config.resolve_strategy)?;
+
+        // bind to socket, connection is established on the first send
+        let sender = match config.transport {
+            TransportKind::Tcp => Sender::Tcp(TcpSender::new(stats.target(target))),
+            _ => Sender::Udp(UdpSender::new(
+                config.sender_port,
+                config.send_buffer,
+                resolver.addrs(),
+                stats.target(target),
+            )?),
+        };
+
+        Some((resolver, sender))
+    };
+
+    // reading waits while channel is full
+    let (records_tx, records_rx) = mpsc::channel(config.queue_capacity);
+    let read_buffer = config.read_buffer;
+    let max_line = config.max_line;
+    let capture = match config.capture {
+        Some(ref path) => Some(Capture::open(path, config.capture_limit)?),
+        None => None,
+    };
+    let sending = tokio::spawn(send_records(
+        records_rx,
+        shared,
+        target,
+        stats.records(),
+        capture,
+    ));
+
+    let (stop, read) = tokio::select! {
+        read = read_lines(input, read_buffer, max_line, records_tx, stats.records()) => (Stop::EndOfInput, read),
+
+        _ = signal::ctrl_c() => {
+            info!("interrupted, sending remaining records");
+            (Stop::Interrupted, Ok(()))
+        }
+    };
+
+    // reader has gone along with the channel's sending half,
+    // so sender stops as soon as the channel is drained;
+    // failure of the sender in strict mode or its limit also explains why reading stopped
+    let sent = sending
+        .await
+        .map_err(|e| Error::InternalError(e.to_string()))??;
+    if sent == Stop::LimitReached {
+        info!("limit of forwarded messages reached");
+        return Ok(sent);
+    }
+
+    read?;
+    Ok(stop)
+}
+
+async fn read_lines<R>(
+    input: R,
+    read_buffer: usize,
+    max_line: usize,
+    records: mpsc::Sender<String>,
+    stats: Arc<RecordStats>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut input = BufReader::with_capacity(read_buffer, input);
+
+    // lines are read as bytes, so that invalid UTF-8 never stops reading
+    loop {
+        let mut line = Vec::new();
+        match read_line(&mut input, &mut line, max_line).await? {
+            Line::End => return Ok(()),
+            Line::Oversized(len) => line_oversized(len, max_line, &stats),
+            Line::Complete => {
+                if records.send(line_text(line, &stats)).await.is_err() {
+                    return Err(Error::InternalError("sender stopped".to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// Read line up to the newline or the end of input, skipping lines longer than `max` bytes.
+async fn read_line<R>(input: &mut BufReader<R>, line: &mut Vec<u8>, max: usize) -> Result<Line>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut read: u64 = 0;
+
+    loop {
+        let available = input.fill_buf().await?;
+        if available.is_empty() {
+            break;
+        }
+
+        let (used, complete) = match available.iter().position(|&c| c == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (available.len(), false),
+        };
+
+        // beyond the limit the rest of the line is only consumed
+        read += used as u64;
+        if read <= max as u64 {
+            line.extend_from_slice(&available[..used]);
+        } else {
+            line.clear();
+        }
+
+        input.consume(used);
+        if complete {
+            break;
+        }
+    }
+
+    Ok(line_outcome(read, max))
+}
+
+async fn send_records(
+    mut records: mpsc::Receiver<String>,
+    shared: SharedConfig,
+    mut target: Option<(Resolver, Sender)>,
+    stats: Arc<RecordStats>,
+    capture: Option<Capture>,
+) -> Result<Stop> {
+    let mut scratch = LogRecord::new();
+    let mut forwarded: u64 = 0;
+
+    while let Some(record) = records.recv().await {
+        let config = shared.snapshot();
+        let chunked = serialize_record(record.trim(), &config, &mut scratch, &stats)?
+            .inspect(|gelf| {
+                forwarded += 1;
+                if let Some(ref capture) = capture {
+                    capture.write(gelf);
+                }
+                if config.preview {
+                    preview::print(gelf);
+                }
+                if config.output == Output::Stdout {
+                    preview::print_json(gelf);
+                }
+            })
+            .and_then(|gelf| compress_message(gelf, &config));
+
+        if let (Some(chunked), Some((resolver, sender))) = (chunked, target.as_mut()) {
+            sender
+                .send(&chunked, &resolver.target(), config.chaos.as_ref())
+                .await;
+        }
+
+        if config.limit.is_some_and(|limit| forwarded >= limit) {
+            return Ok(Stop::LimitReached);
+        }
+    }
+
+    Ok(Stop::EndOfInput)
+}
+
+/// Sender of messages over the configured transport
+enum Sender {
+    Udp(UdpSender),
+    Tcp(TcpSender),
+}
+
+impl Sender {
+    /// Send the message, unless target is in backoff.
+    async fn send(&mut self, chunked: &ChunkedMessage, target: &SocketAddr, chaos: Option<&Chaos>) {
+        match self {
+            Sender::Udp(sender) => sender.send(chunked, target, chaos).await,
+            Sender::Tcp(sender) => sender.send(chunked, target, chaos).await,
+        }
+    }
+}
+
+/// TCP connection along with bookkeeping of its target
+struct TcpSender {
+    stream: Option<TcpStream>,
+    frame: Vec<u8>,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl TcpSender {
+    fn new(stats: Arc<TargetStats>) -> Self {
+        TcpSender {
+            stream: None,
+            frame: Vec::new(),
+            stats,
+            backoff: SendBackoff::new(),
+        }
+    }
+
+    /// Send the whole message, unless target is in backoff.
+    ///
+    /// Established connection is kept regardless of the target address, until it fails.
+    async fn send(&mut self, chunked: &ChunkedMessage, target: &SocketAddr, chaos: Option<&Chaos>) {
+        if !self.backoff.ready() {
+            self.stats.message_dropped();
+            return;
+        }
+
+        let sent = match chaos.map(Chaos::before_send) {
+            Some(Err(e)) => Err(e),
+            _ => self.write(chunked.payload(), target).await,
+        };
+
+        match sent {
+            Ok(sent) => {
+                self.stats.chunk_sent(sent as u64);
+                self.stats.message_sent();
+                self.backoff.success();
+            }
+            Err(e) => {
+                // connection state is unknown after failure
+                self.stream = None;
+                self.stats.chunk_failed(&e);
+                self.stats.message_dropped();
+                self.backoff.failure(e);
+            }
+        }
+    }
+
+    /// Write message terminated by null byte, connecting first if required.
+    async fn write(&mut self, payload: &[u8], target: &SocketAddr) -> io::Result<usize> {
+        if self.stream.is_none() {
+            let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(target))
+                .await
+                .map_err(|_| timed_out("connection"))??;
+            self.stream = Some(stream);
+            debug!("connected to {}", target);
+        }
+
+        // message is written at once, rather than in separate packets
+        self.frame.clear();
+        self.frame.extend_from_slice(payload);
+        self.frame.push(0);
+
+        let stream = self.stream.as_mut().unwrap();
+        timeout(WRITE_TIMEOUT, stream.write_all(&self.frame))
+            .await
+            .map_err(|_| timed_out("write"))??;
+
+        Ok(self.frame.len())
+    }
+}
+
+fn timed_out(operation: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", operation))
+}
+
+/// UDP sockets along with bookkeeping of their target
+struct UdpSender {
+    port: u16,
+    send_buffer: Option<usize>,
+    socket_v4: Option<UdpSocket>,
+    socket_v6: Option<UdpSocket>,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl UdpSender {
+    /// Bind sockets for address families of all given targets.
+    fn new(
+        port: u16,
+        send_buffer: Option<usize>,
+        targets: &[SocketAddr],
+        stats: Arc<TargetStats>,
+    ) -> Result<Self> {
+        let mut sender = UdpSender {
+            port,
+            send_buffer,
+            socket_v4: None,
+            socket_v6: None,
+            stats,
+            backoff: SendBackoff::new(),
+        };
+
+        for target in targets {
+            sender.socket(target)?;
+        }
+
+        Ok(sender)
+    }
+
+    /// Return socket suitable for the target, binding it on first use.
+    fn socket(&mut self, target: &SocketAddr) -> io::Result<&UdpSocket> {
+        let socket = match *target {
+            SocketAddr::V4(_) => &mut self.socket_v4,
+            SocketAddr::V6(_) => &mut self.socket_v6,
+        };
+
+        if socket.is_none() {
+            let std_socket = create_sender_udp(target, self.port, self.send_buffer)?;
+            std_socket.set_nonblocking(true)?;
+            *socket = Some(UdpSocket::from_std(std_socket)?);
+        }
+
+        Ok(socket.as_ref().unwrap())
+    }
+
+    /// Send all chunks of the message, unless target is in backoff.
+    async fn send(&mut self, chunked: &ChunkedMessage, target: &SocketAddr, chaos: Option<&Chaos>) {
+        if !self.backoff.ready() {
+            self.stats.message_dropped();
+            return;
+        }
+
+        for chunk in chunked.chunks() {
+            let sent = match (self.socket(target), chaos) {
+                (Ok(socket), Some(chaos)) => match chaos.before_send() {
+                    Ok(_) => send_chunk(socket, &chunk, target).await,
+                    Err(e) => Err(e),
+                },
+                (Ok(socket), None) => send_chunk(socket, &chunk, target).await,
+                (Err(e), _) => Err(e),
+            };
+
+            match sent {
+                Ok(sent) => self.stats.chunk_sent(sent as u64),
+                Err(e) => {
+                    // rest of the chunks are useless without this one
+                    self.stats.chunk_failed(&e);
+                    self.stats.message_dropped();
+                    self.backoff.failure(e);
+                    return;
+                }
+            }
+        }
+
+        self.stats.message_sent();
+        self.backoff.success();
+    }
+}
+
+/// Send header and payload of the chunk in a single datagram, waiting until socket is writable.
+async fn send_chunk(
+    socket: &UdpSocket,
+    chunk: &Chunk<'_>,
+    target: &SocketAddr,
+) -> io::Result<usize> {
+    let target = SockAddr::from(*target);
+    socket
+        .async_io(Interest::WRITABLE, || {
+            SockRef::from(socket).send_to_vectored(&chunk.io_slices(), &target)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use crate::config::Config;
+
+    fn input(messages: &[&str]) -> Vec<u8> {
+        messages
+            .iter()
+            .map(|msg| {
+                json!({"MESSAGE": msg, "PRIORITY": "3", "_HOSTNAME": "host"}).to_string() + "\n"
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn short_message(gelf: &[u8]) -> String {
+        let gelf: Value = serde_json::from_slice(gelf).unwrap();
+        gelf["short_message"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn sends_records_over_udp() {
+        let target = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        target
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![target.local_addr().unwrap().to_string()];
+        let stats = SendStats::new();
+
+        let data = input(&["first", "second"]);
+        let result = run(async {
+            let stop = process_input(&data[..], SharedConfig::new(config), stats.clone()).await?;
+            assert_eq!(stop, Stop::EndOfInput);
+            Ok(())
+        });
+        assert!(result.is_ok());
+
+        let mut buf = [0; 1024];
+        for expected in ["first", "second"] {
+            let (len, _) = target.recv_from(&mut buf).unwrap();
+            assert_eq!(short_message(&buf[..len]), expected);
+        }
+        assert_eq!(stats.records().snapshot().accepted, 2);
+    }
+
+    #[test]
+    fn sends_null_terminated_frames_over_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![listener.local_addr().unwrap().to_string()];
+        config.transport = TransportKind::Tcp;
+        let stats = SendStats::new();
+
+        let data = input(&["first", "second"]);
+        let result = run(async {
+            process_input(&data[..], SharedConfig::new(config), stats.clone()).await?;
+            Ok(())
+        });
+        assert!(result.is_ok());
+
+        // the connection is closed once the runtime is gone
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        std::io::Read::read_to_end(&mut stream, &mut received).unwrap();
+
+        let frames: Vec<String> = received
+            .split(|&b| b == 0)
+            .filter(|frame| !frame.is_empty())
+            .map(short_message)
+            .collect();
+        assert_eq!(frames, vec!["first", "second"]);
+        assert_eq!(
+            stats
+                .target(&listener.local_addr().unwrap().to_string())
+                .snapshot()
+                .messages,
+            2
+        );
+    }
+}
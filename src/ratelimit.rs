@@ -0,0 +1,167 @@
+//! Rate limiting per systemd unit
+//!
+//! Every unit has its own token bucket, refilled at the configured rate up to the burst size,
+//! so that a single service spamming the journal could not flood Graylog on behalf of others.
+//! Records without unit share a bucket. Suppressed records are counted per unit
+//! and reported into the log once per interval.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Period of reporting suppressed records
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Field of journal records holding the unit
+pub const UNIT_FIELD: &str = "_SYSTEMD_UNIT";
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+struct State {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<String, Bucket>,
+    suppressed: HashMap<String, u64>,
+    reported_at: Instant,
+}
+
+/// Token buckets of units, shared by all workers
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    /// Create limiter passing `rate` records per second of every unit, with bursts up to `burst` records.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        RateLimiter {
+            state: Mutex::new(State {
+                rate,
+                burst: f64::from(burst.max(1)),
+                buckets: HashMap::new(),
+                suppressed: HashMap::new(),
+                reported_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token of the unit, returning whether its record could be sent.
+    pub fn admit(&self, unit: &str) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+
+        let (rate, burst) = (state.rate, state.burst);
+        if !state.buckets.contains_key(unit) {
+            let bucket = Bucket {
+                tokens: burst,
+                updated_at: now,
+            };
+            state.buckets.insert(unit.to_string(), bucket);
+        }
+
+        let bucket = state.buckets.get_mut(unit).expect("bucket is inserted");
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated_at = now;
+
+        let admitted = bucket.tokens >= 1.0;
+        if admitted {
+            bucket.tokens -= 1.0;
+        } else {
+            *state.suppressed.entry(unit.to_string()).or_insert(0) += 1;
+        }
+
+        if now.duration_since(state.reported_at) >= REPORT_INTERVAL {
+            report(&mut state);
+        }
+
+        admitted
+    }
+
+    /// Apply rate and burst of another limiter, keeping tokens of the units, e.g. on reload.
+    pub fn configure_like(&self, other: &RateLimiter) {
+        let (rate, burst) = {
+            let other = other.state.lock().expect("rate limiter lock poisoned");
+            (other.rate, other.burst)
+        };
+
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+        state.rate = rate;
+        state.burst = burst;
+    }
+}
+
+/// Log number of suppressed records of every unit since the previous report.
+fn report(state: &mut State) {
+    let mut suppressed: Vec<_> = state.suppressed.drain().collect();
+    suppressed.sort_unstable();
+
+    for (unit, count) in suppressed {
+        let unit = if unit.is_empty() {
+            "without unit".to_string()
+        } else {
+            format!("of {}", unit)
+        };
+        warn!(
+            "rate limit: {} records {} suppressed in {}s",
+            count,
+            unit,
+            state.reported_at.elapsed().as_secs()
+        );
+    }
+
+    state.reported_at = Instant::now();
+}
+
+impl Drop for RateLimiter {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.get_mut() {
+            report(state);
+        }
+    }
+}
+
+/// Only settings are shown, so that the dump of config does not change while running
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().expect("rate limiter lock poisoned");
+        f.debug_struct("RateLimiter")
+            .field("rate", &state.rate)
+            .field("burst", &state.burst)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_every_unit_separately() {
+        let limiter = RateLimiter::new(0.001, 3);
+
+        let admitted = (0..5).filter(|_| limiter.admit("noisy.service")).count();
+        assert_eq!(admitted, 3);
+
+        assert!(limiter.admit("quiet.service"));
+        assert!(limiter.admit(""));
+        assert!(!limiter.admit("noisy.service"));
+    }
+
+    #[test]
+    fn keeps_tokens_on_reload() {
+        let limiter = RateLimiter::new(0.001, 2);
+        assert_eq!((0..3).filter(|_| limiter.admit("noisy.service")).count(), 2);
+
+        // larger burst does not refill the exhausted bucket
+        limiter.configure_like(&RateLimiter::new(0.001, 10));
+        assert!(!limiter.admit("noisy.service"));
+        assert_eq!(
+            (0..20).filter(|_| limiter.admit("other.service")).count(),
+            10
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Decoded input records
+//!
+//! Journald repeats the same set of field names in every record, so names
+//! are interned per thread and shared by reference counting instead of being
+//! allocated anew for each field of each record.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Maximum number of names interned by a thread, the rest are allocated as usual
+const MAX_INTERNED: usize = 4096;
+
+pub type FieldName = Arc<str>;
+
+pub type LogRecord = HashMap<FieldName, Value>;
+
+thread_local! {
+    static INTERNED: RefCell<HashSet<FieldName>> = RefCell::new(HashSet::new());
+}
+
+/// Return shared instance of the field name.
+pub fn intern(name: &str) -> FieldName {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        if let Some(name) = interned.get(name) {
+            return name.clone();
+        }
+
+        let name = FieldName::from(name);
+        if interned.len() < MAX_INTERNED {
+            interned.insert(name.clone());
+        }
+        name
+    })
+}
+
+/// Decode record into the map, reusing its allocation.
+pub fn deserialize_into<'de, D>(deserializer: D, record: &mut LogRecord) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    record.clear();
+    deserializer.deserialize_map(RecordVisitor(record))
+}
+
+/// Record decoded on its own, e.g. from a stream of records.
+pub struct Interned(pub LogRecord);
+
+impl<'de> Deserialize<'de> for Interned {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut record = LogRecord::new();
+        deserialize_into(deserializer, &mut record)?;
+        Ok(Interned(record))
+    }
+}
+
+struct RecordVisitor<'a>(&'a mut LogRecord);
+
+impl<'de, 'a> Visitor<'de> for RecordVisitor<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while let Some(name) = map.next_key_seed(NameSeed)? {
+            let value = map.next_value()?;
+            self.0.insert(name, value);
+        }
+
+        Ok(())
+    }
+}
+
+struct NameSeed;
+
+impl<'de> DeserializeSeed<'de> for NameSeed {
+    type Value = FieldName;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<FieldName, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NameSeed {
+    type Value = FieldName;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a field name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<FieldName, E> {
+        Ok(intern(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &str) -> LogRecord {
+        let mut record = LogRecord::new();
+        let mut deserializer = serde_json::Deserializer::from_str(data);
+        deserialize_into(&mut deserializer, &mut record).unwrap();
+        record
+    }
+
+    #[test]
+    fn names_are_shared() {
+        let first = decode(r#"{"MESSAGE": "one", "_PID": "1"}"#);
+        let second = decode(r#"{"_PID": "2", "MESSAGE": "two"}"#);
+
+        for (name, _) in first.iter() {
+            let (other, _) = second.get_key_value(name).unwrap();
+            assert!(Arc::ptr_eq(name, other));
+        }
+        assert_eq!(second["MESSAGE"], json!("two"));
+    }
+
+    #[test]
+    fn escaped_names() {
+        let record = decode(r#"{"\u0041B": 1}"#);
+        assert_eq!(record["AB"], json!(1));
+    }
+}
@@ -0,0 +1,168 @@
+//! Replay of captured messages
+//!
+//! Sends a file of GELF messages (e.g. made with `--capture`) or of journald
+//! records (e.g. `journalctl -o json` dump) to the target, either keeping
+//! original pacing by their timestamps or at a given rate.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::errors::Result;
+
+use crate::config::SharedConfig;
+use crate::pipeline::Pipeline;
+use crate::processing;
+use crate::record::{self, LogRecord};
+use crate::stats::SendStats;
+
+/// Pace of replay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// Keep intervals between original timestamps
+    Original,
+    /// Fixed rate, msgs/sec (0 - as fast as possible)
+    Rate(u64),
+}
+
+/// Send all messages of the file to the target.
+pub fn run(config: SharedConfig, stats: SendStats, path: &str, pace: Pace) -> Result<()> {
+    let input = BufReader::with_capacity(config.load().read_buffer, File::open(path)?);
+    let pipeline = Pipeline::start(config, &stats)?;
+
+    info!("replaying {}", path);
+
+    let started = Instant::now();
+    let mut first_timestamp: Option<f64> = None;
+    let mut replayed: u64 = 0;
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut decoded = LogRecord::new();
+        let mut deserializer = serde_json::Deserializer::from_str(&line);
+        if let Err(e) = record::deserialize_into(&mut deserializer, &mut decoded) {
+            warn!("skipping malformed line: {}", e);
+            continue;
+        }
+
+        let due = match pace {
+            Pace::Rate(0) => None,
+            Pace::Rate(rate) => Some(Duration::from_secs_f64(replayed as f64 / rate as f64)),
+            Pace::Original => timestamp(&decoded).map(|ts| {
+                let first = *first_timestamp.get_or_insert(ts);
+                Duration::from_secs_f64((ts - first).max(0.0))
+            }),
+        };
+
+        if let Some(due) = due {
+            let elapsed = started.elapsed();
+            if due > elapsed {
+                thread::sleep(due - elapsed);
+            }
+        }
+
+        if is_gelf(&decoded) {
+            pipeline.push_serialized(line.into_bytes())?;
+        } else {
+            pipeline.push_decoded(decoded)?;
+        }
+        replayed += 1;
+
+        if pipeline.limit_reached() {
+            break;
+        }
+    }
+
+    pipeline.finish()?;
+    info!("replayed {} messages", replayed);
+    Ok(())
+}
+
+/// Check whether record is a GELF message rather than journald record.
+fn is_gelf(record: &LogRecord) -> bool {
+    record.contains_key("version") && record.contains_key("short_message")
+}
+
+/// Return timestamp of GELF message or journald record, secs.
+fn timestamp(record: &LogRecord) -> Option<f64> {
+    if let Some(ts) = record.get("timestamp").and_then(Value::as_f64) {
+        return Some(ts);
+    }
+
+    processing::get_timestamp(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::UdpSocket;
+
+    use crate::config::Config;
+
+    /// Replay lines to a local target, returning received messages along with time spent.
+    fn replay(name: &str, lines: &[String], pace: Pace) -> (Vec<Value>, Duration) {
+        let path = std::env::temp_dir().join(format!("jctl2gray-{}-{}", name, std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+        target
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![target.local_addr().unwrap().to_string()];
+
+        let started = Instant::now();
+        run(SharedConfig::new(config), SendStats::new(), &path, pace).unwrap();
+        let elapsed = started.elapsed();
+        fs::remove_file(&path).unwrap();
+
+        let mut buf = [0; 4096];
+        let mut received = Vec::new();
+        while let Ok(len) = target.recv(&mut buf) {
+            received.push(serde_json::from_slice(&buf[..len]).unwrap());
+        }
+        (received, elapsed)
+    }
+
+    #[test]
+    fn replays_gelf_and_journal_records() {
+        let lines = vec![
+            json!({"version": "1.1", "host": "web-1", "short_message": "captured", "level": 3, "timestamp": 100.0}).to_string(),
+            String::new(),
+            "{\"MESSAGE\": ".to_string(),
+            json!({"MESSAGE": "from journal", "PRIORITY": "3", "_HOSTNAME": "web-2"}).to_string(),
+        ];
+        let (received, _) = replay("replay", &lines, Pace::Rate(0));
+
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["short_message"], json!("captured"));
+        assert_eq!(received[0]["timestamp"], json!(100.0));
+        assert_eq!(received[1]["short_message"], json!("from journal"));
+        assert_eq!(received[1]["host"], json!("web-2"));
+    }
+
+    #[test]
+    fn keeps_original_pace() {
+        let message = |ts: f64| {
+            json!({"version": "1.1", "host": "h", "short_message": "m", "timestamp": ts})
+                .to_string()
+        };
+        let (received, elapsed) = replay(
+            "replay-paced",
+            &[message(100.0), message(100.3)],
+            Pace::Original,
+        );
+
+        assert_eq!(received.len(), 2);
+        assert!(elapsed >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,188 @@
+//! Target address resolution
+//!
+//! Resolved addresses are cached and renewed after TTL expiration.
+//! When host resolves to several addresses, one is picked by the strategy.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// ResolveStrategy defines which of resolved addresses is used for sending.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ResolveStrategy {
+    First,
+    PreferIpv4,
+    PreferIpv6,
+    RoundRobin,
+}
+
+impl<'a> From<&'a str> for ResolveStrategy {
+    fn from(strategy: &'a str) -> Self {
+        match strategy {
+            "ipv4" => ResolveStrategy::PreferIpv4,
+            "ipv6" => ResolveStrategy::PreferIpv6,
+            "round-robin" => ResolveStrategy::RoundRobin,
+            _ => ResolveStrategy::First,
+        }
+    }
+}
+
+impl fmt::Display for ResolveStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResolveStrategy::First => write!(f, "first"),
+            ResolveStrategy::PreferIpv4 => write!(f, "ipv4"),
+            ResolveStrategy::PreferIpv6 => write!(f, "ipv6"),
+            ResolveStrategy::RoundRobin => write!(f, "round-robin"),
+        }
+    }
+}
+
+pub struct Resolver {
+    host: String,
+    ttl: Duration,
+    strategy: ResolveStrategy,
+    addrs: Vec<SocketAddr>,
+    updated_at: Instant,
+    next: usize,
+}
+
+impl Resolver {
+    /// Create resolver for given `host:port`, resolving it immediately.
+    pub fn new(host: &str, ttl: u64, strategy: ResolveStrategy) -> io::Result<Self> {
+        Ok(Resolver {
+            host: host.to_string(),
+            ttl: Duration::from_secs(ttl),
+            strategy,
+            addrs: resolve(host)?,
+            updated_at: Instant::now(),
+            next: 0,
+        })
+    }
+
+    /// Return all currently known addresses.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Return address to send next message to, renewing outdated addresses.
+    pub fn target(&mut self) -> SocketAddr {
+        if self.updated_at.elapsed() > self.ttl {
+            self.refresh();
+        }
+
+        match self.strategy {
+            ResolveStrategy::First => self.addrs[0],
+            ResolveStrategy::PreferIpv4 => self.find_or_first(SocketAddr::is_ipv4),
+            ResolveStrategy::PreferIpv6 => self.find_or_first(SocketAddr::is_ipv6),
+            ResolveStrategy::RoundRobin => {
+                let addr = self.addrs[self.next % self.addrs.len()];
+                self.next = self.next.wrapping_add(1);
+                addr
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        match resolve(&self.host) {
+            Ok(addrs) => {
+                if addrs != self.addrs {
+                    debug!("target addresses updated: {:?}", addrs);
+                }
+                self.addrs = addrs;
+            }
+
+            // use outdated addresses
+            Err(e) => warn!("cannot resolve graylog address: {}", e),
+        }
+
+        // retry failed resolve only after another TTL
+        self.updated_at = Instant::now();
+    }
+
+    fn find_or_first<F: Fn(&SocketAddr) -> bool>(&self, predicate: F) -> SocketAddr {
+        *self
+            .addrs
+            .iter()
+            .find(|addr| predicate(addr))
+            .unwrap_or(&self.addrs[0])
+    }
+}
+
+/// Resolve host into a non-empty list of addresses.
+fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = host.to_socket_addrs()?.collect();
+
+    if addrs.is_empty() {
+        return Err(io::Error::other("empty address list"));
+    }
+
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(strategy: ResolveStrategy) -> Resolver {
+        Resolver {
+            host: "graylog:12201".to_string(),
+            ttl: Duration::from_secs(60),
+            strategy,
+            addrs: vec![
+                "[::1]:12201".parse().unwrap(),
+                "10.0.0.1:12201".parse().unwrap(),
+                "10.0.0.2:12201".parse().unwrap(),
+            ],
+            updated_at: Instant::now(),
+            next: 0,
+        }
+    }
+
+    #[test]
+    fn strategy_first() {
+        let mut r = resolver(ResolveStrategy::First);
+        assert_eq!(r.target(), r.addrs[0]);
+        assert_eq!(r.target(), r.addrs[0]);
+    }
+
+    #[test]
+    fn strategy_prefer_family() {
+        let mut r = resolver(ResolveStrategy::PreferIpv4);
+        assert_eq!(r.target(), r.addrs[1]);
+
+        let mut r = resolver(ResolveStrategy::PreferIpv6);
+        assert_eq!(r.target(), r.addrs[0]);
+
+        r.addrs.remove(0);
+        assert_eq!(r.target(), r.addrs[0]);
+    }
+
+    #[test]
+    fn strategy_round_robin() {
+        let mut r = resolver(ResolveStrategy::RoundRobin);
+        let picked: Vec<SocketAddr> = (0..4).map(|_| r.target()).collect();
+        assert_eq!(picked, vec![r.addrs[0], r.addrs[1], r.addrs[2], r.addrs[0]]);
+    }
+
+    #[test]
+    fn renew_after_ttl() {
+        let mut r = resolver(ResolveStrategy::First);
+        r.host = "127.0.0.1:12201".to_string();
+        assert_eq!(r.target(), "[::1]:12201".parse().unwrap());
+
+        r.updated_at = Instant::now() - Duration::from_secs(120);
+        assert_eq!(r.target(), "127.0.0.1:12201".parse().unwrap());
+        assert_eq!(r.addrs().len(), 1);
+    }
+
+    #[test]
+    fn keep_addresses_on_failed_renew() {
+        let mut r = resolver(ResolveStrategy::First);
+        r.host = "no port".to_string();
+        r.updated_at = Instant::now() - Duration::from_secs(120);
+        assert_eq!(r.target(), "[::1]:12201".parse().unwrap());
+        assert_eq!(r.addrs().len(), 3);
+    }
+}
@@ -0,0 +1,91 @@
+//! Probabilistic sampling of records
+//!
+//! Every record passing the filters is sent with the probability of its system level,
+//! e.g. keeping all errors while sending only a share of debug records.
+//! Records without level, or of levels without their own rate, are sent with the common rate.
+
+use rand;
+
+use crate::gelf::LevelSystem;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    common: f64,
+    levels: Vec<(LevelSystem, f64)>,
+}
+
+impl Sampling {
+    /// Parse rates, each either common for all levels, e.g. `0.1`, or of a level, e.g. `debug=0.01`.
+    /// Without common rate records of other levels are all sent.
+    pub fn parse<'a, I>(specs: I) -> Result<Sampling, String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut sampling = Sampling {
+            common: 1.0,
+            levels: Vec::new(),
+        };
+
+        for spec in specs {
+            match spec.split_once('=') {
+                Some((name, rate)) => {
+                    let level = parse_level(name.trim())?;
+                    let rate = parse_rate(rate.trim())?;
+                    sampling.levels.retain(|&(l, _)| l != level);
+                    sampling.levels.push((level, rate));
+                }
+                None => sampling.common = parse_rate(spec.trim())?,
+            }
+        }
+
+        Ok(sampling)
+    }
+
+    /// Return probability of sending a record of given level.
+    pub fn rate(&self, level: Option<LevelSystem>) -> f64 {
+        level
+            .and_then(|level| self.levels.iter().find(|&&(l, _)| l == level))
+            .map_or(self.common, |&(_, rate)| rate)
+    }
+
+    /// Randomly decide whether a record of given level is sent.
+    pub fn sample(&self, level: Option<LevelSystem>) -> bool {
+        let rate = self.rate(level);
+        rate >= 1.0 || rand::random::<f64>() < rate
+    }
+}
+
+fn parse_level(name: &str) -> Result<LevelSystem, String> {
+    (0..8)
+        .map(LevelSystem::from_num)
+        .find(|level| level.to_string() == name)
+        .ok_or_else(|| format!("unknown level: {}", name))
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("bad sample rate: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_of_levels() {
+        let sampling = Sampling::parse(vec!["0.5", "debug=0.01", "error=1"]).unwrap();
+        assert_eq!(sampling.rate(Some(LevelSystem::Debug)), 0.01);
+        assert_eq!(sampling.rate(Some(LevelSystem::Error)), 1.0);
+        assert_eq!(sampling.rate(Some(LevelSystem::Informational)), 0.5);
+        assert_eq!(sampling.rate(None), 0.5);
+
+        let sampling = Sampling::parse(vec!["info=0"]).unwrap();
+        assert!(!sampling.sample(Some(LevelSystem::Informational)));
+        assert!(sampling.sample(Some(LevelSystem::Warning)));
+
+        assert!(Sampling::parse(vec!["1.5"]).is_err());
+        assert!(Sampling::parse(vec!["verbose=0.1"]).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Reload on SIGHUP
+//!
+//! The signal is blocked in all threads and taken by a dedicated one with `sigwait`,
+//! so that reload runs as ordinary code instead of a signal handler.
+//! Child processes, like journalctl, start with the signal unblocked.
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::thread;
+
+use crate::errors::Result;
+
+/// SIGHUP blocked for the process, waiting to be handled
+pub struct ReloadSignal {
+    signals: libc::sigset_t,
+}
+
+impl ReloadSignal {
+    /// Block SIGHUP in the current thread and in threads it spawns afterwards.
+    ///
+    /// Must be called before any other thread is spawned,
+    /// otherwise the signal could be delivered to one of them, terminating the process.
+    pub fn block() -> Result<Self> {
+        unsafe {
+            let mut signals = mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            libc::sigaddset(&mut signals, libc::SIGHUP);
+
+            let code = libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+            if code != 0 {
+                return Err(io::Error::from_raw_os_error(code).into());
+            }
+
+            Ok(ReloadSignal { signals })
+        }
+    }
+
+    /// Call reload on every SIGHUP, in a separate thread.
+    pub fn spawn<F>(self, reload: F) -> Result<()>
+    where
+        F: Fn() -> Result<()> + Send + 'static,
+    {
+        thread::Builder::new()
+            .name("reload-signal".to_string())
+            .spawn(move || loop {
+                let mut signal = 0;
+                let code = unsafe { libc::sigwait(&self.signals, &mut signal) };
+                if code != 0 {
+                    error!(
+                        "failed to wait for SIGHUP: {}",
+                        io::Error::from_raw_os_error(code)
+                    );
+                    return;
+                }
+
+                info!("SIGHUP received, reloading configuration");
+                if let Err(e) = reload() {
+                    error!("failed to reload configuration: {}", e);
+                }
+            })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+//! Disk spool of undeliverable messages
+//!
+//! Messages which could not be sent to any target are appended to segment files
+//! in the spool directory, each message prefixed with its length. Once sending
+//! succeeds again, spooled messages are sent in batches along with new ones,
+//! and segments are removed as soon as all their messages are sent.
+//!
+//! Segments left by the previous run are sent first after restart. After a crash
+//! messages of a partially sent segment could be sent twice, none are lost.
+//! When the spool reaches its size limit, further undeliverable messages are dropped.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::errors::Result;
+
+/// Size of a segment file, after which the next one is started
+const SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// Maximal number of spooled messages sent at once
+const DRAIN_BATCH: usize = 64;
+
+/// Extension of segment files
+const SEGMENT_EXTENSION: &str = "spool";
+
+/// Size of length prefix of a message
+const LENGTH_SIZE: usize = 4;
+
+/// Segment being appended to
+struct Writer {
+    seq: u64,
+    file: File,
+    size: u64,
+}
+
+/// Messages of the oldest segment, loaded for sending
+struct Loaded {
+    seq: u64,
+    messages: VecDeque<Vec<u8>>,
+    /// Whether some messages of the segment were sent already
+    sent: bool,
+}
+
+pub(crate) struct Spool {
+    dir: PathBuf,
+    limit: u64,
+    /// Segments in the directory, oldest first, with their sizes
+    segments: VecDeque<(u64, u64)>,
+    writer: Option<Writer>,
+    loaded: Option<Loaded>,
+    count: Arc<AtomicUsize>,
+    full: bool,
+}
+
+impl Spool {
+    /// Open spool directory, creating it if required, and pick up segments left by the previous run.
+    pub(crate) fn open(dir: &str, limit: u64) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        let mut count = 0;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let seq = match segment_seq(&path) {
+                Some(seq) => seq,
+                None => continue,
+            };
+
+            let data = fs::read(&path)?;
+            count += split_messages(&data, &path).len();
+            segments.push((seq, data.len() as u64));
+        }
+        segments.sort_unstable();
+
+        if count > 0 {
+            info!(
+                "{} messages left in spool {}, to be sent first",
+                count,
+                dir.display()
+            );
+        }
+
+        Ok(Spool {
+            dir,
+            limit,
+            segments: segments.into(),
+            writer: None,
+            loaded: None,
+            count: Arc::new(AtomicUsize::new(count)),
+            full: false,
+        })
+    }
+
+    /// Return counter of spooled messages, to be reported in statistics.
+    pub(crate) fn counter(&self) -> Arc<AtomicUsize> {
+        self.count.clone()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Relaxed) == 0
+    }
+
+    /// Append message to the spool, returning whether it was stored.
+    pub(crate) fn push(&mut self, payload: &[u8]) -> bool {
+        let size = (LENGTH_SIZE + payload.len()) as u64;
+        if self.size() + size > self.limit {
+            if !self.full {
+                warn!(
+                    "spool {} reached its size limit, undeliverable messages are dropped",
+                    self.dir.display()
+                );
+                self.full = true;
+            }
+            return false;
+        }
+
+        if self.is_empty() {
+            warn!(
+                "messages could not be sent, spooling them to {}",
+                self.dir.display()
+            );
+        }
+
+        match self.append(payload) {
+            Ok(()) => {
+                self.count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(e) => {
+                error!("failed to write spool {}: {}", self.dir.display(), e);
+                false
+            }
+        }
+    }
+
+    fn append(&mut self, payload: &[u8]) -> io::Result<()> {
+        if self
+            .writer
+            .as_ref()
+            .is_none_or(|writer| writer.size >= SEGMENT_SIZE)
+        {
+            let seq = self.segments.back().map_or(0, |&(seq, _)| seq + 1);
+            let file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(self.segment_path(seq))?;
+            self.segments.push_back((seq, 0));
+            self.writer = Some(Writer { seq, file, size: 0 });
+        }
+
+        let writer = self.writer.as_mut().expect("segment is open");
+
+        // message is written at once, so that a crash could only leave it incomplete at the end
+        let mut record = Vec::with_capacity(LENGTH_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(payload);
+        writer.file.write_all(&record)?;
+
+        writer.size += record.len() as u64;
+        if let Some(segment) = self.segments.back_mut() {
+            segment.1 = writer.size;
+        }
+
+        Ok(())
+    }
+
+    /// Send a batch of spooled messages, oldest first, stopping at the first failure.
+    pub(crate) fn drain<F>(&mut self, mut send: F)
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        for _ in 0..DRAIN_BATCH {
+            let loaded = match self.oldest() {
+                Some(loaded) => loaded,
+                None => return,
+            };
+
+            // segment could have no complete messages at all
+            let payload = match loaded.messages.front() {
+                Some(payload) => payload,
+                None => {
+                    self.remove_oldest();
+                    continue;
+                }
+            };
+            if !send(payload) {
+                return;
+            }
+
+            loaded.messages.pop_front();
+            loaded.sent = true;
+            let exhausted = loaded.messages.is_empty();
+            self.count.fetch_sub(1, Ordering::Relaxed);
+
+            if exhausted {
+                self.remove_oldest();
+                if self.is_empty() {
+                    info!("spool {} is sent completely", self.dir.display());
+                }
+            }
+        }
+    }
+
+    /// Return messages of the oldest segment, loading them if required.
+    fn oldest(&mut self) -> Option<&mut Loaded> {
+        while self.loaded.is_none() {
+            let &(seq, _) = self.segments.front()?;
+
+            // segment is not appended to anymore once loaded
+            if self.writer.as_ref().is_some_and(|writer| writer.seq == seq) {
+                self.writer = None;
+            }
+
+            let path = self.segment_path(seq);
+            match fs::read(&path) {
+                Ok(data) => {
+                    let messages: VecDeque<_> = split_messages(&data, &path).into();
+                    self.loaded = Some(Loaded {
+                        seq,
+                        messages,
+                        sent: false,
+                    });
+                }
+                Err(e) => {
+                    error!("failed to read spool segment {}: {}", path.display(), e);
+                    self.segments.pop_front();
+                }
+            }
+        }
+
+        self.loaded.as_mut()
+    }
+
+    fn remove_oldest(&mut self) {
+        if let Some(loaded) = self.loaded.take() {
+            self.segments.pop_front();
+            let path = self.segment_path(loaded.seq);
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("failed to remove spool segment {}: {}", path.display(), e);
+            }
+            self.full = false;
+        }
+    }
+
+    /// Total size of segments in the directory
+    fn size(&self) -> u64 {
+        self.segments.iter().map(|&(_, size)| size).sum()
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+    }
+}
+
+impl Drop for Spool {
+    /// Rewrite partially sent segment with the rest of its messages,
+    /// so that sent ones are not sent again after restart.
+    fn drop(&mut self) {
+        let loaded = match self.loaded.take() {
+            Some(loaded) if loaded.sent => loaded,
+            _ => return,
+        };
+
+        let mut data = Vec::new();
+        for payload in &loaded.messages {
+            data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            data.extend_from_slice(payload);
+        }
+
+        let path = self.segment_path(loaded.seq);
+        let temp = path.with_extension("tmp");
+        let saved = fs::write(&temp, data).and_then(|_| fs::rename(&temp, &path));
+        if let Err(e) = saved {
+            warn!("failed to save spool segment {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Parse sequence number of a segment from its file name.
+fn segment_seq(path: &Path) -> Option<u64> {
+    if path.extension()? != SEGMENT_EXTENSION {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Split segment into messages, skipping an incomplete one at the end.
+fn split_messages(mut data: &[u8], path: &Path) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < LENGTH_SIZE {
+            break;
+        }
+        let mut length = [0; LENGTH_SIZE];
+        length.copy_from_slice(&data[..LENGTH_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+
+        if data.len() - LENGTH_SIZE < length {
+            break;
+        }
+        messages.push(data[LENGTH_SIZE..LENGTH_SIZE + length].to_vec());
+        data = &data[LENGTH_SIZE + length..];
+    }
+
+    if !data.is_empty() {
+        warn!(
+            "incomplete message of {} bytes at the end of spool segment {}, skipped",
+            data.len(),
+            path.display()
+        );
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("jctl2gray-spool-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn drains_in_order_until_failure() {
+        let dir = temp_dir("drain");
+        let mut spool = Spool::open(dir.to_str().unwrap(), 1024).unwrap();
+        for message in &[b"one", b"two", b"six"] {
+            assert!(spool.push(*message));
+        }
+
+        let mut sent = Vec::new();
+        spool.drain(|payload| {
+            if sent.len() == 2 {
+                return false;
+            }
+            sent.push(payload.to_vec());
+            true
+        });
+        assert_eq!(sent, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert!(!spool.is_empty());
+
+        spool.drain(|payload| {
+            sent.push(payload.to_vec());
+            true
+        });
+        assert_eq!(sent.len(), 3);
+        assert!(spool.is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn survives_restart() {
+        let dir = temp_dir("restart");
+        {
+            let mut spool = Spool::open(dir.to_str().unwrap(), 1024).unwrap();
+            assert!(spool.push(b"first"));
+            assert!(spool.push(b"second"));
+
+            let mut attempts = 0;
+            spool.drain(|_| {
+                attempts += 1;
+                attempts == 1
+            });
+        }
+
+        let mut spool = Spool::open(dir.to_str().unwrap(), 1024).unwrap();
+        assert_eq!(spool.counter().load(Ordering::Relaxed), 1);
+
+        let mut sent = Vec::new();
+        spool.drain(|payload| {
+            sent.push(payload.to_vec());
+            true
+        });
+        assert_eq!(sent, vec![b"second".to_vec()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limits_size() {
+        let dir = temp_dir("limit");
+        let mut spool = Spool::open(dir.to_str().unwrap(), 16).unwrap();
+        assert!(spool.push(b"12345678"));
+        assert!(!spool.push(b"12345678"));
+        assert_eq!(spool.counter().load(Ordering::Relaxed), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
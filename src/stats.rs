@@ -0,0 +1,560 @@
+//! Per-target send statistics
+//!
+//! Counters are kept behind `Arc` and updated atomically, so they could be
+//! shared between the sending code and whatever reports them.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// Counters of a single target.
+#[derive(Debug, Default)]
+pub struct TargetStats {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    chunks: AtomicU64,
+    errors: AtomicU64,
+    dropped: AtomicU64,
+    last_success: AtomicU64,
+    last_error: Mutex<Option<(u64, String)>>,
+}
+
+impl TargetStats {
+    /// Account a successfully sent chunk of given size.
+    pub fn chunk_sent(&self, bytes: u64) {
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Account a chunk failed to be sent.
+    pub fn chunk_failed<E: fmt::Display>(&self, error: &E) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().expect("stats lock poisoned") =
+            Some((now_unix(), error.to_string()));
+    }
+
+    /// Account a message which was not sent.
+    pub fn message_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a message which was completely sent.
+    pub fn message_sent(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.last_success.store(now_unix(), Ordering::Relaxed);
+    }
+
+    /// Return UNIX-timestamp and description of the last send error.
+    pub fn last_error(&self) -> Option<(u64, String)> {
+        self.last_error.lock().expect("stats lock poisoned").clone()
+    }
+
+    /// Return a point-in-time copy of all counters.
+    pub fn snapshot(&self) -> TargetSnapshot {
+        let last_success = self.last_success.load(Ordering::Relaxed);
+
+        TargetSnapshot {
+            messages: self.messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            chunks: self.chunks.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            last_success: if last_success > 0 {
+                Some(last_success)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Counters of a single target at some moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetSnapshot {
+    pub messages: u64,
+    pub bytes: u64,
+    pub chunks: u64,
+    pub errors: u64,
+    pub dropped: u64,
+    /// UNIX-timestamp of the last completely sent message
+    pub last_success: Option<u64>,
+}
+
+impl TargetSnapshot {
+    /// Return snapshot as a JSON object.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "messages": self.messages,
+            "bytes": self.bytes,
+            "chunks": self.chunks,
+            "errors": self.errors,
+            "dropped": self.dropped,
+            "last_success": self.last_success,
+        })
+    }
+}
+
+impl fmt::Display for TargetSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "messages: {}, bytes: {}, chunks: {}, errors: {}, dropped: {}, last success: ",
+            self.messages, self.bytes, self.chunks, self.errors, self.dropped
+        )?;
+
+        match self.last_success {
+            Some(ts) => write!(f, "{}", ts),
+            None => write!(f, "never"),
+        }
+    }
+}
+
+/// Counters of processed input records.
+#[derive(Debug, Default)]
+pub struct RecordStats {
+    accepted: AtomicU64,
+    filtered: AtomicU64,
+    no_message: AtomicU64,
+    errors: AtomicU64,
+    invalid_utf8: AtomicU64,
+    oversized: AtomicU64,
+    overflowed: AtomicU64,
+    dropped_fields: AtomicU64,
+}
+
+impl RecordStats {
+    /// Account a record transformed into a message.
+    pub fn record_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a record filtered out by level or facility.
+    pub fn record_filtered(&self) {
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a record without message.
+    pub fn record_without_message(&self) {
+        self.no_message.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a record failed to be parsed or transformed.
+    pub fn record_failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a record which was not valid UTF-8, so that it was converted lossily.
+    pub fn record_invalid_utf8(&self) {
+        self.invalid_utf8.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a line dropped for exceeding the length limit.
+    pub fn record_oversized(&self) {
+        self.oversized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a record dropped because the pipeline queue was full.
+    pub fn record_overflowed(&self) {
+        self.overflowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a field dropped from a record, e.g. the reserved one.
+    pub fn field_dropped(&self) {
+        self.dropped_fields.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return a point-in-time copy of all counters.
+    pub fn snapshot(&self) -> RecordSnapshot {
+        RecordSnapshot {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            no_message: self.no_message.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            invalid_utf8: self.invalid_utf8.load(Ordering::Relaxed),
+            oversized: self.oversized.load(Ordering::Relaxed),
+            overflowed: self.overflowed.load(Ordering::Relaxed),
+            dropped_fields: self.dropped_fields.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of processed input records at some moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordSnapshot {
+    pub accepted: u64,
+    pub filtered: u64,
+    pub no_message: u64,
+    pub errors: u64,
+    pub invalid_utf8: u64,
+    pub oversized: u64,
+    pub overflowed: u64,
+    pub dropped_fields: u64,
+}
+
+impl RecordSnapshot {
+    /// Return snapshot as a JSON object.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "accepted": self.accepted,
+            "filtered": self.filtered,
+            "no_message": self.no_message,
+            "errors": self.errors,
+            "invalid_utf8": self.invalid_utf8,
+            "oversized": self.oversized,
+            "overflowed": self.overflowed,
+            "dropped_fields": self.dropped_fields,
+        })
+    }
+}
+
+impl fmt::Display for RecordSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "accepted: {}, filtered: {}, without message: {}, parse errors: {}, invalid UTF-8: {}, oversized: {}, overflowed: {}, dropped fields: {}",
+            self.accepted,
+            self.filtered,
+            self.no_message,
+            self.errors,
+            self.invalid_utf8,
+            self.oversized,
+            self.overflowed,
+            self.dropped_fields
+        )
+    }
+}
+
+/// Process the input is read from, e.g. journalctl
+#[derive(Debug, Clone)]
+pub struct Subprocess {
+    pub name: String,
+    pub pid: u32,
+    /// Exit status, once the process exited
+    pub exit: Option<String>,
+}
+
+impl Subprocess {
+    /// Return state as a JSON object.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "pid": self.pid,
+            "running": self.exit.is_none(),
+            "exit": self.exit,
+        })
+    }
+}
+
+type TargetList = Vec<(String, Arc<TargetStats>)>;
+
+/// Function returning current length and capacity of a queue
+type QueueGauge = Box<dyn Fn() -> (usize, Option<usize>) + Send>;
+
+/// Queues of the running pipeline, keyed by name
+#[derive(Default)]
+struct QueueList(Vec<(String, QueueGauge)>);
+
+impl fmt::Debug for QueueList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+/// Registry of statistics for all targets, keyed by target name,
+/// along with counters of processed records, depth of queues and state of the input subprocess.
+/// Statistics of pipelines running in the same process are nested, keyed by pipeline name.
+#[derive(Debug, Clone)]
+pub struct SendStats {
+    targets: Arc<Mutex<TargetList>>,
+    records: Arc<RecordStats>,
+    queues: Arc<Mutex<QueueList>>,
+    subprocess: Arc<Mutex<Option<Subprocess>>>,
+    pipelines: Arc<Mutex<Vec<(String, SendStats)>>>,
+    started: Instant,
+}
+
+impl Default for SendStats {
+    fn default() -> Self {
+        SendStats {
+            targets: Arc::default(),
+            records: Arc::default(),
+            queues: Arc::default(),
+            subprocess: Arc::default(),
+            pipelines: Arc::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl SendStats {
+    pub fn new() -> Self {
+        SendStats::default()
+    }
+
+    /// Register queue to be reported, replacing the one of the same name.
+    pub fn queue<F>(&self, name: &str, gauge: F)
+    where
+        F: Fn() -> (usize, Option<usize>) + Send + 'static,
+    {
+        let mut queues = self.queues.lock().expect("stats lock poisoned");
+        queues.0.retain(|(n, _)| n != name);
+        queues.0.push((name.to_string(), Box::new(gauge)));
+    }
+
+    /// Register subprocess the input is read from.
+    pub fn subprocess_started(&self, name: &str, pid: u32) {
+        *self.subprocess.lock().expect("stats lock poisoned") = Some(Subprocess {
+            name: name.to_string(),
+            pid,
+            exit: None,
+        });
+    }
+
+    /// Register exit of the input subprocess with the given status.
+    pub fn subprocess_exited<S: fmt::Display>(&self, status: S) {
+        if let Some(subprocess) = self
+            .subprocess
+            .lock()
+            .expect("stats lock poisoned")
+            .as_mut()
+        {
+            subprocess.exit = Some(status.to_string());
+        }
+    }
+
+    /// Return state of the input subprocess, if any.
+    pub fn subprocess(&self) -> Option<Subprocess> {
+        self.subprocess.lock().expect("stats lock poisoned").clone()
+    }
+
+    /// Return counters of processed records.
+    pub fn records(&self) -> Arc<RecordStats> {
+        self.records.clone()
+    }
+
+    /// Return counters of given target, registering it on first use.
+    pub fn target(&self, name: &str) -> Arc<TargetStats> {
+        let mut targets = self.targets.lock().expect("stats lock poisoned");
+
+        if let Some((_, stats)) = targets.iter().find(|(n, _)| n == name) {
+            return stats.clone();
+        }
+
+        let stats = Arc::new(TargetStats::default());
+        targets.push((name.to_string(), stats.clone()));
+        stats
+    }
+
+    /// Return statistics of given pipeline, registering it on first use.
+    pub fn pipeline(&self, name: &str) -> SendStats {
+        let mut pipelines = self.pipelines.lock().expect("stats lock poisoned");
+
+        if let Some((_, stats)) = pipelines.iter().find(|(n, _)| n == name) {
+            return stats.clone();
+        }
+
+        let stats = SendStats::new();
+        pipelines.push((name.to_string(), stats.clone()));
+        stats
+    }
+
+    /// Return statistics of all registered pipelines.
+    pub fn pipelines(&self) -> Vec<(String, SendStats)> {
+        self.pipelines.lock().expect("stats lock poisoned").clone()
+    }
+
+    /// Return snapshots of all registered targets.
+    pub fn snapshot(&self) -> Vec<(String, TargetSnapshot)> {
+        self.targets
+            .lock()
+            .expect("stats lock poisoned")
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.snapshot()))
+            .collect()
+    }
+
+    /// Return all statistics as a JSON object: uptime, records, queues and targets,
+    /// along with statistics of every pipeline, if any.
+    pub fn to_json(&self) -> Value {
+        let targets = self
+            .targets
+            .lock()
+            .expect("stats lock poisoned")
+            .iter()
+            .map(|(name, stats)| {
+                let mut snapshot = stats.snapshot().to_json();
+                snapshot["last_error"] = match stats.last_error() {
+                    Some((ts, error)) => json!({"timestamp": ts, "error": error}),
+                    None => Value::Null,
+                };
+                (name.clone(), snapshot)
+            })
+            .collect();
+
+        let queues = self
+            .queues
+            .lock()
+            .expect("stats lock poisoned")
+            .0
+            .iter()
+            .map(|(name, gauge)| {
+                let (len, capacity) = gauge();
+                (name.clone(), json!({"len": len, "capacity": capacity}))
+            })
+            .collect();
+
+        let mut report = json!({
+            "uptime": self.started.elapsed().as_secs(),
+            "records": self.records.snapshot().to_json(),
+            "queues": Value::Object(queues),
+            "targets": Value::Object(targets),
+        });
+
+        let pipelines = self.pipelines();
+        if !pipelines.is_empty() {
+            report["pipelines"] = pipelines
+                .iter()
+                .map(|(name, stats)| (name.clone(), stats.to_json()))
+                .collect();
+        }
+        report
+    }
+
+    /// Write current statistics of every target into the log,
+    /// along with records dropped because of full queue, if any.
+    pub fn log(&self) {
+        self.log_as("");
+        for (name, stats) in self.pipelines() {
+            stats.log_as(&format!("pipeline {} | ", name));
+        }
+    }
+
+    fn log_as(&self, prefix: &str) {
+        for (name, snapshot) in self.snapshot() {
+            info!("{}target {} | {}", prefix, name, snapshot);
+        }
+
+        let overflowed = self.records.overflowed.load(Ordering::Relaxed);
+        if overflowed > 0 {
+            info!("{}queue | overflowed: {}", prefix, overflowed);
+        }
+    }
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_counters() {
+        let stats = SendStats::new();
+        let target = stats.target("graylog:12201");
+        assert!(Arc::ptr_eq(&target, &stats.target("graylog:12201")));
+
+        target.chunk_sent(100);
+        target.chunk_sent(50);
+        target.message_sent();
+        target.chunk_failed(&"connection refused");
+        target.message_dropped();
+
+        let snapshot = target.snapshot();
+        assert_eq!(
+            (snapshot.messages, snapshot.bytes, snapshot.chunks),
+            (1, 150, 2)
+        );
+        assert_eq!((snapshot.errors, snapshot.dropped), (1, 1));
+        assert!(snapshot.last_success.is_some());
+        assert_eq!(target.last_error().unwrap().1, "connection refused");
+
+        let idle = stats.target("backup:12201").snapshot();
+        assert_eq!(
+            idle.to_string(),
+            "messages: 0, bytes: 0, chunks: 0, errors: 0, dropped: 0, last success: never"
+        );
+        assert_eq!(idle.to_json()["last_success"], Value::Null);
+
+        let names: Vec<String> = stats.snapshot().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["graylog:12201", "backup:12201"]);
+    }
+
+    #[test]
+    fn record_counters() {
+        let stats = SendStats::new();
+        let records = stats.records();
+        records.record_accepted();
+        records.record_accepted();
+        records.record_filtered();
+        records.record_overflowed();
+        records.field_dropped();
+
+        let snapshot = records.snapshot();
+        assert_eq!((snapshot.accepted, snapshot.filtered), (2, 1));
+        assert_eq!((snapshot.overflowed, snapshot.dropped_fields), (1, 1));
+        assert_eq!(
+            snapshot.to_string(),
+            "accepted: 2, filtered: 1, without message: 0, parse errors: 0, invalid UTF-8: 0, oversized: 0, overflowed: 1, dropped fields: 1"
+        );
+    }
+
+    #[test]
+    fn json_report() {
+        let stats = SendStats::new();
+        stats.target("graylog:12201").chunk_failed(&"timed out");
+        stats.queue("records", || (3, Some(1024)));
+        stats.queue("spool", || (7, None));
+        stats.queue("records", || (5, Some(1024)));
+        stats.subprocess_started("journalctl", 42);
+        stats.subprocess_exited("exit status: 1");
+
+        let report = stats.to_json();
+        assert_eq!(report["records"]["accepted"], json!(0));
+        assert_eq!(
+            report["queues"],
+            json!({"records": {"len": 5, "capacity": 1024}, "spool": {"len": 7, "capacity": null}})
+        );
+        assert_eq!(
+            report["targets"]["graylog:12201"]["last_error"]["error"],
+            json!("timed out")
+        );
+        assert_eq!(report["targets"]["graylog:12201"]["errors"], json!(1));
+
+        let subprocess = stats.subprocess().unwrap().to_json();
+        assert_eq!(subprocess["pid"], json!(42));
+        assert_eq!(subprocess["running"], json!(false));
+        assert_eq!(report["pipelines"], Value::Null);
+    }
+
+    #[test]
+    fn pipeline_report() {
+        let stats = SendStats::new();
+        let first = stats.pipeline("1");
+        first.target("graylog:12201").message_sent();
+        first.records().record_accepted();
+        stats.pipeline("2").target("graylog:12201");
+        stats.pipeline("1").records().record_accepted();
+
+        let report = stats.to_json();
+        assert_eq!(report["targets"], json!({}));
+        assert_eq!(report["pipelines"]["1"]["records"]["accepted"], json!(2));
+        assert_eq!(
+            report["pipelines"]["1"]["targets"]["graylog:12201"]["messages"],
+            json!(1)
+        );
+        assert_eq!(
+            report["pipelines"]["2"]["targets"]["graylog:12201"]["messages"],
+            json!(0)
+        );
+    }
+}
@@ -0,0 +1,592 @@
+//! Syslog listener input
+//!
+//! Syslog messages are received over UDP, one per datagram, or over TCP, framed
+//! either by octet counting or by newlines (RFC 6587). Both RFC 5424 and BSD
+//! RFC 3164 formats are parsed into fields named as journald names them for
+//! syslog records, so that they are transformed into GELF the same way.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::net::{IpAddr, TcpListener, TcpStream, UdpSocket};
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::errors::Result;
+use crate::processing::{read_line, Line};
+use crate::record::{self, LogRecord};
+use crate::stats::RecordStats;
+
+/// Priority of messages without one: user-level notice
+const DEFAULT_PRI: u8 = 13;
+
+/// Maximal size of UDP datagram
+const MAX_DATAGRAM: usize = 65535;
+
+/// Maximal length of octet counting frame length along with the following space
+const MAX_LENGTH_DIGITS: u64 = 21;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Protocols syslog messages are received over
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ListenProtocol {
+    Udp,
+    Tcp,
+    Both,
+}
+
+impl<'a> From<&'a str> for ListenProtocol {
+    fn from(protocol: &'a str) -> Self {
+        match protocol {
+            "tcp" => ListenProtocol::Tcp,
+            "both" => ListenProtocol::Both,
+            _ => ListenProtocol::Udp,
+        }
+    }
+}
+
+impl fmt::Display for ListenProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenProtocol::Udp => write!(f, "UDP"),
+            ListenProtocol::Tcp => write!(f, "TCP"),
+            ListenProtocol::Both => write!(f, "UDP and TCP"),
+        }
+    }
+}
+
+/// Bind the address and receive messages in background threads, passing parsed ones to the channel.
+///
+/// Messages longer than `max_message` bytes are dropped and counted as oversized.
+pub fn listen(
+    addr: &str,
+    protocol: ListenProtocol,
+    max_message: usize,
+    capacity: usize,
+    stats: Arc<RecordStats>,
+) -> Result<Receiver<LogRecord>> {
+    let (records_tx, records_rx) = bounded(capacity);
+
+    if protocol != ListenProtocol::Tcp {
+        let socket = UdpSocket::bind(addr)?;
+        let records = records_tx.clone();
+        let stats = stats.clone();
+        thread::Builder::new()
+            .name("syslog-udp".to_string())
+            .spawn(move || receive_datagrams(socket, max_message, records, stats))?;
+    }
+
+    if protocol != ListenProtocol::Udp {
+        let listener = TcpListener::bind(addr)?;
+        thread::Builder::new()
+            .name("syslog-tcp".to_string())
+            .spawn(move || accept_connections(listener, max_message, records_tx, stats))?;
+    }
+
+    Ok(records_rx)
+}
+
+fn receive_datagrams(
+    socket: UdpSocket,
+    max_message: usize,
+    records: Sender<LogRecord>,
+    stats: Arc<RecordStats>,
+) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("failed to receive syslog datagram: {}", e);
+                continue;
+            }
+        };
+
+        if len > max_message {
+            oversized(len as u64, max_message, &stats);
+            continue;
+        }
+
+        if records.send(parse(&buffer[..len], peer.ip())).is_err() {
+            return;
+        }
+    }
+}
+
+fn accept_connections(
+    listener: TcpListener,
+    max_message: usize,
+    records: Sender<LogRecord>,
+    stats: Arc<RecordStats>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept syslog connection: {}", e);
+                continue;
+            }
+        };
+
+        let records = records.clone();
+        let stats = stats.clone();
+        let spawned = thread::Builder::new()
+            .name("syslog-connection".to_string())
+            .spawn(move || {
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(_) => return,
+                };
+                debug!("syslog connection from {}", peer);
+
+                if let Err(e) = receive_frames(stream, peer.ip(), max_message, &records, &stats) {
+                    warn!("syslog connection from {} failed: {}", peer, e);
+                }
+            });
+
+        if let Err(e) = spawned {
+            warn!("failed to start syslog connection thread: {}", e);
+        }
+    }
+}
+
+/// Read messages of the connection until it is closed.
+fn receive_frames(
+    stream: TcpStream,
+    peer: IpAddr,
+    max_message: usize,
+    records: &Sender<LogRecord>,
+    stats: &RecordStats,
+) -> io::Result<()> {
+    let mut input = BufReader::new(stream);
+    let mut frame = Vec::new();
+
+    loop {
+        frame.clear();
+
+        // octet counting frames start with length, messages themselves start with `<`
+        let counted = match input.fill_buf()?.first() {
+            None => return Ok(()),
+            Some(c) => c.is_ascii_digit(),
+        };
+
+        if counted {
+            let len = read_frame_length(&mut input)?;
+            if len > max_message as u64 {
+                io::copy(&mut (&mut input).take(len), &mut io::sink())?;
+                oversized(len, max_message, stats);
+                continue;
+            }
+            frame.resize(len as usize, 0);
+            input.read_exact(&mut frame)?;
+        } else {
+            match read_line(&mut input, &mut frame, max_message)? {
+                Line::End => return Ok(()),
+                Line::Oversized(len) => {
+                    oversized(len, max_message, stats);
+                    continue;
+                }
+                Line::Complete => {}
+            }
+        }
+
+        if frame.iter().all(|c| c.is_ascii_whitespace() || *c == 0) {
+            continue;
+        }
+
+        if records.send(parse(&frame, peer)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Read length of octet counting frame along with the following space.
+fn read_frame_length<R: BufRead>(input: &mut R) -> io::Result<u64> {
+    // length never takes more than a few digits, so that garbage is not buffered endlessly
+    let mut digits = Vec::new();
+    input
+        .take(MAX_LENGTH_DIGITS)
+        .read_until(b' ', &mut digits)?;
+
+    String::from_utf8_lossy(&digits)
+        .trim_end_matches(' ')
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed frame length"))
+}
+
+fn oversized(len: u64, max: usize, stats: &RecordStats) {
+    warn!(
+        "syslog message of {} bytes exceeds limit of {} bytes, dropped",
+        len, max
+    );
+    stats.record_oversized();
+}
+
+/// Parse syslog message into a record, sender address is used as a host name if it lacks one.
+///
+/// Text not following any of the formats is taken as the message as is.
+pub fn parse(frame: &[u8], peer: IpAddr) -> LogRecord {
+    let text = String::from_utf8_lossy(frame);
+    let text = text.trim_end_matches(['\n', '\r', '\0']);
+    let mut record = LogRecord::new();
+
+    let (pri, rest) = parse_pri(text).unwrap_or((DEFAULT_PRI, text));
+    insert(&mut record, "PRIORITY", (pri & 0x07).to_string());
+    insert(&mut record, "SYSLOG_FACILITY", (pri >> 3).to_string());
+
+    match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(rest, &mut record),
+        None => parse_rfc3164(rest, &mut record),
+    }
+
+    if !record.contains_key("_HOSTNAME") {
+        insert(&mut record, "_HOSTNAME", peer.to_string());
+    }
+    record
+}
+
+fn insert(record: &mut LogRecord, name: &str, value: String) {
+    record.insert(record::intern(name), value.into());
+}
+
+/// Split `<PRI>` from the rest of the message.
+fn parse_pri(text: &str) -> Option<(u8, &str)> {
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>').filter(|end| (1..=3).contains(end))?;
+    let pri = rest[..end]
+        .parse()
+        .ok()
+        .filter(|pri| *pri <= 191 && rest[..end].bytes().all(|c| c.is_ascii_digit()))?;
+    Some((pri, &rest[end + 1..]))
+}
+
+/// Parse RFC 5424 message following version:
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`.
+fn parse_rfc5424(rest: &str, record: &mut LogRecord) {
+    let mut parts = rest.splitn(6, ' ');
+    let mut header = || parts.next().filter(|part| *part != "-" && !part.is_empty());
+
+    if let Some(us) = header().and_then(parse_rfc3339) {
+        insert(record, "__REALTIME_TIMESTAMP", us.to_string());
+    }
+    for name in &[
+        "_HOSTNAME",
+        "SYSLOG_IDENTIFIER",
+        "SYSLOG_PID",
+        "SYSLOG_MSGID",
+    ] {
+        if let Some(value) = header() {
+            insert(record, name, value.to_string());
+        }
+    }
+
+    let (data, message) = split_structured_data(parts.next().unwrap_or(""));
+    if !data.is_empty() {
+        insert(record, "SYSLOG_STRUCTURED_DATA", data.to_string());
+    }
+    insert(
+        record,
+        "MESSAGE",
+        message.trim_start_matches('\u{feff}').to_string(),
+    );
+}
+
+/// Split structured data elements, nil one is omitted, from the message following them.
+fn split_structured_data(rest: &str) -> (&str, &str) {
+    let end = if rest == "-" || rest.starts_with("- ") {
+        1
+    } else if rest.starts_with('[') {
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut end = rest.len();
+
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ']' if !quoted && !rest[i + 1..].starts_with('[') => {
+                    end = i + 1;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        end
+    } else {
+        // malformed, whole text is the message
+        return ("", rest);
+    };
+
+    let message = &rest[end..];
+    let data = &rest[..end];
+    (
+        if data == "-" { "" } else { data },
+        message.strip_prefix(' ').unwrap_or(message),
+    )
+}
+
+/// Parse RFC 3164 message following priority: `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`.
+///
+/// Host name is often omitted by local senders, a tag always ends with a colon or PID.
+fn parse_rfc3164(rest: &str, record: &mut LogRecord) {
+    let timestamp = rest.get(..15).and_then(parse_bsd_timestamp);
+    let mut rest = match timestamp {
+        Some(us) => {
+            insert(record, "__REALTIME_TIMESTAMP", us.to_string());
+            rest[15..].strip_prefix(' ').unwrap_or(&rest[15..])
+        }
+        None => rest,
+    };
+
+    if timestamp.is_some() {
+        if let Some((host, tail)) = rest.split_once(' ') {
+            if !host.is_empty() && !host.ends_with(':') && !host.contains('[') {
+                insert(record, "_HOSTNAME", host.to_string());
+                rest = tail;
+            }
+        }
+    }
+
+    let tag_end = rest.find([':', '[', ' ']).filter(|end| *end > 0);
+    let message = match tag_end.map(|end| (end, rest.as_bytes()[end])) {
+        Some((end, b':')) => {
+            insert(record, "SYSLOG_IDENTIFIER", rest[..end].to_string());
+            &rest[end + 1..]
+        }
+
+        Some((end, b'[')) => match rest[end..].find("]:") {
+            Some(close) => {
+                insert(record, "SYSLOG_IDENTIFIER", rest[..end].to_string());
+                insert(record, "SYSLOG_PID", rest[end + 1..end + close].to_string());
+                &rest[end + close + 2..]
+            }
+            None => rest,
+        },
+
+        _ => rest,
+    };
+
+    insert(
+        record,
+        "MESSAGE",
+        message.strip_prefix(' ').unwrap_or(message).to_string(),
+    );
+}
+
+/// Parse RFC 3339 timestamp into microseconds since the epoch.
+fn parse_rfc3339(timestamp: &str) -> Option<i64> {
+    let bytes = timestamp.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[10] != b'T'
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+
+    let number = |from: usize, to: usize| -> Option<i64> {
+        let digits = timestamp.get(from..to)?;
+        if digits.bytes().all(|c| c.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &timestamp[19..];
+    let mut micros = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        let padded = format!("{:0<6}", &fraction[..digits.min(6)]);
+        micros = padded.parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let hours: i64 = rest[1..3].parse().ok()?;
+            let minutes: i64 = rest[4..6].parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => return None,
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds * 1_000_000 + micros)
+}
+
+/// Return number of days since the epoch for a date of the Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parse BSD timestamp `Mmm dd hh:mm:ss` of local time into microseconds since the epoch.
+///
+/// Year is not sent, so the current one is assumed, unless the time would be in the future.
+fn parse_bsd_timestamp(timestamp: &str) -> Option<i64> {
+    let bytes = timestamp.as_bytes();
+    if bytes[3] != b' ' || bytes[6] != b' ' || bytes[9] != b':' || bytes[12] != b':' {
+        return None;
+    }
+
+    let month = MONTHS
+        .iter()
+        .position(|month| timestamp.starts_with(month))?;
+    let number = |from: usize, to: usize| -> Option<i32> {
+        let digits = timestamp[from..to].trim_start();
+        if !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let day = number(4, 6)?;
+    let (hour, minute, second) = (number(7, 9)?, number(10, 12)?, number(13, 15)?);
+
+    unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut current: libc::tm = mem::zeroed();
+        if libc::localtime_r(&now, &mut current).is_null() {
+            return None;
+        }
+
+        let local_time = |year: i32| {
+            let mut time: libc::tm = mem::zeroed();
+            time.tm_year = year;
+            time.tm_mon = month as i32;
+            time.tm_mday = day;
+            time.tm_hour = hour;
+            time.tm_min = minute;
+            time.tm_sec = second;
+            time.tm_isdst = -1;
+            libc::mktime(&mut time)
+        };
+
+        // messages of the last days of December are received in January
+        let mut time = local_time(current.tm_year);
+        if time > now + 86400 {
+            time = local_time(current.tm_year - 1);
+        }
+        if time == -1 {
+            return None;
+        }
+
+        Some(time as i64 * 1_000_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::net::Ipv4Addr;
+
+    fn parsed(message: &str) -> LogRecord {
+        parse(message.as_bytes(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+    }
+
+    fn field<'a>(record: &'a LogRecord, name: &str) -> Option<&'a str> {
+        record.get(name).and_then(Value::as_str)
+    }
+
+    #[test]
+    fn rfc5424_message() {
+        let record = parsed(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"App\\]\"][x@1 a=\"b\"] \u{feff}An application event\n",
+        );
+
+        assert_eq!(field(&record, "PRIORITY"), Some("5"));
+        assert_eq!(field(&record, "SYSLOG_FACILITY"), Some("20"));
+        assert_eq!(
+            field(&record, "__REALTIME_TIMESTAMP"),
+            Some("1065910455003000")
+        );
+        assert_eq!(field(&record, "_HOSTNAME"), Some("mymachine.example.com"));
+        assert_eq!(field(&record, "SYSLOG_IDENTIFIER"), Some("evntslog"));
+        assert_eq!(field(&record, "SYSLOG_PID"), None);
+        assert_eq!(field(&record, "SYSLOG_MSGID"), Some("ID47"));
+        assert_eq!(
+            field(&record, "SYSLOG_STRUCTURED_DATA"),
+            Some("[exampleSDID@32473 iut=\"3\" eventSource=\"App\\]\"][x@1 a=\"b\"]")
+        );
+        assert_eq!(field(&record, "MESSAGE"), Some("An application event"));
+    }
+
+    #[test]
+    fn rfc5424_nil_values() {
+        let record = parsed("<34>1 2003-08-24T05:14:15.000003-07:00 - su 42 - - 'su root' failed");
+
+        assert_eq!(
+            field(&record, "__REALTIME_TIMESTAMP"),
+            Some("1061727255000003")
+        );
+        assert_eq!(field(&record, "_HOSTNAME"), Some("10.0.0.1"));
+        assert_eq!(field(&record, "SYSLOG_PID"), Some("42"));
+        assert_eq!(field(&record, "SYSLOG_STRUCTURED_DATA"), None);
+        assert_eq!(field(&record, "MESSAGE"), Some("'su root' failed"));
+    }
+
+    #[test]
+    fn rfc3164_message() {
+        let record = parsed("<13>Feb  5 17:32:18 host sshd[4242]: Accepted publickey");
+        assert!(record.contains_key("__REALTIME_TIMESTAMP"));
+        assert_eq!(field(&record, "_HOSTNAME"), Some("host"));
+        assert_eq!(field(&record, "SYSLOG_IDENTIFIER"), Some("sshd"));
+        assert_eq!(field(&record, "SYSLOG_PID"), Some("4242"));
+        assert_eq!(field(&record, "MESSAGE"), Some("Accepted publickey"));
+
+        // local senders omit host name
+        let record = parsed("<14>Oct 11 22:14:15 app: started");
+        assert_eq!(field(&record, "_HOSTNAME"), Some("10.0.0.1"));
+        assert_eq!(field(&record, "SYSLOG_IDENTIFIER"), Some("app"));
+        assert_eq!(field(&record, "MESSAGE"), Some("started"));
+    }
+
+    #[test]
+    fn unstructured_message() {
+        let record = parsed("just text: no header");
+        assert_eq!(field(&record, "PRIORITY"), Some("5"));
+        assert_eq!(field(&record, "SYSLOG_FACILITY"), Some("1"));
+        assert_eq!(field(&record, "SYSLOG_IDENTIFIER"), None);
+        assert_eq!(field(&record, "MESSAGE"), Some("just text: no header"));
+
+        let record = parsed("<999>text");
+        assert_eq!(field(&record, "MESSAGE"), Some("<999>text"));
+    }
+}
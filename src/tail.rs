@@ -0,0 +1,534 @@
+//! File tailing input
+//!
+//! Like `tail -F`, files are followed by name: when a file is rotated, the rest of
+//! the old one is read and then the new one is followed from its beginning, when
+//! a file is truncated, it is read again from the start. Files matching patterns
+//! are looked up while running, so files created later are followed too.
+//!
+//! Files found on start are followed from their end, unless the offsets file keeps
+//! position of a previous run for the same file.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use regex::Regex;
+use serde_json::{self, Map, Value};
+
+use crate::cursor::{self, CursorFile};
+use crate::errors::{Error, Result};
+use crate::processing::line_oversized;
+use crate::stats::RecordStats;
+
+/// Period of checking files for new lines and rotation, when there are none
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes read from a file at once
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Pattern of file paths, with wildcards `*` and `?` allowed in the file name only
+#[derive(Debug)]
+pub struct Pattern {
+    dir: PathBuf,
+    name: Regex,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let path = Path::new(pattern);
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::InternalError(format!("no file name in {}", pattern)))?;
+        let dir = match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => PathBuf::from("."),
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("/"),
+        };
+
+        if dir.to_string_lossy().contains(['*', '?']) {
+            return Err(Error::InternalError(format!(
+                "wildcards are allowed in file name only: {}",
+                pattern
+            )));
+        }
+
+        let mut regex = String::from("^");
+        for c in name.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Pattern {
+            dir,
+            name: Regex::new(&regex).map_err(|e| Error::InternalError(e.to_string()))?,
+        })
+    }
+
+    /// List regular files currently matching the pattern.
+    fn matches(&self) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| self.name.is_match(name))
+            })
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect()
+    }
+}
+
+/// Identity of a file, kept while it is renamed
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    fn of(metadata: &fs::Metadata) -> Self {
+        FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+}
+
+/// File being followed
+struct Followed {
+    file: File,
+    id: FileId,
+    /// Bytes read from the file
+    read: u64,
+    /// Incomplete last line, unless it is already too long
+    pending: Vec<u8>,
+    /// Length of incomplete last line
+    partial: u64,
+}
+
+impl Followed {
+    fn open(path: &Path, position: Option<u64>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let read = match position {
+            Some(position) => file.seek(SeekFrom::Start(position))?,
+            None => file.seek(SeekFrom::End(0))?,
+        };
+
+        Ok(Followed {
+            file,
+            id: FileId::of(&metadata),
+            read,
+            pending: Vec::new(),
+            partial: 0,
+        })
+    }
+
+    /// Position right after the last complete line.
+    fn position(&self) -> u64 {
+        self.read - self.partial
+    }
+
+    /// Start reading the file from its beginning again.
+    fn rewind(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.read = 0;
+        self.pending.clear();
+        self.partial = 0;
+        Ok(())
+    }
+
+    /// Read available data, returning complete lines without line endings.
+    fn read_lines(
+        &mut self,
+        buffer: &mut [u8],
+        max: usize,
+        stats: &RecordStats,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let mut lines = Vec::new();
+
+        loop {
+            let read = match self.file.read(buffer) {
+                Ok(0) => return Ok(lines),
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            self.read += read as u64;
+            self.split_lines(&buffer[..read], max, stats, &mut lines);
+
+            if read < buffer.len() {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Split data into lines, keeping the incomplete one and skipping ones longer than `max` bytes.
+    fn split_lines(
+        &mut self,
+        data: &[u8],
+        max: usize,
+        stats: &RecordStats,
+        lines: &mut Vec<Vec<u8>>,
+    ) {
+        let mut rest = data;
+
+        while let Some(end) = rest.iter().position(|&c| c == b'\n') {
+            // length is counted with the newline, as for lines of stdin
+            let len = self.partial + end as u64 + 1;
+            if len > max as u64 {
+                line_oversized(len, max, stats);
+            } else {
+                let mut line = std::mem::take(&mut self.pending);
+                line.extend_from_slice(&rest[..end]);
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if !line.is_empty() {
+                    lines.push(line);
+                }
+            }
+
+            self.pending.clear();
+            self.partial = 0;
+            rest = &rest[end + 1..];
+        }
+
+        self.partial += rest.len() as u64;
+        if self.partial <= max as u64 {
+            self.pending.extend_from_slice(rest);
+        } else {
+            self.pending = Vec::new();
+        }
+    }
+}
+
+/// Files matching patterns, followed by name.
+///
+/// Lines longer than the limit are dropped and counted as oversized.
+pub struct Tail {
+    patterns: Vec<Pattern>,
+    followed: BTreeMap<PathBuf, Followed>,
+    failed: HashSet<PathBuf>,
+    saved: BTreeMap<PathBuf, (FileId, u64)>,
+    offsets: Option<CursorFile>,
+    buffer: Vec<u8>,
+    max_line: usize,
+    stats: Arc<RecordStats>,
+}
+
+impl Tail {
+    /// Start following files matching the patterns, resuming at offsets saved in the file, if any.
+    pub fn open(
+        patterns: Vec<Pattern>,
+        offsets_path: Option<&str>,
+        max_line: usize,
+        stats: Arc<RecordStats>,
+    ) -> Result<Self> {
+        let saved = match offsets_path {
+            Some(path) => match cursor::load(path)? {
+                Some(offsets) => parse_offsets(&offsets).ok_or_else(|| {
+                    Error::InternalError(format!("malformed offsets file {}", path))
+                })?,
+                None => BTreeMap::new(),
+            },
+            None => BTreeMap::new(),
+        };
+
+        let mut tail = Tail {
+            patterns,
+            followed: BTreeMap::new(),
+            failed: HashSet::new(),
+            saved,
+            offsets: offsets_path.map(CursorFile::new),
+            buffer: vec![0; READ_CHUNK],
+            max_line,
+            stats,
+        };
+
+        tail.discover(true);
+        if tail.followed.is_empty() {
+            warn!("no files match yet, waiting for them to appear");
+        }
+        Ok(tail)
+    }
+
+    /// Read new lines of all files, checking for rotated, truncated and new files.
+    ///
+    /// Return number of lines passed, zero means that files had no new lines.
+    pub fn poll<F>(&mut self, mut emit: F) -> Result<usize>
+    where
+        F: FnMut(&Path, Vec<u8>) -> Result<()>,
+    {
+        self.discover(false);
+
+        let mut count = 0;
+        let paths: Vec<PathBuf> = self.followed.keys().cloned().collect();
+
+        for path in paths {
+            count += self.read_file(&path, &mut emit)?;
+
+            let id = self.followed[&path].id;
+            match fs::metadata(&path) {
+                // rest of the removed file is read, it is followed again once it reappears
+                Err(_) => {
+                    debug!("{} is removed", path.display());
+                    self.followed.remove(&path);
+                }
+
+                Ok(metadata) if FileId::of(&metadata) != id => {
+                    info!("{} is rotated, following the new file", path.display());
+                    self.followed.remove(&path);
+                    self.follow(&path, Some(0));
+                }
+
+                Ok(metadata) if metadata.len() < self.followed[&path].read => {
+                    info!("{} is truncated, reading it from the start", path.display());
+                    self.followed.get_mut(&path).unwrap().rewind()?;
+                }
+
+                Ok(_) => continue,
+            }
+
+            if self.followed.contains_key(&path) {
+                count += self.read_file(&path, &mut emit)?;
+            }
+        }
+
+        self.update_offsets();
+        Ok(count)
+    }
+
+    /// Write offsets of followed files, if they changed since the last save.
+    pub fn save(&mut self) {
+        if let Some(ref mut offsets) = self.offsets {
+            offsets.save();
+        }
+    }
+
+    fn read_file<F>(&mut self, path: &Path, emit: &mut F) -> Result<usize>
+    where
+        F: FnMut(&Path, Vec<u8>) -> Result<()>,
+    {
+        let followed = self.followed.get_mut(path).unwrap();
+        let lines = followed.read_lines(&mut self.buffer, self.max_line, &self.stats)?;
+        let count = lines.len();
+
+        for line in lines {
+            emit(path, line)?;
+        }
+        Ok(count)
+    }
+
+    /// Follow files matching patterns which are not followed yet.
+    ///
+    /// New files are read from the start, unless they are found on startup.
+    fn discover(&mut self, startup: bool) {
+        let found: Vec<PathBuf> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.matches())
+            .filter(|path| !self.followed.contains_key(path))
+            .collect();
+
+        for path in found {
+            let position = match self.saved.remove(&path) {
+                Some((id, offset)) => fs::metadata(&path)
+                    .ok()
+                    .filter(|metadata| FileId::of(metadata) == id && metadata.len() >= offset)
+                    .map(|_| offset)
+                    .or(Some(0)),
+                None if startup => None,
+                None => Some(0),
+            };
+            self.follow(&path, position);
+        }
+    }
+
+    fn follow(&mut self, path: &Path, position: Option<u64>) {
+        match Followed::open(path, position) {
+            Ok(followed) => {
+                debug!("following {} from offset {}", path.display(), followed.read);
+                self.failed.remove(path);
+                self.followed.insert(path.to_path_buf(), followed);
+            }
+
+            // complain once, not on every check
+            Err(e) => {
+                if self.failed.insert(path.to_path_buf()) {
+                    warn!("failed to open {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Pass offsets to the file, which is written at most once per interval.
+    fn update_offsets(&mut self) {
+        let offsets = match self.offsets {
+            Some(ref mut offsets) => offsets,
+            None => return,
+        };
+
+        let state: Map<String, Value> = self
+            .followed
+            .iter()
+            .map(|(path, followed)| {
+                (
+                    path.to_string_lossy().into_owned(),
+                    json!({
+                        "dev": followed.id.dev,
+                        "ino": followed.id.ino,
+                        "offset": followed.position(),
+                    }),
+                )
+            })
+            .collect();
+
+        offsets.update(Arc::from(Value::Object(state).to_string()));
+    }
+}
+
+impl Drop for Tail {
+    fn drop(&mut self) {
+        self.update_offsets();
+    }
+}
+
+/// Parse offsets saved by the previous run.
+fn parse_offsets(offsets: &str) -> Option<BTreeMap<PathBuf, (FileId, u64)>> {
+    let state: Map<String, Value> = serde_json::from_str(offsets).ok()?;
+
+    state
+        .into_iter()
+        .map(|(path, file)| {
+            let id = FileId {
+                dev: file["dev"].as_u64()?,
+                ino: file["ino"].as_u64()?,
+            };
+            Some((PathBuf::from(path), (id, file["offset"].as_u64()?)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("jctl2gray-tail-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, data: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    fn poll(tail: &mut Tail) -> Vec<String> {
+        let mut lines = Vec::new();
+        tail.poll(|_, line| {
+            lines.push(String::from_utf8(line).unwrap());
+            Ok(())
+        })
+        .unwrap();
+        lines
+    }
+
+    fn open(dir: &Path, offsets: Option<&Path>) -> Tail {
+        let pattern = Pattern::parse(&dir.join("*.log").to_string_lossy()).unwrap();
+        let offsets = offsets.map(|path| path.to_string_lossy().into_owned());
+        let stats = Arc::new(RecordStats::default());
+        Tail::open(vec![pattern], offsets.as_deref(), 16, stats).unwrap()
+    }
+
+    #[test]
+    fn pattern_matches_file_names() {
+        let dir = temp_dir("pattern");
+        for name in &["a.log", "b.log", "a.txt", "ab.log1"] {
+            append(&dir.join(name), "");
+        }
+
+        let pattern = Pattern::parse(&dir.join("?.log").to_string_lossy()).unwrap();
+        let mut matches = pattern.matches();
+        matches.sort();
+        assert_eq!(matches, vec![dir.join("a.log"), dir.join("b.log")]);
+
+        assert!(Pattern::parse("/var/log/*/app.log").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn follows_rotation_and_truncation() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("app.log");
+        append(&path, "before start\n");
+
+        let mut tail = open(&dir, None);
+        assert!(poll(&mut tail).is_empty());
+
+        append(&path, "first\nsec");
+        assert_eq!(poll(&mut tail), vec!["first"]);
+        append(&path, "ond\r\n\nthis line is too long\nthird\n");
+        assert_eq!(poll(&mut tail), vec!["second", "third"]);
+
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        append(&path, "rotated\n");
+        assert_eq!(poll(&mut tail), vec!["rotated"]);
+
+        fs::write(&path, "").unwrap();
+        assert!(poll(&mut tail).is_empty());
+        append(&path, "truncated\n");
+        assert_eq!(poll(&mut tail), vec!["truncated"]);
+
+        append(&dir.join("new.log"), "new file\n");
+        assert_eq!(poll(&mut tail), vec!["new file"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resumes_at_saved_offset() {
+        let dir = temp_dir("offsets");
+        let offsets = dir.join("offsets");
+        let path = dir.join("app.log");
+        append(&path, "old\n");
+
+        {
+            let mut tail = open(&dir, Some(&offsets));
+            append(&path, "one\ntw");
+            assert_eq!(poll(&mut tail), vec!["one"]);
+        }
+
+        append(&path, "o\nthree\n");
+        let mut tail = open(&dir, Some(&offsets));
+        assert_eq!(poll(&mut tail), vec!["two", "three"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
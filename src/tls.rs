@@ -0,0 +1,268 @@
+//! TLS configuration of client connections
+//!
+//! Certificates are read from PEM files. The files are loaded again for a new connection
+//! once they are modified, so that short-lived certificates are renewed without restart.
+//! Established connection keeps the certificates it was made with, until it fails.
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::errors::{Error, Result};
+
+/// Certificates and verification of TLS connections
+#[derive(Debug, PartialEq, Clone)]
+pub struct TlsOptions {
+    /// PEM file with certificates of trusted authorities, bundled web roots are used if not set
+    pub ca_cert: Option<String>,
+    /// PEM files with client certificate chain and its private key
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// Whether certificate of the target must be issued for its hostname
+    pub verify_hostname: bool,
+}
+
+/// Client config of TLS connections, loaded again once its certificate files are modified
+pub struct TlsClientConfig {
+    options: TlsOptions,
+    loaded: Mutex<LoadedConfig>,
+}
+
+/// Client config along with modification times of the files it is loaded from
+struct LoadedConfig {
+    config: Arc<ClientConfig>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl TlsClientConfig {
+    /// Load certificates given by the options.
+    pub fn new(options: &TlsOptions) -> Result<Self> {
+        let loaded = LoadedConfig {
+            modified: modified_times(options),
+            config: Arc::new(client_config(options)?),
+        };
+
+        Ok(TlsClientConfig {
+            options: options.clone(),
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// Return client config, loading certificates again if their files are modified since.
+    ///
+    /// Files failing to load, e.g. written partially, are reported and the previous config is kept,
+    /// trying them again on the next connection.
+    pub fn current(&self) -> Arc<ClientConfig> {
+        let mut loaded = self.loaded.lock().expect("tls lock poisoned");
+
+        let modified = modified_times(&self.options);
+        if modified != loaded.modified {
+            match client_config(&self.options) {
+                Ok(config) => {
+                    info!("TLS certificates reloaded");
+                    loaded.config = Arc::new(config);
+                    loaded.modified = modified;
+                }
+                Err(e) => warn!(
+                    "failed to reload TLS certificates, keeping previous ones: {}",
+                    e
+                ),
+            }
+        }
+
+        loaded.config.clone()
+    }
+}
+
+/// Return modification times of the certificate files, None for missing ones.
+fn modified_times(options: &TlsOptions) -> Vec<Option<SystemTime>> {
+    [&options.ca_cert, &options.client_cert, &options.client_key]
+        .iter()
+        .map(|path| {
+            path.as_ref()
+                .and_then(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        })
+        .collect()
+}
+
+fn client_config(options: &TlsOptions) -> Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    match options.ca_cert {
+        Some(ref path) => {
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| Error::Tls(format!("bad CA certificate in {}: {}", path, e)))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::Tls(e.to_string()))?;
+
+    let builder = if options.verify_hostname {
+        builder.with_root_certificates(roots)
+    } else {
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| Error::Tls(e.to_string()))?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(IgnoreHostname(verifier)))
+    };
+
+    match (&options.client_cert, &options.client_key) {
+        (Some(cert), Some(key)) => {
+            let key = PrivateKeyDer::from_pem_file(key)
+                .map_err(|e| Error::Tls(format!("failed to read key {}: {}", key, e)))?;
+            builder
+                .with_client_auth_cert(read_certs(cert)?, key)
+                .map_err(|e| Error::Tls(format!("bad client certificate {}: {}", cert, e)))
+        }
+        _ => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Read all certificates of the PEM file, which must contain at least one.
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::Tls(format!("failed to read certificates {}: {}", path, e)))?;
+
+    if certs.is_empty() {
+        return Err(Error::Tls(format!("no certificates found in {}", path)));
+    }
+
+    Ok(certs)
+}
+
+/// Verifier accepting certificates of trusted authorities, whatever name they are issued for
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        // name is checked after the chain, so the rest of verification is done by then
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+                ..
+            })) => Ok(ServerCertVerified::assertion()),
+            verified => verified,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Self-signed certificate of a test authority
+    const TEST_CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBjzCCATWgAwIBAgIUR4rGYYs1WPzImk365QWZJ8pMRDgwCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRamN0bDJncmF5IHRlc3QgQ0EwIBcNMjYxMDE3MDA1ODUwWhgP
+MjEyNjA5MjMwMDU4NTBaMBwxGjAYBgNVBAMMEWpjdGwyZ3JheSB0ZXN0IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE3I2HUo29d8vWVJiaW7OoRPZcpvdfUd9F
+H8XMyv0ZK1jLIWOn9kQWXn/F5+76QHxdOa99L8Wf8MAYE/x3YTgX8qNTMFEwHQYD
+VR0OBBYEFOwkVrUDRWRTUyeak8USf63y4aCSMB8GA1UdIwQYMBaAFOwkVrUDRWRT
+Uyeak8USf63y4aCSMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIh
+ALYFPStcjdUDVdi2z0bZWe0/LCCK6IDEKe8EIrkPHdD9AiBHNjMv6NCC7N6dfu6x
+5faj6OKxuLd2L46I+5tzvE73gA==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn reloads_modified_certificates() {
+        let path = std::env::temp_dir().join(format!("jctl2gray-ca-{}.pem", std::process::id()));
+        let write = |content: &str, age: u64| {
+            fs::write(&path, content).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        write(TEST_CA, 30);
+
+        let options = TlsOptions {
+            ca_cert: Some(path.to_str().unwrap().to_string()),
+            client_cert: None,
+            client_key: None,
+            verify_hostname: true,
+        };
+        let config = TlsClientConfig::new(&options).unwrap();
+        let initial = config.current();
+        assert!(Arc::ptr_eq(&initial, &config.current()));
+
+        // file written partially is retried, rather than taken as loaded
+        write("-----BEGIN CERTIFICATE-----\n", 20);
+        assert!(Arc::ptr_eq(&initial, &config.current()));
+        assert!(Arc::ptr_eq(&initial, &config.current()));
+
+        write(TEST_CA, 10);
+        let renewed = config.current();
+        assert!(!Arc::ptr_eq(&initial, &renewed));
+        assert!(Arc::ptr_eq(&renewed, &config.current()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_certificates() {
+        let options = TlsOptions {
+            ca_cert: Some("/nonexistent/ca.pem".to_string()),
+            client_cert: None,
+            client_key: None,
+            verify_hostname: true,
+        };
+        assert!(TlsClientConfig::new(&options).is_err());
+    }
+}
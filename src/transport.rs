@@ -0,0 +1,800 @@
+//! Transports of GELF messages
+//!
+//! Messages are sent either as UDP datagrams, split into chunks, or over a TCP
+//! connection, optionally encrypted with TLS. Stream transports carry whole
+//! messages, each terminated by a null byte, as GELF requires. Compressed ones
+//! carry the terminator compressed along, as zstd is the only compression of streams.
+//! Connection is established on the first message and re-established after a failure.
+//! Targets given as `unix:<path>` are local sockets, e.g. of a relay: datagram ones are sent
+//! chunks as over UDP, stream ones are sent frames as over TCP.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, IoSlice, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, StreamOwned};
+use socket2::{SockAddr, SockRef};
+
+use crate::errors::{Error, Result};
+
+use crate::backoff::SendBackoff;
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::gelf::{ChunkedMessage, MessageCompression};
+use crate::processing::{write_all_vectored, UdpSender};
+use crate::resolver::Resolver;
+use crate::stats::{SendStats, TargetStats};
+use crate::tls::{TlsClientConfig, TlsOptions};
+
+/// Time to wait for the target to accept connection
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait for the target to accept a message, before the connection is considered broken
+pub(crate) const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Period of trying the failed target again, while its backup is in use
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// TransportKind defines how messages are delivered to the configured target.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransportKind {
+    Udp,
+    Tcp,
+    Tls,
+}
+
+impl<'a> From<&'a str> for TransportKind {
+    fn from(transport: &'a str) -> Self {
+        match transport {
+            "tcp" => TransportKind::Tcp,
+            "tls" => TransportKind::Tls,
+            _ => TransportKind::Udp,
+        }
+    }
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransportKind::Udp => write!(f, "udp"),
+            TransportKind::Tcp => write!(f, "tcp"),
+            TransportKind::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+impl TransportKind {
+    /// Return whether messages are sent whole over a connection, without compression and chunking.
+    pub fn is_stream(self) -> bool {
+        self != TransportKind::Udp
+    }
+}
+
+/// Producing of messages to Kafka topic
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KafkaOptions {
+    /// Bootstrap brokers, as host:port
+    pub brokers: Vec<String>,
+    pub topic: String,
+    /// Field of GELF-message whose value is the key of Kafka message, messages are not keyed if not set
+    pub key: Option<String>,
+    /// Properties of the producer passed to librdkafka as is, e.g. `compression.type=lz4`
+    pub properties: Vec<(String, String)>,
+}
+
+/// Transport sends serialized GELF messages, compressed as configured, e.g. to a custom
+/// destination or into a mock in tests.
+///
+/// Messages are sent one by one from a single thread. A failed message is counted as dropped,
+/// and further messages are dropped during backoff, the same way as with built-in transports.
+pub trait Transport: Send {
+    fn send(&mut self, payload: &[u8]) -> Result<()>;
+}
+
+/// Custom transport along with bookkeeping of its sending
+pub(crate) struct CustomSender {
+    transport: Box<dyn Transport>,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl CustomSender {
+    pub(crate) fn new(transport: Box<dyn Transport>, stats: Arc<TargetStats>) -> Self {
+        CustomSender {
+            transport,
+            stats,
+            backoff: SendBackoff::new(),
+        }
+    }
+
+    /// Send the whole message, unless transport is in backoff, returning whether it was sent.
+    pub(crate) fn send(&mut self, chunked: &ChunkedMessage) -> bool {
+        if !self.backoff.ready() {
+            self.stats.message_dropped();
+            return false;
+        }
+
+        match self.transport.send(chunked.payload()) {
+            Ok(()) => {
+                self.stats.chunk_sent(chunked.payload().len() as u64);
+                self.stats.message_sent();
+                self.backoff.success();
+                true
+            }
+            Err(e) => {
+                self.stats.chunk_failed(&e);
+                self.stats.message_dropped();
+                self.backoff.failure(e);
+                false
+            }
+        }
+    }
+}
+
+/// Sender of messages over the configured transport
+pub(crate) enum GelfSender {
+    Udp(UdpSender),
+    Tcp(GelfTcpSender),
+    Tls(Box<GelfTlsSender>),
+}
+
+impl GelfSender {
+    /// Create sender for the target, so that configuration errors are reported immediately.
+    ///
+    /// UDP sockets are bound to the given local port, TLS certificate is verified for the target host.
+    pub(crate) fn new(
+        config: &Config,
+        target: &str,
+        port: u16,
+        addrs: &[SocketAddr],
+        stats: Arc<TargetStats>,
+    ) -> Result<Self> {
+        let sender = match config.transport {
+            TransportKind::Udp => {
+                GelfSender::Udp(UdpSender::new(port, config.send_buffer, addrs, stats)?)
+            }
+            TransportKind::Tcp => {
+                GelfSender::Tcp(StreamSender::new(TcpConnector, terminated(config), stats))
+            }
+            TransportKind::Tls => GelfSender::Tls(Box::new(StreamSender::new(
+                TlsConnector::new(&config.tls, target)?,
+                terminated(config),
+                stats,
+            ))),
+        };
+
+        Ok(sender)
+    }
+
+    /// Send the batch of messages, unless target is in backoff, returning how many of them
+    /// were sent from the start of the batch.
+    ///
+    /// Over streams the batch is written at once, datagrams are sent one by one
+    /// until one of them fails.
+    pub(crate) fn send(
+        &mut self,
+        batch: &[ChunkedMessage],
+        target: &SocketAddr,
+        chaos: Option<&Chaos>,
+    ) -> usize {
+        match self {
+            GelfSender::Udp(sender) => batch
+                .iter()
+                .take_while(|chunked| sender.send(chunked, target, chaos))
+                .count(),
+            GelfSender::Tcp(sender) => sender.send(batch, target, chaos),
+            GelfSender::Tls(sender) => sender.send(batch, target, chaos),
+        }
+    }
+}
+
+/// Return whether messages carry their terminator, being compressed along with it.
+fn terminated(config: &Config) -> bool {
+    config.compression != MessageCompression::None
+}
+
+/// Resolve all configured targets and create their senders.
+///
+/// The local port is bound for the first target, others are sent to from ephemeral ports.
+/// Backup, if any, takes over from the first target.
+pub(crate) fn configured_targets(config: &Config, stats: &SendStats) -> Result<Vec<Destination>> {
+    config
+        .graylog_addrs
+        .iter()
+        .enumerate()
+        .map(|(n, target)| {
+            let port = if n == 0 { config.sender_port } else { 0 };
+            let endpoint = Endpoint::new(config, target, port, stats)?;
+
+            let failover = match config.failover_addr {
+                Some(ref backup) if n == 0 => Some(Failover {
+                    backup: Endpoint::new(config, backup, 0, stats)?,
+                    threshold: config.failover_after,
+                    failures: 0,
+                    probed_at: None,
+                }),
+                _ => None,
+            };
+
+            Ok(Destination { endpoint, failover })
+        })
+        .collect()
+}
+
+/// Return path of the local socket, if the target is given as `unix:<path>`.
+pub fn unix_path(target: &str) -> Option<&Path> {
+    target.strip_prefix("unix:").map(Path::new)
+}
+
+/// Target along with its sender
+struct Endpoint {
+    name: String,
+    link: Link,
+}
+
+/// Address of the target and the sender suitable for it
+enum Link {
+    Inet {
+        resolver: Resolver,
+        sender: GelfSender,
+    },
+    Unix {
+        path: PathBuf,
+        sender: UnixSender,
+    },
+}
+
+impl Endpoint {
+    fn new(config: &Config, target: &str, port: u16, stats: &SendStats) -> Result<Self> {
+        let link = match unix_path(target) {
+            Some(path) => Link::Unix {
+                path: path.to_path_buf(),
+                sender: UnixSender::new(config, stats.target(target))?,
+            },
+            None => {
+                // obtain target address (first resolve may fail)
+                let resolver =
+                    Resolver::new(target, config.graylog_addr_ttl, config.resolve_strategy)?;
+                let sender =
+                    GelfSender::new(config, target, port, resolver.addrs(), stats.target(target))?;
+                Link::Inet { resolver, sender }
+            }
+        };
+
+        Ok(Endpoint {
+            name: target.to_string(),
+            link,
+        })
+    }
+
+    fn send(&mut self, batch: &[ChunkedMessage], chaos: Option<&Chaos>) -> usize {
+        match self.link {
+            Link::Inet {
+                ref mut resolver,
+                ref mut sender,
+            } => sender.send(batch, &resolver.target(), chaos),
+            Link::Unix {
+                ref path,
+                ref mut sender,
+            } => sender.send(batch, path, chaos),
+        }
+    }
+}
+
+/// Sender of messages to a local socket, datagram one with UDP transport and stream one with TCP
+enum UnixSender {
+    Datagram(UnixDatagramSender),
+    Stream(StreamSender<UnixConnector>),
+}
+
+impl UnixSender {
+    fn new(config: &Config, stats: Arc<TargetStats>) -> Result<Self> {
+        let sender = match config.transport {
+            TransportKind::Udp => {
+                UnixSender::Datagram(UnixDatagramSender::new(config.send_buffer, stats)?)
+            }
+            TransportKind::Tcp => {
+                UnixSender::Stream(StreamSender::new(UnixConnector, terminated(config), stats))
+            }
+            TransportKind::Tls => {
+                return Err(Error::InternalError(
+                    "tls transport is not supported for unix sockets".to_string(),
+                ))
+            }
+        };
+
+        Ok(sender)
+    }
+
+    fn send(&mut self, batch: &[ChunkedMessage], path: &Path, chaos: Option<&Chaos>) -> usize {
+        match self {
+            UnixSender::Datagram(sender) => batch
+                .iter()
+                .take_while(|chunked| sender.send(chunked, path, chaos))
+                .count(),
+            UnixSender::Stream(sender) => sender.send(batch, path, chaos),
+        }
+    }
+}
+
+/// Unbound datagram socket along with bookkeeping of its target
+struct UnixDatagramSender {
+    socket: UnixDatagram,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl UnixDatagramSender {
+    fn new(send_buffer: Option<usize>, stats: Arc<TargetStats>) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        if let Some(size) = send_buffer {
+            SockRef::from(&socket).set_send_buffer_size(size)?;
+        }
+
+        Ok(UnixDatagramSender {
+            socket,
+            stats,
+            backoff: SendBackoff::new(),
+        })
+    }
+
+    /// Send all chunks of the message, unless target is in backoff, returning whether it was sent.
+    fn send(&mut self, chunked: &ChunkedMessage, path: &Path, chaos: Option<&Chaos>) -> bool {
+        if !self.backoff.ready() {
+            self.stats.message_dropped();
+            return false;
+        }
+
+        let target = match SockAddr::unix(path) {
+            Ok(target) => target,
+            Err(e) => {
+                self.stats.chunk_failed(&e);
+                self.stats.message_dropped();
+                self.backoff.failure(e);
+                return false;
+            }
+        };
+
+        for chunk in chunked.chunks() {
+            let socket = SockRef::from(&self.socket);
+            let sent = match chaos {
+                Some(chaos) => chaos
+                    .before_send()
+                    .and_then(|_| socket.send_to_vectored(&chunk.io_slices(), &target)),
+                None => socket.send_to_vectored(&chunk.io_slices(), &target),
+            };
+
+            match sent {
+                Ok(sent) => self.stats.chunk_sent(sent as u64),
+                Err(e) => {
+                    // rest of the chunks are useless without this one
+                    self.stats.chunk_failed(&e);
+                    self.stats.message_dropped();
+                    self.backoff.failure(e);
+                    return false;
+                }
+            }
+        }
+
+        self.stats.message_sent();
+        self.backoff.success();
+        true
+    }
+}
+
+/// Backup taking over after a number of consecutive failures of the target
+struct Failover {
+    backup: Endpoint,
+    threshold: u32,
+    failures: u32,
+    /// Time the target was last tried while backup is in use
+    probed_at: Option<Instant>,
+}
+
+/// Configured target, possibly with a backup
+pub(crate) struct Destination {
+    endpoint: Endpoint,
+    failover: Option<Failover>,
+}
+
+impl Destination {
+    /// Return name of the target, as configured.
+    pub(crate) fn name(&self) -> &str {
+        &self.endpoint.name
+    }
+
+    /// Send the batch of messages to the target, or to the backup while the target is failing,
+    /// returning how many of them were sent from the start of the batch.
+    ///
+    /// While backup is in use, the target is tried again once per probe interval,
+    /// taking over back on success. Messages left by the failed target are passed to the backup.
+    pub(crate) fn send(&mut self, batch: &[ChunkedMessage], chaos: Option<&Chaos>) -> usize {
+        let failover = match self.failover {
+            Some(ref mut failover) => failover,
+            None => return self.endpoint.send(batch, chaos),
+        };
+
+        match failover.probed_at {
+            Some(at) if at.elapsed() < PROBE_INTERVAL => return failover.backup.send(batch, chaos),
+            Some(_) => failover.probed_at = Some(Instant::now()),
+            None => {}
+        }
+
+        let sent = self.endpoint.send(batch, chaos);
+        if sent == batch.len() {
+            if failover.probed_at.take().is_some() {
+                info!(
+                    "target {} is available again, switching back from {}",
+                    self.endpoint.name, failover.backup.name
+                );
+            }
+            failover.failures = 0;
+            return sent;
+        }
+
+        if failover.probed_at.is_none() {
+            failover.failures += 1;
+            if failover.failures < failover.threshold {
+                return sent;
+            }
+
+            warn!(
+                "target {} failed {} times in a row, switching to {}",
+                self.endpoint.name, failover.failures, failover.backup.name
+            );
+            failover.probed_at = Some(Instant::now());
+        }
+
+        sent + failover.backup.send(&batch[sent..], chaos)
+    }
+}
+
+/// Establishing of connections to the target
+pub(crate) trait Connect {
+    type Target: fmt::Debug + ?Sized;
+    type Stream: Write;
+
+    fn connect(&self, target: &Self::Target) -> io::Result<Self::Stream>;
+}
+
+/// Plain TCP connections
+pub(crate) struct TcpConnector;
+
+impl Connect for TcpConnector {
+    type Target = SocketAddr;
+    type Stream = TcpStream;
+
+    fn connect(&self, target: &SocketAddr) -> io::Result<TcpStream> {
+        connect_tcp(target)
+    }
+}
+
+/// TCP connections encrypted with TLS
+pub(crate) struct TlsConnector {
+    config: TlsClientConfig,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Load certificates, verifying the target by the host part of its address.
+    pub(crate) fn new(options: &TlsOptions, address: &str) -> Result<Self> {
+        let host = host_of(address);
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| Error::Tls(format!("invalid server name {}: {}", host, e)))?;
+
+        Ok(TlsConnector {
+            config: TlsClientConfig::new(options)?,
+            server_name,
+        })
+    }
+}
+
+impl Connect for TlsConnector {
+    type Target = SocketAddr;
+    type Stream = StreamOwned<ClientConnection, TcpStream>;
+
+    fn connect(&self, target: &SocketAddr) -> io::Result<Self::Stream> {
+        let connection = ClientConnection::new(self.config.current(), self.server_name.clone())
+            .map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(connection, connect_tcp(target)?);
+
+        // complete handshake right away, so that certificate problems are reported on connect
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+
+        Ok(stream)
+    }
+}
+
+fn connect_tcp(target: &SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(target, CONNECT_TIMEOUT)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Connections to a local stream socket
+pub(crate) struct UnixConnector;
+
+impl Connect for UnixConnector {
+    type Target = Path;
+    type Stream = UnixStream;
+
+    fn connect(&self, target: &Path) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(target)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+pub(crate) type GelfTcpSender = StreamSender<TcpConnector>;
+pub(crate) type GelfTlsSender = StreamSender<TlsConnector>;
+
+/// Connection to the target along with bookkeeping of the target
+pub(crate) struct StreamSender<C: Connect> {
+    connector: C,
+    stream: Option<C::Stream>,
+    /// Whether messages carry their terminator, rather than being followed by null byte
+    terminated: bool,
+    stats: Arc<TargetStats>,
+    backoff: SendBackoff,
+}
+
+impl<C: Connect> StreamSender<C> {
+    pub(crate) fn new(connector: C, terminated: bool, stats: Arc<TargetStats>) -> Self {
+        StreamSender {
+            connector,
+            stream: None,
+            terminated,
+            stats,
+            backoff: SendBackoff::new(),
+        }
+    }
+
+    /// Send the whole batch of messages, unless target is in backoff, returning how many of them
+    /// were sent: either all or none, as it is unknown how much of a failed write was delivered.
+    ///
+    /// Established connection is kept regardless of the target address, until it fails.
+    pub(crate) fn send(
+        &mut self,
+        batch: &[ChunkedMessage],
+        target: &C::Target,
+        chaos: Option<&Chaos>,
+    ) -> usize {
+        if !self.backoff.ready() {
+            batch.iter().for_each(|_| self.stats.message_dropped());
+            return 0;
+        }
+
+        let sent = match chaos {
+            Some(chaos) => chaos.before_send().and_then(|_| self.write(batch, target)),
+            None => self.write(batch, target),
+        };
+
+        match sent {
+            Ok(()) => {
+                let terminator = if self.terminated { 0 } else { 1 };
+                for chunked in batch {
+                    // every message is a frame terminated by null byte
+                    self.stats
+                        .chunk_sent(chunked.payload().len() as u64 + terminator);
+                    self.stats.message_sent();
+                }
+                self.backoff.success();
+                batch.len()
+            }
+            Err(e) => {
+                // connection state is unknown after failure
+                self.stream = None;
+                self.stats.chunk_failed(&e);
+                batch.iter().for_each(|_| self.stats.message_dropped());
+                self.backoff.failure(e);
+                0
+            }
+        }
+    }
+
+    /// Write messages terminated by null bytes, unless they carry their terminator,
+    /// connecting first if required.
+    fn write(&mut self, batch: &[ChunkedMessage], target: &C::Target) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(self.connector.connect(target)?);
+            debug!("connected to {:?}", target);
+        }
+
+        // messages are written at once, rather than in separate packets or TLS records,
+        // without copying them into a single buffer
+        let mut slices = Vec::with_capacity(batch.len() * 2);
+        for chunked in batch {
+            slices.push(IoSlice::new(chunked.payload()));
+            if !self.terminated {
+                slices.push(IoSlice::new(&[0]));
+            }
+        }
+
+        let stream = self.stream.as_mut().unwrap();
+        write_all_vectored(stream, &mut slices)?;
+        stream.flush()
+    }
+}
+
+/// Return host part of `host:port` address.
+fn host_of(address: &str) -> &str {
+    let host = match address.rfind(':') {
+        Some(colon) => &address[..colon],
+        None => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn host_of_address() {
+        assert_eq!(host_of("graylog.domain.com:12201"), "graylog.domain.com");
+        assert_eq!(host_of("10.0.0.1:12201"), "10.0.0.1");
+        assert_eq!(host_of("[::1]:12201"), "::1");
+    }
+
+    #[test]
+    fn sends_to_unix_sockets() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stats = SendStats::new();
+        let batch = [
+            ChunkedMessage::whole(b"{\"n\":1}".to_vec()),
+            ChunkedMessage::whole(b"{\"n\":2}".to_vec()),
+        ];
+
+        let path = dir.join("gelf.dgram");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let mut sender =
+            UnixSender::Datagram(UnixDatagramSender::new(None, stats.target("dgram")).unwrap());
+        assert_eq!(sender.send(&batch, &path, None), 2);
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"{\"n\":1}");
+
+        let path = dir.join("gelf.stream");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let mut sender = UnixSender::Stream(StreamSender::new(
+            UnixConnector,
+            false,
+            stats.target("stream"),
+        ));
+        assert_eq!(sender.send(&batch, &path, None), 2);
+        drop(sender);
+        let mut frames = Vec::new();
+        io::Read::read_to_end(&mut listener.accept().unwrap().0, &mut frames).unwrap();
+        assert_eq!(frames, b"{\"n\":1}\0{\"n\":2}\0");
+
+        assert!(unix_path("graylog:12201").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Stream collecting written bytes, shared with the test
+    struct Collected(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Collected {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct CollectedConnector(Arc<Mutex<Vec<u8>>>);
+
+    impl Connect for CollectedConnector {
+        type Target = str;
+        type Stream = Collected;
+
+        fn connect(&self, _: &str) -> io::Result<Collected> {
+            Ok(Collected(self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn writes_whole_batch() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let stats = SendStats::new();
+        let batch = [
+            ChunkedMessage::whole(b"{\"n\":1}".to_vec()),
+            ChunkedMessage::whole(b"{\"n\":22}".to_vec()),
+        ];
+
+        let connector = CollectedConnector(written.clone());
+        let mut sender = StreamSender::new(connector, false, stats.target("framed"));
+        assert_eq!(sender.send(&batch, "framed", None), 2);
+        assert_eq!(*written.lock().unwrap(), b"{\"n\":1}\0{\"n\":22}\0");
+        let sent = stats.target("framed").snapshot();
+        assert_eq!(sent.messages, 2);
+        assert_eq!(sent.chunks, 2);
+        assert_eq!(sent.bytes, 17);
+
+        // messages carrying their terminator are written as they are
+        written.lock().unwrap().clear();
+        let connector = CollectedConnector(written.clone());
+        let mut sender = StreamSender::new(connector, true, stats.target("terminated"));
+        assert_eq!(sender.send(&batch, "terminated", None), 2);
+        assert_eq!(*written.lock().unwrap(), b"{\"n\":1}{\"n\":22}");
+        assert_eq!(stats.target("terminated").snapshot().messages, 2);
+    }
+
+    /// Transport failing the first message and counting all attempts
+    struct Flaky(Arc<AtomicUsize>);
+
+    impl Transport for Flaky {
+        fn send(&mut self, _: &[u8]) -> Result<()> {
+            match self.0.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(Error::InternalError("unavailable".to_string())),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_transport_backs_off() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let stats = Arc::new(TargetStats::default());
+        let mut sender = CustomSender::new(Box::new(Flaky(attempts.clone())), stats.clone());
+        let message = ChunkedMessage::whole(b"{}".to_vec());
+
+        assert!(!sender.send(&message));
+        // further messages are dropped without trying the transport
+        assert!(!sender.send(&message));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        // sending is tried again once the backoff passes
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(sender.send(&message));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            (snapshot.messages, snapshot.errors, snapshot.dropped),
+            (1, 1, 2)
+        );
+    }
+
+    #[test]
+    fn fails_over_to_backup() {
+        let dir = std::env::temp_dir().join(format!("jctl2gray-failover-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let primary = format!("unix:{}", dir.join("primary").display());
+        let backup = dir.join("backup");
+        let receiver = UnixDatagram::bind(&backup).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+
+        let mut config = Config::for_tests();
+        config.graylog_addrs = vec![primary.clone()];
+        config.failover_addr = Some(format!("unix:{}", backup.display()));
+        config.failover_after = 2;
+        let stats = SendStats::new();
+        let mut destination = configured_targets(&config, &stats).unwrap().remove(0);
+        let batch = [ChunkedMessage::whole(b"{}".to_vec())];
+
+        // primary target is not listening, the backup takes over after two failures
+        assert_eq!(destination.send(&batch, None), 0);
+        assert!(receiver.recv(&mut [0; 16]).is_err());
+        assert_eq!(destination.send(&batch, None), 1);
+        assert_eq!(destination.send(&batch, None), 1);
+        assert_eq!(receiver.recv(&mut [0; 16]).unwrap(), 2);
+        assert_eq!(receiver.recv(&mut [0; 16]).unwrap(), 2);
+        assert_eq!(destination.name(), primary);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
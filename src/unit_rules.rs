@@ -0,0 +1,143 @@
+//! Rules of systemd units
+//!
+//! Records of a particular unit could have their own level threshold, messages to drop
+//! and additional fields, e.g. a stricter level for a noisy service or the team owning it.
+//! Rules are compiled into a map keyed by unit when the config is built, on start and
+//! on reload, so that every record finds the rules of its unit by a single lookup.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::gelf::LevelSystem;
+
+/// Filters and fields of records of a unit
+#[derive(Debug, Default)]
+pub struct UnitRule {
+    /// Threshold of system level, replacing the common one
+    pub level: Option<LevelSystem>,
+    /// Messages to drop, in addition to the common exclusions
+    pub exclude: Vec<Regex>,
+    /// Additional fields, overriding the common ones
+    pub fields: Vec<(String, String)>,
+}
+
+impl UnitRule {
+    /// Return whether the message is dropped by the rule.
+    pub fn excludes(&self, msg: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(msg))
+    }
+}
+
+/// Rules indexed by unit name
+#[derive(Debug, Default)]
+pub struct UnitRules(HashMap<String, UnitRule>);
+
+impl UnitRules {
+    /// Parse rules given as `<unit>:<rule>`, where rule is either `sys=<level>`, `exclude=<regex>`
+    /// or `field=<name>=<text>`. Rules of the same unit are merged, units without suffix are services.
+    pub fn parse<'a, I>(specs: I) -> Result<UnitRules, String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut rules = UnitRules::default();
+
+        for spec in specs {
+            let (unit, rule) = spec
+                .split_once(':')
+                .filter(|(unit, _)| !unit.is_empty())
+                .ok_or_else(|| format!("unit rule should be given as unit:rule, got {}", spec))?;
+            let (kind, value) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("unit rule should be given as kind=value, got {}", rule))?;
+
+            let entry = rules.0.entry(unit_name(unit)).or_default();
+            match kind {
+                "sys" => entry.level = Some(parse_level(value)?),
+                "exclude" => entry
+                    .exclude
+                    .push(Regex::new(value).map_err(|e| e.to_string())?),
+                "field" => match value.split_once('=') {
+                    Some((name, text)) if !name.is_empty() => {
+                        entry.fields.retain(|(n, _)| n != name);
+                        entry.fields.push((name.to_string(), text.to_string()));
+                    }
+                    _ => return Err(format!("field should be given as name=text, got {}", value)),
+                },
+                kind => return Err(format!("unknown unit rule: {}", kind)),
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Return rules of the unit, if any.
+    pub fn get(&self, unit: &str) -> Option<&UnitRule> {
+        self.0.get(unit)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return fields of all rules, e.g. to validate their names.
+    pub fn fields(&self) -> impl Iterator<Item = &(String, String)> {
+        self.0.values().flat_map(|rule| rule.fields.iter())
+    }
+}
+
+/// Return unit name with `.service` suffix if it has none, as journalctl does.
+fn unit_name(unit: &str) -> String {
+    if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{}.service", unit)
+    }
+}
+
+fn parse_level(name: &str) -> Result<LevelSystem, String> {
+    (0..8)
+        .map(LevelSystem::from_num)
+        .find(|level| level.to_string() == name)
+        .ok_or_else(|| format!("unknown level: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_by_unit() {
+        let rules = UnitRules::parse(vec![
+            "nginx:sys=warning",
+            "nginx.service:exclude=^GET /health",
+            "nginx:field=team=web",
+            "nginx:field=team=edge",
+            "cron.timer:field=owner=ops=core",
+        ])
+        .unwrap();
+
+        let nginx = rules.get("nginx.service").unwrap();
+        assert_eq!(nginx.level, Some(LevelSystem::Warning));
+        assert!(nginx.excludes("GET /health 200"));
+        assert!(!nginx.excludes("GET /index 200"));
+        assert_eq!(nginx.fields, vec![("team".to_string(), "edge".to_string())]);
+
+        let cron = rules.get("cron.timer").unwrap();
+        assert_eq!(cron.level, None);
+        assert_eq!(
+            cron.fields,
+            vec![("owner".to_string(), "ops=core".to_string())]
+        );
+
+        assert!(rules.get("nginx").is_none());
+        assert!(rules.get("sshd.service").is_none());
+
+        assert!(UnitRules::parse(vec!["nginx"]).is_err());
+        assert!(UnitRules::parse(vec![":sys=info"]).is_err());
+        assert!(UnitRules::parse(vec!["nginx:sys=verbose"]).is_err());
+        assert!(UnitRules::parse(vec!["nginx:exclude=("]).is_err());
+        assert!(UnitRules::parse(vec!["nginx:field=team"]).is_err());
+        assert!(UnitRules::parse(vec!["nginx:rename=a=b"]).is_err());
+    }
+}